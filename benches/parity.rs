@@ -0,0 +1,122 @@
+//! Converts one fixture with topodump and, if it's on the PATH, with
+//! `gdal_translate` reading the same quad through a VRT, and reports their
+//! relative throughput.
+
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+use std::time::{Duration, Instant};
+
+use topodump::vrt::tpq_vrt;
+use topodump::{Compression, Conversion, TpqFile};
+
+#[path = "../tests/common/mod.rs"]
+mod common;
+
+// A typical 1:24,000 sheet
+const MAPLETS: u32 = 10;
+const MAPLET_SIZE: u32 = 256;
+const RUNS: u32 = 5;
+
+// (name, topodump compression, gdal_translate creation options)
+const CASES: &[(&str, Compression, &[&str])] = &[
+    ("uncompressed", Compression::None, &[]),
+    (
+        "deflate",
+        Compression::Deflate,
+        &["-co", "COMPRESS=DEFLATE"],
+    ),
+    ("jpeg", Compression::Jpeg, &["-co", "COMPRESS=JPEG"]),
+];
+
+// The fastest of several runs, to discount warm-up and noise
+fn fastest(mut run: impl FnMut() -> bool) -> Option<Duration> {
+    let mut best = None;
+    for _ in 0..RUNS {
+        let start = Instant::now();
+        if !run() {
+            return None;
+        }
+        let elapsed = start.elapsed();
+        best = Some(best.map_or(elapsed, |best: Duration| best.min(elapsed)));
+    }
+    best
+}
+
+fn megapixels_per_second(elapsed: Duration) -> f64 {
+    let pixels = (MAPLETS * MAPLET_SIZE) as f64 * (MAPLETS * MAPLET_SIZE) as f64;
+    pixels / 1e6 / elapsed.as_secs_f64()
+}
+
+fn gdal_translate(vrt: &Path, output: &Path, options: &[&str]) -> bool {
+    Command::new("gdal_translate")
+        .arg("-q")
+        .args(&["-co", "TILED=YES"])
+        .args(options)
+        .arg(vrt)
+        .arg(output)
+        .status()
+        .map_or(false, |status| status.success())
+}
+
+fn main() {
+    let dir = common::scratch_dir("parity");
+    let input = dir.join("fixture.tpq");
+    common::write_tpq(
+        &input,
+        &common::synthetic_tpq_sized(MAPLETS, MAPLETS, MAPLET_SIZE),
+    );
+    let vrt = dir.join("fixture.vrt");
+    fs::write(
+        &vrt,
+        tpq_vrt(&TpqFile::open(&input).unwrap(), &input).unwrap(),
+    )
+    .unwrap();
+
+    println!(
+        "{:<14} {:>16} {:>16} {:>8}",
+        "case", "topodump MP/s", "gdal MP/s", "ratio"
+    );
+    for (name, compression, options) in CASES {
+        let output = dir.join(format!("topodump_{}.tif", name));
+        let topodump = fastest(|| {
+            Conversion::new(&input)
+                .output(&output)
+                .compression(*compression)
+                .run()
+                .is_ok()
+        });
+        // Compressed output needs the gdal feature
+        let topodump = match topodump {
+            Some(topodump) => topodump,
+            None => {
+                println!("{:<14} {:>16}", name, "unsupported");
+                continue;
+            }
+        };
+
+        let gdal_output = dir.join(format!("gdal_{}.tif", name));
+        let gdal = fastest(|| {
+            let _ = fs::remove_file(&gdal_output);
+            gdal_translate(&vrt, &gdal_output, options)
+        });
+
+        match gdal {
+            Some(gdal) => println!(
+                "{:<14} {:>16.1} {:>16.1} {:>7.2}x",
+                name,
+                megapixels_per_second(topodump),
+                megapixels_per_second(gdal),
+                gdal.as_secs_f64() / topodump.as_secs_f64()
+            ),
+            None => println!(
+                "{:<14} {:>16.1} {:>16} {:>8}",
+                name,
+                megapixels_per_second(topodump),
+                "-",
+                "-"
+            ),
+        }
+    }
+    println!("ratio > 1 means topodump is faster; gdal columns need gdal_translate on the PATH");
+}
@@ -0,0 +1,76 @@
+use std::io::Cursor;
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use image::{DynamicImage, GenericImageView};
+use topodump::header::read_tpq_header;
+use topodump::output::encode_geotiff;
+use topodump::{GeoRef, TpqFile};
+
+#[path = "../tests/common/mod.rs"]
+mod common;
+
+// (columns, rows) of maplets, from a small quad up to a typical 1:24,000 sheet
+const SIZES: &[(u32, u32)] = &[(2, 2), (5, 5), (10, 10)];
+const MAPLET_SIZE: u32 = 256;
+
+fn fixtures() -> Vec<(String, Vec<u8>)> {
+    SIZES
+        .iter()
+        .map(|&(columns, rows)| {
+            (
+                format!("{}x{}", columns, rows),
+                common::synthetic_tpq_sized(columns, rows, MAPLET_SIZE),
+            )
+        })
+        .collect()
+}
+
+fn header_parse(c: &mut Criterion) {
+    let data = common::synthetic_tpq_sized(1, 1, MAPLET_SIZE);
+    c.bench_function("header parse", |b| {
+        b.iter(|| read_tpq_header(&mut Cursor::new(&data)).unwrap())
+    });
+}
+
+fn maplet_decode(c: &mut Criterion) {
+    let tpq = TpqFile::from_bytes(common::synthetic_tpq_sized(1, 1, MAPLET_SIZE)).unwrap();
+    c.bench_function("maplet decode", |b| b.iter(|| tpq.maplet(0, 0).unwrap()));
+}
+
+fn collage(c: &mut Criterion) {
+    let mut group = c.benchmark_group("collage");
+    for (name, data) in fixtures() {
+        let tpq = TpqFile::from_bytes(data).unwrap();
+        group.throughput(Throughput::Elements(tpq.maplet_count() as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(name), &tpq, |b, tpq| {
+            b.iter(|| tpq.collage().unwrap())
+        });
+    }
+    group.finish();
+}
+
+fn tiff_encode(c: &mut Criterion) {
+    let mut group = c.benchmark_group("tiff encode");
+    for (name, data) in fixtures() {
+        let tpq = TpqFile::from_bytes(data).unwrap();
+        let collage_img =
+            DynamicImage::ImageRgb8(DynamicImage::ImageRgba8(tpq.collage().unwrap()).to_rgb8());
+        let georef = GeoRef::from_header(
+            tpq.header(),
+            collage_img.width() as f64,
+            collage_img.height() as f64,
+        );
+        group.throughput(Throughput::Bytes(collage_img.as_bytes().len() as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(name), &collage_img, |b, img| {
+            b.iter(|| {
+                let mut tiff = Cursor::new(Vec::new());
+                encode_geotiff(&mut tiff, img, Some(&georef), None).unwrap();
+                tiff
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, header_parse, maplet_decode, collage, tiff_encode);
+criterion_main!(benches);
@@ -0,0 +1,93 @@
+#ifndef TOPODUMP_H
+#define TOPODUMP_H
+
+/* Generated with cbindgen --config cbindgen.toml --output include/topodump.h */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+typedef enum TopodumpCompression {
+  TOPODUMP_COMPRESSION_NONE = 0,
+  TOPODUMP_COMPRESSION_DEFLATE,
+  TOPODUMP_COMPRESSION_LZW,
+  TOPODUMP_COMPRESSION_JPEG,
+} TopodumpCompression;
+
+typedef enum TopodumpFormat {
+  TOPODUMP_FORMAT_G_TIFF = 0,
+  TOPODUMP_FORMAT_COG,
+  TOPODUMP_FORMAT_PNG,
+  /**
+   * From the output's extension, or GeoTIFF without one.
+   */
+  TOPODUMP_FORMAT_AUTO,
+} TopodumpFormat;
+
+typedef enum TopodumpStatus {
+  TOPODUMP_STATUS_OK = 0,
+  TOPODUMP_STATUS_INVALID_ARGUMENT,
+  TOPODUMP_STATUS_IO,
+  TOPODUMP_STATUS_HEADER,
+  TOPODUMP_STATUS_DECODE,
+  TOPODUMP_STATUS_GDAL,
+  TOPODUMP_STATUS_CANCELLED,
+  TOPODUMP_STATUS_UNSUPPORTED,
+  TOPODUMP_STATUS_OTHER,
+} TopodumpStatus;
+
+typedef struct TopodumpOptions {
+  const char *input;
+  /**
+   * May be null to name the output after the input.
+   */
+  const char *output;
+  enum TopodumpFormat format;
+  enum TopodumpCompression compression;
+} TopodumpOptions;
+
+typedef struct TopodumpHeader {
+  uint32_t version;
+  double west;
+  double north;
+  double east;
+  double south;
+  char quad_name[129];
+  char state_name[33];
+  char source[33];
+  char year1[5];
+  char year2[5];
+  char contour[25];
+  uint32_t color_depth;
+  uint32_t columns;
+  uint32_t rows;
+} TopodumpHeader;
+
+/**
+ * Converts a tpq file as described by `options`.
+ *
+ * # Safety
+ *
+ * `options` must point to a valid `TopodumpOptions` whose `input` (and
+ * `output`, if not null) are NUL-terminated strings.
+ */
+enum TopodumpStatus topodump_convert(const struct TopodumpOptions *options);
+
+/**
+ * Reads the header of the tpq file at `path` into `header`.
+ *
+ * # Safety
+ *
+ * `path` must be a NUL-terminated string and `header` must point to writable
+ * memory for a `TopodumpHeader`.
+ */
+enum TopodumpStatus topodump_read_header(const char *path, struct TopodumpHeader *header);
+
+/**
+ * The message of the last error on this thread, or null. Valid until the
+ * next topodump call on the same thread.
+ */
+const char *topodump_last_error(void);
+
+#endif /* TOPODUMP_H */
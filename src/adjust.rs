@@ -0,0 +1,309 @@
+//! Tone adjustments for faded scans, colour keying and deblocking, applied
+//! to each maplet as it's composited, and sharpening of the composited
+//! image.
+
+use image::{imageops, DynamicImage, RgbaImage};
+
+use crate::error::Result;
+use crate::output::OutputWriter;
+use crate::pixels;
+
+// The share of the darkest and of the lightest samples auto-levels clips, so
+// a few stray pixels don't set the range
+const CLIP_FRACTION: f64 = 0.005;
+
+// JPEG's blocks are this many pixels across
+const JPEG_BLOCK: u32 = 8;
+// Deblocking smooths a step across a block edge at most this steep, leaving
+// real edges alone...
+const DEBLOCK_STEP: i16 = 24;
+// ...where either side is at least this flat
+const DEBLOCK_FLATNESS: i16 = 6;
+
+// Unsharp masking subtracts a blur of this standard deviation, in pixels...
+const SHARPEN_SIGMA: f32 = 1.0;
+// ...which reaches about this far
+const SHARPEN_MARGIN: u32 = 3;
+
+/// Brightness, contrast and gamma, each unchanged at its default.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) struct Tone {
+    /// Added to every channel, from -1 to 1 of full range.
+    pub(crate) brightness: f64,
+    /// Scales every channel's distance from mid-gray.
+    pub(crate) contrast: f64,
+    /// Above 1 lightens the midtones, below 1 darkens them.
+    pub(crate) gamma: f64,
+}
+
+impl Default for Tone {
+    fn default() -> Self {
+        Tone {
+            brightness: 0.0,
+            contrast: 1.0,
+            gamma: 1.0,
+        }
+    }
+}
+
+/// A lookup table per channel: each channel's range stretched to the full
+/// range, then the tone applied.
+#[derive(Clone, Debug)]
+pub(crate) struct Levels {
+    tables: [[u8; 256]; 3],
+}
+
+impl Levels {
+    pub(crate) fn new(ranges: [(u8, u8); 3], tone: Tone) -> Self {
+        let mut tables = [[0; 256]; 3];
+        for (table, &(low, high)) in tables.iter_mut().zip(&ranges) {
+            let (low, high) = (low as f64, (high as f64).max(low as f64 + 1.0));
+            let (low, high) = (low / 255.0, high / 255.0);
+            for (value, out) in table.iter_mut().enumerate() {
+                let v = ((value as f64 / 255.0 - low) / (high - low)).clamp(0.0, 1.0);
+                let v = ((v - 0.5) * tone.contrast + 0.5 + tone.brightness).clamp(0.0, 1.0);
+                *out = (v.powf(1.0 / tone.gamma.max(0.01)) * 255.0).round() as u8;
+            }
+        }
+        Levels { tables }
+    }
+
+    /// `image` with its colour channels looked up, alpha left as it is.
+    pub(crate) fn apply(&self, image: DynamicImage) -> DynamicImage {
+        match image {
+            DynamicImage::ImageRgb8(mut rgb) => {
+                self.map(&mut rgb, 3);
+                DynamicImage::ImageRgb8(rgb)
+            }
+            other => {
+                let mut rgba = other.into_rgba8();
+                self.map(&mut rgba, 4);
+                DynamicImage::ImageRgba8(rgba)
+            }
+        }
+    }
+
+    fn map(&self, samples: &mut [u8], channels: usize) {
+        for pixel in samples.chunks_exact_mut(channels) {
+            for (sample, table) in pixel.iter_mut().zip(&self.tables) {
+                *sample = table[*sample as usize];
+            }
+        }
+    }
+}
+
+/// `image` with alpha, transparent wherever every channel is within
+/// `tolerance` of `colour`.
+pub(crate) fn key_out(image: DynamicImage, colour: [u8; 3], tolerance: u8) -> DynamicImage {
+    let mut rgba = image.into_rgba8();
+    for pixel in rgba.pixels_mut() {
+        let near = pixel.0[..3]
+            .iter()
+            .zip(&colour)
+            .all(|(&a, &b)| (a as i16 - b as i16).unsigned_abs() <= tolerance as u16);
+        if near {
+            pixel.0[3] = 0;
+        }
+    }
+    DynamicImage::ImageRgba8(rgba)
+}
+
+/// `image`, a decoded maplet, with the steps JPEG compression leaves along
+/// the edges of its blocks smoothed over. Alpha is left as it is.
+pub(crate) fn deblock(image: DynamicImage) -> DynamicImage {
+    match image {
+        DynamicImage::ImageLuma8(mut luma) => {
+            let width = luma.width();
+            deblock_samples(&mut luma, width, 1);
+            DynamicImage::ImageLuma8(luma)
+        }
+        DynamicImage::ImageRgb8(mut rgb) => {
+            let width = rgb.width();
+            deblock_samples(&mut rgb, width, 3);
+            DynamicImage::ImageRgb8(rgb)
+        }
+        other => {
+            let mut rgba = other.into_rgba8();
+            let width = rgba.width();
+            deblock_samples(&mut rgba, width, 4);
+            DynamicImage::ImageRgba8(rgba)
+        }
+    }
+}
+
+fn deblock_samples(samples: &mut [u8], width: u32, channels: usize) {
+    let stride = width as usize * channels;
+    let height = (samples.len() / stride.max(1)) as u32;
+    let colour = channels.min(3);
+    // Across the edges between columns of blocks, then between rows
+    for x in (JPEG_BLOCK..width.saturating_sub(1)).step_by(JPEG_BLOCK as usize) {
+        for y in 0..height as usize {
+            let at = y * stride + x as usize * channels;
+            for c in 0..colour {
+                smooth_edge(samples, at + c, channels);
+            }
+        }
+    }
+    for y in (JPEG_BLOCK..height.saturating_sub(1)).step_by(JPEG_BLOCK as usize) {
+        for x in 0..width as usize {
+            let at = y as usize * stride + x * channels;
+            for c in 0..colour {
+                smooth_edge(samples, at + c, stride);
+            }
+        }
+    }
+}
+
+// Smooths the step between the samples either side of an edge, the first
+// after it at `at`, `step` apart
+fn smooth_edge(samples: &mut [u8], at: usize, step: usize) {
+    let sample = |offset: isize| samples[(at as isize + offset * step as isize) as usize] as i16;
+    let (p1, p0, q0, q1) = (sample(-2), sample(-1), sample(0), sample(1));
+    if (p0 - q0).abs() >= DEBLOCK_STEP
+        || (p1 - p0).abs() >= DEBLOCK_FLATNESS
+        || (q1 - q0).abs() >= DEBLOCK_FLATNESS
+    {
+        return;
+    }
+    samples[at - step] = ((p1 + 2 * p0 + q0 + 2) / 4) as u8;
+    samples[at] = ((p0 + 2 * q0 + q1 + 2) / 4) as u8;
+}
+
+/// The range of each colour channel over `images`, less the darkest and
+/// lightest samples, for auto-levels.
+pub(crate) fn auto_ranges(images: &[DynamicImage]) -> [(u8, u8); 3] {
+    let mut histograms = [[0u64; 256]; 3];
+    for image in images {
+        for pixel in image.to_rgb8().pixels() {
+            for (histogram, &sample) in histograms.iter_mut().zip(&pixel.0) {
+                histogram[sample as usize] += 1;
+            }
+        }
+    }
+    let mut ranges = [(0, 255); 3];
+    for (range, histogram) in ranges.iter_mut().zip(&histograms) {
+        let total: u64 = histogram.iter().sum();
+        if total == 0 {
+            continue;
+        }
+        let clipped = (total as f64 * CLIP_FRACTION) as u64;
+        let bound = |values: &mut dyn Iterator<Item = usize>| {
+            let mut seen = 0;
+            for value in values {
+                seen += histogram[value];
+                if seen > clipped {
+                    return value as u8;
+                }
+            }
+            0
+        };
+        *range = (bound(&mut (0..256)), bound(&mut (0..256).rev()));
+    }
+    ranges
+}
+
+/// Unsharp masking of an image arriving a stripe of maplets at a time, top
+/// to bottom. Each stripe is written once the one below it is complete,
+/// sharpened with the edges of its neighbours in view so no seams show
+/// between them.
+pub(crate) struct Sharpener {
+    amount: f32,
+    width: u32,
+    // The stripe being filled: its row of maplets, top and pixels
+    filling: Option<(u32, u32, RgbaImage)>,
+    // The complete stripe waiting on the next, by its top
+    pending: Option<(u32, RgbaImage)>,
+    // The last rows of the stripe last written
+    above: Option<RgbaImage>,
+}
+
+impl Sharpener {
+    /// Sharpens by `amount` an image `width` pixels wide.
+    pub(crate) fn new(amount: f32, width: u32) -> Self {
+        Sharpener {
+            amount,
+            width,
+            filling: None,
+            pending: None,
+            above: None,
+        }
+    }
+
+    /// Takes `image` at (`x`, `y`) in maplet row `row`, which must come
+    /// after the rows above it.
+    pub(crate) fn write_region(
+        &mut self,
+        writer: &mut dyn OutputWriter,
+        row: u32,
+        (x, y): (u32, u32),
+        image: &DynamicImage,
+    ) -> Result<()> {
+        if self
+            .filling
+            .as_ref()
+            .map_or(true, |&(filling, ..)| filling != row)
+        {
+            self.complete(writer)?;
+            self.filling = Some((row, y, RgbaImage::new(self.width, image.height())));
+        }
+        let (_, top, stripe) = self.filling.as_mut().expect("just started");
+        pixels::copy_into(stripe, &*pixels::rgba(image), x, y - *top);
+        Ok(())
+    }
+
+    /// Writes the stripes still held.
+    pub(crate) fn finish(mut self, writer: &mut dyn OutputWriter) -> Result<()> {
+        self.complete(writer)?;
+        match self.pending.take() {
+            Some(pending) => self.flush(writer, pending, None),
+            None => Ok(()),
+        }
+    }
+
+    // Holds the stripe being filled as complete, writing the one before it
+    fn complete(&mut self, writer: &mut dyn OutputWriter) -> Result<()> {
+        let (_, top, stripe) = match self.filling.take() {
+            Some(filling) => filling,
+            None => return Ok(()),
+        };
+        if let Some(pending) = self.pending.take() {
+            self.flush(writer, pending, Some(&stripe))?;
+        }
+        self.pending = Some((top, stripe));
+        Ok(())
+    }
+
+    fn flush(
+        &mut self,
+        writer: &mut dyn OutputWriter,
+        (top_y, stripe): (u32, RgbaImage),
+        below: Option<&RgbaImage>,
+    ) -> Result<()> {
+        let (width, height) = stripe.dimensions();
+        let top = self.above.as_ref().map_or(0, RgbaImage::height);
+        let bottom = below.map_or(0, |below| SHARPEN_MARGIN.min(below.height()));
+        let mut context = RgbaImage::new(width, top + height + bottom);
+        if let Some(above) = &self.above {
+            pixels::copy_into(&mut context, above, 0, 0);
+        }
+        pixels::copy_into(&mut context, &stripe, 0, top);
+        if let Some(below) = below {
+            pixels::copy_into(&mut context, below, 0, top + height);
+        }
+        let blurred = imageops::blur(&context, SHARPEN_SIGMA);
+        let margin = SHARPEN_MARGIN.min(height);
+        let last_rows = imageops::crop_imm(&stripe, 0, height - margin, width, margin).to_image();
+
+        let mut sharpened = stripe;
+        let stride = width as usize * 4;
+        let blurred = &blurred.as_raw()[top as usize * stride..];
+        for (pixel, blur) in sharpened.chunks_exact_mut(4).zip(blurred.chunks_exact(4)) {
+            for (sample, &blur) in pixel[..3].iter_mut().zip(&blur[..3]) {
+                let value = *sample as f32 + self.amount * (*sample as f32 - blur as f32);
+                *sample = value.round().clamp(0.0, 255.0) as u8;
+            }
+        }
+        self.above = Some(last_rows);
+        writer.write_region(0, top_y, &DynamicImage::ImageRgba8(sharpened))
+    }
+}
@@ -0,0 +1,59 @@
+//! Async conversion of many files, for use from tokio services.
+
+use std::panic;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use futures::stream::{Stream, StreamExt};
+use tokio::task;
+
+use crate::conversion::Conversion;
+use crate::error::{Result, TopodumpError};
+
+/// Converts each input path with default options, at most `concurrency` at a
+/// time. See [`convert_all_with`].
+pub fn convert_all<S>(
+    paths: S,
+    concurrency: usize,
+) -> impl Stream<Item = (PathBuf, Result<PathBuf>)>
+where
+    S: Stream<Item = PathBuf>,
+{
+    convert_all_with(paths, concurrency, |conversion| conversion)
+}
+
+/// Converts each input path on tokio's blocking pool, yielding `(input,
+/// result)` pairs as conversions finish.
+///
+/// No more than `concurrency` conversions run at once, and `paths` is only
+/// polled as slots free up, so slow conversions apply backpressure upstream.
+/// `configure` sets options on each conversion before it runs.
+pub fn convert_all_with<S, F>(
+    paths: S,
+    concurrency: usize,
+    configure: F,
+) -> impl Stream<Item = (PathBuf, Result<PathBuf>)>
+where
+    S: Stream<Item = PathBuf>,
+    F: Fn(Conversion) -> Conversion + Send + Sync + 'static,
+{
+    let configure = Arc::new(configure);
+    paths
+        .map(move |path| {
+            let configure = configure.clone();
+            async move {
+                let input = path.clone();
+                let result = task::spawn_blocking(move || configure(Conversion::new(input)).run())
+                    .await
+                    .unwrap_or_else(|join_err| {
+                        if join_err.is_panic() {
+                            panic::resume_unwind(join_err.into_panic())
+                        }
+                        // The runtime is shutting down
+                        Err(TopodumpError::Cancelled)
+                    });
+                (path, result)
+            }
+        })
+        .buffer_unordered(concurrency.max(1))
+}
@@ -0,0 +1,61 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+use std::thread;
+
+use crate::error::Result;
+
+fn is_tpq(path: &Path) -> bool {
+    path.extension()
+        .map_or(false, |extension| extension.eq_ignore_ascii_case("tpq"))
+}
+
+/// Every tpq file under `dir`, recursively, in a stable order.
+pub fn find_tpq_files(dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut found = Vec::new();
+    let mut pending = vec![dir.to_path_buf()];
+    while let Some(dir) = pending.pop() {
+        for entry in fs::read_dir(&dir)? {
+            let path = entry?.path();
+            if path.is_dir() {
+                pending.push(path);
+            } else if is_tpq(&path) {
+                found.push(path);
+            }
+        }
+    }
+    found.sort();
+    Ok(found)
+}
+
+/// Where a file found under `input_root` goes under `output_root`, keeping
+/// its relative directory.
+pub fn mirrored_dir(input_root: &Path, input: &Path, output_root: &Path) -> PathBuf {
+    let relative = input
+        .parent()
+        .and_then(|parent| parent.strip_prefix(input_root).ok())
+        .unwrap_or_else(|| Path::new(""));
+    output_root.join(relative)
+}
+
+/// Reads `paths` in order on a background thread, keeping up to `depth` files
+/// (at least one) read ahead of the consumer, so slow storage such as a
+/// network share is read while earlier files are converted.
+pub fn prefetch(
+    paths: Vec<PathBuf>,
+    depth: usize,
+) -> mpsc::IntoIter<(PathBuf, io::Result<Vec<u8>>)> {
+    // The reading thread holds one more file while it waits to send it
+    let (sender, receiver) = mpsc::sync_channel(depth.saturating_sub(1));
+    thread::spawn(move || {
+        for path in paths {
+            let data = fs::read(&path);
+            if sender.send((path, data)).is_err() {
+                // Nothing is reading any more
+                return;
+            }
+        }
+    });
+    receiver.into_iter()
+}
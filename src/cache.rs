@@ -0,0 +1,93 @@
+//! A record of finished conversions keyed by input content, so batch runs can
+//! skip unchanged inputs even when copying lost their timestamps.
+
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::{self, BufReader, Read};
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::conversion::ConversionSummary;
+use crate::error::Result;
+
+/// Where [`ConversionCache`] lives under a batch's output directory.
+pub const CACHE_FILE_NAME: &str = ".topodump-cache.json";
+
+#[derive(Default, Serialize, Deserialize)]
+pub struct ConversionCache {
+    entries: BTreeMap<String, ConversionSummary>,
+}
+
+impl ConversionCache {
+    /// Reads a cache written by [`save`](Self::save), or starts an empty one
+    /// if there is none yet.
+    pub fn load(path: &Path) -> Result<Self> {
+        match fs::read(path) {
+            Ok(data) => Ok(serde_json::from_slice(&data)?),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        fs::write(path, serde_json::to_vec_pretty(self)?)?;
+        Ok(())
+    }
+
+    /// Identifies the contents of `input` converted with `options`, any
+    /// description of the settings that change the output.
+    pub fn key(input: &Path, options: &str) -> Result<String> {
+        let mut hash = Fnv::new(options);
+        let mut reader = BufReader::new(File::open(input)?);
+        let mut buffer = [0; 64 * 1024];
+        loop {
+            let read = reader.read(&mut buffer)?;
+            if read == 0 {
+                break;
+            }
+            hash.update(&buffer[..read]);
+        }
+        Ok(hash.finish())
+    }
+
+    /// [`key`](Self::key) for an input already read into memory.
+    pub fn key_for_data(data: &[u8], options: &str) -> String {
+        let mut hash = Fnv::new(options);
+        hash.update(data);
+        hash.finish()
+    }
+
+    /// The earlier conversion of `key` if its output is still in `output_dir`.
+    pub fn get(&self, key: &str, output_dir: &Path) -> Option<&ConversionSummary> {
+        self.entries.get(key).filter(|summary| {
+            summary.output.parent() == Some(output_dir) && summary.output.exists()
+        })
+    }
+
+    pub fn insert(&mut self, key: String, summary: ConversionSummary) {
+        self.entries.insert(key, summary);
+    }
+}
+
+// 64-bit FNV-1a; collisions between real quads are vanishingly unlikely
+struct Fnv(u64);
+
+impl Fnv {
+    fn new(options: &str) -> Self {
+        let mut hash = Fnv(0xcbf2_9ce4_8422_2325);
+        hash.update(options.as_bytes());
+        hash.update(&[0]);
+        hash
+    }
+
+    fn update(&mut self, data: &[u8]) {
+        for &byte in data {
+            self.0 = (self.0 ^ byte as u64).wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+
+    fn finish(&self) -> String {
+        format!("{:016x}", self.0)
+    }
+}
@@ -0,0 +1,59 @@
+use std::io::{self, Seek, SeekFrom, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+/// Shared flag for aborting a conversion from another thread.
+#[derive(Clone, Debug, Default)]
+pub struct CancelToken(Arc<AtomicBool>);
+
+impl CancelToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+// Fails writes once cancelled so encoders bail out mid-stream
+pub(crate) struct CancellableWriter<W> {
+    inner: W,
+    token: Option<CancelToken>,
+}
+
+impl<W> CancellableWriter<W> {
+    pub(crate) fn new(inner: W, token: Option<CancelToken>) -> Self {
+        CancellableWriter { inner, token }
+    }
+
+    fn check(&self) -> io::Result<()> {
+        match &self.token {
+            Some(token) if token.is_cancelled() => {
+                Err(io::Error::new(io::ErrorKind::Other, "conversion cancelled"))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+impl<W: Write> Write for CancellableWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.check()?;
+        self.inner.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl<W: Seek> Seek for CancellableWriter<W> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.inner.seek(pos)
+    }
+}
@@ -0,0 +1,153 @@
+//! A SQLite record of every input batches have processed, queryable for
+//! audits, and of the conversions `--incremental` runs can skip.
+
+use std::path::Path;
+
+use rusqlite::{params, Connection, OptionalExtension};
+
+use crate::conversion::ConversionSummary;
+use crate::error::Result;
+
+/// What became of an input.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Status {
+    Converted,
+    /// Skipped as converted by an earlier run.
+    Unchanged,
+    /// Skipped as a quad with nothing to convert.
+    Degenerate,
+    Failed,
+}
+
+impl Status {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Status::Converted => "converted",
+            Status::Unchanged => "unchanged",
+            Status::Degenerate => "degenerate",
+            Status::Failed => "failed",
+        }
+    }
+}
+
+/// The `conversions` table of a catalog database, one row per input each
+/// time it's processed.
+pub struct Catalog {
+    connection: Connection,
+}
+
+impl Catalog {
+    /// Opens the catalog at `path`, creating it if there is none yet.
+    pub fn open(path: &Path) -> Result<Self> {
+        let connection = Connection::open(path)?;
+        connection.execute_batch(
+            "CREATE TABLE IF NOT EXISTS conversions (
+                 id INTEGER PRIMARY KEY,
+                 recorded_at TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP,
+                 input TEXT NOT NULL,
+                 key TEXT,
+                 quad TEXT,
+                 state TEXT,
+                 year1 TEXT,
+                 year2 TEXT,
+                 series TEXT,
+                 west REAL,
+                 south REAL,
+                 east REAL,
+                 north REAL,
+                 output TEXT,
+                 sha256 TEXT,
+                 options TEXT NOT NULL,
+                 seconds REAL,
+                 status TEXT NOT NULL,
+                 error TEXT,
+                 summary TEXT
+             );
+             CREATE INDEX IF NOT EXISTS conversions_key ON conversions (key);",
+        )?;
+        Ok(Catalog { connection })
+    }
+
+    /// Records an input that produced an output, `key` identifying its
+    /// contents and `options` as for [`ConversionCache`](crate::cache::ConversionCache).
+    pub fn record_conversion(
+        &self,
+        key: Option<&str>,
+        options: &str,
+        status: Status,
+        summary: &ConversionSummary,
+    ) -> Result<()> {
+        let header = &summary.header;
+        let seconds = summary
+            .timings
+            .stages()
+            .iter()
+            .map(|(_, time)| time.wall.as_secs_f64())
+            .sum::<f64>();
+        self.connection.execute(
+            "INSERT INTO conversions (input, key, quad, state, year1, year2, series, west, south, east, north,
+                 output, sha256, options, seconds, status, error, summary)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16, NULL, ?17)",
+            params![
+                summary.input.display().to_string(),
+                key,
+                header.quad_name.trim(),
+                header.state_name.trim(),
+                header.year1.trim(),
+                header.year2.trim(),
+                summary.series.map(|series| series.to_string()),
+                header.w_long,
+                header.s_lat,
+                header.e_long,
+                header.n_lat,
+                summary.output.display().to_string(),
+                summary.sha256,
+                options,
+                seconds,
+                status.as_str(),
+                serde_json::to_string(summary)?,
+            ],
+        )?;
+        Ok(())
+    }
+
+    /// Records an input that produced nothing, and why.
+    pub fn record_failure(
+        &self,
+        input: &Path,
+        options: &str,
+        status: Status,
+        error: &str,
+    ) -> Result<()> {
+        self.connection.execute(
+            "INSERT INTO conversions (input, options, status, error) VALUES (?1, ?2, ?3, ?4)",
+            params![input.display().to_string(), options, status.as_str(), error],
+        )?;
+        Ok(())
+    }
+
+    /// The latest conversion of `key` if its output is still in
+    /// `output_dir`, as [`ConversionCache::get`](crate::cache::ConversionCache::get).
+    pub fn get(&self, key: &str, output_dir: &Path) -> Result<Option<ConversionSummary>> {
+        let summary: Option<String> = self
+            .connection
+            .query_row(
+                "SELECT summary FROM conversions
+                 WHERE key = ?1 AND status IN ('converted', 'unchanged') AND summary IS NOT NULL
+                 ORDER BY id DESC LIMIT 1",
+                params![key],
+                |row| row.get(0),
+            )
+            .optional()?;
+        let summary = match summary {
+            Some(summary) => serde_json::from_str::<ConversionSummary>(&summary)?,
+            None => return Ok(None),
+        };
+        // Incomplete outputs are retried
+        Ok(Some(summary).filter(|summary| {
+            summary.warnings.is_empty()
+                && summary.output.parent() == Some(output_dir)
+                && summary.output.exists()
+        }))
+    }
+}
@@ -0,0 +1,28 @@
+//! SHA-256 digests of outputs, for fixity checks downstream.
+
+use std::fs::File;
+use std::io::{BufReader, Read};
+use std::path::Path;
+
+use sha2::{Digest, Sha256};
+
+use crate::error::Result;
+
+pub(crate) fn sha256_hex(data: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(data))
+}
+
+/// The lowercase hex SHA-256 of `path`'s contents, as `sha256sum` prints it.
+pub fn sha256_file(path: &Path) -> Result<String> {
+    let mut hash = Sha256::new();
+    let mut reader = BufReader::new(File::open(path)?);
+    let mut buffer = [0; 64 * 1024];
+    loop {
+        let read = reader.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        hash.update(&buffer[..read]);
+    }
+    Ok(format!("{:x}", hash.finalize()))
+}
@@ -0,0 +1,1619 @@
+use std::fs::remove_file;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+
+#[cfg(feature = "geo")]
+use geo::Polygon;
+use image::{imageops::FilterType, DynamicImage, GenericImageView};
+use serde::{Deserialize, Serialize};
+
+use crate::adjust::{auto_ranges, deblock, key_out, Levels, Sharpener, Tone};
+use crate::cancel::CancelToken;
+use crate::checksum::sha256_file;
+#[cfg(feature = "geo")]
+use crate::cutline::apply_cutline;
+use crate::disk::{available_space, name_disk_full};
+use crate::error::{Result, TopodumpError};
+#[cfg(feature = "gdal")]
+use crate::gdal_util;
+use crate::georef::GeoRef;
+use crate::header::{check_image_size, TpqHeader, DEFAULT_MAX_PIXELS};
+use crate::icc::IccProfile;
+use crate::maplet::{default_decoder, MapletDecoder, ScaledDecoder};
+#[cfg(feature = "mbtiles")]
+use crate::output::MbTilesWriter;
+use crate::output::{
+    stats_path, write_jpeg_tiff, ExistingTiles, GeoTiffWriter, HtmlPreviewWriter, OutputWriter,
+    PartFile, PngWriter, StatsWriter, Thumbnail, ThumbnailWriter, TileCacheWriter,
+};
+use crate::overlay::{Overlay, Stamp};
+#[cfg(feature = "gdal")]
+use crate::pixels;
+use crate::progress::{Progress, ProgressCallback};
+use crate::scale::Series;
+use crate::tiles::{native_zoom, tile_grid_extent, MAX_ZOOM, TILE_SIZE};
+use crate::timings::{SharedStage, Stopwatch, TimedDecoder, Timings};
+use crate::tpq::TpqFile;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    GTiff,
+    Cog,
+    Png,
+    #[cfg(feature = "mbtiles")]
+    MbTiles,
+}
+
+impl Format {
+    #[cfg(feature = "gdal")]
+    pub(crate) fn driver(self) -> &'static str {
+        match self {
+            Format::Cog => "COG",
+            _ => "GTiff",
+        }
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            Format::GTiff | Format::Cog => "tif",
+            Format::Png => "png",
+            #[cfg(feature = "mbtiles")]
+            Format::MbTiles => "mbtiles",
+        }
+    }
+}
+
+/// The format `path`'s extension calls for, if it's one topodump writes.
+pub fn format_for_extension(path: &Path) -> Option<Format> {
+    let extension = path.extension()?.to_str()?.to_ascii_lowercase();
+    match extension.as_str() {
+        "tif" | "tiff" => Some(Format::GTiff),
+        "png" => Some(Format::Png),
+        #[cfg(feature = "mbtiles")]
+        "mbtiles" => Some(Format::MbTiles),
+        _ => None,
+    }
+}
+
+// `explicit` if given, otherwise the format `output`'s extension calls for,
+// falling back to GeoTIFF only without an extension; an extension naming
+// another format, or none topodump writes, fails
+pub(crate) fn resolve_format(explicit: Option<Format>, output: &Path) -> Result<Format> {
+    if output.extension().is_none() {
+        return Ok(explicit.unwrap_or(Format::GTiff));
+    }
+    match (explicit, format_for_extension(output)) {
+        (Some(format), Some(named)) if format.extension() == named.extension() => Ok(format),
+        (None, Some(named)) => Ok(named),
+        (Some(format), _) => Err(TopodumpError::FormatMismatch {
+            path: output.to_path_buf(),
+            format,
+        }),
+        (None, None) => Err(TopodumpError::UnknownExtension(output.to_path_buf())),
+    }
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "gtiff" | "geotiff" | "tiff" => Ok(Format::GTiff),
+            "cog" => Ok(Format::Cog),
+            "png" => Ok(Format::Png),
+            #[cfg(feature = "mbtiles")]
+            "mbtiles" => Ok(Format::MbTiles),
+            _ => Err(format!("unknown format: {}", s)),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Compression {
+    None,
+    Deflate,
+    Lzw,
+    Jpeg,
+}
+
+impl Compression {
+    #[cfg(feature = "gdal")]
+    pub(crate) fn gdal_name(self) -> &'static str {
+        match self {
+            Compression::None => "NONE",
+            Compression::Deflate => "DEFLATE",
+            Compression::Lzw => "LZW",
+            Compression::Jpeg => "JPEG",
+        }
+    }
+}
+
+/// The filter used wherever imagery is resized or warped: odd-sized
+/// maplets, reprojection, COG overviews and tile pyramids. Nearest-neighbour
+/// visibly breaks up contour lines; Lanczos keeps them sharpest.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Resampling {
+    Nearest,
+    Bilinear,
+    Cubic,
+    Lanczos,
+}
+
+impl Resampling {
+    pub(crate) fn filter(self) -> FilterType {
+        match self {
+            Resampling::Nearest => FilterType::Nearest,
+            Resampling::Bilinear => FilterType::Triangle,
+            Resampling::Cubic => FilterType::CatmullRom,
+            Resampling::Lanczos => FilterType::Lanczos3,
+        }
+    }
+
+    #[cfg(feature = "gdal")]
+    pub(crate) fn gdal_name(self) -> &'static str {
+        match self {
+            Resampling::Nearest => "NEAREST",
+            Resampling::Bilinear => "BILINEAR",
+            Resampling::Cubic => "CUBIC",
+            Resampling::Lanczos => "LANCZOS",
+        }
+    }
+
+    // As gdalwarp's -r names it
+    #[cfg(feature = "gdal")]
+    fn warp_name(self) -> &'static str {
+        match self {
+            Resampling::Nearest => "near",
+            Resampling::Bilinear => "bilinear",
+            Resampling::Cubic => "cubic",
+            Resampling::Lanczos => "lanczos",
+        }
+    }
+}
+
+impl FromStr for Resampling {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "nearest" => Ok(Resampling::Nearest),
+            "bilinear" => Ok(Resampling::Bilinear),
+            "cubic" => Ok(Resampling::Cubic),
+            "lanczos" => Ok(Resampling::Lanczos),
+            _ => Err(format!("unknown resampling: {}", s)),
+        }
+    }
+}
+
+/// A corner of the output, where a stamp goes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Corner {
+    TopLeft,
+    TopRight,
+    BottomLeft,
+    BottomRight,
+}
+
+impl FromStr for Corner {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "top-left" => Ok(Corner::TopLeft),
+            "top-right" => Ok(Corner::TopRight),
+            "bottom-left" => Ok(Corner::BottomLeft),
+            "bottom-right" => Ok(Corner::BottomRight),
+            _ => Err(format!("unknown corner: {}", s)),
+        }
+    }
+}
+
+/// The JPEG quality, 1 to 100, of output re-encoded lossily, which can
+/// differ by format. Left unset, GDAL's default of 75 is used.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Quality {
+    default: Option<u8>,
+    formats: Vec<(Format, u8)>,
+}
+
+impl Quality {
+    pub fn new(quality: u8) -> Self {
+        Quality {
+            default: Some(quality.clamp(1, 100)),
+            formats: Vec::new(),
+        }
+    }
+
+    /// Uses `quality` for `format` in place of the default.
+    pub fn format(mut self, format: Format, quality: u8) -> Self {
+        self.formats.retain(|&(other, _)| other != format);
+        self.formats.push((format, quality.clamp(1, 100)));
+        self
+    }
+
+    pub fn for_format(&self, format: Format) -> Option<u8> {
+        self.formats
+            .iter()
+            .find(|&&(other, _)| other == format)
+            .map(|&(_, quality)| quality)
+            .or(self.default)
+    }
+}
+
+impl From<u8> for Quality {
+    fn from(quality: u8) -> Self {
+        Quality::new(quality)
+    }
+}
+
+// The GDAL creation option setting `quality` for `format`, if it's
+// JPEG-compressed
+#[cfg(feature = "gdal")]
+pub(crate) fn quality_option(
+    format: Format,
+    compression: Compression,
+    quality: Option<u8>,
+) -> Option<(&'static str, String)> {
+    let name = match format {
+        Format::Cog => "QUALITY",
+        _ => "JPEG_QUALITY",
+    };
+    match (compression, quality) {
+        (Compression::Jpeg, Some(quality)) => Some((name, quality.to_string())),
+        _ => None,
+    }
+}
+
+impl FromStr for Compression {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "none" => Ok(Compression::None),
+            "deflate" => Ok(Compression::Deflate),
+            "lzw" => Ok(Compression::Lzw),
+            "jpeg" => Ok(Compression::Jpeg),
+            _ => Err(format!("unknown compression: {}", s)),
+        }
+    }
+}
+
+/// Names an output after its input and inferred series, e.g. `N34113G3_24k.tif`.
+pub fn default_output(input: &Path, series: Option<Series>, extension: &str) -> PathBuf {
+    let stem = input
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().to_string())
+        .unwrap_or_else(|| "output".to_string());
+    let file_name = match series {
+        Some(series) => format!("{}_{}.{}", stem, series.slug(), extension),
+        None => format!("{}.{}", stem, extension),
+    };
+    input.with_file_name(file_name)
+}
+
+// gdalwarp's default of 64 MB leaves its threads starved
+#[cfg(feature = "gdal")]
+const DEFAULT_WARP_MEMORY_MB: u64 = 512;
+
+// How far a geotransform read back from an output may stray from the one written
+#[cfg(feature = "gdal")]
+const GEOTRANSFORM_EPSILON: f64 = 1e-9;
+
+// Red stands out against a topo map's black, brown, blue and green
+const DEFAULT_OVERLAY_COLOUR: [u8; 3] = [255, 0, 0];
+
+// How far a quad's NAD27 bounds are widened before snapping them out to a
+// tile grid, in degrees, covering the shift to Web Mercator's WGS 84
+const GRID_DATUM_MARGIN: f64 = 0.002;
+
+// Auto-levels measures up to this many maplets down and across a quad
+const LEVELS_SAMPLES: u32 = 4;
+
+/// What a finished conversion produced.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ConversionSummary {
+    pub input: PathBuf,
+    pub output: PathBuf,
+    pub header: TpqHeader,
+    pub width: u32,
+    pub height: u32,
+    pub series: Option<Series>,
+    pub georef: GeoRef,
+    #[serde(default)]
+    pub timings: Timings,
+    /// Problems that left the output usable but incomplete, such as a
+    /// GeoTIFF kept without georeferencing.
+    #[serde(default)]
+    pub warnings: Vec<String>,
+    /// The output's SHA-256 in hex, if [`Conversion::checksum`] was set.
+    #[serde(default)]
+    pub sha256: Option<String>,
+}
+
+/// A tpq conversion, configured builder-style and executed with [`Conversion::run`].
+///
+/// ```no_run
+/// use topodump::{Compression, Conversion, Format};
+///
+/// Conversion::new("N34113G3.tpq")
+///     .output("N34113G3.tif")
+///     .format(Format::Cog)
+///     .compression(Compression::Deflate)
+///     .run()?;
+/// # Ok::<(), topodump::TopodumpError>(())
+/// ```
+pub struct Conversion {
+    input: PathBuf,
+    output: Option<PathBuf>,
+    output_dir: Option<PathBuf>,
+    format: Format,
+    format_given: bool,
+    compression: Compression,
+    quality: Quality,
+    resampling: Resampling,
+    quantize: Option<u16>,
+    grayscale: bool,
+    tone: Tone,
+    auto_levels: bool,
+    sharpen: Option<f32>,
+    deblock: bool,
+    transparent_colour: Option<([u8; 3], u8)>,
+    draw_bounds: bool,
+    graticule: Option<f64>,
+    overlay_colour: [u8; 3],
+    stamp: Option<String>,
+    stamp_corner: Corner,
+    progress: Option<ProgressCallback>,
+    cancel: Option<CancelToken>,
+    writer: Option<Box<dyn OutputWriter + Send>>,
+    decoder: Option<Arc<dyn MapletDecoder>>,
+    #[cfg(feature = "geo")]
+    cutline: Option<Polygon<f64>>,
+    #[cfg(feature = "mmap")]
+    mmap: bool,
+    max_memory: Option<u64>,
+    alpha: bool,
+    preview: Option<u16>,
+    max_size: Option<u32>,
+    thumbnail: Option<Thumbnail>,
+    icc_profile: Option<IccProfile>,
+    html_preview: Option<PathBuf>,
+    wms_xml: bool,
+    tile_url: Option<String>,
+    tile_cache: Option<PathBuf>,
+    existing_tiles: ExistingTiles,
+    tms: bool,
+    input_data: Option<Vec<u8>>,
+    t_srs: Option<String>,
+    align_to_grid: Option<(u32, Option<u8>)>,
+    warp_threads: Option<usize>,
+    keep_partial: bool,
+    strict: bool,
+    verify_output: bool,
+    max_pixels: u64,
+    checksum: bool,
+    stats: bool,
+}
+
+impl Conversion {
+    pub fn new<P: Into<PathBuf>>(input: P) -> Self {
+        Conversion {
+            input: input.into(),
+            output: None,
+            output_dir: None,
+            format: Format::GTiff,
+            format_given: false,
+            compression: Compression::None,
+            quality: Quality::default(),
+            resampling: Resampling::Bilinear,
+            quantize: None,
+            grayscale: false,
+            tone: Tone::default(),
+            auto_levels: false,
+            sharpen: None,
+            deblock: false,
+            transparent_colour: None,
+            draw_bounds: false,
+            graticule: None,
+            overlay_colour: DEFAULT_OVERLAY_COLOUR,
+            stamp: None,
+            stamp_corner: Corner::BottomRight,
+            progress: None,
+            cancel: None,
+            writer: None,
+            decoder: None,
+            #[cfg(feature = "geo")]
+            cutline: None,
+            #[cfg(feature = "mmap")]
+            mmap: false,
+            max_memory: None,
+            alpha: false,
+            preview: None,
+            max_size: None,
+            thumbnail: None,
+            icc_profile: None,
+            html_preview: None,
+            wms_xml: false,
+            tile_url: None,
+            tile_cache: None,
+            existing_tiles: ExistingTiles::Merge,
+            tms: false,
+            input_data: None,
+            t_srs: None,
+            align_to_grid: None,
+            warp_threads: None,
+            keep_partial: false,
+            strict: false,
+            verify_output: false,
+            max_pixels: DEFAULT_MAX_PIXELS,
+            checksum: false,
+            stats: false,
+        }
+    }
+
+    /// Defaults to [`default_output`] when unset.
+    pub fn output<P: Into<PathBuf>>(mut self, output: P) -> Self {
+        self.output = Some(output.into());
+        self
+    }
+
+    /// Places the default-named output in `dir` instead of next to the input.
+    pub fn output_dir<P: Into<PathBuf>>(mut self, dir: P) -> Self {
+        self.output_dir = Some(dir.into());
+        self
+    }
+
+    /// Defaults to the format the output's extension calls for, or GeoTIFF
+    /// without one; fails the conversion if the output is named for another
+    /// format, or for none topodump writes.
+    pub fn format(mut self, format: Format) -> Self {
+        self.format = format;
+        self.format_given = true;
+        self
+    }
+
+    pub fn compression(mut self, compression: Compression) -> Self {
+        self.compression = compression;
+        self
+    }
+
+    /// The JPEG quality of JPEG-compressed output, including its overviews.
+    /// Maplets passed through as they are stored aren't re-encoded, so
+    /// aren't affected.
+    pub fn quality(mut self, quality: impl Into<Quality>) -> Self {
+        self.quality = quality.into();
+        self
+    }
+
+    /// Defaults to bilinear.
+    pub fn resampling(mut self, resampling: Resampling) -> Self {
+        self.resampling = resampling;
+        self
+    }
+
+    /// Writes PNG or GeoTIFF output as paletted images of at most `colors`
+    /// colours, up to 256.
+    pub fn quantize(mut self, colors: u16) -> Self {
+        self.quantize = Some(colors.clamp(2, 256));
+        self
+    }
+
+    /// Writes a single gray band, plus any alpha, in place of RGB, with
+    /// GeoTIFFs marked as such; MBTiles tiles are gray but kept RGB.
+    pub fn grayscale(mut self, grayscale: bool) -> Self {
+        self.grayscale = grayscale;
+        self
+    }
+
+    /// Stretches each colour channel to the full range, less the darkest and
+    /// lightest 0.5%, measured over a spread of maplets, before any
+    /// brightness, contrast or gamma.
+    pub fn auto_levels(mut self, auto_levels: bool) -> Self {
+        self.auto_levels = auto_levels;
+        self
+    }
+
+    /// Lightens, or when negative darkens, by up to 1, the full range.
+    pub fn brightness(mut self, brightness: f64) -> Self {
+        self.tone.brightness = brightness.clamp(-1.0, 1.0);
+        self
+    }
+
+    /// Scales each channel's distance from mid-gray; 1 leaves it as it is.
+    pub fn contrast(mut self, contrast: f64) -> Self {
+        self.tone.contrast = contrast.max(0.0);
+        self
+    }
+
+    /// Above 1 lightens the midtones, below 1 darkens them.
+    pub fn gamma(mut self, gamma: f64) -> Self {
+        self.tone.gamma = gamma;
+        self
+    }
+
+    /// Leaves pixels within `tolerance` of `colour` in every channel
+    /// transparent, e.g. a product's background fill, for overlays. Adds an
+    /// alpha band.
+    pub fn transparent_colour(mut self, colour: [u8; 3], tolerance: u8) -> Self {
+        self.transparent_colour = Some((colour, tolerance));
+        self
+    }
+
+    /// Draws the quad's neatline, the edges of the output, over it.
+    pub fn draw_bounds(mut self, draw_bounds: bool) -> Self {
+        self.draw_bounds = draw_bounds;
+        self
+    }
+
+    /// Draws a graticule over the output, a line every `degrees` of
+    /// longitude and latitude.
+    pub fn graticule(mut self, degrees: f64) -> Self {
+        self.graticule = Some(degrees);
+        self
+    }
+
+    /// The colour of [`draw_bounds`](Self::draw_bounds) and
+    /// [`graticule`](Self::graticule) lines, red by default.
+    pub fn overlay_colour(mut self, colour: [u8; 3]) -> Self {
+        self.overlay_colour = colour;
+        self
+    }
+
+    /// Stamps `text`, e.g. an attribution some licences require on derived
+    /// products, into a corner of the output, black on white.
+    pub fn stamp(mut self, text: impl Into<String>) -> Self {
+        self.stamp = Some(text.into());
+        self
+    }
+
+    /// The corner of the [`stamp`](Self::stamp), the bottom right by
+    /// default.
+    pub fn stamp_corner(mut self, corner: Corner) -> Self {
+        self.stamp_corner = corner;
+        self
+    }
+
+    /// Sharpens the composited image by unsharp masking, adding `amount`
+    /// times the difference from a slight blur, so contour labels stay
+    /// legible once downsampled.
+    pub fn sharpen(mut self, amount: f32) -> Self {
+        self.sharpen = Some(amount.max(0.0));
+        self
+    }
+
+    /// Smooths over the steps along JPEG block edges in heavily compressed
+    /// maplets before anything else, leaving real edges alone. Previews,
+    /// whose blocks are decoded only a few pixels across, are left as they
+    /// are.
+    pub fn deblock(mut self, deblock: bool) -> Self {
+        self.deblock = deblock;
+        self
+    }
+
+    pub fn on_progress(mut self, f: impl FnMut(Progress) + Send + 'static) -> Self {
+        self.progress = Some(Box::new(f));
+        self
+    }
+
+    /// Checked between maplets and while encoding; a cancelled conversion
+    /// removes anything it wrote, leaving any earlier output in place, and
+    /// returns [`TopodumpError::Cancelled`].
+    pub fn cancel_token(mut self, token: CancelToken) -> Self {
+        self.cancel = Some(token);
+        self
+    }
+
+    fn is_cancelled(&self) -> bool {
+        self.cancel
+            .as_ref()
+            .map_or(false, CancelToken::is_cancelled)
+    }
+
+    fn check_cancelled(&self) -> Result<()> {
+        if self.is_cancelled() {
+            Err(TopodumpError::Cancelled)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Writes through a custom [`OutputWriter`] instead of the one chosen by
+    /// [`format`](Conversion::format).
+    pub fn writer(mut self, writer: Box<dyn OutputWriter + Send>) -> Self {
+        self.writer = Some(writer);
+        self
+    }
+
+    pub fn decoder(mut self, decoder: Arc<dyn MapletDecoder>) -> Self {
+        self.decoder = Some(decoder);
+        self
+    }
+
+    /// Writes a preview at 1/2, 1/4 or 1/8 of full resolution, decoding
+    /// maplets at that scale with a [`ScaledDecoder`] in place of
+    /// [`decoder`](Conversion::decoder).
+    pub fn preview(mut self, denominator: u16) -> Self {
+        self.preview = Some(denominator);
+        self
+    }
+
+    /// Downscales the output proportionally, each maplet with
+    /// [`resampling`](Conversion::resampling)'s filter, until neither side
+    /// is longer than `pixels`, georeferencing it to match. Outputs already
+    /// that small are left as they are.
+    pub fn max_size(mut self, pixels: u32) -> Self {
+        self.max_size = Some(pixels.max(1));
+        self
+    }
+
+    /// Also writes a quick-look of the output with a [`ThumbnailWriter`],
+    /// in the same pass.
+    pub fn thumbnail(mut self, thumbnail: Thumbnail) -> Self {
+        self.thumbnail = Some(thumbnail);
+        self
+    }
+
+    /// Also writes a Leaflet page showing the output as tiles into `dir`
+    /// with an [`HtmlPreviewWriter`], in the same pass.
+    pub fn html_preview<P: Into<PathBuf>>(mut self, dir: P) -> Self {
+        self.html_preview = Some(dir.into());
+        self
+    }
+
+    /// Also describes the [`html_preview`](Self::html_preview)'s tiles in a
+    /// GDAL WMS `wms.xml` beside its page, fetching them from under
+    /// `tile_url` if that's where its directory will be served, or
+    /// otherwise from the directory.
+    pub fn wms_xml(mut self, tile_url: Option<String>) -> Self {
+        self.wms_xml = true;
+        self.tile_url = tile_url;
+        self
+    }
+
+    /// Also adds the output's tiles to the `{z}/{x}/{y}.png` cache in `dir`
+    /// with a [`TileCacheWriter`], in the same pass.
+    pub fn tile_cache<P: Into<PathBuf>>(mut self, dir: P) -> Self {
+        self.tile_cache = Some(dir.into());
+        self
+    }
+
+    /// What the [`tile_cache`](Self::tile_cache) does with tiles it
+    /// already has, merged with the new ones by default.
+    pub fn existing_tiles(mut self, existing: ExistingTiles) -> Self {
+        self.existing_tiles = existing;
+        self
+    }
+
+    /// Numbers the [`tile_cache`](Self::tile_cache)'s rows from the south,
+    /// as TMS does.
+    pub fn tms(mut self, tms: bool) -> Self {
+        self.tms = tms;
+        self
+    }
+
+    /// Tags GeoTIFF, COG and PNG output with an ICC profile, so
+    /// colour-managed viewers render it as intended.
+    pub fn icc_profile(mut self, profile: IccProfile) -> Self {
+        self.icc_profile = Some(profile);
+        self
+    }
+
+    /// Leaves everything outside `cutline`, in NAD27 longitude/latitude,
+    /// transparent.
+    #[cfg(feature = "geo")]
+    pub fn cutline(mut self, cutline: Polygon<f64>) -> Self {
+        self.cutline = Some(cutline);
+        self
+    }
+
+    /// Reprojects the output to `srs`, anything GDAL's `-t_srs` accepts such
+    /// as `EPSG:3857`. Needs the `gdal` feature and a GeoTIFF or COG output.
+    pub fn t_srs<S: Into<String>>(mut self, srs: S) -> Self {
+        self.t_srs = Some(srs.into());
+        self
+    }
+
+    /// Reprojects the output to Web Mercator, padded with transparency to
+    /// the tiles `tile_size` pixels across at `zoom` covering it, so its
+    /// pixels line up exactly with theirs and quads tiled independently
+    /// later don't need resampling. The zoom defaults to the deepest whose
+    /// pixels are no finer than the quad's.
+    pub fn align_to_grid(mut self, tile_size: u32, zoom: Option<u8>) -> Self {
+        self.align_to_grid = Some((tile_size.max(1), zoom));
+        self
+    }
+
+    /// Threads GDAL warps with when reprojecting; all CPUs by default. Warp
+    /// memory comes from [`max_memory`](Conversion::max_memory) when set.
+    pub fn warp_threads(mut self, threads: usize) -> Self {
+        self.warp_threads = Some(threads);
+        self
+    }
+
+    /// Converts `data`, the already-read contents of the input, instead of
+    /// reading the input path, which is still used to name the output.
+    pub fn input_data(mut self, data: Vec<u8>) -> Self {
+        self.input_data = Some(data);
+        self
+    }
+
+    /// Memory-maps the input instead of reading it; see
+    /// [`MmapSource::open`](crate::source::MmapSource::open) for the caveats.
+    #[cfg(feature = "mmap")]
+    pub fn mmap(mut self, mmap: bool) -> Self {
+        self.mmap = mmap;
+        self
+    }
+
+    /// Keeps the conversion's own memory use under `bytes`, decoding fewer
+    /// maplets at once as needed. Fails up front with
+    /// [`TopodumpError::MemoryBudget`] when the output format has to hold the
+    /// whole image and that doesn't fit; plain GeoTIFFs written through GDAL don't.
+    pub fn max_memory(mut self, bytes: u64) -> Self {
+        self.max_memory = Some(bytes);
+        self
+    }
+
+    /// Writes an alpha band alongside RGB. Always on with a cutline, which
+    /// leaves the outside transparent; MBTiles tiles always have one.
+    pub fn alpha(mut self, alpha: bool) -> Self {
+        self.alpha = alpha;
+        self
+    }
+
+    /// Keeps a GeoTIFF that couldn't be georeferenced, recording the failure
+    /// in [`ConversionSummary::warnings`] instead of removing the output and
+    /// failing.
+    pub fn keep_partial(mut self, keep_partial: bool) -> Self {
+        self.keep_partial = keep_partial;
+        self
+    }
+
+    /// Fails on maplets whose size differs from the first one's, instead of
+    /// resampling them to their grid cell, and on cells sharing a maplet,
+    /// instead of repeating it; either adds a warning otherwise.
+    pub fn strict(mut self, strict: bool) -> Self {
+        self.strict = strict;
+        self
+    }
+
+    /// Besides checking a GeoTIFF's georeferencing once it's written, which
+    /// is always done with the `gdal` feature, compares a few of its maplets
+    /// with the tpq's. Lossy and cut-out outputs only get the former.
+    pub fn verify_output(mut self, verify_output: bool) -> Self {
+        self.verify_output = verify_output;
+        self
+    }
+
+    /// Refuses quads whose image would have more pixels than this, whatever
+    /// the memory budget; defaults to [`DEFAULT_MAX_PIXELS`].
+    pub fn max_pixels(mut self, max_pixels: u64) -> Self {
+        self.max_pixels = max_pixels;
+        self
+    }
+
+    /// Records the output's SHA-256 in the summary, read back once written.
+    pub fn checksum(mut self, checksum: bool) -> Self {
+        self.checksum = checksum;
+        self
+    }
+
+    /// Writes each band's histogram and statistics, counted as maplets are
+    /// composited, beside the output with a [`StatsWriter`].
+    pub fn stats(mut self, stats: bool) -> Self {
+        self.stats = stats;
+        self
+    }
+
+    fn has_alpha(&self) -> bool {
+        #[cfg(feature = "geo")]
+        if self.cutline.is_some() {
+            return true;
+        }
+        self.alpha || self.transparent_colour.is_some()
+    }
+
+    // Whether the output is assembled in memory before being written
+    fn buffers_output(&self) -> bool {
+        if self.writer.is_some() || self.passes_jpeg_through() {
+            return false;
+        }
+        match self.format {
+            Format::GTiff => !cfg!(feature = "gdal") || self.quantize.is_some(),
+            _ => true,
+        }
+    }
+
+    /// How many maplets can be decoded at once within `budget` bytes.
+    fn plan_memory(&self, budget: u64, header: &TpqHeader, maplet: (u32, u32)) -> Result<usize> {
+        let maplet_bytes = maplet.0 as u64 * maplet.1 as u64 * 4;
+        // HTML previews and tile caches keep copies of their own
+        let copies = self.buffers_output() as u64
+            + self.html_preview.is_some() as u64
+            + self.tile_cache.is_some() as u64;
+        let fixed = maplet_bytes * header.long_count as u64 * header.lat_count as u64 * copies;
+        // Each decoding thread may also have a result queued, and converting
+        // a maplet for writing copies it
+        let per_decoder = maplet_bytes * 4;
+        if fixed + per_decoder > budget {
+            return Err(TopodumpError::MemoryBudget {
+                needed: fixed + per_decoder,
+                budget,
+            });
+        }
+        Ok(((budget - fixed) / per_decoder) as usize)
+    }
+
+    fn open_input(&mut self) -> Result<TpqFile> {
+        if let Some(data) = self.input_data.take() {
+            return TpqFile::from_bytes(data);
+        }
+        #[cfg(feature = "mmap")]
+        if self.mmap {
+            return TpqFile::open_mmap(&self.input);
+        }
+        TpqFile::open(&self.input)
+    }
+
+    #[cfg_attr(not(feature = "mbtiles"), allow(unused_variables))]
+    fn default_writer(
+        &self,
+        output: &Path,
+        header: &TpqHeader,
+        icc_profile: Option<Vec<u8>>,
+    ) -> Box<dyn OutputWriter + Send> {
+        match self.format {
+            Format::GTiff | Format::Cog => Box::new(
+                GeoTiffWriter::new(output, self.format, self.compression)
+                    .cancel_token(self.cancel.clone())
+                    .quality(self.quality.for_format(self.format))
+                    .resampling(self.resampling)
+                    .quantize(self.quantize)
+                    .grayscale(self.grayscale)
+                    .alpha(self.has_alpha())
+                    .icc_profile(icc_profile)
+                    .keep_partial(self.keep_partial),
+            ),
+            Format::Png => Box::new(
+                PngWriter::new(output)
+                    .quantize(self.quantize)
+                    .grayscale(self.grayscale)
+                    .alpha(self.has_alpha())
+                    .icc_profile(icc_profile),
+            ),
+            #[cfg(feature = "mbtiles")]
+            Format::MbTiles => {
+                Box::new(MbTilesWriter::new(output, &header.quad_name).resampling(self.resampling))
+            }
+        }
+    }
+
+    fn report(&mut self, progress: Progress) {
+        if let Some(f) = self.progress.as_mut() {
+            f(progress);
+        }
+    }
+
+    /// Runs the conversion, returning the path of the written output.
+    pub fn run(self) -> Result<PathBuf> {
+        self.run_with_summary().map(|summary| summary.output)
+    }
+
+    pub fn run_with_summary(mut self) -> Result<ConversionSummary> {
+        if let Some(output) = &self.output {
+            self.format = resolve_format(self.format_given.then(|| self.format), output)?;
+        }
+        if self.align_to_grid.is_some() {
+            match &self.t_srs {
+                None => self.t_srs = Some("EPSG:3857".to_string()),
+                Some(t_srs) if t_srs.eq_ignore_ascii_case("EPSG:3857") => {}
+                Some(_) => {
+                    return Err(TopodumpError::Unsupported(
+                        "aligning to a tile grid other than Web Mercator's",
+                    ))
+                }
+            }
+        }
+        if self.wms_xml && self.html_preview.is_none() {
+            return Err(TopodumpError::Unsupported(
+                "a WMS description without an HTML preview",
+            ));
+        }
+        if self.quantize.is_some() {
+            if !matches!(self.format, Format::GTiff | Format::Cog | Format::Png) {
+                return Err(TopodumpError::Unsupported("paletted MBTiles"));
+            }
+            if self.t_srs.is_some() {
+                return Err(TopodumpError::Unsupported("paletted reprojected output"));
+            }
+            if self.grayscale {
+                return Err(TopodumpError::Unsupported("paletted grayscale output"));
+            }
+        }
+        if self.icc_profile.is_some() {
+            if !matches!(self.format, Format::GTiff | Format::Cog | Format::Png) {
+                return Err(TopodumpError::Unsupported("ICC profiles in MBTiles"));
+            }
+            if self.t_srs.is_some() {
+                return Err(TopodumpError::Unsupported(
+                    "ICC profiles on reprojected output",
+                ));
+            }
+        }
+        if self.stats {
+            if !matches!(self.format, Format::GTiff | Format::Cog | Format::Png) {
+                return Err(TopodumpError::Unsupported("statistics of MBTiles"));
+            }
+            if self.quantize.is_some() {
+                return Err(TopodumpError::Unsupported("statistics of paletted output"));
+            }
+            if self.t_srs.is_some() {
+                return Err(TopodumpError::Unsupported(
+                    "statistics of reprojected output",
+                ));
+            }
+        }
+        let icc_profile = self
+            .icc_profile
+            .as_ref()
+            .map(IccProfile::bytes)
+            .transpose()?;
+        let stopwatch = Stopwatch::start();
+        let mut tpq = self.open_input()?;
+        let mut timings = Timings {
+            header: stopwatch.elapsed(),
+            ..Timings::default()
+        };
+        if let Some(denominator) = self.preview {
+            let decoder = ScaledDecoder::new(denominator).ok_or(TopodumpError::Unsupported(
+                "preview scales other than 1/2, 1/4 or 1/8",
+            ))?;
+            self.decoder = Some(Arc::new(decoder));
+        }
+        let decode_time = Arc::new(SharedStage::default());
+        tpq.set_decoder(Arc::new(TimedDecoder {
+            inner: self.decoder.take().unwrap_or_else(default_decoder),
+            spent: Arc::clone(&decode_time),
+        }));
+        let mut warnings = tpq.warnings().to_vec();
+        for duplicate in tpq.duplicate_maplets() {
+            if self.strict {
+                return Err(duplicate);
+            }
+            warnings.push(duplicate.to_string());
+        }
+        self.report(Progress::HeaderParsed {
+            long_count: tpq.header().long_count,
+            lat_count: tpq.header().lat_count,
+        });
+
+        let first_maplet = tpq.maplet(0, 0)?;
+        let (maplet_width, maplet_height) = first_maplet.dimensions();
+        if let Some(budget) = self.max_memory {
+            let max_decoding =
+                self.plan_memory(budget, tpq.header(), (maplet_width, maplet_height))?;
+            tpq.set_max_decoding(max_decoding);
+        }
+        let header = tpq.header();
+
+        let series = header.series(maplet_height);
+        let output = match (self.output.take(), &self.output_dir) {
+            (Some(output), _) => output,
+            (None, output_dir) => {
+                let output = default_output(&self.input, series, self.format.extension());
+                match output_dir {
+                    Some(output_dir) => output_dir.join(output.file_name().unwrap_or_default()),
+                    None => output,
+                }
+            }
+        };
+
+        let (width, height) = match self.max_size {
+            Some(max_size) => {
+                let (width, height) = header.image_size((maplet_width, maplet_height), u64::MAX)?;
+                let scale = (max_size as f64 / width.max(height) as f64).min(1.0);
+                let scaled = |span: u32| ((span as f64 * scale).round() as u32).max(1);
+                let (width, height) = (scaled(width), scaled(height));
+                check_image_size(width as u64, height as u64, self.max_pixels)?
+            }
+            None => header.image_size((maplet_width, maplet_height), self.max_pixels)?,
+        };
+        let georef = GeoRef::from_header(header, width as f64, height as f64);
+        // Reprojected outputs are warped from a plain GeoTIFF afterwards
+        let unwarped = match self.t_srs {
+            Some(_) => Some(self.unwarped_path(&output)?),
+            None => None,
+        };
+        let custom_writer = self.writer.is_some();
+        if !custom_writer {
+            let needed = self.estimated_output_size(width, height);
+            let dir = output.parent().unwrap_or_else(|| Path::new(""));
+            match available_space(dir) {
+                Some(available) if available < needed => warnings.push(format!(
+                    "{} may not fit: it needs about {} MB, and only {} MB are free",
+                    output.display(),
+                    needed / 1_000_000,
+                    available / 1_000_000
+                )),
+                _ => {}
+            }
+        }
+        let composite_time = SharedStage::default();
+        // Only outputs written here are read back
+        #[cfg_attr(not(feature = "gdal"), allow(unused_assignments))]
+        let mut checkable = !custom_writer
+            && unwarped.is_none()
+            && matches!(self.format, Format::GTiff | Format::Cog);
+        // Those are written beside their path until they pass, so a mismatch
+        // leaves any earlier output there alone
+        let checked = (cfg!(feature = "gdal") && checkable).then(|| PartFile::new(&output));
+        let target = checked
+            .as_ref()
+            .map_or_else(|| output.clone(), |part| part.path().to_path_buf());
+        let stopwatch = Stopwatch::start();
+        if self.passes_jpeg_through()
+            && self
+                .write_jpeg_tiff(&tpq, &target, &georef, icc_profile.as_deref())
+                .map_err(|err| name_disk_full(err, &output))?
+        {
+            timings.encode = stopwatch.elapsed();
+        } else {
+            let mut writer = match (self.writer.take(), &unwarped) {
+                (Some(writer), _) => writer,
+                (None, Some(unwarped)) => Box::new(
+                    GeoTiffWriter::new(unwarped, Format::GTiff, Compression::None)
+                        .cancel_token(self.cancel.clone())
+                        .grayscale(self.grayscale)
+                        .alpha(self.has_alpha()),
+                ),
+                (None, None) => self.default_writer(&target, header, icc_profile),
+            };
+            if let Some(thumbnail) = &self.thumbnail {
+                writer = Box::new(ThumbnailWriter::new(writer, thumbnail.path_for(&output)));
+            }
+            if let Some(dir) = &self.html_preview {
+                let mut preview = HtmlPreviewWriter::new(writer, dir, header.quad_name.trim())
+                    .resampling(self.resampling);
+                if self.wms_xml {
+                    preview = preview.wms_xml(self.tile_url.clone());
+                }
+                writer = Box::new(preview);
+            }
+            if let Some(dir) = &self.tile_cache {
+                writer = Box::new(
+                    TileCacheWriter::new(writer, dir)
+                        .resampling(self.resampling)
+                        .existing(self.existing_tiles)
+                        .tms(self.tms),
+                );
+            }
+            if self.stats {
+                writer = Box::new(StatsWriter::new(
+                    writer,
+                    stats_path(&output),
+                    self.grayscale,
+                    self.has_alpha(),
+                ));
+            }
+            writer
+                .create(width, height)
+                .map_err(|err| name_disk_full(err, &output))?;
+            let written = self
+                .write_maplets(
+                    &tpq,
+                    first_maplet,
+                    &georef,
+                    (width, height),
+                    writer.as_mut(),
+                    &composite_time,
+                    &mut warnings,
+                )
+                .and_then(|()| {
+                    let stopwatch = Stopwatch::start();
+                    writer.set_georef(&georef)?;
+                    timings.georef = stopwatch.elapsed();
+                    let stopwatch = Stopwatch::start();
+                    writer.finalize()?;
+                    timings.encode = stopwatch.elapsed();
+                    Ok(())
+                });
+            match written {
+                Ok(()) => {}
+                Err(err @ TopodumpError::GeoreferenceFailed { .. }) if self.keep_partial => {
+                    checkable = false;
+                    warnings.push(err.to_string())
+                }
+                Err(err) => {
+                    let err = name_disk_full(err, &output);
+                    return Err(err.after_cleanup(&target, writer.discard()));
+                }
+            }
+        }
+        if let Some(part) = checked {
+            #[cfg(feature = "gdal")]
+            if checkable {
+                let stopwatch = Stopwatch::start();
+                let size = (maplet_width, maplet_height);
+                if let Err(err) = self.check_output(&tpq, &target, &output, &georef, size) {
+                    return Err(part.remove_after(err));
+                }
+                timings.georef += stopwatch.elapsed();
+            }
+            part.commit()?;
+        }
+        timings.decode = decode_time.get();
+        timings.composite = composite_time.get();
+
+        let (georef, width, height) = match (&unwarped, &self.t_srs) {
+            (Some(unwarped), Some(t_srs)) => {
+                let stopwatch = Stopwatch::start();
+                let grid = self.align_to_grid.map(|(tile_size, zoom)| {
+                    let (west, south, east, north) = georef.bounds(width, height);
+                    let zoom = zoom.unwrap_or_else(|| {
+                        native_zoom(georef.transform[1] * tile_size as f64 / TILE_SIZE as f64)
+                    });
+                    let bounds = (
+                        west - GRID_DATUM_MARGIN,
+                        south - GRID_DATUM_MARGIN,
+                        east + GRID_DATUM_MARGIN,
+                        north + GRID_DATUM_MARGIN,
+                    );
+                    tile_grid_extent(bounds, tile_size, zoom.min(MAX_ZOOM))
+                });
+                let warped = self.reproject(unwarped, &output, t_srs, grid);
+                let removed = remove_file(unwarped);
+                timings.encode += stopwatch.elapsed();
+                match (warped, removed) {
+                    (Ok(warped), Ok(())) => warped,
+                    (Ok(warped), Err(err)) => {
+                        warnings.push(format!("couldn't remove {}: {}", unwarped.display(), err));
+                        warped
+                    }
+                    (Err(err), removed) => {
+                        let err = name_disk_full(err, &output);
+                        return Err(
+                            err.after_cleanup(unwarped, removed.map_err(TopodumpError::from))
+                        );
+                    }
+                }
+            }
+            _ => (georef, width, height),
+        };
+
+        // Hashed once complete, since GDAL may rewrite parts of the file as it closes it
+        let sha256 = if self.checksum && !custom_writer {
+            Some(sha256_file(&output)?)
+        } else {
+            None
+        };
+
+        self.report(Progress::OutputWritten {
+            path: output.clone(),
+        });
+
+        Ok(ConversionSummary {
+            input: self.input.clone(),
+            output,
+            header: header.clone(),
+            width,
+            height,
+            series,
+            georef,
+            timings,
+            warnings,
+            sha256,
+        })
+    }
+
+    // Roughly how many bytes the output will take, erring large: compressed
+    // outputs are guessed at, and reprojection first writes an uncompressed copy
+    fn estimated_output_size(&self, width: u32, height: u32) -> u64 {
+        let colour = if self.grayscale { 1 } else { 3 };
+        let raw = width as u64 * height as u64 * (colour + self.has_alpha() as u64);
+        let output = match (self.format, self.compression) {
+            (Format::GTiff | Format::Cog, Compression::None) => raw,
+            (Format::GTiff | Format::Cog, Compression::Jpeg) => raw / 10,
+            _ => raw / 2,
+        };
+        match self.t_srs {
+            Some(_) => raw + output,
+            None => output,
+        }
+    }
+
+    // Whether the output's pixels differ from the maplets'
+    fn alters_pixels(&self) -> bool {
+        self.quantize.is_some()
+            || self.grayscale
+            || self.auto_levels
+            || self.tone != Tone::default()
+            || self.sharpen.is_some()
+            || self.deblock
+            || self.max_size.is_some()
+            || self.draw_bounds
+            || self.graticule.is_some()
+            || self.stamp.is_some()
+            // Keyed-out pixels become transparent
+            || self.transparent_colour.is_some()
+    }
+
+    // JPEG GeoTIFFs can reuse the maplets' own JPEG streams if nothing alters the pixels
+    fn passes_jpeg_through(&self) -> bool {
+        // JPEG tiles can't carry an alpha band, which a cutline also needs,
+        // and are always full size and unwarped
+        !self.has_alpha()
+            && !self.alters_pixels()
+            && self.preview.is_none()
+            && self.t_srs.is_none()
+            && self.writer.is_none()
+            && self.thumbnail.is_none()
+            && !self.stats
+            && self.html_preview.is_none()
+            && self.tile_cache.is_none()
+            && self.format == Format::GTiff
+            && self.compression == Compression::Jpeg
+    }
+
+    fn unwarped_path(&self, output: &Path) -> Result<PathBuf> {
+        if !cfg!(feature = "gdal") {
+            return Err(TopodumpError::Unsupported(
+                "reprojection requires the gdal feature",
+            ));
+        }
+        if self.writer.is_some() {
+            return Err(TopodumpError::Unsupported(
+                "reprojecting through a custom writer",
+            ));
+        }
+        match self.format {
+            Format::GTiff | Format::Cog => Ok(output.with_extension("unwarped.tif")),
+            _ => Err(TopodumpError::Unsupported(
+                "reprojected output other than GeoTIFF or COG",
+            )),
+        }
+    }
+
+    /// Warps `source` into `output`, returning the result's georeferencing
+    /// and size, onto `grid`'s extent and resolution if given.
+    #[cfg(feature = "gdal")]
+    fn reproject(
+        &self,
+        source: &Path,
+        output: &Path,
+        t_srs: &str,
+        grid: Option<((f64, f64, f64, f64), f64)>,
+    ) -> Result<(GeoRef, u32, u32)> {
+        let threads = self
+            .warp_threads
+            .map_or_else(|| "ALL_CPUS".to_string(), |threads| threads.to_string());
+        // Leave half of any budget to everything else
+        let warp_memory = self.max_memory.map_or(DEFAULT_WARP_MEMORY_MB, |budget| {
+            (budget / 2 / 1_000_000).max(1)
+        });
+        let mut args = vec![
+            "-t_srs".to_string(),
+            t_srs.to_string(),
+            "-of".to_string(),
+            self.format.driver().to_string(),
+            "-r".to_string(),
+            self.resampling.warp_name().to_string(),
+            "-multi".to_string(),
+            "-wo".to_string(),
+            format!("NUM_THREADS={}", threads),
+            "-wm".to_string(),
+            warp_memory.to_string(),
+            "-co".to_string(),
+            format!("COMPRESS={}", self.compression.gdal_name()),
+            "-co".to_string(),
+            format!("NUM_THREADS={}", threads),
+        ];
+        match self.format {
+            Format::GTiff => {
+                args.push("-co".to_string());
+                args.push("TILED=YES".to_string());
+            }
+            Format::Cog => {
+                args.push("-co".to_string());
+                args.push(format!("RESAMPLING={}", self.resampling.gdal_name()));
+            }
+            _ => {}
+        }
+        let quality = self.quality.for_format(self.format);
+        if let Some((name, value)) = quality_option(self.format, self.compression, quality) {
+            args.push("-co".to_string());
+            args.push(format!("{}={}", name, value));
+        }
+        if let Some(((min_x, min_y, max_x, max_y), resolution)) = grid {
+            args.push("-te".to_string());
+            args.extend([min_x, min_y, max_x, max_y].iter().map(f64::to_string));
+            args.push("-tr".to_string());
+            args.extend([resolution, resolution].iter().map(f64::to_string));
+        }
+        // Padding to a grid is left transparent
+        if self.has_alpha() || grid.is_some() {
+            args.push("-dstalpha".to_string());
+        }
+
+        let dataset = gdal_util::open(source, false)?;
+        let part = PartFile::new(output);
+        gdal_util::warp(&dataset, part.path(), &args)?;
+        let warped = gdal_util::open(part.path(), false)?;
+        let (width, height) = warped.raster_size();
+        let georef = GeoRef {
+            transform: warped.geo_transform()?,
+            wkt: warped.projection(),
+        };
+        drop(warped);
+        part.commit()?;
+        Ok((georef, width as u32, height as u32))
+    }
+
+    /// Reopens `output` to check it carries `georef`, and with
+    /// [`verify_output`](Self::verify_output), sample maplets unchanged.
+    #[cfg(feature = "gdal")]
+    fn check_output(
+        &self,
+        tpq: &TpqFile,
+        written: &Path,
+        output: &Path,
+        georef: &GeoRef,
+        (maplet_width, maplet_height): (u32, u32),
+    ) -> Result<()> {
+        let mismatch = |reason: String| TopodumpError::OutputMismatch {
+            path: output.to_path_buf(),
+            reason,
+        };
+        let dataset = gdal_util::open(written, false)?;
+        let transform = dataset.geo_transform()?;
+        let moved = transform
+            .iter()
+            .zip(&georef.transform)
+            .any(|(read, written)| (read - written).abs() > GEOTRANSFORM_EPSILON);
+        if moved {
+            return Err(mismatch(format!(
+                "geotransform is {:?}, not {:?}",
+                transform, georef.transform
+            )));
+        }
+        if !gdal_util::same_crs(&dataset.projection(), &georef.wkt)? {
+            return Err(mismatch("its CRS differs".to_string()));
+        }
+
+        #[cfg(feature = "geo")]
+        if self.cutline.is_some() {
+            return Ok(());
+        }
+        if !self.verify_output || self.compression == Compression::Jpeg || self.alters_pixels() {
+            return Ok(());
+        }
+        let header = tpq.header();
+        let (last_row, last_col) = (header.lat_count - 1, header.long_count - 1);
+        for &(row, col) in &[(0, 0), (last_row / 2, last_col / 2), (last_row, last_col)] {
+            let maplet = tpq.maplet(row, col)?;
+            // Resampled to fit, so not comparable
+            if maplet.dimensions() != (maplet_width, maplet_height) {
+                continue;
+            }
+            let position = (col * maplet_width, row * maplet_height);
+            let size = (maplet_width, maplet_height);
+            let unchanged = if self.has_alpha() {
+                gdal_util::read_window(&dataset, position, size, 4)?
+                    == *pixels::rgba(&maplet).as_raw()
+            } else {
+                gdal_util::read_window(&dataset, position, size, 3)?
+                    == *pixels::rgb(&maplet).as_raw()
+            };
+            if !unchanged {
+                return Err(mismatch(format!(
+                    "maplet ({}, {}) differs from the tpq's",
+                    row, col
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    #[cfg(not(feature = "gdal"))]
+    fn reproject(
+        &self,
+        _source: &Path,
+        _output: &Path,
+        _t_srs: &str,
+        _grid: Option<((f64, f64, f64, f64), f64)>,
+    ) -> Result<(GeoRef, u32, u32)> {
+        unreachable!("unwarped_path refuses reprojection without gdal")
+    }
+
+    fn write_jpeg_tiff(
+        &mut self,
+        tpq: &TpqFile,
+        output: &Path,
+        georef: &GeoRef,
+        icc_profile: Option<&[u8]>,
+    ) -> Result<bool> {
+        let total = tpq.maplet_count();
+        write_jpeg_tiff(tpq, output, georef, icc_profile, &mut |row, col| {
+            self.check_cancelled()?;
+            self.report(Progress::MapletDecoded { row, col, total });
+            Ok(())
+        })
+    }
+
+    // The tone adjustments, if any, with auto-levels measured over a spread
+    // of maplets standing in for the quad
+    fn levels(&self, tpq: &TpqFile) -> Option<Levels> {
+        if !self.auto_levels && self.tone == Tone::default() {
+            return None;
+        }
+        let ranges = if self.auto_levels {
+            let header = tpq.header();
+            let spread = |count: u32| {
+                let step = ((count + LEVELS_SAMPLES - 1) / LEVELS_SAMPLES).max(1);
+                (0..count).step_by(step as usize)
+            };
+            // Maplets that fail to decode are reported once they're written
+            let samples = spread(header.lat_count)
+                .flat_map(|row| spread(header.long_count).map(move |col| (row, col)))
+                .filter_map(|(row, col)| tpq.maplet(row, col).ok())
+                .collect::<Vec<_>>();
+            auto_ranges(&samples)
+        } else {
+            [(0, 255); 3]
+        };
+        Some(Levels::new(ranges, self.tone))
+    }
+
+    fn write_maplets(
+        &mut self,
+        tpq: &TpqFile,
+        first_maplet: DynamicImage,
+        georef: &GeoRef,
+        (width, height): (u32, u32),
+        writer: &mut dyn OutputWriter,
+        composite_time: &SharedStage,
+        warnings: &mut Vec<String>,
+    ) -> Result<()> {
+        let total = tpq.maplet_count();
+        let (maplet_width, maplet_height) = first_maplet.dimensions();
+        let levels = self.levels(tpq);
+        // The pixels maplet (row, col) fills, all of its own unless the
+        // output is downscaled: (x, y, width, height)
+        let (columns, rows) = (tpq.header().long_count, tpq.header().lat_count);
+        let start =
+            |index: u32, count: u32, span: u32| (index as u64 * span as u64 / count as u64) as u32;
+        let cell = move |row: u32, col: u32| {
+            let (x, y) = (start(col, columns, width), start(row, rows, height));
+            (
+                x,
+                y,
+                start(col + 1, columns, width) - x,
+                start(row + 1, rows, height) - y,
+            )
+        };
+
+        // Runs on the decoding threads, leaving this one free to write
+        #[cfg(feature = "geo")]
+        let cutline = self.cutline.clone();
+        let strict = self.strict;
+        let filter = self.resampling.filter();
+        let transparent_colour = self.transparent_colour;
+        let deblocks = self.deblock && self.preview.is_none();
+        let overlay = (self.draw_bounds || self.graticule.is_some()).then(|| {
+            Overlay::new(
+                self.overlay_colour,
+                georef,
+                (width, height),
+                self.draw_bounds,
+                self.graticule,
+            )
+        });
+        let stamp = self
+            .stamp
+            .as_deref()
+            .filter(|text| !text.is_empty())
+            .map(|text| Stamp::new(text, self.stamp_corner, (width, height)));
+        let (grayscale, alpha) = (self.grayscale, self.has_alpha());
+        let resampled = Mutex::new(Vec::new());
+        #[cfg_attr(not(feature = "geo"), allow(unused_variables))]
+        let prepare = |row: u32, col: u32, maplet: DynamicImage| -> Result<DynamicImage> {
+            let (width, height) = maplet.dimensions();
+            // Pasted as-is, an odd-sized maplet would shift or overlap its neighbours
+            if (width, height) != (maplet_width, maplet_height) {
+                let err = TopodumpError::MapletSize {
+                    row,
+                    col,
+                    width,
+                    height,
+                    expected_width: maplet_width,
+                    expected_height: maplet_height,
+                };
+                if strict {
+                    return Err(err);
+                }
+                resampled
+                    .lock()
+                    .unwrap()
+                    .push(format!("{}; resampled to fit", err));
+            }
+            let maplet = if deblocks { deblock(maplet) } else { maplet };
+            let (x, y, cell_width, cell_height) = cell(row, col);
+            let maplet = if maplet.dimensions() == (cell_width, cell_height) {
+                maplet
+            } else {
+                maplet.resize_exact(cell_width, cell_height, filter)
+            };
+            let maplet = match transparent_colour {
+                Some((colour, tolerance)) => key_out(maplet, colour, tolerance),
+                None => maplet,
+            };
+            let maplet = match &levels {
+                Some(levels) => levels.apply(maplet),
+                None => maplet,
+            };
+            #[cfg(feature = "geo")]
+            let maplet = match &cutline {
+                Some(cutline) => {
+                    let stopwatch = Stopwatch::start();
+                    let maplet = apply_cutline(&maplet, x, y, georef, cutline).unwrap_or(maplet);
+                    composite_time.add(stopwatch.elapsed());
+                    maplet
+                }
+                None => maplet,
+            };
+            let maplet = match &overlay {
+                Some(overlay) => overlay.draw(maplet, (x, y)),
+                None => maplet,
+            };
+            let maplet = match &stamp {
+                Some(stamp) => stamp.draw(maplet, (x, y)),
+                None => maplet,
+            };
+            Ok(match (grayscale, alpha) {
+                (false, _) => maplet,
+                (true, false) => DynamicImage::ImageLuma8(maplet.to_luma8()),
+                (true, true) => DynamicImage::ImageLumaA8(maplet.to_luma_alpha8()),
+            })
+        };
+
+        // Sharpened a stripe of maplets at a time, since they arrive in order
+        let mut sharpener = self.sharpen.map(|amount| Sharpener::new(amount, width));
+        let first_maplet = prepare(0, 0, first_maplet)?;
+        let stopwatch = Stopwatch::start();
+        match &mut sharpener {
+            Some(sharpener) => sharpener.write_region(writer, 0, (0, 0), &first_maplet)?,
+            None => writer.write_region(0, 0, &first_maplet)?,
+        }
+        composite_time.add(stopwatch.elapsed());
+        self.report(Progress::MapletDecoded {
+            row: 0,
+            col: 0,
+            total,
+        });
+
+        // We've already read the first jpg
+        tpq.for_each_maplet_with(1, prepare, |row, col, maplet| {
+            self.check_cancelled()?;
+            let stopwatch = Stopwatch::start();
+            let (x, y, ..) = cell(row, col);
+            match &mut sharpener {
+                Some(sharpener) => sharpener.write_region(writer, row, (x, y), &maplet)?,
+                None => writer.write_region(x, y, &maplet)?,
+            }
+            composite_time.add(stopwatch.elapsed());
+            self.report(Progress::MapletDecoded { row, col, total });
+            Ok(())
+        })?;
+        if let Some(sharpener) = sharpener {
+            let stopwatch = Stopwatch::start();
+            sharpener.finish(writer)?;
+            composite_time.add(stopwatch.elapsed());
+        }
+
+        // Maplets finish decoding in no particular order
+        let mut resampled = resampled.into_inner().unwrap();
+        resampled.sort();
+        warnings.extend(resampled);
+        self.check_cancelled()
+    }
+}
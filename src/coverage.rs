@@ -0,0 +1,193 @@
+//! Which cells of the standard USGS quad grid a collection of quads covers,
+//! for reassembling incomplete archives.
+//!
+//! A state's outline isn't known, so the cells missing from a state are
+//! those inside the rectangle its quads span.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::PathBuf;
+
+use crate::error::{Result, TopodumpError};
+use crate::scale::Series;
+use crate::tpq::TpqFile;
+
+// How far into a neighbouring cell a quad's bounds may stray before it's
+// taken to cover that cell too, as a fraction of a cell
+const CELL_TOLERANCE: f64 = 0.05;
+
+// Offsets of the eight cells around one, from the north-west
+const NEIGHBOURS: [(i32, i32); 8] = [
+    (-1, 1),
+    (0, 1),
+    (1, 1),
+    (-1, 0),
+    (1, 0),
+    (-1, -1),
+    (0, -1),
+    (1, -1),
+];
+
+/// A cell of a series' grid, counted in cells east and north of 0°, 0°.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Cell {
+    pub col: i32,
+    pub row: i32,
+}
+
+/// The quads of one series in one state, and the cells they leave out.
+#[derive(Clone, Debug)]
+pub struct CoverageGroup {
+    pub state: String,
+    pub series: Series,
+    /// Degrees of longitude and latitude per cell.
+    pub cell_size: (f64, f64),
+    /// Every quad and the cells it covers.
+    pub quads: Vec<(PathBuf, Vec<Cell>)>,
+    /// Cells inside the rectangle spanned by the quads that none covers.
+    pub missing: Vec<Cell>,
+    /// For each quad lacking some of its eight neighbours, those neighbours.
+    pub absent_neighbours: Vec<(PathBuf, Vec<Cell>)>,
+}
+
+impl CoverageGroup {
+    /// The cells covered, counted once however many quads cover them.
+    pub fn present(&self) -> BTreeSet<Cell> {
+        self.quads
+            .iter()
+            .flat_map(|(_, cells)| cells.iter().copied())
+            .collect()
+    }
+
+    /// The bounds of `cell`: (west, south, east, north).
+    pub fn bounds(&self, cell: Cell) -> (f64, f64, f64, f64) {
+        let (width, height) = self.cell_size;
+        let (west, south) = (cell.col as f64 * width, cell.row as f64 * height);
+        (west, south, west + width, south + height)
+    }
+
+    /// A name for `cell`: the USGS quadrangle code, e.g. `34113-G3`, for
+    /// 7.5′ cells in the northern and western hemispheres, otherwise its
+    /// south-west corner.
+    pub fn name(&self, cell: Cell) -> String {
+        let (west, south, ..) = self.bounds(cell);
+        if self.series == Series::Scale24k && west < 0.0 && south >= 0.0 {
+            // Eighths of a degree, lettered from the south and numbered
+            // from the east
+            let (lat, long) = (cell.row.div_euclid(8), (-cell.col - 1).div_euclid(8));
+            let (letter, number) = (cell.row.rem_euclid(8), (-cell.col - 1).rem_euclid(8));
+            format!(
+                "{:02}{:03}-{}{}",
+                lat,
+                long,
+                (b'A' + letter as u8) as char,
+                number + 1
+            )
+        } else {
+            format!("{:.4},{:.4}", west, south)
+        }
+    }
+}
+
+/// What [`coverage`] found.
+#[derive(Clone, Debug, Default)]
+pub struct Coverage {
+    /// By state, then from the finest series to the coarsest.
+    pub groups: Vec<CoverageGroup>,
+    /// Inputs left out, and why.
+    pub skipped: Vec<(PathBuf, String)>,
+}
+
+// The series of `tpq`, its grid's cell size and the cells it covers
+fn quad_cells(tpq: &TpqFile) -> Result<(Series, (f64, f64), Vec<Cell>)> {
+    let (_, maplet_height) = tpq.maplet_dimensions()?;
+    let header = tpq.header();
+    let series = header
+        .series(maplet_height)
+        .ok_or(TopodumpError::Unsupported("quads of unknown series"))?;
+    let (width, height) = series
+        .cell_size()
+        .ok_or(TopodumpError::Unsupported("series without a regular grid"))?;
+    let cells = |low: f64, high: f64, size: f64| {
+        let first = (low / size + CELL_TOLERANCE).floor() as i32;
+        let last = (high / size - CELL_TOLERANCE).ceil() as i32;
+        first..last.max(first + 1)
+    };
+    let mut covered = Vec::new();
+    for row in cells(header.s_lat, header.n_lat, height) {
+        for col in cells(header.w_long, header.e_long, width) {
+            covered.push(Cell { col, row });
+        }
+    }
+    Ok((series, (width, height), covered))
+}
+
+/// The coverage of `inputs`, grouped by the state named in their headers
+/// and their series. Only headers and one maplet per quad are read.
+pub fn coverage(inputs: &[PathBuf]) -> Coverage {
+    let mut groups = BTreeMap::<(String, Series), CoverageGroup>::new();
+    let mut skipped = Vec::new();
+    for input in inputs {
+        let quad = TpqFile::open(input).and_then(|tpq| {
+            let state = tpq.header().state_name.trim().to_string();
+            quad_cells(&tpq).map(|quad| (state, quad))
+        });
+        let (state, (series, cell_size, cells)) = match quad {
+            Ok(quad) => quad,
+            Err(err) => {
+                skipped.push((input.clone(), err.to_string()));
+                continue;
+            }
+        };
+        groups
+            .entry((state.clone(), series))
+            .or_insert_with(|| CoverageGroup {
+                state,
+                series,
+                cell_size,
+                quads: Vec::new(),
+                missing: Vec::new(),
+                absent_neighbours: Vec::new(),
+            })
+            .quads
+            .push((input.clone(), cells));
+    }
+
+    let mut groups = groups.into_values().collect::<Vec<_>>();
+    for group in &mut groups {
+        let present = group.present();
+        let (cols, rows) = (
+            present.iter().map(|cell| cell.col),
+            present.iter().map(|cell| cell.row),
+        );
+        let (west, east) = (cols.clone().min().unwrap_or(0), cols.max().unwrap_or(0));
+        let (south, north) = (rows.clone().min().unwrap_or(0), rows.max().unwrap_or(0));
+        for row in (south..=north).rev() {
+            for col in west..=east {
+                let cell = Cell { col, row };
+                if !present.contains(&cell) {
+                    group.missing.push(cell);
+                }
+            }
+        }
+        for (path, cells) in &group.quads {
+            let mut absent = BTreeSet::new();
+            for cell in cells {
+                for (dx, dy) in NEIGHBOURS {
+                    let neighbour = Cell {
+                        col: cell.col + dx,
+                        row: cell.row + dy,
+                    };
+                    if !present.contains(&neighbour) {
+                        absent.insert(neighbour);
+                    }
+                }
+            }
+            if !absent.is_empty() {
+                group
+                    .absent_neighbours
+                    .push((path.clone(), absent.into_iter().collect()));
+            }
+        }
+    }
+    Coverage { groups, skipped }
+}
@@ -0,0 +1,51 @@
+//! Masking outputs to a polygon, e.g. to drop a quad's collar.
+
+use geo::{AffineOps, Contains, Coord, Intersects, Point, Polygon, Rect};
+use image::{DynamicImage, GenericImageView, Rgba, RgbaImage};
+
+use crate::georef::GeoRef;
+
+/// Makes the pixels of a region at (`x`, `y`) whose centres fall outside
+/// `cutline` transparent. `cutline` is in the same coordinates as `georef`.
+pub(crate) fn apply_cutline(
+    image: &DynamicImage,
+    x: u32,
+    y: u32,
+    georef: &GeoRef,
+    cutline: &Polygon<f64>,
+) -> Option<DynamicImage> {
+    let (width, height) = image.dimensions();
+    let to_ground = georef.affine();
+    let region = Rect::new(
+        Coord {
+            x: x as f64,
+            y: y as f64,
+        },
+        Coord {
+            x: (x + width) as f64,
+            y: (y + height) as f64,
+        },
+    )
+    .to_polygon()
+    .affine_transform(&to_ground);
+
+    if cutline.contains(&region) {
+        return None;
+    }
+    if !cutline.intersects(&region) {
+        return Some(DynamicImage::ImageRgba8(RgbaImage::from_pixel(
+            width,
+            height,
+            Rgba([0, 0, 0, 0]),
+        )));
+    }
+
+    let mut masked = image.to_rgba8();
+    for (px, py, pixel) in masked.enumerate_pixels_mut() {
+        let centre = Point::new((x + px) as f64 + 0.5, (y + py) as f64 + 0.5);
+        if !cutline.contains(&centre.affine_transform(&to_ground)) {
+            pixel[3] = 0;
+        }
+    }
+    Some(DynamicImage::ImageRgba8(masked))
+}
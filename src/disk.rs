@@ -0,0 +1,66 @@
+//! Free space on the output's filesystem, and recognising its exhaustion.
+
+use std::io;
+use std::path::Path;
+
+use image::ImageError;
+
+use crate::error::TopodumpError;
+
+/// Bytes available to unprivileged users on the filesystem holding `dir`,
+/// if the platform can say.
+#[cfg(unix)]
+pub(crate) fn available_space(dir: &Path) -> Option<u64> {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+
+    let dir = if dir.as_os_str().is_empty() {
+        Path::new(".")
+    } else {
+        dir
+    };
+    let dir = CString::new(dir.as_os_str().as_bytes()).ok()?;
+    let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+    if unsafe { libc::statvfs(dir.as_ptr(), &mut stat) } != 0 {
+        return None;
+    }
+    Some(stat.f_bavail as u64 * stat.f_frsize as u64)
+}
+
+#[cfg(not(unix))]
+pub(crate) fn available_space(_dir: &Path) -> Option<u64> {
+    None
+}
+
+#[cfg(unix)]
+fn is_enospc(err: &io::Error) -> bool {
+    err.raw_os_error() == Some(libc::ENOSPC)
+}
+
+#[cfg(not(unix))]
+fn is_enospc(_err: &io::Error) -> bool {
+    false
+}
+
+// Whether `err` came from the output's filesystem filling up
+fn is_disk_full(err: &TopodumpError) -> bool {
+    match err {
+        TopodumpError::Io(err) => is_enospc(err),
+        TopodumpError::Image(ImageError::IoError(err)) => is_enospc(err),
+        TopodumpError::CleanupFailed { source, .. } => is_disk_full(source),
+        // GDAL only passes on the system's message
+        other => other.to_string().contains("No space left on device"),
+    }
+}
+
+/// `err`, naming `output` if it came from the disk filling up while writing it.
+pub(crate) fn name_disk_full(err: TopodumpError, output: &Path) -> TopodumpError {
+    if is_disk_full(&err) {
+        TopodumpError::DiskFull {
+            path: output.to_path_buf(),
+            source: Box::new(err),
+        }
+    } else {
+        err
+    }
+}
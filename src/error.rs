@@ -0,0 +1,160 @@
+use std::io;
+use std::path::{Path, PathBuf};
+
+#[cfg(feature = "gdal")]
+use gdal::errors::GdalError;
+use image::ImageError;
+use thiserror::Error;
+
+use crate::conversion::Format;
+
+#[derive(Debug, Error)]
+pub enum TopodumpError {
+    #[error("failed to parse tpq header: {0}")]
+    HeaderParse(#[source] io::Error),
+    #[error("maplet ({row}, {col}) has offset {offset} outside of the file")]
+    MapletOffset { row: u32, col: u32, offset: usize },
+    #[error(
+        "a {width}×{height} image is too large, the limit being {max_pixels} pixels and {} on a side",
+        crate::header::MAX_DIMENSION
+    )]
+    ImageTooLarge {
+        width: u64,
+        height: u64,
+        max_pixels: u64,
+    },
+    #[error("bounds W {west} N {north} E {east} S {south} lie outside ±180°/±90°")]
+    BoundsOutOfRange {
+        west: f64,
+        north: f64,
+        east: f64,
+        south: f64,
+    },
+    #[error("degenerate quad: {0}")]
+    DegenerateQuad(&'static str),
+    #[error("header claims {columns}×{rows} maplets but the file is only {size} bytes")]
+    MapletCountExceedsFile { columns: u32, rows: u32, size: u64 },
+    #[error("header claims {columns}×{rows} maplets but offset table holds {entries}")]
+    MapletCountExceedsTable {
+        columns: u32,
+        rows: u32,
+        entries: u64,
+    },
+    #[error("maplet ({row}, {col}) is {width}×{height} but the grid expects {expected_width}×{expected_height}")]
+    MapletSize {
+        row: u32,
+        col: u32,
+        width: u32,
+        height: u32,
+        expected_width: u32,
+        expected_height: u32,
+    },
+    #[error("maplet ({row}, {col}) has the same offset as ({first_row}, {first_col})")]
+    DuplicateMaplet {
+        row: u32,
+        col: u32,
+        first_row: u32,
+        first_col: u32,
+    },
+    #[error("failed to decode maplet ({row}, {col}): {source}")]
+    MapletDecode {
+        row: u32,
+        col: u32,
+        #[source]
+        source: Box<dyn std::error::Error + Send + Sync>,
+    },
+    #[error("failed to write image: {0}")]
+    Image(#[from] ImageError),
+    #[cfg(feature = "gdal")]
+    #[error("gdal error: {0}")]
+    Gdal(#[from] GdalError),
+    #[error("tiff error: {0}")]
+    Tiff(#[from] tiff::TiffError),
+    #[error("unsupported: {0}")]
+    Unsupported(&'static str),
+    #[cfg(feature = "mbtiles")]
+    #[error("sqlite error: {0}")]
+    Sqlite(#[from] rusqlite::Error),
+    #[error("unknown decoder {name}, expected one of: {available}")]
+    UnknownDecoder { name: String, available: String },
+    #[error("json error: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("{failed} of {total} conversions failed")]
+    BatchFailed { failed: usize, total: usize },
+    #[error("{failed} of {total} files have problems")]
+    VerifyFailed { failed: usize, total: usize },
+    #[error(
+        "conversion needs about {} MB, more than the {} MB allowed",
+        needed / 1_000_000,
+        budget / 1_000_000
+    )]
+    MemoryBudget { needed: u64, budget: u64 },
+    #[error("{} is named for a different format than {format:?}", path.display())]
+    FormatMismatch { path: PathBuf, format: Format },
+    #[error("{} isn't named for a format topodump writes", .0.display())]
+    UnknownExtension(PathBuf),
+    #[error("{} doesn't match what was written: {reason}", path.display())]
+    OutputMismatch { path: PathBuf, reason: String },
+    #[error("wrote {} without georeferencing: {source}", path.display())]
+    GeoreferenceFailed {
+        path: PathBuf,
+        #[source]
+        source: Box<TopodumpError>,
+    },
+    #[error("{source}; removing {} also failed: {cleanup}", path.display())]
+    CleanupFailed {
+        path: PathBuf,
+        #[source]
+        source: Box<TopodumpError>,
+        cleanup: Box<TopodumpError>,
+    },
+    #[error("ran out of disk space writing {}: {source}", path.display())]
+    DiskFull {
+        path: PathBuf,
+        #[source]
+        source: Box<TopodumpError>,
+    },
+    #[error("no quads intersect W {west} S {south} E {east} N {north}")]
+    NoQuadsInExtent {
+        west: f64,
+        south: f64,
+        east: f64,
+        north: f64,
+    },
+    #[error("{} isn't an ICC profile", .0.display())]
+    InvalidIccProfile(PathBuf),
+    #[error("{0} isn't an s3://, gcs:// or az:// URL")]
+    InvalidStoreUrl(String),
+    #[cfg(feature = "geoparquet")]
+    #[error("arrow error: {0}")]
+    Arrow(#[from] arrow::error::ArrowError),
+    #[cfg(feature = "geoparquet")]
+    #[error("parquet error: {0}")]
+    Parquet(#[from] parquet::errors::ParquetError),
+    #[cfg(feature = "object-store")]
+    #[error("object store error: {0}")]
+    ObjectStore(#[from] object_store::Error),
+    #[cfg(feature = "gui")]
+    #[error("couldn't open a window: {0}")]
+    Gui(String),
+    #[error("conversion cancelled")]
+    Cancelled,
+    #[error(transparent)]
+    Io(#[from] io::Error),
+}
+
+impl TopodumpError {
+    // This error, along with any failure to clean up `path` after it
+    pub(crate) fn after_cleanup(self, path: &Path, cleanup: Result<()>) -> TopodumpError {
+        match cleanup {
+            Ok(()) => self,
+            Err(cleanup) => TopodumpError::CleanupFailed {
+                path: path.to_path_buf(),
+                source: Box::new(self),
+                cleanup: Box::new(cleanup),
+            },
+        }
+    }
+}
+
+pub type Result<T> = std::result::Result<T, TopodumpError>;
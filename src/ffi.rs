@@ -0,0 +1,233 @@
+//! C ABI for embedding topodump, see `include/topodump.h`.
+
+use std::cell::RefCell;
+use std::ffi::{CStr, CString};
+use std::os::raw::c_char;
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::path::PathBuf;
+use std::ptr;
+
+use crate::conversion::{Compression, Conversion, Format};
+use crate::error::TopodumpError;
+use crate::tpq::TpqFile;
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = RefCell::new(None);
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TopodumpStatus {
+    Ok = 0,
+    InvalidArgument,
+    Io,
+    Header,
+    Decode,
+    Gdal,
+    Cancelled,
+    Unsupported,
+    Other,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TopodumpFormat {
+    GTiff = 0,
+    Cog,
+    Png,
+    /// From the output's extension, or GeoTIFF without one.
+    Auto,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TopodumpCompression {
+    None = 0,
+    Deflate,
+    Lzw,
+    Jpeg,
+}
+
+#[repr(C)]
+pub struct TopodumpOptions {
+    pub input: *const c_char,
+    /// May be null to name the output after the input.
+    pub output: *const c_char,
+    pub format: TopodumpFormat,
+    pub compression: TopodumpCompression,
+}
+
+#[repr(C)]
+pub struct TopodumpHeader {
+    pub version: u32,
+    pub west: f64,
+    pub north: f64,
+    pub east: f64,
+    pub south: f64,
+    pub quad_name: [c_char; 129],
+    pub state_name: [c_char; 33],
+    pub source: [c_char; 33],
+    pub year1: [c_char; 5],
+    pub year2: [c_char; 5],
+    pub contour: [c_char; 25],
+    pub color_depth: u32,
+    pub columns: u32,
+    pub rows: u32,
+}
+
+fn set_last_error(message: &str) {
+    let message = CString::new(message.replace('\0', "")).unwrap_or_default();
+    LAST_ERROR.with(|last_error| *last_error.borrow_mut() = Some(message));
+}
+
+fn status_of(err: &TopodumpError) -> TopodumpStatus {
+    match err {
+        TopodumpError::Io(_) => TopodumpStatus::Io,
+        TopodumpError::HeaderParse(_) => TopodumpStatus::Header,
+        TopodumpError::MapletOffset { .. } | TopodumpError::MapletDecode { .. } => {
+            TopodumpStatus::Decode
+        }
+        #[cfg(feature = "gdal")]
+        TopodumpError::Gdal(_) => TopodumpStatus::Gdal,
+        TopodumpError::Cancelled => TopodumpStatus::Cancelled,
+        TopodumpError::Unsupported(_) => TopodumpStatus::Unsupported,
+        _ => TopodumpStatus::Other,
+    }
+}
+
+// Runs `f`, recording any error or panic for topodump_last_error
+fn guard(f: impl FnOnce() -> Result<(), TopodumpStatus>) -> TopodumpStatus {
+    match catch_unwind(AssertUnwindSafe(f)) {
+        Ok(Ok(())) => TopodumpStatus::Ok,
+        Ok(Err(status)) => status,
+        Err(_) => {
+            set_last_error("topodump panicked");
+            TopodumpStatus::Other
+        }
+    }
+}
+
+fn fail(err: TopodumpError) -> TopodumpStatus {
+    set_last_error(&err.to_string());
+    status_of(&err)
+}
+
+unsafe fn path_arg(path: *const c_char, name: &str) -> Result<PathBuf, TopodumpStatus> {
+    if path.is_null() {
+        set_last_error(&format!("{} is null", name));
+        return Err(TopodumpStatus::InvalidArgument);
+    }
+    path_from_bytes(CStr::from_ptr(path).to_bytes()).ok_or_else(|| {
+        set_last_error(&format!("{} is not valid UTF-8", name));
+        TopodumpStatus::InvalidArgument
+    })
+}
+
+// Unix paths are arbitrary bytes; elsewhere they're taken as UTF-8
+#[cfg(unix)]
+fn path_from_bytes(bytes: &[u8]) -> Option<PathBuf> {
+    use std::os::unix::ffi::OsStrExt;
+    Some(std::ffi::OsStr::from_bytes(bytes).into())
+}
+
+#[cfg(not(unix))]
+fn path_from_bytes(bytes: &[u8]) -> Option<PathBuf> {
+    std::str::from_utf8(bytes).ok().map(PathBuf::from)
+}
+
+fn copy_str<const N: usize>(dst: &mut [c_char; N], src: &str) {
+    let len = src.len().min(N - 1);
+    for (d, s) in dst.iter_mut().zip(src.as_bytes()[..len].iter()) {
+        *d = *s as c_char;
+    }
+    dst[len] = 0;
+}
+
+/// Converts a tpq file as described by `options`.
+///
+/// # Safety
+///
+/// `options` must point to a valid `TopodumpOptions` whose `input` (and
+/// `output`, if not null) are NUL-terminated strings.
+#[no_mangle]
+pub unsafe extern "C" fn topodump_convert(options: *const TopodumpOptions) -> TopodumpStatus {
+    guard(|| {
+        let options = options.as_ref().ok_or_else(|| {
+            set_last_error("options is null");
+            TopodumpStatus::InvalidArgument
+        })?;
+
+        let mut conversion = Conversion::new(path_arg(options.input, "input")?).compression(
+            match options.compression {
+                TopodumpCompression::None => Compression::None,
+                TopodumpCompression::Deflate => Compression::Deflate,
+                TopodumpCompression::Lzw => Compression::Lzw,
+                TopodumpCompression::Jpeg => Compression::Jpeg,
+            },
+        );
+        if !options.output.is_null() {
+            conversion = conversion.output(path_arg(options.output, "output")?);
+        }
+        let format = match options.format {
+            TopodumpFormat::Auto => None,
+            TopodumpFormat::GTiff => Some(Format::GTiff),
+            TopodumpFormat::Cog => Some(Format::Cog),
+            TopodumpFormat::Png => Some(Format::Png),
+        };
+        if let Some(format) = format {
+            conversion = conversion.format(format);
+        }
+
+        conversion.run().map(|_| ()).map_err(fail)
+    })
+}
+
+/// Reads the header of the tpq file at `path` into `header`.
+///
+/// # Safety
+///
+/// `path` must be a NUL-terminated string and `header` must point to writable
+/// memory for a `TopodumpHeader`.
+#[no_mangle]
+pub unsafe extern "C" fn topodump_read_header(
+    path: *const c_char,
+    header: *mut TopodumpHeader,
+) -> TopodumpStatus {
+    guard(|| {
+        let path = path_arg(path, "path")?;
+        let out = header.as_mut().ok_or_else(|| {
+            set_last_error("header is null");
+            TopodumpStatus::InvalidArgument
+        })?;
+
+        let tpq = TpqFile::open(path).map_err(fail)?;
+        let header = tpq.header();
+        out.version = header.version;
+        out.west = header.w_long;
+        out.north = header.n_lat;
+        out.east = header.e_long;
+        out.south = header.s_lat;
+        copy_str(&mut out.quad_name, &header.quad_name);
+        copy_str(&mut out.state_name, &header.state_name);
+        copy_str(&mut out.source, &header.source);
+        copy_str(&mut out.year1, &header.year1);
+        copy_str(&mut out.year2, &header.year2);
+        copy_str(&mut out.contour, &header.contour);
+        out.color_depth = header.color_depth;
+        out.columns = header.long_count;
+        out.rows = header.lat_count;
+        Ok(())
+    })
+}
+
+/// The message of the last error on this thread, or null. Valid until the
+/// next topodump call on the same thread.
+#[no_mangle]
+pub extern "C" fn topodump_last_error() -> *const c_char {
+    LAST_ERROR.with(|last_error| {
+        last_error
+            .borrow()
+            .as_ref()
+            .map_or(ptr::null(), |message| message.as_ptr())
+    })
+}
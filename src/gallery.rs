@@ -0,0 +1,126 @@
+//! A static HTML catalogue of converted quads, browsable without a server.
+
+use std::fmt::Write;
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+
+use crate::conversion::ConversionSummary;
+use crate::error::Result;
+use crate::output::thumbnail_path;
+use crate::vrt::escape_xml;
+
+// A link from the gallery's directory to `path`, or its file URL if the two
+// share no directory
+fn href(path: &Path, gallery_dir: &Path) -> String {
+    let path = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+    let gallery_dir = gallery_dir
+        .canonicalize()
+        .unwrap_or_else(|_| gallery_dir.to_path_buf());
+    let (prefix, relative) = match path.strip_prefix(&gallery_dir) {
+        Ok(relative) => ("", relative),
+        Err(_) => ("file://", path.as_path()),
+    };
+    let parts = relative
+        .components()
+        .filter_map(|component| match component {
+            Component::Normal(part) => Some(part.to_string_lossy()),
+            _ => None,
+        })
+        .map(|part| {
+            part.replace('%', "%25")
+                .replace(' ', "%20")
+                .replace('#', "%23")
+                .replace('?', "%3F")
+        })
+        .collect::<Vec<_>>()
+        .join("/");
+    if prefix.is_empty() {
+        parts
+    } else {
+        format!("{}/{}", prefix, parts)
+    }
+}
+
+fn years(conversion: &ConversionSummary) -> String {
+    let year1 = conversion.header.year1.trim();
+    let year2 = conversion.header.year2.trim();
+    if year2.is_empty() || year2 == year1 {
+        year1.to_string()
+    } else {
+        format!("{}, {}", year1, year2)
+    }
+}
+
+/// Writes a page listing `conversions` by state and quad name with their
+/// thumbnails, where [`Thumbnail::Auto`](crate::output::Thumbnail::Auto)
+/// wrote them, each quad's series and years, and links to the outputs.
+pub fn write_gallery(path: &Path, conversions: &[ConversionSummary]) -> Result<()> {
+    let gallery_dir = path
+        .parent()
+        .map_or_else(|| PathBuf::from("."), Path::to_path_buf);
+
+    let mut conversions = conversions.iter().collect::<Vec<_>>();
+    conversions.sort_by(|a, b| {
+        (a.header.state_name.trim(), a.header.quad_name.trim())
+            .cmp(&(b.header.state_name.trim(), b.header.quad_name.trim()))
+    });
+
+    let mut html = String::new();
+    let _ = writeln!(html, "<!DOCTYPE html>");
+    let _ = writeln!(html, "<html>");
+    let _ = writeln!(html, "<head>");
+    let _ = writeln!(html, r#"<meta charset="utf-8">"#);
+    let _ = writeln!(html, "<title>topodump gallery</title>");
+    let _ = writeln!(
+        html,
+        "<style>body {{ font-family: sans-serif; }} table {{ border-collapse: collapse; }} \
+         td, th {{ border-bottom: 1px solid #ccc; padding: 4px 8px; text-align: left; }}</style>"
+    );
+    let _ = writeln!(html, "</head>");
+    let _ = writeln!(html, "<body>");
+    let _ = writeln!(html, "<h1>{} quads</h1>", conversions.len());
+    let _ = writeln!(html, "<table>");
+    let _ = writeln!(
+        html,
+        "<tr><th></th><th>Quad</th><th>State</th><th>Year</th><th>Scale</th><th>Size</th><th>Output</th></tr>"
+    );
+    for conversion in conversions {
+        let output = href(&conversion.output, &gallery_dir);
+        let thumbnail = thumbnail_path(&conversion.output);
+        let image = if thumbnail.is_file() {
+            format!(
+                r#"<a href="{}"><img src="{}" alt="" loading="lazy"></a>"#,
+                escape_xml(&output),
+                escape_xml(&href(&thumbnail, &gallery_dir))
+            )
+        } else {
+            String::new()
+        };
+        let scale = conversion
+            .series
+            .map_or_else(|| "Unknown".to_string(), |series| series.to_string());
+        let name = conversion
+            .output
+            .file_name()
+            .map_or_else(String::new, |name| name.to_string_lossy().to_string());
+        let _ = writeln!(
+            html,
+            r#"<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{} × {}</td><td><a href="{}">{}</a></td></tr>"#,
+            image,
+            escape_xml(conversion.header.quad_name.trim()),
+            escape_xml(conversion.header.state_name.trim()),
+            escape_xml(&years(conversion)),
+            scale,
+            conversion.width,
+            conversion.height,
+            escape_xml(&output),
+            escape_xml(&name)
+        );
+    }
+    let _ = writeln!(html, "</table>");
+    let _ = writeln!(html, "</body>");
+    let _ = writeln!(html, "</html>");
+
+    fs::write(path, html)?;
+    Ok(())
+}
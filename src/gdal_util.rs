@@ -0,0 +1,396 @@
+use std::ffi::{CStr, CString};
+use std::os::raw::{c_char, c_int, c_void};
+use std::path::Path;
+use std::ptr;
+
+use gdal::errors::GdalError;
+use gdal::spatial_ref::SpatialRef;
+use gdal::{Dataset, Driver};
+use gdal_sys::{
+    vsi_l_offset, CPLErr, CPLGetLastErrorMsg, CSLDestroy, CSLSetNameValue, GDALAccess, GDALClose,
+    GDALColorEntry, GDALColorInterp, GDALCreate, GDALCreateColorTable, GDALCreateCopy,
+    GDALDataType, GDALDatasetRasterIO, GDALDestroyColorTable, GDALGetRasterBand, GDALOpen,
+    GDALPaletteInterp, GDALRWFlag, GDALSetColorEntry, GDALSetMetadataItem,
+    GDALSetRasterColorInterpretation, GDALSetRasterColorTable, GDALWarp, GDALWarpAppOptionsFree,
+    GDALWarpAppOptionsNew, VSIFree, VSIGetMemFileBuffer,
+};
+
+use image::{DynamicImage, GenericImageView, RgbaImage};
+
+use crate::error::Result;
+use crate::georef::GeoRef;
+use crate::palette::quantize;
+use crate::pixels;
+
+fn last_error(method_name: &'static str) -> GdalError {
+    let msg = unsafe { CStr::from_ptr(CPLGetLastErrorMsg()) }
+        .to_string_lossy()
+        .into_owned();
+    GdalError::NullPointer { method_name, msg }
+}
+
+// GDAL takes paths as bytes on Unix, so non-UTF-8 names survive there;
+// elsewhere it expects UTF-8 and lossy conversion is the best available
+#[cfg(unix)]
+fn c_path(path: &Path) -> Result<CString> {
+    use std::os::unix::ffi::OsStrExt;
+    Ok(CString::new(path.as_os_str().as_bytes()).map_err(GdalError::from)?)
+}
+
+#[cfg(not(unix))]
+fn c_path(path: &Path) -> Result<CString> {
+    Ok(CString::new(path.to_string_lossy().as_bytes()).map_err(GdalError::from)?)
+}
+
+// `Dataset::open` goes through a lossy `&str`
+pub(crate) fn open(path: &Path, update: bool) -> Result<Dataset> {
+    let c_path = c_path(path)?;
+    let access = if update {
+        GDALAccess::GA_Update
+    } else {
+        GDALAccess::GA_ReadOnly
+    };
+    let c_dataset = unsafe { GDALOpen(c_path.as_ptr(), access) };
+    if c_dataset.is_null() {
+        return Err(last_error("GDALOpen").into());
+    }
+    Ok(unsafe { Dataset::from_c_dataset(c_dataset) })
+}
+
+// A GDAL string list of creation options, destroyed on drop
+struct CreationOptions(*mut *mut c_char);
+
+impl CreationOptions {
+    fn new(options: &[(&str, &str)]) -> Result<Self> {
+        let mut c_options = CreationOptions(ptr::null_mut());
+        for (key, value) in options {
+            let c_key = CString::new(*key).map_err(GdalError::from)?;
+            let c_value = CString::new(*value).map_err(GdalError::from)?;
+            c_options.0 = unsafe { CSLSetNameValue(c_options.0, c_key.as_ptr(), c_value.as_ptr()) };
+        }
+        Ok(c_options)
+    }
+}
+
+impl Drop for CreationOptions {
+    fn drop(&mut self) {
+        unsafe { CSLDestroy(self.0) };
+    }
+}
+
+/// A dataset owned by one writer at a time.
+pub(crate) struct OwnedDataset(pub Dataset);
+
+// GDAL datasets may move between threads as long as they aren't used from two at once,
+// which exclusive ownership guarantees
+unsafe impl Send for OwnedDataset {}
+
+// The pinned gdal crate can't pass creation options to Create, so call it directly
+pub(crate) fn create(
+    driver: &str,
+    path: &Path,
+    (width, height): (u32, u32),
+    bands: u32,
+    options: &[(&str, &str)],
+) -> Result<OwnedDataset> {
+    let driver = Driver::get(driver)?;
+    let c_path = c_path(path)?;
+    let c_options = CreationOptions::new(options)?;
+
+    let c_dataset = unsafe {
+        GDALCreate(
+            driver.c_driver(),
+            c_path.as_ptr(),
+            width as c_int,
+            height as c_int,
+            bands as c_int,
+            GDALDataType::GDT_Byte,
+            c_options.0,
+        )
+    };
+    if c_dataset.is_null() {
+        return Err(last_error("GDALCreate").into());
+    }
+    Ok(OwnedDataset(unsafe { Dataset::from_c_dataset(c_dataset) }))
+}
+
+/// An in-memory dataset, e.g. to compose an image before copying it to a
+/// driver that can't be written incrementally. Its bands are interpreted as
+/// gray, gray and alpha, RGB or RGBA, as [`write_image`] writes them, so
+/// copies tag their photometric and alpha bands.
+pub(crate) fn create_mem(width: u32, height: u32, bands: u32) -> Result<OwnedDataset> {
+    let dataset = create("MEM", Path::new(""), (width, height), bands, &[])?;
+    let interps: &[GDALColorInterp::Type] = match bands {
+        1 => &[GDALColorInterp::GCI_GrayIndex],
+        2 => &[
+            GDALColorInterp::GCI_GrayIndex,
+            GDALColorInterp::GCI_AlphaBand,
+        ],
+        3 => &[
+            GDALColorInterp::GCI_RedBand,
+            GDALColorInterp::GCI_GreenBand,
+            GDALColorInterp::GCI_BlueBand,
+        ],
+        _ => &[
+            GDALColorInterp::GCI_RedBand,
+            GDALColorInterp::GCI_GreenBand,
+            GDALColorInterp::GCI_BlueBand,
+            GDALColorInterp::GCI_AlphaBand,
+        ],
+    };
+    for (band, &interp) in interps.iter().enumerate() {
+        let err = unsafe {
+            let c_band = GDALGetRasterBand(dataset.0.c_dataset(), band as c_int + 1);
+            GDALSetRasterColorInterpretation(c_band, interp)
+        };
+        if err != CPLErr::CE_None {
+            return Err(last_error("GDALSetRasterColorInterpretation").into());
+        }
+    }
+    Ok(dataset)
+}
+
+/// Writes `image` at (`x`, `y`) into the first `bands` bands, as gray, gray
+/// and alpha, RGB or RGBA, straight from its interleaved samples.
+pub(crate) fn write_image(
+    dataset: &Dataset,
+    x: u32,
+    y: u32,
+    image: &DynamicImage,
+    bands: u32,
+) -> Result<()> {
+    let position = (x, y);
+    let size = image.dimensions();
+    match bands {
+        1 => write_window(dataset, position, size, 1, pixels::luma(image).as_raw()),
+        2 => write_window(
+            dataset,
+            position,
+            size,
+            2,
+            pixels::luma_alpha(image).as_raw(),
+        ),
+        3 => write_window(dataset, position, size, 3, pixels::rgb(image).as_raw()),
+        _ => write_window(dataset, position, size, 4, pixels::rgba(image).as_raw()),
+    }
+}
+
+/// Writes interleaved samples of the first `bands` bands into a window.
+pub(crate) fn write_window(
+    dataset: &Dataset,
+    (x, y): (u32, u32),
+    (width, height): (u32, u32),
+    bands: u32,
+    samples: &[u8],
+) -> Result<()> {
+    let bands = bands as c_int;
+    let mut band_map = (1..=bands).collect::<Vec<_>>();
+
+    // GDAL only reads the buffer when writing
+    let err = unsafe {
+        GDALDatasetRasterIO(
+            dataset.c_dataset(),
+            GDALRWFlag::GF_Write,
+            x as c_int,
+            y as c_int,
+            width as c_int,
+            height as c_int,
+            samples.as_ptr() as *mut c_void,
+            width as c_int,
+            height as c_int,
+            GDALDataType::GDT_Byte,
+            bands,
+            band_map.as_mut_ptr(),
+            bands,
+            bands * width as c_int,
+            1,
+        )
+    };
+    if err != CPLErr::CE_None {
+        return Err(last_error("GDALDatasetRasterIO").into());
+    }
+    Ok(())
+}
+
+/// Reads a window of the first `bands` bands as interleaved samples.
+pub(crate) fn read_window(
+    dataset: &Dataset,
+    (x, y): (u32, u32),
+    (width, height): (u32, u32),
+    bands: u32,
+) -> Result<Vec<u8>> {
+    let bands = bands as c_int;
+    let mut samples = vec![0u8; width as usize * height as usize * bands as usize];
+    let mut band_map = (1..=bands).collect::<Vec<_>>();
+    let err = unsafe {
+        GDALDatasetRasterIO(
+            dataset.c_dataset(),
+            GDALRWFlag::GF_Read,
+            x as c_int,
+            y as c_int,
+            width as c_int,
+            height as c_int,
+            samples.as_mut_ptr() as *mut c_void,
+            width as c_int,
+            height as c_int,
+            GDALDataType::GDT_Byte,
+            bands,
+            band_map.as_mut_ptr(),
+            bands,
+            bands * width as c_int,
+            1,
+        )
+    };
+    if err != CPLErr::CE_None {
+        return Err(last_error("GDALDatasetRasterIO").into());
+    }
+    Ok(samples)
+}
+
+/// A one-band in-memory copy of `dataset`'s first `bands` bands, three or
+/// four, reduced to a palette of at most `colors` colours. Alpha is dropped,
+/// since GeoTIFF palettes can't hold it.
+pub(crate) fn paletted(dataset: &Dataset, bands: u32, colors: u16) -> Result<OwnedDataset> {
+    let (width, height) = dataset.raster_size();
+    let size = (width as u32, height as u32);
+    let samples = read_window(dataset, (0, 0), size, bands)?;
+    let samples = if bands == 4 {
+        samples
+    } else {
+        pixels::rgb_to_rgba(&samples)
+    };
+    let image = RgbaImage::from_raw(size.0, size.1, samples).expect("buffer matches dimensions");
+    let paletted = quantize(&image, colors);
+
+    let output = create_mem(size.0, size.1, 1)?;
+    write_window(&output.0, (0, 0), size, 1, &paletted.indices)?;
+    let err = unsafe {
+        let table = GDALCreateColorTable(GDALPaletteInterp::GPI_RGB);
+        for (i, &[red, green, blue, _]) in paletted.palette.iter().enumerate() {
+            let entry = GDALColorEntry {
+                c1: red as i16,
+                c2: green as i16,
+                c3: blue as i16,
+                c4: 255,
+            };
+            GDALSetColorEntry(table, i as c_int, &entry);
+        }
+        let err = GDALSetRasterColorTable(GDALGetRasterBand(output.0.c_dataset(), 1), table);
+        GDALDestroyColorTable(table);
+        err
+    };
+    if err != CPLErr::CE_None {
+        return Err(last_error("GDALSetRasterColorTable").into());
+    }
+    Ok(output)
+}
+
+/// Whether two WKT strings describe the same CRS, however they're written.
+pub(crate) fn same_crs(wkt: &str, other: &str) -> Result<bool> {
+    Ok(SpatialRef::from_wkt(wkt)? == SpatialRef::from_wkt(other)?)
+}
+
+pub(crate) fn georeference(dataset: &Dataset, georef: &GeoRef) -> Result<()> {
+    dataset.set_spatial_ref(&SpatialRef::from_wkt(&georef.wkt)?)?;
+    dataset.set_geo_transform(&georef.transform)?;
+    Ok(())
+}
+
+/// Embeds `profile` in `dataset`, which GeoTIFF and COG drivers write as
+/// its ICC profile tag.
+pub(crate) fn set_icc_profile(dataset: &Dataset, profile: &[u8]) -> Result<()> {
+    let value = CString::new(base64::encode(profile)).map_err(GdalError::from)?;
+    let err = unsafe {
+        GDALSetMetadataItem(
+            dataset.c_dataset() as *mut c_void,
+            b"SOURCE_ICC_PROFILE\0".as_ptr() as *const c_char,
+            value.as_ptr(),
+            b"COLOR_PROFILE\0".as_ptr() as *const c_char,
+        )
+    };
+    if err != CPLErr::CE_None {
+        return Err(last_error("GDALSetMetadataItem").into());
+    }
+    Ok(())
+}
+
+// Likewise for CreateCopy
+pub(crate) fn create_copy(
+    source: &Dataset,
+    driver: &str,
+    path: &Path,
+    options: &[(&str, &str)],
+) -> Result<()> {
+    let driver = Driver::get(driver)?;
+    let c_path = c_path(path)?;
+    let c_options = CreationOptions::new(options)?;
+
+    let c_dataset = unsafe {
+        GDALCreateCopy(
+            driver.c_driver(),
+            c_path.as_ptr(),
+            source.c_dataset(),
+            0,
+            c_options.0,
+            None,
+            ptr::null_mut(),
+        )
+    };
+    if c_dataset.is_null() {
+        return Err(last_error("GDALCreateCopy").into());
+    }
+    unsafe { GDALClose(c_dataset) };
+
+    Ok(())
+}
+
+/// Runs the equivalent of `gdalwarp <args> <source> <path>`.
+pub(crate) fn warp(source: &Dataset, path: &Path, args: &[String]) -> Result<()> {
+    let c_path = c_path(path)?;
+    let c_args = args
+        .iter()
+        .map(|arg| CString::new(arg.as_str()))
+        .collect::<std::result::Result<Vec<_>, _>>()
+        .map_err(GdalError::from)?;
+    let mut argv = c_args
+        .iter()
+        .map(|arg| arg.as_ptr() as *mut c_char)
+        .chain(Some(ptr::null_mut()))
+        .collect::<Vec<_>>();
+
+    let options = unsafe { GDALWarpAppOptionsNew(argv.as_mut_ptr(), ptr::null_mut()) };
+    if options.is_null() {
+        return Err(last_error("GDALWarpAppOptionsNew").into());
+    }
+    let mut sources = [source.c_dataset()];
+    let mut usage_error: c_int = 0;
+    let c_dataset = unsafe {
+        GDALWarp(
+            c_path.as_ptr(),
+            ptr::null_mut(),
+            1,
+            sources.as_mut_ptr(),
+            options,
+            &mut usage_error,
+        )
+    };
+    unsafe { GDALWarpAppOptionsFree(options) };
+    if c_dataset.is_null() {
+        return Err(last_error("GDALWarp").into());
+    }
+    unsafe { GDALClose(c_dataset) };
+    Ok(())
+}
+
+/// Removes a `/vsimem/` file written by GDAL, returning its contents.
+pub(crate) fn take_mem_file(path: &str) -> Result<Vec<u8>> {
+    let c_path = CString::new(path).map_err(GdalError::from)?;
+    let mut len: vsi_l_offset = 0;
+    let buffer = unsafe { VSIGetMemFileBuffer(c_path.as_ptr(), &mut len, 1) };
+    if buffer.is_null() {
+        return Err(last_error("VSIGetMemFileBuffer").into());
+    }
+    let data = unsafe { std::slice::from_raw_parts(buffer, len as usize) }.to_vec();
+    unsafe { VSIFree(buffer as *mut _) };
+    Ok(data)
+}
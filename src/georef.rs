@@ -0,0 +1,113 @@
+use std::fs;
+use std::path::Path;
+
+#[cfg(feature = "geo")]
+use geo::{AffineTransform, Rect};
+use serde::{Deserialize, Serialize};
+
+use crate::error::Result;
+#[cfg(feature = "gdal")]
+use crate::gdal_util;
+use crate::header::TpqHeader;
+
+pub const NAD27_WKT: &str = r#"GEODCRS["NAD 27",
+    DATUM["North American Datum of 1927",
+        ELLIPSOID["NAD 27", 6378206.4, 294.978698214, LENGTHUNIT["metre", 1]]],
+    CS[ellipsoidal, 2],
+        AXIS["Latitude (lat)", north, ORDER[1]],
+        AXIS["Longitude (lon)", east, ORDER[2]],
+        ANGLEUNIT["degree", 0.0174532925199433]]"#;
+
+/// Where an output sits on the ground: a GDAL-style geotransform plus CRS WKT.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct GeoRef {
+    pub transform: [f64; 6],
+    pub wkt: String,
+}
+
+impl GeoRef {
+    pub fn from_header(header: &TpqHeader, width: f64, height: f64) -> Self {
+        GeoRef {
+            transform: [
+                header.w_long,
+                (header.e_long - header.w_long) / width,
+                0.0,
+                header.n_lat,
+                0.0,
+                -(header.n_lat - header.s_lat) / height,
+            ],
+            wkt: NAD27_WKT.to_string(),
+        }
+    }
+
+    /// (west, south, east, north) of an image of the given size.
+    pub fn bounds(&self, width: u32, height: u32) -> (f64, f64, f64, f64) {
+        let [x, dx, _, y, _, dy] = self.transform;
+        let (x2, y2) = (x + dx * width as f64, y + dy * height as f64);
+        (x.min(x2), y.min(y2), x.max(x2), y.max(y2))
+    }
+
+    /// The georeferencing of a window whose top-left pixel is (`x`, `y`).
+    pub fn window(&self, x: u32, y: u32) -> GeoRef {
+        let [x0, dx, rx, y0, ry, dy] = self.transform;
+        let (x, y) = (x as f64, y as f64);
+        GeoRef {
+            transform: [x0 + x * dx + y * rx, dx, rx, y0 + x * ry + y * dy, ry, dy],
+            wkt: self.wkt.clone(),
+        }
+    }
+
+    /// [`bounds`](GeoRef::bounds) as a rectangle.
+    #[cfg(feature = "geo")]
+    pub fn bounds_rect(&self, width: u32, height: u32) -> Rect<f64> {
+        let (west, south, east, north) = self.bounds(width, height);
+        Rect::new((west, south), (east, north))
+    }
+
+    /// The pixel (column, row) to ground coordinate mapping.
+    #[cfg(feature = "geo")]
+    pub fn affine(&self) -> AffineTransform<f64> {
+        let [x, dx, rx, y, ry, dy] = self.transform;
+        AffineTransform::new(dx, rx, x, ry, dy, y)
+    }
+}
+
+#[cfg(feature = "gdal")]
+pub fn set_geo_data<P: AsRef<Path>>(path: P, georef: &GeoRef) -> Result<()> {
+    let dataset = gdal_util::open(path.as_ref(), true)?;
+    gdal_util::georeference(&dataset, georef)
+}
+
+/// The contents of an ESRI world file for `georef`.
+pub fn world_file_contents(georef: &GeoRef) -> String {
+    let [x, dx, rx, y, ry, dy] = georef.transform;
+    // World files locate the centre of the top-left pixel, not its corner
+    format!(
+        "{}\n{}\n{}\n{}\n{}\n{}\n",
+        dx,
+        ry,
+        rx,
+        dy,
+        x + dx / 2.0 + rx / 2.0,
+        y + ry / 2.0 + dy / 2.0
+    )
+}
+
+/// Writes an ESRI world file and .prj next to `path`, e.g. `out.pgw` for `out.png`.
+pub fn write_world_file(path: &Path, georef: &GeoRef) -> Result<()> {
+    let extension = path
+        .extension()
+        .map(|extension| extension.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let world_extension = match extension.chars().collect::<Vec<_>>().as_slice() {
+        [first, .., last] => format!("{}{}w", first, last),
+        _ => "wld".to_string(),
+    };
+    fs::write(
+        path.with_extension(world_extension),
+        world_file_contents(georef),
+    )?;
+    fs::write(path.with_extension("prj"), &georef.wkt)?;
+
+    Ok(())
+}
@@ -0,0 +1,285 @@
+//! A GeoPackage of every quad on a TOPO! disc in one Web Mercator tile
+//! pyramid, each zoom level drawn from the finest series suited to it, so
+//! zooming in moves from the state map to regional and quad maps as TOPO!
+//! did.
+//!
+//! NAD27 coordinates are used as-is, as for MBTiles.
+
+use std::collections::BTreeMap;
+use std::f64::consts::PI;
+use std::fs::remove_file;
+use std::ops::RangeInclusive;
+use std::path::{Path, PathBuf};
+
+use image::RgbaImage;
+use rusqlite::{params, Connection, OptionalExtension, Transaction};
+
+use crate::conversion::Resampling;
+use crate::error::{Result, TopodumpError};
+use crate::georef::GeoRef;
+use crate::output::PartFile;
+use crate::scale::Series;
+use crate::tiles::{
+    encode_png, image_for_zoom, is_empty, native_zoom, render_tile, tiles_covering, TileCoord,
+    OVERVIEW_LEVELS, TILE_SIZE,
+};
+use crate::tpq::TpqFile;
+
+/// The tile table a GeoPackage's pyramid is stored in.
+pub const TILE_TABLE: &str = "topo";
+
+// Half the width of the Web Mercator world, in metres
+const MERCATOR_EXTENT: f64 = 20_037_508.342_789_244;
+
+// What identifies an SQLite database as a GeoPackage 1.3
+const APPLICATION_ID: i32 = 0x4750_4B47;
+const USER_VERSION: i32 = 10_300;
+
+const WGS84_WKT: &str = r#"GEOGCS["WGS 84",DATUM["WGS_1984",SPHEROID["WGS 84",6378137,298.257223563,AUTHORITY["EPSG","7030"]],AUTHORITY["EPSG","6326"]],PRIMEM["Greenwich",0],UNIT["degree",0.0174532925199433],AUTHORITY["EPSG","4326"]]"#;
+const WEB_MERCATOR_WKT: &str = r#"PROJCS["WGS 84 / Pseudo-Mercator",GEOGCS["WGS 84",DATUM["WGS_1984",SPHEROID["WGS 84",6378137,298.257223563]],PRIMEM["Greenwich",0],UNIT["degree",0.0174532925199433]],PROJECTION["Mercator_1SP"],PARAMETER["central_meridian",0],PARAMETER["scale_factor",1],PARAMETER["false_easting",0],PARAMETER["false_northing",0],UNIT["metre",1],AXIS["X",EAST],AXIS["Y",NORTH],AUTHORITY["EPSG","3857"]]"#;
+
+/// The zoom levels drawn from one series.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct GpkgLevel {
+    pub series: Series,
+    pub zooms: RangeInclusive<u8>,
+    pub quads: usize,
+}
+
+/// What [`GeoPackageBuild::run`] put in the GeoPackage.
+#[derive(Clone, Debug, Default)]
+pub struct GpkgSummary {
+    /// From the coarsest series to the finest.
+    pub levels: Vec<GpkgLevel>,
+    /// Inputs left out, and why.
+    pub skipped: Vec<(PathBuf, String)>,
+}
+
+/// A multi-scale GeoPackage, executed with [`GeoPackageBuild::run`].
+///
+/// Each series present draws the zoom levels from just below its own
+/// resolution, or from the coarsest levels for the coarsest series, down to
+/// its native zoom; the finest series present ends the pyramid.
+pub struct GeoPackageBuild {
+    inputs: Vec<PathBuf>,
+    output: PathBuf,
+    resampling: Resampling,
+}
+
+// A quad's series and degrees per pixel
+fn classify(input: &Path) -> Result<(Series, f64)> {
+    let tpq = TpqFile::open(input)?;
+    let (maplet_width, maplet_height) = tpq.maplet_dimensions()?;
+    let header = tpq.header();
+    let series = header
+        .series(maplet_height)
+        .ok_or(TopodumpError::Unsupported("quads of unknown series"))?;
+    let (width, _) = header.image_size((maplet_width, maplet_height), u64::MAX)?;
+    Ok((series, (header.e_long - header.w_long) / width as f64))
+}
+
+fn mercator(long: f64, lat: f64) -> (f64, f64) {
+    let y = (PI / 4.0 + lat.to_radians() / 2.0).tan().ln();
+    (long / 180.0 * MERCATOR_EXTENT, y / PI * MERCATOR_EXTENT)
+}
+
+fn create_schema(connection: &Connection, zooms: RangeInclusive<u8>) -> Result<()> {
+    connection.pragma_update(None, "application_id", &APPLICATION_ID)?;
+    connection.pragma_update(None, "user_version", &USER_VERSION)?;
+    connection.execute_batch(&format!(
+        "CREATE TABLE gpkg_spatial_ref_sys (srs_name TEXT NOT NULL, srs_id INTEGER NOT NULL PRIMARY KEY, organization TEXT NOT NULL, organization_coordsys_id INTEGER NOT NULL, definition TEXT NOT NULL, description TEXT);
+         CREATE TABLE gpkg_contents (table_name TEXT NOT NULL PRIMARY KEY, data_type TEXT NOT NULL, identifier TEXT UNIQUE, description TEXT DEFAULT '', last_change DATETIME NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%fZ', 'now')), min_x DOUBLE, min_y DOUBLE, max_x DOUBLE, max_y DOUBLE, srs_id INTEGER REFERENCES gpkg_spatial_ref_sys (srs_id));
+         CREATE TABLE gpkg_tile_matrix_set (table_name TEXT NOT NULL PRIMARY KEY REFERENCES gpkg_contents (table_name), srs_id INTEGER NOT NULL REFERENCES gpkg_spatial_ref_sys (srs_id), min_x DOUBLE NOT NULL, min_y DOUBLE NOT NULL, max_x DOUBLE NOT NULL, max_y DOUBLE NOT NULL);
+         CREATE TABLE gpkg_tile_matrix (table_name TEXT NOT NULL REFERENCES gpkg_contents (table_name), zoom_level INTEGER NOT NULL, matrix_width INTEGER NOT NULL, matrix_height INTEGER NOT NULL, tile_width INTEGER NOT NULL, tile_height INTEGER NOT NULL, pixel_x_size DOUBLE NOT NULL, pixel_y_size DOUBLE NOT NULL, PRIMARY KEY (table_name, zoom_level));
+         CREATE TABLE {table} (id INTEGER PRIMARY KEY AUTOINCREMENT, zoom_level INTEGER NOT NULL, tile_column INTEGER NOT NULL, tile_row INTEGER NOT NULL, tile_data BLOB NOT NULL, UNIQUE (zoom_level, tile_column, tile_row));",
+        table = TILE_TABLE
+    ))?;
+    let srs = [
+        ("Undefined cartesian SRS", -1, "NONE", -1, "undefined"),
+        ("Undefined geographic SRS", 0, "NONE", 0, "undefined"),
+        ("WGS 84 geodetic", 4326, "EPSG", 4326, WGS84_WKT),
+        (
+            "WGS 84 / Pseudo-Mercator",
+            3857,
+            "EPSG",
+            3857,
+            WEB_MERCATOR_WKT,
+        ),
+    ];
+    for (name, id, organization, coordsys_id, definition) in &srs {
+        connection.execute(
+            "INSERT INTO gpkg_spatial_ref_sys (srs_name, srs_id, organization, organization_coordsys_id, definition) VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![name, id, organization, coordsys_id, definition],
+        )?;
+    }
+    for z in zooms {
+        let matrix = 1u32 << z;
+        let pixel_size = 2.0 * MERCATOR_EXTENT / (TILE_SIZE as f64 * matrix as f64);
+        connection.execute(
+            "INSERT INTO gpkg_tile_matrix (table_name, zoom_level, matrix_width, matrix_height, tile_width, tile_height, pixel_x_size, pixel_y_size) VALUES (?1, ?2, ?3, ?3, ?4, ?4, ?5, ?5)",
+            params![TILE_TABLE, z, matrix, TILE_SIZE, pixel_size],
+        )?;
+    }
+    Ok(())
+}
+
+// Draws `tile_img` over whatever an earlier quad left in the same tile
+fn store_tile(transaction: &Transaction, tile: TileCoord, tile_img: RgbaImage) -> Result<()> {
+    let existing = transaction
+        .query_row(
+            &format!(
+                "SELECT tile_data FROM {} WHERE zoom_level = ?1 AND tile_column = ?2 AND tile_row = ?3",
+                TILE_TABLE
+            ),
+            params![tile.z, tile.x, tile.y],
+            |row| row.get::<_, Vec<u8>>(0),
+        )
+        .optional()?;
+    let tile_img = match existing {
+        Some(data) => {
+            let mut below = image::load_from_memory(&data)?.to_rgba8();
+            for (x, y, pixel) in tile_img.enumerate_pixels() {
+                if pixel[3] > 0 {
+                    below.put_pixel(x, y, *pixel);
+                }
+            }
+            below
+        }
+        None => tile_img,
+    };
+    // GeoPackage rows count from the north, like XYZ tiles
+    transaction.execute(
+        &format!(
+            "INSERT OR REPLACE INTO {} (zoom_level, tile_column, tile_row, tile_data) VALUES (?1, ?2, ?3, ?4)",
+            TILE_TABLE
+        ),
+        params![tile.z, tile.x, tile.y, encode_png(&tile_img)?],
+    )?;
+    Ok(())
+}
+
+// Tiles `path` at `zooms`, returning its (west, south, east, north)
+fn add_quad(
+    transaction: &Transaction,
+    path: &Path,
+    zooms: RangeInclusive<u8>,
+    resampling: Resampling,
+) -> Result<(f64, f64, f64, f64)> {
+    let tpq = TpqFile::open(path)?;
+    let collage = tpq.collage()?;
+    let (width, height) = collage.dimensions();
+    let georef = GeoRef::from_header(tpq.header(), width as f64, height as f64);
+    let bounds = georef.bounds(width, height);
+    for z in zooms {
+        let (image, transform) = image_for_zoom(&collage, &georef.transform, z, resampling);
+        for tile in tiles_covering(bounds, z) {
+            let tile_img = render_tile(&image, &transform, tile, resampling);
+            if !is_empty(&tile_img) {
+                store_tile(transaction, tile, tile_img)?;
+            }
+        }
+    }
+    Ok(bounds)
+}
+
+impl GeoPackageBuild {
+    pub fn new<P: Into<PathBuf>>(inputs: Vec<PathBuf>, output: P) -> Self {
+        GeoPackageBuild {
+            inputs,
+            output: output.into(),
+            resampling: Resampling::Bilinear,
+        }
+    }
+
+    /// How tiles are resampled from each quad, bilinearly by default.
+    pub fn resampling(mut self, resampling: Resampling) -> Self {
+        self.resampling = resampling;
+        self
+    }
+
+    /// Builds the GeoPackage, skipping inputs that can't be opened or whose
+    /// series can't be told.
+    pub fn run(self) -> Result<GpkgSummary> {
+        let mut summary = GpkgSummary::default();
+        // Finest series first
+        let mut by_series = BTreeMap::<Series, Vec<(PathBuf, f64)>>::new();
+        for input in self.inputs {
+            match classify(&input) {
+                Ok((series, dx)) => by_series.entry(series).or_default().push((input, dx)),
+                Err(err) => summary.skipped.push((input, err.to_string())),
+            }
+        }
+
+        let mut next_zoom = None;
+        for (series, quads) in by_series.iter().rev() {
+            let native = quads
+                .iter()
+                .map(|(_, dx)| native_zoom(*dx))
+                .max()
+                .unwrap_or(0);
+            let first = next_zoom.unwrap_or_else(|| native.saturating_sub(OVERVIEW_LEVELS));
+            if first > native {
+                for (path, _) in quads {
+                    summary.skipped.push((
+                        path.clone(),
+                        format!("{} is no finer than the series above it", series),
+                    ));
+                }
+                continue;
+            }
+            summary.levels.push(GpkgLevel {
+                series: *series,
+                zooms: first..=native,
+                quads: quads.len(),
+            });
+            next_zoom = Some(native + 1);
+        }
+        let (min_zoom, max_zoom) = match (summary.levels.first(), summary.levels.last()) {
+            (Some(coarsest), Some(finest)) => (*coarsest.zooms.start(), *finest.zooms.end()),
+            _ => {
+                return Err(TopodumpError::Unsupported(
+                    "GeoPackages without quads of a known series",
+                ))
+            }
+        };
+
+        let part = PartFile::new(&self.output);
+        // Left behind if an earlier run was killed
+        if part.path().exists() {
+            remove_file(part.path())?;
+        }
+        let mut connection = Connection::open(part.path())?;
+        create_schema(&connection, min_zoom..=max_zoom)?;
+
+        let transaction = connection.transaction()?;
+        let (mut west, mut south, mut east, mut north) = (
+            f64::INFINITY,
+            f64::INFINITY,
+            f64::NEG_INFINITY,
+            f64::NEG_INFINITY,
+        );
+        for level in &summary.levels {
+            for (path, _) in &by_series[&level.series] {
+                let bounds = add_quad(&transaction, path, level.zooms.clone(), self.resampling)?;
+                west = west.min(bounds.0);
+                south = south.min(bounds.1);
+                east = east.max(bounds.2);
+                north = north.max(bounds.3);
+            }
+        }
+        let (min_x, min_y) = mercator(west, south);
+        let (max_x, max_y) = mercator(east, north);
+        transaction.execute(
+            "INSERT INTO gpkg_contents (table_name, data_type, identifier, min_x, min_y, max_x, max_y, srs_id) VALUES (?1, 'tiles', ?1, ?2, ?3, ?4, ?5, 3857)",
+            params![TILE_TABLE, min_x, min_y, max_x, max_y],
+        )?;
+        transaction.execute(
+            "INSERT INTO gpkg_tile_matrix_set (table_name, srs_id, min_x, min_y, max_x, max_y) VALUES (?1, 3857, ?2, ?2, ?3, ?3)",
+            params![TILE_TABLE, -MERCATOR_EXTENT, MERCATOR_EXTENT],
+        )?;
+        transaction.commit()?;
+        drop(connection);
+        part.commit()?;
+        Ok(summary)
+    }
+}
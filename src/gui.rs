@@ -0,0 +1,310 @@
+//! A small window for converting tpq files without the command line: drop
+//! files or folders onto it, pick a format, and watch them convert.
+
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+
+use eframe::egui;
+
+use crate::batch::find_tpq_files;
+use crate::cancel::CancelToken;
+use crate::conversion::{Compression, Conversion, Format};
+use crate::error::{Result, TopodumpError};
+use crate::progress::Progress;
+
+#[derive(Clone, Debug)]
+enum JobState {
+    Queued,
+    Converting(f32),
+    Done(PathBuf),
+    Failed(String),
+}
+
+#[derive(Clone, Debug)]
+struct Job {
+    input: PathBuf,
+    state: JobState,
+}
+
+// What each queued file is converted with
+#[derive(Clone, Debug)]
+struct Settings {
+    format: Format,
+    compression: Compression,
+    quality: u8,
+    // Beside each input if empty
+    output_dir: String,
+}
+
+fn format_name(format: Format) -> &'static str {
+    match format {
+        Format::GTiff => "GeoTIFF",
+        Format::Cog => "Cloud-optimized GeoTIFF",
+        Format::Png => "PNG",
+        #[cfg(feature = "mbtiles")]
+        Format::MbTiles => "MBTiles",
+    }
+}
+
+fn compression_name(compression: Compression) -> &'static str {
+    match compression {
+        Compression::None => "None",
+        Compression::Deflate => "Deflate",
+        Compression::Lzw => "LZW",
+        Compression::Jpeg => "JPEG",
+    }
+}
+
+const FORMATS: &[Format] = &[
+    Format::GTiff,
+    Format::Cog,
+    Format::Png,
+    #[cfg(feature = "mbtiles")]
+    Format::MbTiles,
+];
+
+const COMPRESSIONS: [Compression; 4] = [
+    Compression::None,
+    Compression::Deflate,
+    Compression::Lzw,
+    Compression::Jpeg,
+];
+
+// Converts every queued job in turn, until none are left or it's cancelled
+fn convert_queued(
+    jobs: Arc<Mutex<Vec<Job>>>,
+    settings: Settings,
+    cancel: CancelToken,
+    ctx: egui::Context,
+) {
+    loop {
+        let next = {
+            let mut jobs = jobs.lock().unwrap();
+            let next = jobs
+                .iter()
+                .position(|job| matches!(job.state, JobState::Queued));
+            if let Some(index) = next {
+                jobs[index].state = JobState::Converting(0.0);
+            }
+            next.map(|index| (index, jobs[index].input.clone()))
+        };
+        let (index, input) = match next {
+            Some(next) if !cancel.is_cancelled() => next,
+            _ => break,
+        };
+
+        let progress_jobs = Arc::clone(&jobs);
+        let progress_ctx = ctx.clone();
+        let mut decoded = 0;
+        let mut conversion = Conversion::new(&input)
+            .format(settings.format)
+            .compression(settings.compression)
+            .quality(settings.quality)
+            .cancel_token(cancel.clone())
+            .on_progress(move |progress| {
+                if let Progress::MapletDecoded { total, .. } = progress {
+                    decoded += 1;
+                    progress_jobs.lock().unwrap()[index].state =
+                        JobState::Converting(decoded as f32 / total.max(1) as f32);
+                    progress_ctx.request_repaint();
+                }
+            });
+        if !settings.output_dir.trim().is_empty() {
+            conversion = conversion.output_dir(settings.output_dir.trim());
+        }
+        let state = match conversion.run() {
+            Ok(output) => JobState::Done(output),
+            Err(err) => JobState::Failed(err.to_string()),
+        };
+        jobs.lock().unwrap()[index].state = state;
+        ctx.request_repaint();
+    }
+}
+
+struct App {
+    jobs: Arc<Mutex<Vec<Job>>>,
+    settings: Settings,
+    worker: Option<JoinHandle<()>>,
+    cancel: CancelToken,
+    message: String,
+}
+
+impl App {
+    fn running(&self) -> bool {
+        self.worker
+            .as_ref()
+            .map_or(false, |worker| !worker.is_finished())
+    }
+
+    // Queues dropped tpq files, and every tpq file under dropped folders
+    fn add(&mut self, path: PathBuf) {
+        let inputs = if path.is_dir() {
+            match find_tpq_files(&path) {
+                Ok(inputs) => inputs,
+                Err(err) => {
+                    self.message = format!("Couldn't read {}: {}", path.display(), err);
+                    return;
+                }
+            }
+        } else {
+            vec![path]
+        };
+        let mut jobs = self.jobs.lock().unwrap();
+        for input in inputs {
+            if !jobs.iter().any(|job| job.input == input) {
+                jobs.push(Job {
+                    input,
+                    state: JobState::Queued,
+                });
+            }
+        }
+    }
+
+    fn start(&mut self, ctx: &egui::Context) {
+        let jobs = Arc::clone(&self.jobs);
+        let settings = self.settings.clone();
+        self.cancel = CancelToken::new();
+        let cancel = self.cancel.clone();
+        let ctx = ctx.clone();
+        self.message.clear();
+        self.worker = Some(thread::spawn(move || {
+            convert_queued(jobs, settings, cancel, ctx)
+        }));
+    }
+}
+
+impl eframe::App for App {
+    fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
+        let dropped = ctx.input(|input| input.raw.dropped_files.clone());
+        for file in dropped {
+            if let Some(path) = file.path {
+                self.add(path);
+            }
+        }
+        let hovering = ctx.input(|input| !input.raw.hovered_files.is_empty());
+        let running = self.running();
+
+        egui::TopBottomPanel::top("settings").show(ctx, |ui| {
+            ui.add_enabled_ui(!running, |ui| {
+                egui::ComboBox::from_label("Format")
+                    .selected_text(format_name(self.settings.format))
+                    .show_ui(ui, |ui| {
+                        for &format in FORMATS {
+                            ui.selectable_value(
+                                &mut self.settings.format,
+                                format,
+                                format_name(format),
+                            );
+                        }
+                    });
+                egui::ComboBox::from_label("Compression")
+                    .selected_text(compression_name(self.settings.compression))
+                    .show_ui(ui, |ui| {
+                        for &compression in &COMPRESSIONS {
+                            ui.selectable_value(
+                                &mut self.settings.compression,
+                                compression,
+                                compression_name(compression),
+                            );
+                        }
+                    });
+                ui.add(egui::Slider::new(&mut self.settings.quality, 1..=100).text("JPEG quality"));
+                ui.horizontal(|ui| {
+                    ui.label("Output folder");
+                    ui.text_edit_singleline(&mut self.settings.output_dir)
+                        .on_hover_text("Leave empty to write each output beside its tpq file");
+                });
+            });
+            ui.horizontal(|ui| {
+                let queued = self
+                    .jobs
+                    .lock()
+                    .unwrap()
+                    .iter()
+                    .filter(|job| matches!(job.state, JobState::Queued))
+                    .count();
+                if running {
+                    if ui.button("Cancel").clicked() {
+                        self.cancel.cancel();
+                    }
+                } else if ui
+                    .add_enabled(
+                        queued > 0,
+                        egui::Button::new(format!("Convert {} files", queued)),
+                    )
+                    .clicked()
+                {
+                    self.start(ctx);
+                }
+                if ui
+                    .add_enabled(!running, egui::Button::new("Clear"))
+                    .clicked()
+                {
+                    self.jobs.lock().unwrap().clear();
+                }
+                ui.label(&self.message);
+            });
+        });
+
+        egui::CentralPanel::default().show(ctx, |ui| {
+            let jobs = self.jobs.lock().unwrap().clone();
+            if jobs.is_empty() || hovering {
+                ui.centered_and_justified(|ui| {
+                    ui.heading("Drop .tpq files or folders here");
+                });
+                return;
+            }
+            egui::ScrollArea::vertical().show(ui, |ui| {
+                for job in &jobs {
+                    let name = job
+                        .input
+                        .file_name()
+                        .map_or_else(String::new, |name| name.to_string_lossy().to_string());
+                    ui.horizontal(|ui| {
+                        ui.label(name)
+                            .on_hover_text(job.input.display().to_string());
+                        match &job.state {
+                            JobState::Queued => {
+                                ui.weak("Queued");
+                            }
+                            JobState::Converting(fraction) => {
+                                ui.add(egui::ProgressBar::new(*fraction).show_percentage());
+                            }
+                            JobState::Done(output) => {
+                                ui.label(format!("Wrote {}", output.display()));
+                            }
+                            JobState::Failed(err) => {
+                                ui.colored_label(egui::Color32::RED, err);
+                            }
+                        }
+                    });
+                }
+            });
+        });
+    }
+}
+
+/// Opens the window, returning once it's closed.
+pub fn run() -> Result<()> {
+    let app = App {
+        jobs: Arc::default(),
+        settings: Settings {
+            format: Format::GTiff,
+            compression: Compression::Deflate,
+            quality: 90,
+            output_dir: String::new(),
+        },
+        worker: None,
+        cancel: CancelToken::new(),
+        message: String::new(),
+    };
+    let options = eframe::NativeOptions {
+        viewport: egui::ViewportBuilder::default()
+            .with_inner_size([560.0, 480.0])
+            .with_drag_and_drop(true),
+        ..Default::default()
+    };
+    eframe::run_native("topodump", options, Box::new(|_| Box::new(app)))
+        .map_err(|err| TopodumpError::Gui(err.to_string()))
+}
@@ -0,0 +1,200 @@
+use std::cmp::min;
+use std::io::{self, BufRead, Cursor, Read};
+
+#[cfg(feature = "geo")]
+use geo::Rect;
+use serde::{Deserialize, Serialize};
+
+use crate::error::{Result, TopodumpError};
+use crate::scale::{infer_series, Series};
+
+/// Size in bytes of the fields parsed into [`TpqHeader`].
+pub const HEADER_LEN: usize = 508;
+
+/// The most pixels a single quad's image may have unless configured
+/// otherwise, far beyond any real product.
+pub const DEFAULT_MAX_PIXELS: u64 = 1 << 32;
+
+/// The widest or tallest image that can be written, GDAL's limit.
+pub const MAX_DIMENSION: u64 = i32::MAX as u64;
+
+/// `width`×`height` as `u32`s if it's at most `max_pixels` pixels and
+/// [`MAX_DIMENSION`] on a side.
+pub fn check_image_size(width: u64, height: u64, max_pixels: u64) -> Result<(u32, u32)> {
+    if width > MAX_DIMENSION || height > MAX_DIMENSION || width * height > max_pixels {
+        return Err(TopodumpError::ImageTooLarge {
+            width,
+            height,
+            max_pixels,
+        });
+    }
+    Ok((width as u32, height as u32))
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct TpqHeader {
+    pub version: u32,
+    #[serde(rename = "west", alias = "w_long")]
+    pub w_long: f64,
+    #[serde(rename = "north", alias = "n_lat")]
+    pub n_lat: f64,
+    #[serde(rename = "east", alias = "e_long")]
+    pub e_long: f64,
+    #[serde(rename = "south", alias = "s_lat")]
+    pub s_lat: f64,
+    pub topo: String,
+    pub quad_name: String,
+    pub state_name: String,
+    pub source: String,
+    pub year1: String,
+    pub year2: String,
+    pub contour: String,
+    pub extension: String,
+    pub color_depth: u32,
+    #[serde(rename = "columns", alias = "long_count")]
+    pub long_count: u32,
+    #[serde(rename = "rows", alias = "lat_count")]
+    pub lat_count: u32,
+    pub maplet_screen_width: u32,
+    pub maplet_screen_height: u32,
+    /// The undecoded header, when read with [`read_tpq_header_with_raw`].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub raw: Option<Vec<u8>>,
+}
+
+impl TpqHeader {
+    pub fn series(&self, maplet_height: u32) -> Option<Series> {
+        infer_series(
+            self.n_lat - self.s_lat,
+            self.e_long - self.w_long,
+            self.lat_count.saturating_mul(maplet_height),
+        )
+    }
+
+    /// The size of the whole quad's image with maplets of the given size,
+    /// checked as by [`check_image_size`] rather than wrapping around.
+    pub fn image_size(
+        &self,
+        (maplet_width, maplet_height): (u32, u32),
+        max_pixels: u64,
+    ) -> Result<(u32, u32)> {
+        check_image_size(
+            self.long_count as u64 * maplet_width as u64,
+            self.lat_count as u64 * maplet_height as u64,
+            max_pixels,
+        )
+    }
+
+    /// Puts bounds stored in the wrong order, as some third-party quads have
+    /// them, the right way round, describing each fix, and fails for
+    /// coordinates off the globe.
+    pub fn normalize_bounds(&mut self) -> Result<Vec<String>> {
+        let longitude = |value: f64| (-180.0..=180.0).contains(&value);
+        let latitude = |value: f64| (-90.0..=90.0).contains(&value);
+        if !longitude(self.w_long)
+            || !longitude(self.e_long)
+            || !latitude(self.n_lat)
+            || !latitude(self.s_lat)
+        {
+            return Err(TopodumpError::BoundsOutOfRange {
+                west: self.w_long,
+                north: self.n_lat,
+                east: self.e_long,
+                south: self.s_lat,
+            });
+        }
+
+        let mut fixes = Vec::new();
+        if self.w_long > self.e_long {
+            std::mem::swap(&mut self.w_long, &mut self.e_long);
+            fixes.push("header had its west and east bounds swapped".to_string());
+        }
+        if self.s_lat > self.n_lat {
+            std::mem::swap(&mut self.s_lat, &mut self.n_lat);
+            fixes.push("header had its north and south bounds swapped".to_string());
+        }
+        Ok(fixes)
+    }
+
+    /// Fails for quads without maplets or without extent, which would make
+    /// empty images or infinite pixel sizes.
+    pub fn check_degenerate(&self) -> Result<()> {
+        if self.long_count == 0 || self.lat_count == 0 {
+            return Err(TopodumpError::DegenerateQuad("it has no maplets"));
+        }
+        let (width, height) = (self.e_long - self.w_long, self.n_lat - self.s_lat);
+        if !width.is_finite() || !height.is_finite() || width == 0.0 || height == 0.0 {
+            return Err(TopodumpError::DegenerateQuad("its bounds enclose no area"));
+        }
+        Ok(())
+    }
+
+    /// The quad's extent in NAD27 longitude/latitude.
+    #[cfg(feature = "geo")]
+    pub fn bounds(&self) -> Rect<f64> {
+        Rect::new((self.w_long, self.s_lat), (self.e_long, self.n_lat))
+    }
+}
+
+pub(crate) fn read_tpq_u32(input: &mut impl Read) -> io::Result<u32> {
+    let mut buf: [u8; 4] = Default::default();
+    input.read_exact(&mut buf)?;
+    Ok(u32::from_le_bytes(buf))
+}
+
+fn read_tpq_f64(input: &mut impl Read) -> io::Result<f64> {
+    let mut buf: [u8; 8] = Default::default();
+    input.read_exact(&mut buf)?;
+    Ok(f64::from_le_bytes(buf))
+}
+
+fn read_tpq_string(input: &mut impl Read, size: usize) -> io::Result<String> {
+    let mut buf = vec![0u8; size + 1];
+    input.read_exact(&mut buf[..size])?;
+    let mut chars = Vec::<u8>::new();
+    let chars_read = Cursor::new(buf).read_until(0, &mut chars)?;
+    Ok(String::from_utf8_lossy(&chars[..min(chars_read - 1, size)]).to_string())
+}
+
+pub fn read_tpq_header(input: &mut impl Read) -> Result<TpqHeader> {
+    read_tpq_header_fields(input).map_err(TopodumpError::HeaderParse)
+}
+
+/// Like [`read_tpq_header`], but keeps the header's bytes in [`TpqHeader::raw`].
+pub fn read_tpq_header_with_raw(input: &mut impl Read) -> Result<TpqHeader> {
+    let mut raw = vec![0u8; HEADER_LEN];
+    input
+        .read_exact(&mut raw)
+        .map_err(TopodumpError::HeaderParse)?;
+    let mut header = read_tpq_header(&mut Cursor::new(&raw))?;
+    header.raw = Some(raw);
+    Ok(header)
+}
+
+fn read_tpq_header_fields(input: &mut impl Read) -> io::Result<TpqHeader> {
+    Ok(TpqHeader {
+        version: read_tpq_u32(input)?,
+        w_long: read_tpq_f64(input)?,
+        n_lat: read_tpq_f64(input)?,
+        e_long: read_tpq_f64(input)?,
+        s_lat: read_tpq_f64(input)?,
+        topo: read_tpq_string(input, 220)?,
+        quad_name: read_tpq_string(input, 128)?,
+        state_name: read_tpq_string(input, 32)?,
+        source: read_tpq_string(input, 32)?,
+        year1: read_tpq_string(input, 4)?,
+        year2: read_tpq_string(input, 4)?,
+        contour: read_tpq_string(input, 24)?,
+        extension: read_tpq_string(input, 4)?,
+        color_depth: read_tpq_u32(input)?,
+        // Skip unknown u32
+        long_count: {
+            read_tpq_u32(input)?;
+            read_tpq_u32(input)?
+        },
+        lat_count: read_tpq_u32(input)?,
+        maplet_screen_width: read_tpq_u32(input)?,
+        maplet_screen_height: read_tpq_u32(input)?,
+        raw: None,
+    })
+}
@@ -0,0 +1,155 @@
+//! ICC profiles to tag outputs with, so colour-managed viewers and print
+//! workflows render the scans consistently instead of guessing.
+
+use std::convert::Infallible;
+use std::fs;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use crate::error::{Result, TopodumpError};
+
+// sRGB's primaries and the white point, adapted to the D50 connection space
+const SRGB_RED: [f64; 3] = [0.436_074_7, 0.222_504_5, 0.013_932_2];
+const SRGB_GREEN: [f64; 3] = [0.385_064_9, 0.716_878_6, 0.097_104_5];
+const SRGB_BLUE: [f64; 3] = [0.143_080_4, 0.060_616_9, 0.714_173_3];
+const D50: [f64; 3] = [0.964_2, 1.0, 0.824_9];
+
+// Samples of the tone curve all three channels share
+const CURVE_ENTRIES: usize = 1024;
+
+// Where every profile's header says "acsp"
+const SIGNATURE_OFFSET: usize = 36;
+
+const HEADER_SIZE: usize = 128;
+
+/// A profile to tag outputs with: a built-in sRGB profile, or one read from
+/// a file.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum IccProfile {
+    Srgb,
+    Path(PathBuf),
+}
+
+impl IccProfile {
+    /// The profile's bytes, failing if a file's aren't an ICC profile.
+    pub fn bytes(&self) -> Result<Vec<u8>> {
+        match self {
+            IccProfile::Srgb => Ok(srgb()),
+            IccProfile::Path(path) => {
+                let profile = fs::read(path)?;
+                if profile.get(SIGNATURE_OFFSET..SIGNATURE_OFFSET + 4) != Some(&b"acsp"[..]) {
+                    return Err(TopodumpError::InvalidIccProfile(path.clone()));
+                }
+                Ok(profile)
+            }
+        }
+    }
+}
+
+impl FromStr for IccProfile {
+    type Err = Infallible;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        Ok(if s.eq_ignore_ascii_case("srgb") {
+            IccProfile::Srgb
+        } else {
+            IccProfile::Path(s.into())
+        })
+    }
+}
+
+fn s15_fixed16(value: f64) -> [u8; 4] {
+    ((value * 65536.0).round() as i32).to_be_bytes()
+}
+
+fn xyz(value: [f64; 3]) -> Vec<u8> {
+    let mut tag = b"XYZ \0\0\0\0".to_vec();
+    for &v in &value {
+        tag.extend_from_slice(&s15_fixed16(v));
+    }
+    tag
+}
+
+fn text_description(text: &str) -> Vec<u8> {
+    let mut tag = b"desc\0\0\0\0".to_vec();
+    tag.extend_from_slice(&(text.len() as u32 + 1).to_be_bytes());
+    tag.extend_from_slice(text.as_bytes());
+    tag.push(0);
+    // No Unicode or ScriptCode description
+    tag.extend_from_slice(&[0; 8 + 3 + 67]);
+    tag
+}
+
+// sRGB's transfer function, sampled
+fn srgb_curve() -> Vec<u8> {
+    let mut tag = b"curv\0\0\0\0".to_vec();
+    tag.extend_from_slice(&(CURVE_ENTRIES as u32).to_be_bytes());
+    for i in 0..CURVE_ENTRIES {
+        let v = i as f64 / (CURVE_ENTRIES - 1) as f64;
+        let linear = if v <= 0.040_45 {
+            v / 12.92
+        } else {
+            ((v + 0.055) / 1.055).powf(2.4)
+        };
+        tag.extend_from_slice(&((linear * 65535.0).round() as u16).to_be_bytes());
+    }
+    tag
+}
+
+/// A version 2 display profile for sRGB.
+fn srgb() -> Vec<u8> {
+    let data = [
+        text_description("sRGB"),
+        b"text\0\0\0\0No copyright, use freely\0".to_vec(),
+        xyz(D50),
+        xyz(SRGB_RED),
+        xyz(SRGB_GREEN),
+        xyz(SRGB_BLUE),
+        srgb_curve(),
+    ];
+    let tags: [(&[u8; 4], usize); 9] = [
+        (b"desc", 0),
+        (b"cprt", 1),
+        (b"wtpt", 2),
+        (b"rXYZ", 3),
+        (b"gXYZ", 4),
+        (b"bXYZ", 5),
+        (b"rTRC", 6),
+        (b"gTRC", 6),
+        (b"bTRC", 6),
+    ];
+
+    let mut profile = vec![0; HEADER_SIZE];
+    profile[8..12].copy_from_slice(&[2, 0x10, 0, 0]);
+    profile[12..16].copy_from_slice(b"mntr");
+    profile[16..20].copy_from_slice(b"RGB ");
+    profile[20..24].copy_from_slice(b"XYZ ");
+    for (i, part) in [2021u16, 1, 1, 0, 0, 0].iter().enumerate() {
+        profile[24 + i * 2..26 + i * 2].copy_from_slice(&part.to_be_bytes());
+    }
+    profile[SIGNATURE_OFFSET..SIGNATURE_OFFSET + 4].copy_from_slice(b"acsp");
+    for (i, &v) in D50.iter().enumerate() {
+        profile[68 + i * 4..72 + i * 4].copy_from_slice(&s15_fixed16(v));
+    }
+
+    // Each tag's data follows the tag table, at a multiple of four bytes
+    let mut offsets = Vec::new();
+    let mut offset = HEADER_SIZE + 4 + 12 * tags.len();
+    for tag in &data {
+        offsets.push(offset);
+        offset += (tag.len() + 3) / 4 * 4;
+    }
+    profile.extend_from_slice(&(tags.len() as u32).to_be_bytes());
+    for &(signature, index) in &tags {
+        profile.extend_from_slice(signature);
+        profile.extend_from_slice(&(offsets[index] as u32).to_be_bytes());
+        profile.extend_from_slice(&(data[index].len() as u32).to_be_bytes());
+    }
+    for tag in &data {
+        profile.extend_from_slice(tag);
+        profile.resize((profile.len() + 3) / 4 * 4, 0);
+    }
+    let size = profile.len() as u32;
+    profile[..4].copy_from_slice(&size.to_be_bytes());
+    profile
+}
@@ -0,0 +1,547 @@
+//! Tables of quad footprints and their metadata, for querying a collection
+//! with tools such as DuckDB or Spark rather than converting it.
+//!
+//! As elsewhere, NAD27 coordinates are used as-is.
+
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+#[cfg(feature = "geoparquet")]
+use std::sync::Arc;
+
+#[cfg(feature = "geoparquet")]
+use arrow::array::{ArrayRef, BinaryArray, Float64Array, StringArray, UInt32Array};
+#[cfg(feature = "geoparquet")]
+use arrow::datatypes::{DataType, Field, Schema};
+#[cfg(feature = "geoparquet")]
+use arrow::record_batch::RecordBatch;
+#[cfg(feature = "geoparquet")]
+use parquet::arrow::ArrowWriter;
+#[cfg(feature = "geoparquet")]
+use parquet::file::properties::WriterProperties;
+#[cfg(feature = "geoparquet")]
+use parquet::format::KeyValue;
+use serde_json::json;
+
+use crate::conversion::default_output;
+use crate::error::{Result, TopodumpError};
+use crate::header::TpqHeader;
+use crate::scale::Series;
+use crate::tpq::TpqFile;
+use crate::vrt::escape_xml;
+
+/// What [`Index::write`] writes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum IndexFormat {
+    GeoJson,
+    /// One row per quad, its footprint as WKT in a `geometry` column.
+    Csv,
+    /// GeoParquet 1.0, its footprint as WKB in a `geometry` column.
+    #[cfg(feature = "geoparquet")]
+    GeoParquet,
+    /// KML for Google Earth: each footprint's outline, with its name, years
+    /// and series in its balloon.
+    Kml,
+    /// An ESRI shapefile like `gdaltindex` writes, by
+    /// [`Index::write_shapefile`].
+    Shapefile,
+}
+
+impl FromStr for IndexFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "geojson" => Ok(IndexFormat::GeoJson),
+            "csv" => Ok(IndexFormat::Csv),
+            "kml" => Ok(IndexFormat::Kml),
+            "shp" | "shapefile" => Ok(IndexFormat::Shapefile),
+            #[cfg(feature = "geoparquet")]
+            "geoparquet" => Ok(IndexFormat::GeoParquet),
+            _ => Err(format!("unknown index format: {}", s)),
+        }
+    }
+}
+
+/// A quad's path, header and series.
+#[derive(Clone, Debug)]
+pub struct Footprint {
+    pub path: PathBuf,
+    pub header: TpqHeader,
+    pub series: Option<Series>,
+    /// Its converted raster, by default the GeoTIFF `convert` names after
+    /// it beside it.
+    pub location: PathBuf,
+}
+
+impl Footprint {
+    // The footprint's corners, clockwise from the north-west and closed
+    fn ring(&self) -> [(f64, f64); 5] {
+        let header = &self.header;
+        let (west, south, east, north) = (header.w_long, header.s_lat, header.e_long, header.n_lat);
+        [
+            (west, north),
+            (east, north),
+            (east, south),
+            (west, south),
+            (west, north),
+        ]
+    }
+
+    fn wkt(&self) -> String {
+        let points = self
+            .ring()
+            .iter()
+            .map(|(x, y)| format!("{} {}", x, y))
+            .collect::<Vec<_>>();
+        format!("POLYGON(({}))", points.join(", "))
+    }
+
+    #[cfg(feature = "geoparquet")]
+    fn wkb(&self) -> Vec<u8> {
+        let ring = self.ring();
+        // Little-endian, a polygon, of one ring
+        let mut wkb = vec![1];
+        wkb.extend_from_slice(&3u32.to_le_bytes());
+        wkb.extend_from_slice(&1u32.to_le_bytes());
+        wkb.extend_from_slice(&(ring.len() as u32).to_le_bytes());
+        for (x, y) in &ring {
+            wkb.extend_from_slice(&x.to_le_bytes());
+            wkb.extend_from_slice(&y.to_le_bytes());
+        }
+        wkb
+    }
+
+    fn series_name(&self) -> String {
+        self.series
+            .map_or_else(String::new, |series| series.to_string())
+    }
+}
+
+/// The footprints of a collection of quads.
+#[derive(Clone, Debug, Default)]
+pub struct Index {
+    pub footprints: Vec<Footprint>,
+    /// Inputs left out, and why.
+    pub skipped: Vec<(PathBuf, String)>,
+}
+
+/// The footprints of `inputs`. Only headers and one maplet per quad, for
+/// its series, are read.
+pub fn index(inputs: &[PathBuf]) -> Index {
+    let mut index = Index::default();
+    for input in inputs {
+        let footprint = TpqFile::open(input).and_then(|tpq| {
+            let (_, maplet_height) = tpq.maplet_dimensions()?;
+            let series = tpq.header().series(maplet_height);
+            Ok(Footprint {
+                path: input.clone(),
+                header: tpq.header().clone(),
+                series,
+                location: default_output(input, series, "tif"),
+            })
+        });
+        match footprint {
+            Ok(footprint) => index.footprints.push(footprint),
+            Err(err) => index.skipped.push((input.clone(), err.to_string())),
+        }
+    }
+    index
+}
+
+// Shapefile's code for polygons
+const SHAPE_POLYGON: i32 = 5;
+
+const SHAPEFILE_HEADER_LEN: usize = 100;
+
+// The shapefile attributes: name, dBASE type and width
+const DBF_FIELDS: [(&str, u8, usize); 8] = [
+    ("location", b'C', 254),
+    ("quad", b'C', 64),
+    ("state", b'C', 32),
+    ("year1", b'C', 4),
+    ("year2", b'C', 4),
+    ("series", b'C', 16),
+    ("columns", b'N', 10),
+    ("rows", b'N', 10),
+];
+
+// Headers give NAD27 coordinates
+const NAD27_PRJ: &str = "GEOGCS[\"GCS_North_American_1927\",DATUM[\"D_North_American_1927\",\
+    SPHEROID[\"Clarke_1866\",6378206.4,294.9786982]],PRIMEM[\"Greenwich\",0.0],\
+    UNIT[\"Degree\",0.0174532925199433]]";
+
+// The header shared by .shp and .shx files, for a file `len` bytes long
+fn shapefile_header(len: usize, bbox: [f64; 4]) -> Vec<u8> {
+    let mut header = Vec::with_capacity(SHAPEFILE_HEADER_LEN);
+    header.extend_from_slice(&9994i32.to_be_bytes());
+    header.extend_from_slice(&[0; 20]);
+    // Lengths are counted in 16-bit words
+    header.extend_from_slice(&((len / 2) as i32).to_be_bytes());
+    header.extend_from_slice(&1000i32.to_le_bytes());
+    header.extend_from_slice(&SHAPE_POLYGON.to_le_bytes());
+    for value in &bbox {
+        header.extend_from_slice(&value.to_le_bytes());
+    }
+    // No Z or M range
+    header.extend_from_slice(&[0; 32]);
+    header
+}
+
+// `text` cut to at most `width` bytes, on a character boundary
+fn truncate(text: &str, width: usize) -> &str {
+    let mut end = text.len().min(width);
+    while !text.is_char_boundary(end) {
+        end -= 1;
+    }
+    &text[..end]
+}
+
+fn dbf(footprints: &[Footprint]) -> Vec<u8> {
+    let record_len = 1 + DBF_FIELDS.iter().map(|&(_, _, width)| width).sum::<usize>();
+    let header_len = 32 + 32 * DBF_FIELDS.len() + 1;
+    // dBASE III, with no date of last update so indexes are reproducible
+    let mut dbf = vec![3, 0, 0, 0];
+    dbf.extend_from_slice(&(footprints.len() as u32).to_le_bytes());
+    dbf.extend_from_slice(&(header_len as u16).to_le_bytes());
+    dbf.extend_from_slice(&(record_len as u16).to_le_bytes());
+    dbf.extend_from_slice(&[0; 20]);
+    for &(name, kind, width) in &DBF_FIELDS {
+        let mut descriptor = [0; 32];
+        descriptor[..name.len()].copy_from_slice(name.as_bytes());
+        descriptor[11] = kind;
+        descriptor[16] = width as u8;
+        dbf.extend_from_slice(&descriptor);
+    }
+    dbf.push(0x0d);
+
+    for footprint in footprints {
+        let header = &footprint.header;
+        let values = [
+            footprint.location.display().to_string(),
+            header.quad_name.trim().to_string(),
+            header.state_name.trim().to_string(),
+            header.year1.trim().to_string(),
+            header.year2.trim().to_string(),
+            footprint.series_name(),
+            header.long_count.to_string(),
+            header.lat_count.to_string(),
+        ];
+        dbf.push(b' ');
+        for (value, &(_, kind, width)) in values.iter().zip(&DBF_FIELDS) {
+            let value = truncate(value, width);
+            let field = if kind == b'N' {
+                format!("{:>width$}", value, width = width)
+            } else {
+                format!("{:<width$}", value, width = width)
+            };
+            // Padded by characters, so cut again by bytes
+            dbf.extend_from_slice(&field.as_bytes()[..width]);
+        }
+    }
+    dbf.push(0x1a);
+    dbf
+}
+
+// Quoted if it would otherwise be misread
+fn csv_field(field: &str) -> String {
+    if field.contains(&[',', '"', '\n', '\r'][..]) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+impl Index {
+    pub fn write<W: Write + Send>(&self, format: IndexFormat, mut writer: W) -> Result<()> {
+        match format {
+            IndexFormat::GeoJson => {
+                let features = self
+                    .footprints
+                    .iter()
+                    .map(|footprint| {
+                        let header = &footprint.header;
+                        let ring = footprint
+                            .ring()
+                            .iter()
+                            .map(|&(x, y)| [x, y])
+                            .collect::<Vec<_>>();
+                        json!({
+                            "type": "Feature",
+                            "properties": {
+                                "path": footprint.path.display().to_string(),
+                                "location": footprint.location.display().to_string(),
+                                "quad": header.quad_name.trim(),
+                                "state": header.state_name.trim(),
+                                "year1": header.year1.trim(),
+                                "year2": header.year2.trim(),
+                                "series": footprint.series.map(|series| series.to_string()),
+                                "columns": header.long_count,
+                                "rows": header.lat_count,
+                            },
+                            "geometry": {
+                                "type": "Polygon",
+                                "coordinates": [ring],
+                            },
+                        })
+                    })
+                    .collect::<Vec<_>>();
+                let collection = json!({
+                    "type": "FeatureCollection",
+                    "features": features,
+                });
+                serde_json::to_writer_pretty(&mut writer, &collection)?;
+                writeln!(writer)?;
+            }
+            IndexFormat::Kml => {
+                writeln!(writer, r#"<?xml version="1.0" encoding="UTF-8"?>"#)?;
+                writeln!(writer, r#"<kml xmlns="http://www.opengis.net/kml/2.2">"#)?;
+                writeln!(writer, "<Document>")?;
+                // Outlines only, so overlapping quads stay visible
+                writeln!(
+                    writer,
+                    "<Style id=\"footprint\"><LineStyle><color>ff0000ff</color><width>2</width></LineStyle>\
+                     <PolyStyle><fill>0</fill></PolyStyle></Style>"
+                )?;
+                for footprint in &self.footprints {
+                    let header = &footprint.header;
+                    let coordinates = footprint
+                        .ring()
+                        .iter()
+                        .map(|(x, y)| format!("{},{}", x, y))
+                        .collect::<Vec<_>>();
+                    let description = format!(
+                        "<table><tr><td>State</td><td>{}</td></tr><tr><td>Years</td><td>{} {}</td></tr>\
+                         <tr><td>Series</td><td>{}</td></tr><tr><td>Raster</td><td>{}</td></tr></table>",
+                        escape_xml(header.state_name.trim()),
+                        escape_xml(header.year1.trim()),
+                        escape_xml(header.year2.trim()),
+                        footprint.series_name(),
+                        escape_xml(&footprint.location.display().to_string())
+                    );
+                    writeln!(writer, "<Placemark>")?;
+                    writeln!(
+                        writer,
+                        "<name>{}</name>",
+                        escape_xml(header.quad_name.trim())
+                    )?;
+                    writeln!(
+                        writer,
+                        "<description>{}</description>",
+                        escape_xml(&description)
+                    )?;
+                    writeln!(writer, "<styleUrl>#footprint</styleUrl>")?;
+                    writeln!(
+                        writer,
+                        "<Polygon><outerBoundaryIs><LinearRing><coordinates>{}</coordinates>\
+                         </LinearRing></outerBoundaryIs></Polygon>",
+                        coordinates.join(" ")
+                    )?;
+                    writeln!(writer, "</Placemark>")?;
+                }
+                writeln!(writer, "</Document>")?;
+                writeln!(writer, "</kml>")?;
+            }
+            IndexFormat::Csv => {
+                writeln!(
+                    writer,
+                    "path,location,quad,state,year1,year2,series,west,south,east,north,columns,rows,geometry"
+                )?;
+                for footprint in &self.footprints {
+                    let header = &footprint.header;
+                    writeln!(
+                        writer,
+                        "{},{},{},{},{},{},{},{},{},{},{},{},{},{}",
+                        csv_field(&footprint.path.display().to_string()),
+                        csv_field(&footprint.location.display().to_string()),
+                        csv_field(header.quad_name.trim()),
+                        csv_field(header.state_name.trim()),
+                        csv_field(header.year1.trim()),
+                        csv_field(header.year2.trim()),
+                        csv_field(&footprint.series_name()),
+                        header.w_long,
+                        header.s_lat,
+                        header.e_long,
+                        header.n_lat,
+                        header.long_count,
+                        header.lat_count,
+                        csv_field(&footprint.wkt())
+                    )?;
+                }
+            }
+            #[cfg(feature = "geoparquet")]
+            IndexFormat::GeoParquet => self.write_geoparquet(writer)?,
+            IndexFormat::Shapefile => {
+                return Err(TopodumpError::Unsupported(
+                    "shapefiles in one stream; write_shapefile writes their files",
+                ))
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes an ESRI shapefile of polygons at `path`, with its `.shx`,
+    /// `.dbf`, `.cpg` and `.prj`, each footprint carrying a `location`
+    /// attribute as a `gdaltindex` tile index does.
+    pub fn write_shapefile(&self, path: &Path) -> Result<()> {
+        // Header, bounding box, one part and the ring's points
+        let content_len = 4 + 32 + 4 + 4 + 4 + 16 * 5;
+        let mut shp = Vec::new();
+        let mut shx = Vec::new();
+        let mut bbox = [
+            f64::INFINITY,
+            f64::INFINITY,
+            f64::NEG_INFINITY,
+            f64::NEG_INFINITY,
+        ];
+        for (i, footprint) in self.footprints.iter().enumerate() {
+            let header = &footprint.header;
+            let footprint_bbox = [header.w_long, header.s_lat, header.e_long, header.n_lat];
+            bbox = [
+                bbox[0].min(footprint_bbox[0]),
+                bbox[1].min(footprint_bbox[1]),
+                bbox[2].max(footprint_bbox[2]),
+                bbox[3].max(footprint_bbox[3]),
+            ];
+
+            shx.extend_from_slice(&(((SHAPEFILE_HEADER_LEN + shp.len()) / 2) as i32).to_be_bytes());
+            shx.extend_from_slice(&((content_len / 2) as i32).to_be_bytes());
+            shp.extend_from_slice(&(i as i32 + 1).to_be_bytes());
+            shp.extend_from_slice(&((content_len / 2) as i32).to_be_bytes());
+            shp.extend_from_slice(&SHAPE_POLYGON.to_le_bytes());
+            for value in &footprint_bbox {
+                shp.extend_from_slice(&value.to_le_bytes());
+            }
+            let ring = footprint.ring();
+            shp.extend_from_slice(&1i32.to_le_bytes());
+            shp.extend_from_slice(&(ring.len() as i32).to_le_bytes());
+            shp.extend_from_slice(&0i32.to_le_bytes());
+            for (x, y) in &ring {
+                shp.extend_from_slice(&x.to_le_bytes());
+                shp.extend_from_slice(&y.to_le_bytes());
+            }
+        }
+        if self.footprints.is_empty() {
+            bbox = [0.0; 4];
+        }
+
+        let mut shp_file = shapefile_header(SHAPEFILE_HEADER_LEN + shp.len(), bbox);
+        shp_file.extend_from_slice(&shp);
+        let mut shx_file = shapefile_header(SHAPEFILE_HEADER_LEN + shx.len(), bbox);
+        shx_file.extend_from_slice(&shx);
+        fs::write(path.with_extension("shp"), shp_file)?;
+        fs::write(path.with_extension("shx"), shx_file)?;
+        fs::write(path.with_extension("dbf"), dbf(&self.footprints))?;
+        fs::write(path.with_extension("cpg"), "UTF-8")?;
+        fs::write(path.with_extension("prj"), NAD27_PRJ)?;
+        Ok(())
+    }
+
+    #[cfg(feature = "geoparquet")]
+    fn write_geoparquet<W: Write + Send>(&self, writer: W) -> Result<()> {
+        let footprints = &self.footprints;
+        let strings = |field: &dyn Fn(&Footprint) -> String| -> ArrayRef {
+            Arc::new(StringArray::from(
+                footprints.iter().map(field).collect::<Vec<_>>(),
+            ))
+        };
+        let floats = |field: &dyn Fn(&TpqHeader) -> f64| -> ArrayRef {
+            Arc::new(Float64Array::from(
+                footprints
+                    .iter()
+                    .map(|footprint| field(&footprint.header))
+                    .collect::<Vec<_>>(),
+            ))
+        };
+        let counts = |field: &dyn Fn(&TpqHeader) -> u32| -> ArrayRef {
+            Arc::new(UInt32Array::from(
+                footprints
+                    .iter()
+                    .map(|footprint| field(&footprint.header))
+                    .collect::<Vec<_>>(),
+            ))
+        };
+        let wkb = footprints.iter().map(Footprint::wkb).collect::<Vec<_>>();
+        let columns: Vec<ArrayRef> = vec![
+            strings(&|footprint| footprint.path.display().to_string()),
+            strings(&|footprint| footprint.location.display().to_string()),
+            strings(&|footprint| footprint.header.quad_name.trim().to_string()),
+            strings(&|footprint| footprint.header.state_name.trim().to_string()),
+            strings(&|footprint| footprint.header.year1.trim().to_string()),
+            strings(&|footprint| footprint.header.year2.trim().to_string()),
+            Arc::new(StringArray::from(
+                footprints
+                    .iter()
+                    .map(|footprint| footprint.series.map(|series| series.to_string()))
+                    .collect::<Vec<_>>(),
+            )) as ArrayRef,
+            floats(&|header| header.w_long),
+            floats(&|header| header.s_lat),
+            floats(&|header| header.e_long),
+            floats(&|header| header.n_lat),
+            counts(&|header| header.long_count),
+            counts(&|header| header.lat_count),
+            Arc::new(BinaryArray::from(
+                wkb.iter().map(Vec::as_slice).collect::<Vec<_>>(),
+            )) as ArrayRef,
+        ];
+        let schema = Arc::new(Schema::new(vec![
+            Field::new("path", DataType::Utf8, false),
+            Field::new("location", DataType::Utf8, false),
+            Field::new("quad", DataType::Utf8, false),
+            Field::new("state", DataType::Utf8, false),
+            Field::new("year1", DataType::Utf8, false),
+            Field::new("year2", DataType::Utf8, false),
+            Field::new("series", DataType::Utf8, true),
+            Field::new("west", DataType::Float64, false),
+            Field::new("south", DataType::Float64, false),
+            Field::new("east", DataType::Float64, false),
+            Field::new("north", DataType::Float64, false),
+            Field::new("columns", DataType::UInt32, false),
+            Field::new("rows", DataType::UInt32, false),
+            Field::new("geometry", DataType::Binary, false),
+        ]));
+        let batch = RecordBatch::try_new(schema.clone(), columns)?;
+
+        let bbox = footprints.iter().fold(
+            [
+                f64::INFINITY,
+                f64::INFINITY,
+                f64::NEG_INFINITY,
+                f64::NEG_INFINITY,
+            ],
+            |[west, south, east, north], footprint| {
+                let header = &footprint.header;
+                [
+                    west.min(header.w_long),
+                    south.min(header.s_lat),
+                    east.max(header.e_long),
+                    north.max(header.n_lat),
+                ]
+            },
+        );
+        let mut geometry = json!({
+            "encoding": "WKB",
+            "geometry_types": ["Polygon"],
+        });
+        if !footprints.is_empty() {
+            geometry["bbox"] = json!(bbox);
+        }
+        let geo = json!({
+            "version": "1.0.0",
+            "primary_column": "geometry",
+            "columns": {"geometry": geometry},
+        });
+        let properties = WriterProperties::builder()
+            .set_key_value_metadata(Some(vec![KeyValue::new(
+                "geo".to_string(),
+                geo.to_string(),
+            )]))
+            .build();
+        let mut writer = ArrowWriter::try_new(writer, schema, Some(properties))?;
+        writer.write(&batch)?;
+        writer.close()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,83 @@
+//! Converts National Geographic TOPO! `.tpq` quads into georeferenced imagery.
+//!
+//! # Threads
+//!
+//! The library keeps no global state of its own and never changes the
+//! working directory, so independent conversions can run concurrently.
+//! [`TpqFile`] is `Send + Sync` and can be shared to decode maplets from
+//! several threads; [`Conversion`] is `Send`. GDAL is registered once behind
+//! a lock, its error messages are per thread, and in-memory conversions use
+//! uniquely named `/vsimem/` files. Two conversions writing the same output
+//! path will still race.
+
+mod adjust;
+#[cfg(feature = "tokio")]
+pub mod async_batch;
+pub mod batch;
+pub mod cache;
+pub mod cancel;
+#[cfg(feature = "mbtiles")]
+pub mod catalog;
+pub mod checksum;
+pub mod conversion;
+pub mod coverage;
+#[cfg(feature = "geo")]
+mod cutline;
+mod disk;
+pub mod error;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+pub mod gallery;
+#[cfg(feature = "gdal")]
+mod gdal_util;
+pub mod georef;
+#[cfg(feature = "mbtiles")]
+pub mod gpkg;
+#[cfg(feature = "gui")]
+pub mod gui;
+pub mod header;
+pub mod icc;
+pub mod index;
+pub mod maplet;
+pub mod memory;
+pub mod mosaic;
+pub mod output;
+mod overlay;
+mod palette;
+mod pixels;
+pub mod postgis;
+pub mod progress;
+pub mod publish;
+#[cfg(feature = "python")]
+mod python;
+pub mod qgis;
+pub mod scale;
+pub mod source;
+pub mod stac;
+pub mod tiles;
+pub mod timings;
+pub mod tpq;
+#[cfg(feature = "tui")]
+pub mod tui;
+#[cfg(feature = "object-store")]
+pub mod upload;
+pub mod verify;
+pub mod vrt;
+#[cfg(all(feature = "wasm", target_arch = "wasm32"))]
+pub mod wasm;
+
+pub use cancel::CancelToken;
+pub use conversion::{
+    Compression, Conversion, ConversionSummary, Corner, Format, Quality, Resampling,
+};
+pub use error::{Result, TopodumpError};
+pub use georef::GeoRef;
+pub use header::TpqHeader;
+pub use icc::IccProfile;
+pub use maplet::MapletDecoder;
+pub use memory::{convert_to_vec, ConvertOptions};
+pub use output::OutputWriter;
+pub use progress::Progress;
+pub use scale::Series;
+pub use timings::Timings;
+pub use tpq::TpqFile;
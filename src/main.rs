@@ -1,122 +1,45 @@
-use std::cmp::min;
-use std::fs::{remove_file, File};
-use std::io::{BufRead, Cursor, Read};
-use std::path::Path;
-
-use clap::{crate_authors, crate_version, Clap};
-use gdal::{spatial_ref::SpatialRef, Dataset, DatasetOptions, GdalOpenFlags};
-use image::{
-    imageops, io::Reader as ImageReader, DynamicImage, GenericImageView, ImageFormat, Rgba,
-    RgbaImage,
-};
+#[cfg(any(feature = "gui", feature = "object-store"))]
+use std::env;
+use std::ffi::OsString;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
 
-type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
-
-#[derive(Debug)]
-struct TpqHeader {
-    version: u32,
-    w_long: f64,
-    n_lat: f64,
-    e_long: f64,
-    s_lat: f64,
-    topo: String,
-    quad_name: String,
-    state_name: String,
-    source: String,
-    year1: String,
-    year2: String,
-    contour: String,
-    extension: String,
-    color_depth: u32,
-    long_count: u32,
-    lat_count: u32,
-    maplet_screen_width: u32,
-    maplet_screen_height: u32,
-}
-
-fn read_tpq_u32(input: &mut impl Read) -> Result<u32> {
-    let mut buf: [u8; 4] = Default::default();
-    input.read_exact(&mut buf)?;
-    Ok(u32::from_le_bytes(buf))
-}
-
-fn read_tpq_f64(input: &mut impl Read) -> Result<f64> {
-    let mut buf: [u8; 8] = Default::default();
-    input.read_exact(&mut buf)?;
-    Ok(f64::from_le_bytes(buf))
-}
-
-fn read_tpq_string(input: &mut impl Read, size: usize) -> Result<String> {
-    let mut buf = vec![0u8; size + 1];
-    input.read_exact(&mut buf[..size])?;
-    let mut chars = Vec::<u8>::new();
-    let chars_read = Cursor::new(buf).read_until(0, &mut chars)?;
-    Ok(String::from_utf8_lossy(&chars[..min(chars_read - 1, size)]).to_string())
-}
-
-fn read_tpq_header(input: &mut impl Read) -> Result<TpqHeader> {
-    Ok(TpqHeader {
-        version: read_tpq_u32(input)?,
-        w_long: read_tpq_f64(input)?,
-        n_lat: read_tpq_f64(input)?,
-        e_long: read_tpq_f64(input)?,
-        s_lat: read_tpq_f64(input)?,
-        topo: read_tpq_string(input, 220)?,
-        quad_name: read_tpq_string(input, 128)?,
-        state_name: read_tpq_string(input, 32)?,
-        source: read_tpq_string(input, 32)?,
-        year1: read_tpq_string(input, 4)?,
-        year2: read_tpq_string(input, 4)?,
-        contour: read_tpq_string(input, 24)?,
-        extension: read_tpq_string(input, 4)?,
-        color_depth: read_tpq_u32(input)?,
-        // Skip unknown u32
-        long_count: {
-            read_tpq_u32(input)?;
-            read_tpq_u32(input)?
-        },
-        lat_count: read_tpq_u32(input)?,
-        maplet_screen_width: read_tpq_u32(input)?,
-        maplet_screen_height: read_tpq_u32(input)?,
-    })
-}
-
-fn set_geo_data<P: AsRef<Path>>(
-    path: P,
-    header: &TpqHeader,
-    width: f64,
-    height: f64,
-) -> Result<()> {
-    let dataset = Dataset::open_ex(
-        path.as_ref(),
-        DatasetOptions {
-            open_flags: GdalOpenFlags::GDAL_OF_UPDATE,
-            allowed_drivers: None,
-            open_options: None,
-            sibling_files: None,
-        },
-    )?;
-
-    let spatial_ref = r#"GEODCRS["NAD 27",
-    DATUM["North American Datum of 1927",
-        ELLIPSOID["NAD 27", 6378206.4, 294.978698214, LENGTHUNIT["metre", 1]]],
-    CS[ellipsoidal, 2],
-        AXIS["Latitude (lat)", north, ORDER[1]],
-        AXIS["Longitude (lon)", east, ORDER[2]],
-        ANGLEUNIT["degree", 0.0174532925199433]]"#;
-
-    dataset.set_spatial_ref(&SpatialRef::from_wkt(&spatial_ref)?)?;
-    dataset.set_geo_transform(&[
-        header.w_long,
-        (header.e_long - header.w_long) / width,
-        0.0,
-        header.n_lat,
-        0.0,
-        -(header.n_lat - header.s_lat) / height,
-    ])?;
+use clap::{crate_authors, crate_version, Clap, IntoApp};
+use image::GenericImageView;
 
-    Ok(())
-}
+use serde::Serialize;
+
+use topodump::batch::{find_tpq_files, mirrored_dir, prefetch};
+use topodump::cache::{ConversionCache, CACHE_FILE_NAME};
+#[cfg(feature = "mbtiles")]
+use topodump::catalog::{Catalog, Status};
+use topodump::coverage::coverage;
+use topodump::gallery::write_gallery;
+#[cfg(feature = "mbtiles")]
+use topodump::gpkg::GeoPackageBuild;
+use topodump::index::{index, IndexFormat};
+use topodump::maplet::{decoder_by_name, DECODERS};
+use topodump::mosaic::{
+    deduplicate, group_by_series, mosaic_inputs, quads_in_extent, series_output, Mosaic, Overlap,
+};
+use topodump::output::{ExistingTiles, Thumbnail};
+use topodump::postgis::postgis_sql;
+use topodump::publish::{mapserver_layers, write_geoserver_mosaic};
+use topodump::qgis::write_qgis_project;
+use topodump::stac::write_stac_catalog;
+#[cfg(feature = "object-store")]
+use topodump::upload::Uploader;
+use topodump::verify::{seams, Edge};
+use topodump::vrt::{batch_vrt, tpq_vrt};
+use topodump::{
+    Compression, Conversion, ConversionSummary, Corner, Format, IccProfile, Progress, Quality,
+    Resampling, Result, Series, Timings, TopodumpError, TpqFile, TpqHeader,
+};
 
 #[derive(Clap)]
 #[clap(
@@ -125,73 +48,1882 @@ fn set_geo_data<P: AsRef<Path>>(
     version = crate_version!()
 )]
 struct Args {
-    input: String,
-    output: String,
+    #[clap(subcommand)]
+    command: Command,
 }
 
-fn main() -> Result<()> {
-    let args = Args::parse();
+#[derive(Clap)]
+enum Command {
+    #[clap(about = "Convert tpq files to GeoTIFF")]
+    Convert(ConvertArgs),
+    #[clap(about = "Print information about a tpq file")]
+    Info(InfoArgs),
+    #[clap(about = "Write a GDAL VRT that reads a tpq file in place")]
+    Vrt(VrtArgs),
+    #[clap(about = "Merge tpq files into one output per series")]
+    Mosaic(MosaicArgs),
+    #[clap(about = "Check tpq files for corrupt offsets and maplets")]
+    Verify(VerifyArgs),
+    #[clap(about = "Report the standard quad cells a collection covers and those it's missing")]
+    Coverage(CoverageArgs),
+    #[clap(
+        about = "List the footprints and metadata of tpq files as GeoJSON, CSV, KML, shapefiles or GeoParquet"
+    )]
+    Index(IndexArgs),
+    #[cfg(feature = "mbtiles")]
+    #[clap(about = "Tile every series on a TOPO! disc into one multi-scale GeoPackage")]
+    BuildGpkg(BuildGpkgArgs),
+    #[cfg(feature = "tui")]
+    #[clap(about = "Browse tpq files in the terminal, converting, extracting or verifying them")]
+    Tui(TuiArgs),
+    #[cfg(feature = "gui")]
+    #[clap(about = "Open a window to convert tpq files dropped onto it")]
+    Gui,
+}
 
-    let mut tpq_data = Vec::<u8>::new();
-    File::open(&args.input)?.read_to_end(&mut tpq_data)?;
-    let mut cursor = Cursor::new(&tpq_data);
+#[derive(Clap)]
+struct ConvertArgs {
+    #[clap(
+        parse(from_os_str),
+        about = "A tpq file, or a directory to convert every tpq file under"
+    )]
+    input: PathBuf,
+    #[clap(
+        parse(from_os_str),
+        about = "Defaults to <input stem>_<series>.<format extension>, or the input directory; s3://, gcs:// or az:// URLs upload outputs as they're written"
+    )]
+    output: Option<PathBuf>,
+    #[clap(
+        long,
+        about = "gtiff, cog, png or mbtiles; defaults to the output's extension, or gtiff"
+    )]
+    format: Option<Format>,
+    #[clap(long, default_value = "none", about = "none, deflate, lzw or jpeg")]
+    compression: Compression,
+    #[clap(
+        long,
+        value_name = "1-100",
+        number_of_values = 1,
+        parse(try_from_str = parse_quality),
+        about = "JPEG quality when re-encoding lossily; FORMAT=N, e.g. cog=90, sets it for one format"
+    )]
+    quality: Vec<(Option<Format>, u8)>,
+    #[clap(
+        long,
+        default_value = "bilinear",
+        about = "nearest, bilinear, cubic or lanczos, wherever imagery is resized or warped"
+    )]
+    resampling: Resampling,
+    #[clap(
+        long,
+        value_name = "COLORS",
+        about = "Write paletted PNG or GeoTIFF output of at most this many colours, up to 256"
+    )]
+    quantize: Option<u16>,
+    #[clap(long, about = "Write a single gray band instead of RGB")]
+    grayscale: bool,
+    #[clap(
+        long,
+        about = "Stretch each colour channel to the full range, for faded scans"
+    )]
+    auto_levels: bool,
+    #[clap(
+        long,
+        default_value = "0",
+        allow_hyphen_values = true,
+        about = "Lighten, or darken when negative, by up to 1, the full range"
+    )]
+    brightness: f64,
+    #[clap(
+        long,
+        default_value = "1",
+        about = "Scale each channel's distance from mid-gray"
+    )]
+    contrast: f64,
+    #[clap(
+        long,
+        default_value = "1",
+        about = "Lighten the midtones above 1, darken them below"
+    )]
+    gamma: f64,
+    #[clap(
+        long,
+        value_name = "AMOUNT",
+        about = "Sharpen the composited image by unsharp masking, by AMOUNT or 1"
+    )]
+    sharpen: Option<Option<f32>>,
+    #[clap(
+        long,
+        about = "Smooth over JPEG block edges in heavily compressed maplets before re-encoding"
+    )]
+    deblock: bool,
+    #[clap(
+        long,
+        value_name = "RRGGBB",
+        parse(try_from_str = parse_colour),
+        about = "Leave pixels of this colour transparent, e.g. a background fill, adding an alpha band"
+    )]
+    transparent_color: Option<[u8; 3]>,
+    #[clap(
+        long,
+        value_name = "N",
+        default_value = "0",
+        about = "How far each channel may stray from --transparent-color, in levels"
+    )]
+    tolerance: u8,
+    #[clap(long, about = "Draw the quad's neatline over the output")]
+    draw_bounds: bool,
+    #[clap(
+        long,
+        value_name = "DEGREES",
+        about = "Draw a line every this many degrees of longitude and latitude over the output"
+    )]
+    graticule: Option<f64>,
+    #[clap(
+        long,
+        value_name = "RRGGBB",
+        parse(try_from_str = parse_colour),
+        default_value = "ff0000",
+        about = "The colour of --draw-bounds and --graticule lines"
+    )]
+    overlay_color: [u8; 3],
+    #[clap(
+        long,
+        value_name = "TEXT",
+        about = "Stamp this text, e.g. an attribution, into a corner of the output"
+    )]
+    stamp: Option<String>,
+    #[clap(
+        long,
+        value_name = "CORNER",
+        default_value = "bottom-right",
+        about = "Where --stamp goes: top-left, top-right, bottom-left or bottom-right"
+    )]
+    stamp_corner: Corner,
+    #[clap(long, about = "Keep an alpha band instead of writing RGB")]
+    alpha: bool,
+    #[clap(
+        long,
+        value_name = "N",
+        about = "Write a preview at 1/N resolution, where N is 2, 4 or 8, decoding maplets at that scale"
+    )]
+    preview: Option<u16>,
+    #[clap(
+        long,
+        value_name = "PIXELS",
+        about = "Downscale the output until neither side is longer than this"
+    )]
+    max_size: Option<u32>,
+    #[clap(
+        long,
+        value_name = "PATH|auto",
+        about = "Also write a quick-look JPEG or PNG up to 1024 pixels wide; auto puts <name>_thumb.jpg beside each output"
+    )]
+    thumbnail: Option<Thumbnail>,
+    #[clap(
+        long,
+        value_name = "srgb|PATH",
+        about = "Tag GeoTIFF, COG and PNG output with the sRGB ICC profile or one read from a file"
+    )]
+    icc_profile: Option<IccProfile>,
+    #[clap(
+        long,
+        value_name = "DIR",
+        about = "Also write tiles and a Leaflet index.html for viewing the output in a browser, in DIR/<name> for a directory"
+    )]
+    html_preview: Option<PathBuf>,
+    #[clap(
+        long,
+        about = "Also describe the --html-preview tiles in a GDAL WMS wms.xml beside its index.html"
+    )]
+    wms_xml: bool,
+    #[clap(
+        long,
+        value_name = "URL",
+        about = "Where the --html-preview directory will be served, for --wms-xml to fetch tiles from"
+    )]
+    tile_url: Option<String>,
+    #[clap(
+        long,
+        value_name = "DIR",
+        about = "Also add the output's tiles to the {z}/{x}/{y}.png tile cache in DIR"
+    )]
+    tile_cache: Option<PathBuf>,
+    #[clap(
+        long,
+        value_name = "keep|merge|overwrite",
+        default_value = "merge",
+        about = "What --tile-cache does with tiles it already has: leave them, draw new ones over them or replace them"
+    )]
+    existing_tiles: ExistingTiles,
+    #[clap(long, about = "Number --tile-cache rows from the south, as TMS does")]
+    tms: bool,
+    #[clap(
+        long = "t_srs",
+        value_name = "SRS",
+        about = "Reproject GeoTIFF or COG output, e.g. to EPSG:3857"
+    )]
+    t_srs: Option<String>,
+    #[clap(
+        long,
+        value_name = "TILE_SIZE",
+        about = "Reproject to Web Mercator, padded so the pixels line up with tiles of this size"
+    )]
+    align_to_grid: Option<u32>,
+    #[clap(
+        long,
+        value_name = "Z",
+        about = "The zoom --align-to-grid lines up with, by default the quad's native zoom"
+    )]
+    align_zoom: Option<u8>,
+    #[clap(
+        long,
+        default_value = "image",
+        about = "JPEG decoder to use for maplets: image, or zune with the zune-jpeg feature"
+    )]
+    decoder: String,
+    #[clap(
+        long,
+        parse(from_os_str),
+        about = "Write a QGIS project (.qgs) or layer definition (.qlr) loading the outputs"
+    )]
+    qgis_project: Option<PathBuf>,
+    #[clap(
+        long,
+        parse(from_os_str),
+        about = "Write a GDAL VRT mosaicking every GeoTIFF output"
+    )]
+    vrt: Option<PathBuf>,
+    #[clap(
+        long,
+        parse(from_os_str),
+        about = "Write an HTML page cataloguing the outputs, with their thumbnails if --thumbnail auto"
+    )]
+    gallery: Option<PathBuf>,
+    #[clap(
+        long,
+        value_name = "DIR",
+        parse(from_os_str),
+        about = "Write a STAC catalog of the outputs, with a collection per state and scale"
+    )]
+    stac_catalog: Option<PathBuf>,
+    #[clap(
+        long,
+        value_name = "PATH",
+        parse(from_os_str),
+        about = "Write SQL registering every GeoTIFF output in PostGIS as an out-db raster"
+    )]
+    postgis_sql: Option<PathBuf>,
+    #[clap(
+        long,
+        value_name = "TABLE",
+        default_value = "topo_quads",
+        about = "The table --postgis-sql creates and inserts into"
+    )]
+    postgis_table: String,
+    #[clap(
+        long,
+        value_name = "PATH",
+        parse(from_os_str),
+        about = "Write a MapServer LAYER for every GeoTIFF output, grouped under --layer-name"
+    )]
+    mapserver_layers: Option<PathBuf>,
+    #[clap(
+        long,
+        about = "Write a GeoServer ImageMosaic indexer.properties named --layer-name beside the outputs"
+    )]
+    geoserver_mosaic: bool,
+    #[clap(
+        long,
+        value_name = "NAME",
+        default_value = "topodump",
+        about = "The layer --mapserver-layers and --geoserver-mosaic publish"
+    )]
+    layer_name: String,
+    #[clap(
+        long,
+        value_name = "MB",
+        about = "Keep memory use under this many megabytes, decoding fewer maplets at once"
+    )]
+    max_memory: Option<u64>,
+    #[clap(
+        long,
+        value_name = "PIXELS",
+        about = "Refuse quads whose image would have more pixels than this"
+    )]
+    max_pixels: Option<u64>,
+    #[clap(
+        long,
+        about = "Convert this many files of a directory at once; defaults to the CPU count"
+    )]
+    jobs: Option<usize>,
+    #[clap(
+        long,
+        about = "Skip files of a directory whose contents were already converted with these settings"
+    )]
+    incremental: bool,
+    #[cfg(feature = "mbtiles")]
+    #[clap(
+        long,
+        value_name = "PATH",
+        parse(from_os_str),
+        about = "Record every input processed in this SQLite database, also consulted by --incremental"
+    )]
+    catalog: Option<PathBuf>,
+    #[clap(
+        long,
+        value_name = "COMMAND",
+        about = "Run this shell command after each file converts, with {input} and {output} replaced and TOPODUMP_* variables set"
+    )]
+    on_success: Option<String>,
+    #[clap(
+        long,
+        value_name = "COMMAND",
+        about = "Run this shell command after each file fails to convert, with {input} replaced and TOPODUMP_ERROR set"
+    )]
+    on_failure: Option<String>,
+    #[clap(
+        long,
+        default_value = "0",
+        value_name = "N",
+        about = "Read up to this many files of a directory ahead of converting them, for slow or network storage"
+    )]
+    prefetch: usize,
+    #[clap(
+        long,
+        about = "Report the time spent in each stage of every conversion"
+    )]
+    timings: bool,
+    #[clap(
+        long,
+        about = "Keep a GeoTIFF that couldn't be georeferenced instead of removing it; always on for directories"
+    )]
+    keep_partial: bool,
+    #[clap(
+        long,
+        about = "Fail on maplets of inconsistent size or shared between cells instead of warning"
+    )]
+    strict: bool,
+    #[clap(
+        long,
+        about = "Compare a few maplets of each GeoTIFF with the tpq after writing it"
+    )]
+    verify_output: bool,
+    #[clap(
+        long,
+        about = "Print each output's SHA-256 in sha256sum's format, for fixity checks"
+    )]
+    checksum: bool,
+    #[clap(
+        long,
+        about = "Write each band's histogram and statistics to a .aux.xml beside the output"
+    )]
+    stats: bool,
+    #[cfg(feature = "mmap")]
+    #[clap(
+        long,
+        about = "Memory-map inputs; they must not change during conversion"
+    )]
+    mmap: bool,
+    #[clap(short, long, about = "Don't print progress")]
+    quiet: bool,
+}
 
-    let header = read_tpq_header(&mut cursor)?;
+#[derive(Clap)]
+struct InfoArgs {
+    #[clap(parse(from_os_str))]
+    input: PathBuf,
+    #[clap(long, about = "Print as JSON")]
+    json: bool,
+    #[clap(long, about = "Include the raw header bytes in JSON output")]
+    raw: bool,
+}
 
-    cursor.set_position(1024);
+#[derive(Clap)]
+struct VrtArgs {
+    #[clap(parse(from_os_str))]
+    input: PathBuf,
+    #[clap(parse(from_os_str), about = "Defaults to <input>.vrt")]
+    output: Option<PathBuf>,
+}
 
-    let mut read_jpg = || -> Result<DynamicImage> {
-        let maplet_offset = read_tpq_u32(&mut cursor)? as usize;
-        let maplet_cursor = Cursor::new(&tpq_data[maplet_offset..]);
-        Ok(ImageReader::with_format(maplet_cursor, ImageFormat::Jpeg).decode()?)
-    };
+#[derive(Clap)]
+struct MosaicArgs {
+    #[clap(
+        parse(from_os_str),
+        required = true,
+        about = "Tpq files, or directories to include every tpq file under"
+    )]
+    inputs: Vec<PathBuf>,
+    #[clap(short, long, parse(from_os_str))]
+    output: PathBuf,
+    #[clap(
+        long,
+        about = "gtiff, cog or png; defaults to the output's extension, or gtiff"
+    )]
+    format: Option<Format>,
+    #[clap(long, default_value = "none", about = "none, deflate, lzw or jpeg")]
+    compression: Compression,
+    #[clap(
+        long,
+        value_name = "1-100",
+        number_of_values = 1,
+        parse(try_from_str = parse_quality),
+        about = "JPEG quality when re-encoding lossily; FORMAT=N, e.g. cog=90, sets it for one format"
+    )]
+    quality: Vec<(Option<Format>, u8)>,
+    #[clap(
+        long,
+        default_value = "bilinear",
+        about = "nearest, bilinear, cubic or lanczos, wherever imagery is resized or warped"
+    )]
+    resampling: Resampling,
+    #[clap(long, about = "Leave areas no quad covers transparent")]
+    alpha: bool,
+    #[clap(
+        long,
+        value_name = "W,S,E,N",
+        parse(try_from_str = parse_extent),
+        allow_hyphen_values = true,
+        about = "Only mosaic the inputs' quads intersecting these bounds in degrees"
+    )]
+    extent: Option<(f64, f64, f64, f64)>,
+    #[clap(
+        long,
+        default_value = "last",
+        about = "Which quad to keep where quads overlap: first, last, newest or highest-res"
+    )]
+    overlap: Overlap,
+    #[clap(
+        long,
+        default_value = "newest",
+        about = "Which copy to keep of a quad given more than once: first, last, newest or highest-res"
+    )]
+    duplicates: Overlap,
+    #[clap(
+        long,
+        default_value = "0",
+        value_name = "PIXELS",
+        about = "Feather overlapping quads into each other over this many pixels at their edges"
+    )]
+    blend: u32,
+    #[clap(
+        long,
+        about = "Crop each quad to its neatline, leaving out white map collars and marginalia"
+    )]
+    trim_collar: bool,
+    #[clap(
+        long,
+        value_name = "WIDTHxHEIGHT",
+        parse(try_from_str = parse_tile_size),
+        about = "Write GeoTIFF tiles of this many pixels named after the output, with a GeoJSON index"
+    )]
+    retile: Option<(u32, u32)>,
+    #[clap(
+        long,
+        value_name = "PIXELS",
+        about = "Rows written at a time, bounding how many quads are open at once"
+    )]
+    stripe_height: Option<u32>,
+    #[clap(
+        long,
+        about = "Continue a retiled mosaic from the tiles an interrupted run finished"
+    )]
+    resume: bool,
+    #[clap(short, long, about = "Don't print progress")]
+    quiet: bool,
+}
+
+#[derive(Clap)]
+struct VerifyArgs {
+    #[clap(
+        parse(from_os_str),
+        required = true,
+        about = "Tpq files, or directories to check every tpq file under"
+    )]
+    inputs: Vec<PathBuf>,
+    #[clap(
+        long,
+        about = "Also report maplet boundaries that stand out from the imagery around them"
+    )]
+    seams: bool,
+}
+
+#[derive(Clap)]
+struct CoverageArgs {
+    #[clap(
+        parse(from_os_str),
+        required = true,
+        about = "Tpq files, or directories to include every tpq file under"
+    )]
+    inputs: Vec<PathBuf>,
+}
+
+#[derive(Clap)]
+struct IndexArgs {
+    #[clap(
+        parse(from_os_str),
+        required = true,
+        about = "Tpq files, or directories to include every tpq file under"
+    )]
+    inputs: Vec<PathBuf>,
+    #[clap(
+        short,
+        long,
+        parse(from_os_str),
+        about = "Where to write the index; defaults to standard output, except for shapefiles"
+    )]
+    output: Option<PathBuf>,
+    #[clap(
+        long,
+        default_value = "geojson",
+        about = "geojson, csv, kml, shp, or geoparquet with the geoparquet feature"
+    )]
+    format: IndexFormat,
+    #[clap(
+        long,
+        value_name = "DIR",
+        parse(from_os_str),
+        about = "Where a directory was converted, for each quad's location; defaults to beside it"
+    )]
+    converted: Option<PathBuf>,
+    #[clap(
+        long,
+        default_value = "tif",
+        about = "The extension of the converted rasters, for each quad's location"
+    )]
+    extension: String,
+}
+
+#[cfg(feature = "mbtiles")]
+#[derive(Clap)]
+struct BuildGpkgArgs {
+    #[clap(
+        parse(from_os_str),
+        about = "A TOPO! disc, or any directory to include every tpq file under"
+    )]
+    root: PathBuf,
+    #[clap(short, long, parse(from_os_str))]
+    output: PathBuf,
+    #[clap(
+        long,
+        default_value = "bilinear",
+        about = "nearest, bilinear, cubic or lanczos, as tiles are resampled"
+    )]
+    resampling: Resampling,
+}
+
+#[cfg(feature = "tui")]
+#[derive(Clap)]
+struct TuiArgs {
+    #[clap(
+        parse(from_os_str),
+        required = true,
+        about = "Tpq files, or directories to browse every tpq file under"
+    )]
+    inputs: Vec<PathBuf>,
+}
+
+#[derive(Serialize)]
+struct InfoReport<'a> {
+    #[serde(flatten)]
+    header: &'a TpqHeader,
+    maplet_width: u32,
+    maplet_height: u32,
+    width: u32,
+    height: u32,
+    series: Option<Series>,
+}
 
-    let first_maplet = read_jpg()?;
-    let (maplet_width, maplet_height) = first_maplet.dimensions();
+fn info(args: InfoArgs) -> Result<()> {
+    let tpq = TpqFile::open(&args.input)?;
+    let mut header = tpq.header().clone();
+    let (maplet_width, maplet_height) = tpq.maplet(0, 0)?.dimensions();
+    let (width, height) = header.image_size((maplet_width, maplet_height), u64::MAX)?;
 
-    let mut collage_img = RgbaImage::from_pixel(
-        header.long_count * maplet_width,
-        header.lat_count * maplet_height,
-        Rgba([255, 255, 255, 255]),
+    if args.json {
+        if args.raw {
+            header.raw = Some(tpq.raw_header().to_vec());
+        }
+        let report = InfoReport {
+            header: &header,
+            maplet_width,
+            maplet_height,
+            width,
+            height,
+            series: header.series(maplet_height),
+        };
+        println!("{}", serde_json::to_string_pretty(&report)?);
+        return Ok(());
+    }
+
+    let text = |field: &str| field.trim_matches(char::from(0)).trim().to_string();
+    println!(
+        "File:        {} ({} bytes)",
+        args.input.display(),
+        fs::metadata(&args.input)?.len()
+    );
+    println!("Quad:        {}", text(&header.quad_name));
+    println!("State:       {}", text(&header.state_name));
+    println!("Source:      {}", text(&header.source));
+    let years = [text(&header.year1), text(&header.year2)];
+    if years[1].is_empty() || years[1] == years[0] {
+        println!("Years:       {}", years[0]);
+    } else {
+        println!("Years:       {}, {}", years[0], years[1]);
+    }
+    println!("Contour:     {}", text(&header.contour));
+    match header.series(maplet_height) {
+        Some(series) => println!("Scale:       {} ({} series)", series, series.slug()),
+        None => println!("Scale:       unknown"),
+    }
+    println!("Datum:       NAD27 longitude/latitude");
+    println!(
+        "Maplets:     {} x {} of {} x {} px ({} in all)",
+        header.long_count,
+        header.lat_count,
+        maplet_width,
+        maplet_height,
+        header.long_count as u64 * header.lat_count as u64
+    );
+    println!(
+        "Output size: {} x {} px, {:.1} MB as uncompressed RGB",
+        width,
+        height,
+        width as f64 * height as f64 * 3.0 / 1e6
+    );
+    let (dx, dy) = (
+        (header.e_long - header.w_long) / width as f64,
+        (header.n_lat - header.s_lat) / height as f64,
     );
+    let latitude = (header.n_lat + header.s_lat) / 2.0;
+    println!(
+        "Pixel size:  {:.9} x {:.9} degrees, about {:.2} x {:.2} m",
+        dx,
+        dy,
+        dx * METRES_PER_DEGREE * latitude.to_radians().cos(),
+        dy * METRES_PER_DEGREE
+    );
+    println!("Corner coordinates:");
+    let corners = [
+        ("Upper left", header.w_long, header.n_lat),
+        ("Lower left", header.w_long, header.s_lat),
+        ("Upper right", header.e_long, header.n_lat),
+        ("Lower right", header.e_long, header.s_lat),
+        (
+            "Centre",
+            (header.w_long + header.e_long) / 2.0,
+            (header.n_lat + header.s_lat) / 2.0,
+        ),
+    ];
+    for (name, long, lat) in &corners {
+        println!(
+            "  {:<12}({:>12.7}, {:>11.7}) ({}, {})",
+            name,
+            long,
+            lat,
+            dms(*long, 'E', 'W'),
+            dms(*lat, 'N', 'S')
+        );
+    }
+
+    Ok(())
+}
+
+// Roughly, along a meridian
+const METRES_PER_DEGREE: f64 = 111_320.0;
+
+// As gdalinfo prints them, e.g. 120d 7'30.00"W
+fn dms(degrees: f64, positive: char, negative: char) -> String {
+    let hemisphere = if degrees < 0.0 { negative } else { positive };
+    // Rounded to the hundredths of a second printed, so 59.999" carries over
+    let hundredths = (degrees.abs() * 360_000.0).round() as u64;
+    format!(
+        "{}d{:>2}'{:>5.2}\"{}",
+        hundredths / 360_000,
+        hundredths / 6000 % 60,
+        (hundredths % 6000) as f64 / 100.0,
+        hemisphere
+    )
+}
+
+fn progress_bar() -> impl FnMut(Progress) + Send {
+    const WIDTH: u32 = 40;
+    let mut decoded = 0;
+    move |progress| match progress {
+        Progress::HeaderParsed { .. } => {}
+        Progress::MapletDecoded { total, .. } => {
+            decoded += 1;
+            let filled = (decoded * WIDTH / total.max(1)) as usize;
+            eprint!(
+                "\r[{}{}] {}/{} maplets",
+                "#".repeat(filled),
+                " ".repeat(WIDTH as usize - filled),
+                decoded,
+                total
+            );
+        }
+        Progress::OutputWritten { path } => eprintln!("\nWrote {}", path.display()),
+    }
+}
+
+fn build_conversion(args: &ConvertArgs, input: &Path, jobs: usize) -> Result<Conversion> {
+    let decoder = decoder_by_name(&args.decoder).ok_or_else(|| TopodumpError::UnknownDecoder {
+        name: args.decoder.clone(),
+        available: DECODERS.join(", "),
+    })?;
+    let mut conversion = Conversion::new(input)
+        .compression(args.compression)
+        .alpha(args.alpha)
+        .grayscale(args.grayscale)
+        .draw_bounds(args.draw_bounds)
+        .overlay_colour(args.overlay_color)
+        .stamp_corner(args.stamp_corner)
+        .auto_levels(args.auto_levels)
+        .brightness(args.brightness)
+        .contrast(args.contrast)
+        .gamma(args.gamma)
+        .deblock(args.deblock)
+        .strict(args.strict)
+        .verify_output(args.verify_output)
+        .checksum(args.checksum)
+        .stats(args.stats)
+        .quality(quality(&args.quality))
+        .resampling(args.resampling)
+        .decoder(decoder);
+    if let Some(format) = args.format {
+        conversion = conversion.format(format);
+    }
+    #[cfg(feature = "mmap")]
+    {
+        conversion = conversion.mmap(args.mmap);
+    }
+    if let Some(t_srs) = &args.t_srs {
+        conversion = conversion.t_srs(t_srs.as_str());
+    }
+    if let Some(tile_size) = args.align_to_grid {
+        conversion = conversion.align_to_grid(tile_size, args.align_zoom);
+    }
+    if args.t_srs.is_some() || args.align_to_grid.is_some() {
+        // Split the CPUs between the files converted at once
+        let cpus = thread::available_parallelism().map_or(1, |n| n.get());
+        conversion = conversion.warp_threads((cpus / jobs).max(1));
+    }
+    if let Some(denominator) = args.preview {
+        conversion = conversion.preview(denominator);
+    }
+    if let Some(max_size) = args.max_size {
+        conversion = conversion.max_size(max_size);
+    }
+    if let Some(thumbnail) = &args.thumbnail {
+        conversion = conversion.thumbnail(thumbnail.clone());
+    }
+    if let Some(profile) = &args.icc_profile {
+        conversion = conversion.icc_profile(profile.clone());
+    }
+    if let Some(colour) = args.transparent_color {
+        conversion = conversion.transparent_colour(colour, args.tolerance);
+    }
+    if let Some(text) = &args.stamp {
+        conversion = conversion.stamp(text.as_str());
+    }
+    if let Some(degrees) = args.graticule {
+        conversion = conversion.graticule(degrees);
+    }
+    if let Some(amount) = args.sharpen {
+        conversion = conversion.sharpen(amount.unwrap_or(1.0));
+    }
+    if let Some(colors) = args.quantize {
+        conversion = conversion.quantize(colors);
+    }
+    if let Some(max_memory) = args.max_memory {
+        // Shared between the files converted at once
+        conversion = conversion.max_memory(max_memory * 1_000_000 / jobs as u64);
+    }
+    if let Some(max_pixels) = args.max_pixels {
+        conversion = conversion.max_pixels(max_pixels);
+    }
+    if let Some(dir) = &args.tile_cache {
+        conversion = conversion
+            .tile_cache(dir)
+            .existing_tiles(args.existing_tiles)
+            .tms(args.tms);
+    }
+    Ok(conversion)
+}
+
+// The settings that change a conversion's output, for ConversionCache keys
+fn cache_options(args: &ConvertArgs) -> String {
+    format!(
+        "{:?} {:?} {:?} {:?} quantize={:?} grayscale={} levels={} {} {} {} sharpen={:?} deblock={} max_size={:?} preview={:?} thumbnail={:?} icc={:?} html={:?}/{}/{:?} tiles={:?}/{:?}/{} key={:?}/{} overlay={} {:?} {:?} stamp={:?} {:?} grid={:?}/{:?} t_srs={:?} max_pixels={:?} alpha={} decoder={} checksum={} stats={}",
+        args.format,
+        args.compression,
+        args.quality,
+        args.resampling,
+        args.quantize,
+        args.grayscale,
+        args.auto_levels,
+        args.brightness,
+        args.contrast,
+        args.gamma,
+        args.sharpen,
+        args.deblock,
+        args.max_size,
+        args.preview,
+        args.thumbnail,
+        args.icc_profile,
+        args.html_preview,
+        args.wms_xml,
+        args.tile_url,
+        args.tile_cache,
+        args.existing_tiles,
+        args.tms,
+        args.transparent_color,
+        args.tolerance,
+        args.draw_bounds,
+        args.graticule,
+        args.overlay_color,
+        args.stamp,
+        args.stamp_corner,
+        args.align_to_grid,
+        args.align_zoom,
+        args.t_srs,
+        args.max_pixels,
+        args.alpha,
+        args.decoder,
+        args.checksum,
+        args.stats
+    )
+}
+
+fn parse_quality(s: &str) -> std::result::Result<(Option<Format>, u8), String> {
+    let (format, quality) = match s.split_once('=') {
+        Some((format, quality)) => (Some(format.parse()?), quality),
+        None => (None, s),
+    };
+    match quality.parse() {
+        Ok(quality) if (1..=100).contains(&quality) => Ok((format, quality)),
+        _ => Err(format!("expected a quality from 1 to 100: {}", s)),
+    }
+}
+
+fn quality(values: &[(Option<Format>, u8)]) -> Quality {
+    let default = values
+        .iter()
+        .rev()
+        .find_map(|&(format, value)| format.is_none().then(|| value));
+    values
+        .iter()
+        .filter_map(|&(format, value)| Some((format?, value)))
+        .fold(
+            default.map_or_else(Quality::default, Quality::new),
+            |quality, (format, value)| quality.format(format, value),
+        )
+}
+
+// Every file in flight on one status line, since per-file bars from
+// parallel jobs would overwrite each other
+struct BatchProgress {
+    finished: usize,
+    total: usize,
+    // (index, name, decoded, total) of the files being converted
+    active: Vec<(usize, String, u32, u32)>,
+}
+
+impl BatchProgress {
+    fn update(&mut self, index: usize, name: &str, progress: Progress) {
+        match progress {
+            Progress::HeaderParsed {
+                long_count,
+                lat_count,
+            } => self
+                .active
+                .push((index, name.to_owned(), 0, long_count * lat_count)),
+            Progress::MapletDecoded { .. } => {
+                if let Some(file) = self.active.iter_mut().find(|file| file.0 == index) {
+                    file.2 += 1;
+                }
+            }
+            Progress::OutputWritten { path } => {
+                self.finish(index);
+                eprintln!("\r\x1b[KWrote {}", path.display());
+            }
+        }
+        self.draw();
+    }
+
+    fn fail(&mut self, index: usize, input: &Path, err: &TopodumpError) {
+        self.finish(index);
+        eprintln!("\r\x1b[KFailed to convert {}: {}", input.display(), err);
+        self.draw();
+    }
+
+    fn skip(&mut self, index: usize, output: &Path) {
+        self.finish(index);
+        eprintln!("\r\x1b[KUnchanged {}", output.display());
+        self.draw();
+    }
+
+    fn skip_degenerate(&mut self, index: usize, input: &Path, err: &TopodumpError) {
+        self.finish(index);
+        eprintln!("\r\x1b[KSkipped {}: {}", input.display(), err);
+        self.draw();
+    }
+
+    fn finish(&mut self, index: usize) {
+        self.active.retain(|file| file.0 != index);
+        self.finished += 1;
+    }
+
+    fn draw(&self) {
+        let files = self
+            .active
+            .iter()
+            .map(|(_, name, decoded, total)| format!("{} {}%", name, decoded * 100 / total.max(&1)))
+            .collect::<Vec<_>>();
+        eprint!(
+            "\r\x1b[K[{}/{} files] {}",
+            self.finished,
+            self.total,
+            files.join(", ")
+        );
+    }
+}
+
+fn print_timings(name: &Path, timings: &Timings) {
+    eprintln!("{}", name.display());
+    eprintln!("  {:<10} {:>9} {:>9}", "stage", "wall", "cpu");
+    for (stage, time) in timings.stages().iter() {
+        eprintln!(
+            "  {:<10} {:>8.3}s {:>8.3}s",
+            stage,
+            time.wall.as_secs_f64(),
+            time.cpu.as_secs_f64()
+        );
+    }
+}
+
+// What became of each input, in the --catalog database if one was given,
+// and the --on-success and --on-failure hooks run for it
+struct Recorder {
+    #[cfg(feature = "mbtiles")]
+    catalog: Option<(Mutex<Catalog>, String)>,
+    on_success: Option<String>,
+    on_failure: Option<String>,
+}
+
+impl Recorder {
+    fn open(args: &ConvertArgs) -> Result<Self> {
+        #[cfg(feature = "mbtiles")]
+        let catalog = match &args.catalog {
+            Some(path) => Some((Mutex::new(Catalog::open(path)?), cache_options(args))),
+            None => None,
+        };
+        Ok(Recorder {
+            #[cfg(feature = "mbtiles")]
+            catalog,
+            on_success: args.on_success.clone(),
+            on_failure: args.on_failure.clone(),
+        })
+    }
+
+    fn converted(&self, key: Option<&str>, summary: &ConversionSummary) {
+        self.record_converted(key, summary);
+        if let Some(command) = &self.on_success {
+            run_hook(command, &summary.input, Some(summary), None);
+        }
+    }
+
+    fn unchanged(&self, key: Option<&str>, summary: &ConversionSummary) {
+        self.record_unchanged(key, summary);
+    }
+
+    fn failed(&self, input: &Path, err: &TopodumpError) {
+        self.record_failed(input, err);
+        // Degenerate quads are skipped rather than failed
+        if matches!(err, TopodumpError::DegenerateQuad(_)) {
+            return;
+        }
+        if let Some(command) = &self.on_failure {
+            run_hook(command, input, None, Some(err));
+        }
+    }
+}
+
+#[cfg(feature = "mbtiles")]
+impl Recorder {
+    fn enabled(&self) -> bool {
+        self.catalog.is_some()
+    }
+
+    fn get(&self, key: &str, output_dir: &Path) -> Option<ConversionSummary> {
+        let (catalog, _) = self.catalog.as_ref()?;
+        match catalog.lock().unwrap().get(key, output_dir) {
+            Ok(summary) => summary,
+            Err(err) => {
+                eprintln!("Failed to read the catalog: {}", err);
+                None
+            }
+        }
+    }
+
+    fn record(&self, record: impl FnOnce(&Catalog, &str) -> Result<()>) {
+        if let Some((catalog, options)) = &self.catalog {
+            if let Err(err) = record(&catalog.lock().unwrap(), options) {
+                eprintln!("Failed to record in the catalog: {}", err);
+            }
+        }
+    }
+
+    fn record_converted(&self, key: Option<&str>, summary: &ConversionSummary) {
+        self.record(|catalog, options| {
+            catalog.record_conversion(key, options, Status::Converted, summary)
+        })
+    }
+
+    fn record_unchanged(&self, key: Option<&str>, summary: &ConversionSummary) {
+        self.record(|catalog, options| {
+            catalog.record_conversion(key, options, Status::Unchanged, summary)
+        })
+    }
+
+    fn record_failed(&self, input: &Path, err: &TopodumpError) {
+        let status = match err {
+            TopodumpError::DegenerateQuad(_) => Status::Degenerate,
+            _ => Status::Failed,
+        };
+        self.record(|catalog, options| {
+            catalog.record_failure(input, options, status, &err.to_string())
+        })
+    }
+}
+
+#[cfg(not(feature = "mbtiles"))]
+impl Recorder {
+    fn enabled(&self) -> bool {
+        false
+    }
+
+    fn get(&self, _: &str, _: &Path) -> Option<ConversionSummary> {
+        None
+    }
+
+    fn record_converted(&self, _: Option<&str>, _: &ConversionSummary) {}
+
+    fn record_unchanged(&self, _: Option<&str>, _: &ConversionSummary) {}
+
+    fn record_failed(&self, _: &Path, _: &TopodumpError) {}
+}
+
+// `path`, also held in the environment as `variable`, quoted for the shell
+// so it's passed as one argument. cmd.exe has no way to escape a quote
+// inside quotes, but a Windows path can't hold one, so there the variable is
+// expanded inside quotes instead, leaving its metacharacters literal
+fn shell_quote(path: &str, variable: &str) -> String {
+    if !cfg!(windows) {
+        format!("'{}'", path.replace('\'', "'\\''"))
+    } else if path.is_empty() {
+        "\"\"".to_string()
+    } else {
+        format!("\"%{}%\"", variable)
+    }
+}
+
+// Runs a hook command through the shell with {input} and {output} replaced
+// and what's known of the conversion in TOPODUMP_* variables, warning
+// rather than failing if it doesn't succeed
+fn run_hook(
+    command: &str,
+    input: &Path,
+    summary: Option<&ConversionSummary>,
+    err: Option<&TopodumpError>,
+) {
+    let output = summary.map_or_else(String::new, |summary| summary.output.display().to_string());
+    let line = command
+        .replace(
+            "{input}",
+            &shell_quote(&input.display().to_string(), "TOPODUMP_INPUT"),
+        )
+        .replace("{output}", &shell_quote(&output, "TOPODUMP_OUTPUT"));
+    #[cfg(windows)]
+    let mut hook = {
+        use std::os::windows::process::CommandExt;
+        // Passed as written, since cmd.exe doesn't undo the escaping other
+        // programs' arguments get; /S just strips the outer quotes
+        let mut hook = process::Command::new("cmd");
+        hook.raw_arg(format!("/S /C \"{}\"", line));
+        hook
+    };
+    #[cfg(not(windows))]
+    let mut hook = {
+        let mut hook = process::Command::new("sh");
+        hook.arg("-c").arg(&line);
+        hook
+    };
+    hook.env("TOPODUMP_INPUT", input);
+    if let Some(summary) = summary {
+        let header = &summary.header;
+        hook.env("TOPODUMP_OUTPUT", &summary.output)
+            .env("TOPODUMP_QUAD", header.quad_name.trim())
+            .env("TOPODUMP_STATE", header.state_name.trim())
+            .env("TOPODUMP_YEAR1", header.year1.trim())
+            .env("TOPODUMP_YEAR2", header.year2.trim())
+            .env(
+                "TOPODUMP_BOUNDS",
+                format!(
+                    "{},{},{},{}",
+                    header.w_long, header.s_lat, header.e_long, header.n_lat
+                ),
+            )
+            .env("TOPODUMP_WIDTH", summary.width.to_string())
+            .env("TOPODUMP_HEIGHT", summary.height.to_string())
+            .env(
+                "TOPODUMP_SERIES",
+                summary
+                    .series
+                    .map_or_else(String::new, |series| series.slug().to_string()),
+            )
+            .env("TOPODUMP_SHA256", summary.sha256.as_deref().unwrap_or(""));
+    }
+    if let Some(err) = err {
+        hook.env("TOPODUMP_ERROR", err.to_string());
+    }
+    match hook.status() {
+        Ok(status) if status.success() => {}
+        Ok(status) => eprintln!("Hook for {} exited with {}", input.display(), status),
+        Err(err) => eprintln!("Failed to run hook for {}: {}", input.display(), err),
+    }
+}
+
+fn report_failure(
+    args: &ConvertArgs,
+    recorder: &Recorder,
+    progress: &Mutex<BatchProgress>,
+    index: usize,
+    input: &Path,
+    err: &TopodumpError,
+) {
+    recorder.failed(input, err);
+    if args.quiet {
+        eprintln!("Failed to convert {}: {}", input.display(), err);
+    } else {
+        progress.lock().unwrap().fail(index, input, err);
+    }
+}
 
-    imageops::overlay(&mut collage_img, &first_maplet, 0, 0);
+// Quads with nothing to convert don't fail a batch
+fn report_skip(
+    args: &ConvertArgs,
+    recorder: &Recorder,
+    progress: &Mutex<BatchProgress>,
+    index: usize,
+    input: &Path,
+    err: &TopodumpError,
+) {
+    recorder.failed(input, err);
+    if args.quiet {
+        eprintln!("Skipped {}: {}", input.display(), err);
+    } else {
+        progress.lock().unwrap().skip_degenerate(index, input, err);
+    }
+}
+
+fn print_warnings(summary: &ConversionSummary) {
+    for warning in &summary.warnings {
+        eprintln!("Warning: {}", warning);
+    }
+}
 
-    for i in 0..header.lat_count {
-        for j in 0..header.long_count {
-            if i == 0 && j == 0 {
-                // We've already read the first jpg
+// Sends what was converted into a directory elsewhere, given the directory,
+// where its input was relative to the input root, and its summary
+type Upload<'a> = &'a (dyn Fn(&Path, &Path, ConversionSummary) -> Result<ConversionSummary> + Sync);
+
+fn convert_dir(
+    args: &ConvertArgs,
+    input_root: &Path,
+    output_root: &Path,
+    upload: Option<Upload>,
+) -> Result<Vec<ConversionSummary>> {
+    let inputs = find_tpq_files(input_root)?;
+    let jobs = args
+        .jobs
+        .unwrap_or_else(|| thread::available_parallelism().map_or(1, |n| n.get()))
+        .clamp(1, inputs.len().max(1));
+
+    let progress = Arc::new(Mutex::new(BatchProgress {
+        finished: 0,
+        total: inputs.len(),
+        active: Vec::new(),
+    }));
+    let cache_path = output_root.join(CACHE_FILE_NAME);
+    let cache = Mutex::new(if args.incremental {
+        ConversionCache::load(&cache_path)?
+    } else {
+        ConversionCache::default()
+    });
+    let recorder = Recorder::open(args)?;
+    let skipped = AtomicUsize::new(0);
+    // Files in input order, with their contents if read ahead
+    let queue: Mutex<Box<dyn Iterator<Item = (usize, Option<io::Result<Vec<u8>>>)> + Send>> =
+        Mutex::new(match args.prefetch {
+            0 => Box::new((0..inputs.len()).map(|index| (index, None))),
+            depth => Box::new(
+                prefetch(inputs.clone(), depth)
+                    .map(|(_, data)| Some(data))
+                    .enumerate(),
+            ),
+        });
+    let convert_next = || {
+        let mut converted = Vec::new();
+        loop {
+            let next = queue.lock().unwrap().next();
+            let (index, data) = match next {
+                Some(next) => next,
+                None => return converted,
+            };
+            let input = &inputs[index];
+            let data = match data.transpose() {
+                Ok(data) => data,
+                Err(err) => {
+                    report_failure(args, &recorder, &progress, index, input, &err.into());
+                    continue;
+                }
+            };
+            let output_dir = match upload {
+                // Apart, so only this file's outputs are uploaded with it
+                Some(_) => output_root.join(index.to_string()),
+                None => mirrored_dir(input_root, input, output_root),
+            };
+            let key = if args.incremental || recorder.enabled() {
+                let key = match &data {
+                    Some(data) => Ok(ConversionCache::key_for_data(data, &cache_options(args))),
+                    None => ConversionCache::key(input, &cache_options(args)),
+                };
+                match key {
+                    Ok(key) => Some(key),
+                    Err(err) => {
+                        report_failure(args, &recorder, &progress, index, input, &err);
+                        continue;
+                    }
+                }
+            } else {
+                None
+            };
+            let cached = key.as_ref().filter(|_| args.incremental).and_then(|key| {
+                let cache = cache.lock().unwrap();
+                cache
+                    .get(key, &output_dir)
+                    .cloned()
+                    .or_else(|| recorder.get(key, &output_dir))
+            });
+            if let Some(mut summary) = cached {
+                if !args.quiet {
+                    progress.lock().unwrap().skip(index, &summary.output);
+                }
+                summary.input = input.clone();
+                summary.timings = Timings::default();
+                recorder.unchanged(key.as_deref(), &summary);
+                converted.push((index, summary));
                 continue;
             }
-            let maplet = read_jpg()?;
-            imageops::overlay(
-                &mut collage_img,
-                &maplet,
-                j * maplet_width,
-                i * maplet_height,
+
+            let result = fs::create_dir_all(&output_dir)
+                .map_err(TopodumpError::from)
+                .and_then(|()| {
+                    let mut conversion = build_conversion(args, input, jobs)?
+                        .output_dir(&output_dir)
+                        .keep_partial(true);
+                    if let Some(dir) = &args.html_preview {
+                        let name = input.file_stem().unwrap_or_default();
+                        conversion = conversion.html_preview(dir.join(name));
+                        if args.wms_xml {
+                            let tile_url = args.tile_url.as_ref().map(|url| {
+                                format!("{}/{}", url.trim_end_matches('/'), name.to_string_lossy())
+                            });
+                            conversion = conversion.wms_xml(tile_url);
+                        }
+                    }
+                    if let Some(data) = data {
+                        conversion = conversion.input_data(data);
+                    }
+                    if !args.quiet {
+                        let progress = Arc::clone(&progress);
+                        let name = input
+                            .file_stem()
+                            .unwrap_or_default()
+                            .to_string_lossy()
+                            .into_owned();
+                        conversion = conversion.on_progress(move |update| {
+                            progress.lock().unwrap().update(index, &name, update)
+                        });
+                    }
+                    let summary = conversion.run_with_summary()?;
+                    match upload {
+                        Some(upload) => upload(
+                            &output_dir,
+                            &mirrored_dir(input_root, input, Path::new("")),
+                            summary,
+                        ),
+                        None => Ok(summary),
+                    }
+                });
+            match result {
+                Ok(summary) => {
+                    recorder.converted(key.as_deref(), &summary);
+                    // Retry incomplete outputs on the next run
+                    if let Some(key) = key.filter(|_| summary.warnings.is_empty()) {
+                        cache.lock().unwrap().insert(key, summary.clone());
+                    }
+                    converted.push((index, summary))
+                }
+                Err(err @ TopodumpError::DegenerateQuad(_)) => {
+                    skipped.fetch_add(1, Ordering::Relaxed);
+                    report_skip(args, &recorder, &progress, index, input, &err)
+                }
+                Err(err) => report_failure(args, &recorder, &progress, index, input, &err),
+            }
+        }
+    };
+    let mut converted = thread::scope(|scope| {
+        let workers = (0..jobs)
+            .map(|_| scope.spawn(convert_next))
+            .collect::<Vec<_>>();
+        workers
+            .into_iter()
+            .flat_map(|worker| worker.join().expect("conversion thread panicked"))
+            .collect::<Vec<_>>()
+    });
+    // Keep outputs describing the run in input order
+    converted.sort_by_key(|(index, _)| *index);
+    let summaries = converted
+        .into_iter()
+        .map(|(_, summary)| summary)
+        .collect::<Vec<_>>();
+
+    let skipped = skipped.into_inner();
+    if !args.quiet {
+        eprint!(
+            "\r\x1b[KConverted {} of {} files",
+            summaries.len(),
+            inputs.len()
+        );
+        if skipped > 0 {
+            eprint!(", skipped {} degenerate", skipped);
+        }
+        eprintln!();
+    }
+    for summary in &summaries {
+        print_warnings(summary);
+    }
+    if args.timings {
+        let mut total = Timings::default();
+        for summary in &summaries {
+            print_timings(&summary.input, &summary.timings);
+            total += summary.timings;
+        }
+        print_timings(Path::new("total"), &total);
+    }
+    if args.incremental {
+        fs::create_dir_all(output_root)?;
+        cache.into_inner().unwrap().save(&cache_path)?;
+    }
+    write_batch_outputs(args, &summaries)?;
+    let failed = inputs.len() - summaries.len() - skipped;
+    if failed > 0 {
+        return Err(TopodumpError::BatchFailed {
+            failed,
+            total: inputs.len(),
+        });
+    }
+    Ok(summaries)
+}
+
+// Outputs describing a whole run rather than a single file
+fn write_batch_outputs(args: &ConvertArgs, summaries: &[ConversionSummary]) -> Result<()> {
+    for summary in summaries {
+        if let Some(sha256) = &summary.sha256 {
+            println!("{}  {}", sha256, summary.output.display());
+        }
+    }
+    if let Some(project) = &args.qgis_project {
+        write_qgis_project(project, summaries)?;
+    }
+    if let Some(vrt) = &args.vrt {
+        fs::write(vrt, batch_vrt(summaries, vrt, args.alpha)?)?;
+    }
+    if let Some(gallery) = &args.gallery {
+        write_gallery(gallery, summaries)?;
+    }
+    if let Some(dir) = &args.stac_catalog {
+        write_stac_catalog(dir, summaries)?;
+    }
+    if let Some(path) = &args.postgis_sql {
+        let bands = if args.grayscale || args.quantize.is_some() {
+            1
+        } else if args.alpha {
+            4
+        } else {
+            3
+        };
+        fs::write(path, postgis_sql(summaries, &args.postgis_table, bands)?)?;
+    }
+    if let Some(path) = &args.mapserver_layers {
+        fs::write(path, mapserver_layers(summaries, &args.layer_name)?)?;
+    }
+    if args.geoserver_mosaic {
+        let path = write_geoserver_mosaic(summaries, &args.layer_name)?;
+        if !args.quiet {
+            eprintln!("Wrote {}", path.display());
+        }
+    }
+    Ok(())
+}
+
+// Converts a single file to `output`, or named after it in `output_dir`
+fn convert_file(
+    args: &ConvertArgs,
+    output: Option<&Path>,
+    output_dir: Option<&Path>,
+) -> Result<ConversionSummary> {
+    let mut conversion = build_conversion(args, &args.input, 1)?;
+    if !args.quiet {
+        conversion = conversion.on_progress(progress_bar());
+    }
+    if let Some(output) = output {
+        conversion = conversion.output(output);
+    }
+    if let Some(dir) = output_dir {
+        conversion = conversion.output_dir(dir);
+    }
+    if let Some(dir) = &args.html_preview {
+        conversion = conversion.html_preview(dir);
+    }
+    if args.wms_xml {
+        conversion = conversion.wms_xml(args.tile_url.clone());
+    }
+    let summary = conversion
+        .keep_partial(args.keep_partial)
+        .run_with_summary()?;
+    print_warnings(&summary);
+    if args.timings {
+        print_timings(&summary.input, &summary.timings);
+    }
+    Ok(summary)
+}
+
+// Records how converting the single input went and runs its hooks
+fn record_file(args: &ConvertArgs, result: Result<ConversionSummary>) -> Result<ConversionSummary> {
+    let recorder = Recorder::open(args)?;
+    match &result {
+        Ok(summary) => {
+            let key = Some(&args.input)
+                .filter(|_| recorder.enabled())
+                .and_then(|input| ConversionCache::key(input, &cache_options(args)).ok());
+            recorder.converted(key.as_deref(), summary);
+        }
+        Err(err) => recorder.failed(&args.input, err),
+    }
+    result
+}
+
+fn store_url(args: &ConvertArgs) -> Option<&str> {
+    args.output
+        .as_deref()
+        .and_then(Path::to_str)
+        .filter(|output| output.contains("://"))
+}
+
+// Uploads everything converted into `dir` under the directory `key`,
+// reporting the output by its URL
+#[cfg(feature = "object-store")]
+fn upload_output(
+    uploader: &Uploader,
+    dir: &Path,
+    key: &Path,
+    mut summary: ConversionSummary,
+) -> Result<ConversionSummary> {
+    let key = key.to_string_lossy().replace('\\', "/");
+    uploader.upload_dir(dir, &key)?;
+    let name = summary
+        .output
+        .file_name()
+        .unwrap_or_default()
+        .to_string_lossy()
+        .to_string();
+    summary.output = PathBuf::from(uploader.url(&format!("{}/{}", key, name))?);
+    Ok(summary)
+}
+
+// Converts into a staging directory, uploading each output and what was
+// written beside it as soon as it's finished
+#[cfg(feature = "object-store")]
+fn convert_to_store(args: &ConvertArgs, url: &str) -> Result<()> {
+    if args.incremental {
+        return Err(TopodumpError::Unsupported(
+            "--incremental can't check outputs in object storage",
+        ));
+    }
+    let staging = env::temp_dir().join(format!("topodump-{}", process::id()));
+    let input = args.input.as_path();
+    let result = if input.is_dir() {
+        let uploader = Uploader::new(url)?;
+        let upload = |dir: &Path, key: &Path, summary: ConversionSummary| {
+            upload_output(&uploader, dir, key, summary)
+        };
+        convert_dir(args, input, &staging, Some(&upload)).map(drop)
+    } else {
+        // A URL not ending in / names the output
+        let (prefix, name) = match url.rsplit_once('/') {
+            Some((prefix, name)) if !name.is_empty() && !prefix.ends_with('/') => {
+                (prefix, Some(name))
+            }
+            _ => (url, None),
+        };
+        let uploader = Uploader::new(prefix)?;
+        fs::create_dir_all(&staging)?;
+        let summary = match name {
+            Some(name) => convert_file(args, Some(&staging.join(name)), None),
+            None => convert_file(args, None, Some(&staging)),
+        };
+        let summary =
+            summary.and_then(|summary| upload_output(&uploader, &staging, Path::new(""), summary));
+        record_file(args, summary).and_then(|summary| write_batch_outputs(args, &[summary]))
+    };
+    let _ = fs::remove_dir_all(&staging);
+    result
+}
+
+fn convert(args: ConvertArgs) -> Result<()> {
+    #[cfg(feature = "object-store")]
+    if let Some(url) = store_url(&args) {
+        return convert_to_store(&args, url);
+    }
+    #[cfg(not(feature = "object-store"))]
+    if store_url(&args).is_some() {
+        return Err(TopodumpError::Unsupported(
+            "uploading outputs needs the object-store feature",
+        ));
+    }
+
+    let input = args.input.as_path();
+    if input.is_dir() {
+        let output_root = args.output.as_deref().unwrap_or(input);
+        convert_dir(&args, input, output_root, None)?;
+        return Ok(());
+    }
+    let summary = record_file(&args, convert_file(&args, args.output.as_deref(), None))?;
+    write_batch_outputs(&args, &[summary])
+}
+
+fn parse_tile_size(s: &str) -> std::result::Result<(u32, u32), String> {
+    let invalid = || format!("expected WIDTHxHEIGHT, e.g. 4096x4096: {}", s);
+    let (width, height) = s.split_once('x').ok_or_else(invalid)?;
+    match (width.parse(), height.parse()) {
+        (Ok(width), Ok(height)) if width > 0 && height > 0 => Ok((width, height)),
+        _ => Err(invalid()),
+    }
+}
+
+fn parse_colour(s: &str) -> std::result::Result<[u8; 3], String> {
+    let hex = s.trim_start_matches('#');
+    let channel = |i: usize| {
+        hex.get(i..i + 2)
+            .and_then(|channel| u8::from_str_radix(channel, 16).ok())
+    };
+    match (hex.len(), channel(0), channel(2), channel(4)) {
+        (6, Some(red), Some(green), Some(blue)) => Ok([red, green, blue]),
+        _ => Err(format!(
+            "expected a colour as RRGGBB hex, e.g. f5f0e1: {}",
+            s
+        )),
+    }
+}
+
+fn parse_extent(s: &str) -> std::result::Result<(f64, f64, f64, f64), String> {
+    let invalid = || {
+        format!(
+            "expected W,S,E,N in degrees, e.g. -113.2,34,-112.9,34.2: {}",
+            s
+        )
+    };
+    let bounds = s
+        .split(',')
+        .map(|value| value.trim().parse::<f64>())
+        .collect::<std::result::Result<Vec<_>, _>>()
+        .map_err(|_| invalid())?;
+    match bounds[..] {
+        [west, south, east, north] if west < east && south < north => {
+            Ok((west, south, east, north))
+        }
+        _ => Err(invalid()),
+    }
+}
+
+fn mosaic(args: MosaicArgs) -> Result<()> {
+    let mut inputs = mosaic_inputs(&args.inputs)?;
+    if let Some(extent) = args.extent {
+        inputs = quads_in_extent(inputs, extent)?;
+    }
+    let (inputs, duplicates) = deduplicate(inputs, args.duplicates)?;
+    for duplicate in &duplicates {
+        for dropped in &duplicate.dropped {
+            eprintln!(
+                "Skipped {}: the same quad as {}",
+                dropped.display(),
+                duplicate.kept.display()
+            );
+        }
+    }
+    // Quads of different scales can't share a pixel grid, so each series
+    // found gets a mosaic of its own
+    let groups = group_by_series(inputs)?;
+    let split = groups.len() > 1;
+    for (series, inputs) in groups {
+        let output = if split {
+            series_output(&args.output, series)
+        } else {
+            args.output.clone()
+        };
+        if split && !args.quiet {
+            eprintln!(
+                "Mosaicking {} quads into {}",
+                inputs.len(),
+                output.display()
             );
         }
+        let mut mosaic = Mosaic::new(inputs, &output)
+            .compression(args.compression)
+            .quality(quality(&args.quality))
+            .resampling(args.resampling)
+            .alpha(args.alpha)
+            .overlap(args.overlap)
+            .blend(args.blend)
+            .trim_collar(args.trim_collar)
+            .resume(args.resume);
+        if let Some(format) = args.format {
+            mosaic = mosaic.format(format);
+        }
+        if let Some(rows) = args.stripe_height {
+            mosaic = mosaic.stripe_height(rows);
+        }
+        if let Some((width, height)) = args.retile {
+            mosaic = mosaic.retile(width, height);
+        }
+        if !args.quiet {
+            mosaic = mosaic.on_progress(progress_bar());
+        }
+        mosaic.run()?;
     }
+    Ok(())
+}
 
-    collage_img.save_with_format(&args.output, ImageFormat::Tiff)?;
+// Diagnostics rather than failures, since real imagery can change sharply
+// along a boundary
+fn print_seams(input: &Path, tpq: &TpqFile) {
+    let collage = tpq
+        .collage()
+        .and_then(|collage| Ok((collage, tpq.maplet_dimensions()?)));
+    let (collage, maplet_size) = match collage {
+        Ok(collage) => collage,
+        // Left to verify to report
+        Err(_) => return,
+    };
+    for seam in seams(&collage, maplet_size) {
+        let side = match seam.edge {
+            Edge::Right => "right",
+            Edge::Bottom => "bottom",
+        };
+        println!(
+            "{}: possible seam along the {} edge of maplet ({}, {}), {:.1}x its surroundings",
+            input.display(),
+            side,
+            seam.row,
+            seam.col,
+            seam.score
+        );
+    }
+}
 
-    set_geo_data(
-        &args.output,
-        &header,
-        collage_img.width() as f64,
-        collage_img.height() as f64,
-    )
-    .map_err(|geo_err| {
-        eprintln!("Error setting geo data: {}", geo_err);
-        eprintln!("Attempting to remove {}...", &args.output);
-        match remove_file(&args.output) {
-            Ok(()) => {
-                eprintln!("Successfully removed {}", &args.output);
-                geo_err
+fn verify(args: VerifyArgs) -> Result<()> {
+    let inputs = mosaic_inputs(&args.inputs)?;
+    let mut failed = 0;
+    for input in &inputs {
+        let problems = match TpqFile::open(input) {
+            Ok(tpq) => {
+                for warning in tpq.warnings() {
+                    println!("{}: warning: {}", input.display(), warning);
+                }
+                if args.seams {
+                    print_seams(input, &tpq);
+                }
+                topodump::verify::verify(&tpq)
             }
-            Err(remove_err) => panic!("Failed to remove {}: {}", &args.output, remove_err),
+            Err(err) => vec![err],
+        };
+        if problems.is_empty() {
+            println!("{}: OK", input.display());
+            continue;
         }
-    })?;
+        failed += 1;
+        for problem in &problems {
+            println!("{}: {}", input.display(), problem);
+        }
+    }
+    if failed > 0 {
+        return Err(TopodumpError::VerifyFailed {
+            failed,
+            total: inputs.len(),
+        });
+    }
+    Ok(())
+}
 
+fn vrt(args: VrtArgs) -> Result<()> {
+    let tpq = TpqFile::open(&args.input)?;
+    let output = args.output.clone().unwrap_or_else(|| {
+        let mut output = OsString::from(&args.input);
+        output.push(".vrt");
+        PathBuf::from(output)
+    });
+    fs::write(&output, tpq_vrt(&tpq, &args.input)?)?;
+    Ok(())
+}
+
+fn print_coverage(args: CoverageArgs) -> Result<()> {
+    let report = coverage(&mosaic_inputs(&args.inputs)?);
+    for (input, reason) in &report.skipped {
+        eprintln!("Skipped {}: {}", input.display(), reason);
+    }
+    for group in &report.groups {
+        let present = group.present().len();
+        let state = if group.state.is_empty() {
+            "Unknown state"
+        } else {
+            &group.state
+        };
+        println!(
+            "{} {}: {} of {} cells present",
+            state,
+            group.series,
+            present,
+            present + group.missing.len()
+        );
+        for &cell in &group.missing {
+            let (west, south, east, north) = group.bounds(cell);
+            println!(
+                "  missing {} ({}, {}, {}, {})",
+                group.name(cell),
+                west,
+                south,
+                east,
+                north
+            );
+        }
+        for (input, neighbours) in &group.absent_neighbours {
+            let names = neighbours
+                .iter()
+                .map(|&cell| group.name(cell))
+                .collect::<Vec<_>>();
+            println!(
+                "  {}: no neighbour at {}",
+                input.display(),
+                names.join(", ")
+            );
+        }
+    }
     Ok(())
 }
+
+fn write_index(args: IndexArgs) -> Result<()> {
+    let mut index = index(&mosaic_inputs(&args.inputs)?);
+    for (input, reason) in &index.skipped {
+        eprintln!("Skipped {}: {}", input.display(), reason);
+    }
+    for footprint in &mut index.footprints {
+        let mut location = footprint.location.with_extension(&args.extension);
+        if let Some(converted) = &args.converted {
+            // Mirrored from whichever input directory it was found under
+            let root = args
+                .inputs
+                .iter()
+                .find(|input| input.is_dir() && footprint.path.starts_with(input));
+            if let (Some(root), Some(name)) = (root, location.file_name()) {
+                location = mirrored_dir(root, &footprint.path, converted).join(name);
+            }
+        }
+        footprint.location = location;
+    }
+    match (args.format, &args.output) {
+        (IndexFormat::Shapefile, Some(output)) => index.write_shapefile(output),
+        (IndexFormat::Shapefile, None) => Err(TopodumpError::Unsupported(
+            "writing a shapefile to standard output",
+        )),
+        (format, Some(output)) => {
+            index.write(format, io::BufWriter::new(fs::File::create(output)?))
+        }
+        (format, None) => index.write(format, io::BufWriter::new(io::stdout())),
+    }
+}
+
+#[cfg(feature = "mbtiles")]
+fn build_gpkg(args: BuildGpkgArgs) -> Result<()> {
+    let summary = GeoPackageBuild::new(find_tpq_files(&args.root)?, &args.output)
+        .resampling(args.resampling)
+        .run()?;
+    for (input, reason) in &summary.skipped {
+        eprintln!("Skipped {}: {}", input.display(), reason);
+    }
+    for level in &summary.levels {
+        eprintln!(
+            "{} quads of {} at zoom {} to {}",
+            level.quads,
+            level.series,
+            level.zooms.start(),
+            level.zooms.end()
+        );
+    }
+    eprintln!("Wrote {}", args.output.display());
+    Ok(())
+}
+
+// A bare `topodump <input> [output]` converts, as it did before there were
+// subcommands
+fn with_default_command(mut args: Vec<OsString>) -> Vec<OsString> {
+    let app = Args::into_app();
+    let is_command = |arg: &OsString| {
+        arg == "help"
+            || app
+                .get_subcommands()
+                .any(|command| arg == command.get_name())
+    };
+    match args.get(1) {
+        Some(arg) if !is_command(arg) && !arg.to_string_lossy().starts_with('-') => {
+            args.insert(1, "convert".into())
+        }
+        _ => {}
+    }
+    args
+}
+
+fn main() -> Result<()> {
+    // Started from a file manager rather than a terminal
+    #[cfg(feature = "gui")]
+    if env::args_os().len() == 1 {
+        return topodump::gui::run();
+    }
+    match Args::parse_from(with_default_command(env::args_os().collect())).command {
+        Command::Convert(args) => convert(args),
+        Command::Info(args) => info(args),
+        Command::Vrt(args) => vrt(args),
+        Command::Mosaic(args) => mosaic(args),
+        Command::Verify(args) => verify(args),
+        Command::Coverage(args) => print_coverage(args),
+        Command::Index(args) => write_index(args),
+        #[cfg(feature = "mbtiles")]
+        Command::BuildGpkg(args) => build_gpkg(args),
+        #[cfg(feature = "tui")]
+        Command::Tui(args) => topodump::tui::browse(mosaic_inputs(&args.inputs)?),
+        #[cfg(feature = "gui")]
+        Command::Gui => topodump::gui::run(),
+    }
+}
@@ -0,0 +1,113 @@
+use std::error::Error;
+use std::io::Cursor;
+use std::sync::Arc;
+
+use image::{io::Reader as ImageReader, DynamicImage, GrayImage, ImageFormat, RgbImage};
+use jpeg_decoder::PixelFormat;
+#[cfg(feature = "zune-jpeg")]
+use zune_jpeg::zune_core::{colorspace::ColorSpace, options::DecoderOptions};
+#[cfg(feature = "zune-jpeg")]
+use zune_jpeg::JpegDecoder;
+
+// Offset table of maplet positions follows the header
+pub const OFFSET_TABLE_START: u64 = 1024;
+
+pub type DecodeResult = std::result::Result<DynamicImage, Box<dyn Error + Send + Sync>>;
+
+/// Turns the raw bytes of one maplet into an image.
+pub trait MapletDecoder: Send + Sync {
+    fn decode(&self, data: &[u8]) -> DecodeResult;
+}
+
+/// The `image` crate's JPEG decoder.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ImageDecoder;
+
+impl MapletDecoder for ImageDecoder {
+    fn decode(&self, data: &[u8]) -> DecodeResult {
+        Ok(ImageReader::with_format(Cursor::new(data), ImageFormat::Jpeg).decode()?)
+    }
+}
+
+/// Decodes at 1/2, 1/4 or 1/8 scale by skipping the higher DCT coefficients,
+/// far faster than decoding in full and resizing; for previews.
+#[derive(Clone, Copy, Debug)]
+pub struct ScaledDecoder {
+    denominator: u16,
+}
+
+impl ScaledDecoder {
+    /// `None` unless `denominator` is 1, 2, 4 or 8.
+    pub fn new(denominator: u16) -> Option<Self> {
+        if [1, 2, 4, 8].contains(&denominator) {
+            Some(ScaledDecoder { denominator })
+        } else {
+            None
+        }
+    }
+}
+
+impl MapletDecoder for ScaledDecoder {
+    fn decode(&self, data: &[u8]) -> DecodeResult {
+        let mut decoder = jpeg_decoder::Decoder::new(data);
+        decoder.read_info()?;
+        let info = decoder.info().ok_or("missing JPEG header")?;
+        // Widened, as a side near u16::MAX would overflow rounding up; the
+        // result is never larger than the side
+        let scaled = |side: u16| {
+            let denominator = u32::from(self.denominator);
+            ((u32::from(side) + denominator - 1) / denominator) as u16
+        };
+        let (width, height) = decoder.scale(scaled(info.width), scaled(info.height))?;
+        let (width, height) = (width as u32, height as u32);
+        let pixels = decoder.decode()?;
+        let image = match info.pixel_format {
+            PixelFormat::L8 => {
+                GrayImage::from_raw(width, height, pixels).map(DynamicImage::ImageLuma8)
+            }
+            PixelFormat::RGB24 => {
+                RgbImage::from_raw(width, height, pixels).map(DynamicImage::ImageRgb8)
+            }
+            _ => return Err("unsupported JPEG pixel format".into()),
+        };
+        Ok(image.ok_or("JPEG decoded to the wrong number of pixels")?)
+    }
+}
+
+/// zune-jpeg's SIMD decoder, typically several times faster than `image`'s.
+#[cfg(feature = "zune-jpeg")]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ZuneDecoder;
+
+#[cfg(feature = "zune-jpeg")]
+impl MapletDecoder for ZuneDecoder {
+    fn decode(&self, data: &[u8]) -> DecodeResult {
+        let options = DecoderOptions::default().jpeg_set_out_colorspace(ColorSpace::RGB);
+        let mut decoder = JpegDecoder::new_with_options(options, data);
+        let pixels = decoder.decode()?;
+        let (width, height) = decoder.dimensions().ok_or("missing JPEG dimensions")?;
+        let image = RgbImage::from_raw(width as u32, height as u32, pixels)
+            .ok_or("JPEG decoded to the wrong number of pixels")?;
+        Ok(DynamicImage::ImageRgb8(image))
+    }
+}
+
+/// Names accepted by [`decoder_by_name`], depending on enabled features.
+pub const DECODERS: &[&str] = &[
+    "image",
+    #[cfg(feature = "zune-jpeg")]
+    "zune",
+];
+
+pub fn decoder_by_name(name: &str) -> Option<Arc<dyn MapletDecoder>> {
+    match name {
+        "image" => Some(Arc::new(ImageDecoder)),
+        #[cfg(feature = "zune-jpeg")]
+        "zune" => Some(Arc::new(ZuneDecoder)),
+        _ => None,
+    }
+}
+
+pub fn default_decoder() -> Arc<dyn MapletDecoder> {
+    Arc::new(ImageDecoder)
+}
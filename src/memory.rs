@@ -0,0 +1,123 @@
+//! Conversions that never touch the filesystem, e.g. for web services
+//! converting uploads.
+
+use std::io::Cursor;
+#[cfg(feature = "gdal")]
+use std::path::Path;
+#[cfg(feature = "gdal")]
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use image::{codecs::png::PngEncoder, DynamicImage, GenericImageView};
+
+#[cfg(feature = "gdal")]
+use crate::conversion::quality_option;
+use crate::conversion::{Compression, Format};
+use crate::error::{Result, TopodumpError};
+#[cfg(feature = "gdal")]
+use crate::gdal_util::{self, create_copy};
+use crate::georef::GeoRef;
+use crate::output::encode_geotiff;
+use crate::pixels;
+use crate::tpq::TpqFile;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ConvertOptions {
+    pub format: Format,
+    pub compression: Compression,
+    /// Keeps an alpha channel instead of writing RGB.
+    pub alpha: bool,
+    /// The JPEG quality with JPEG compression, or GDAL's default if `None`.
+    pub quality: Option<u8>,
+}
+
+impl Default for ConvertOptions {
+    fn default() -> Self {
+        ConvertOptions {
+            format: Format::GTiff,
+            compression: Compression::None,
+            alpha: false,
+            quality: None,
+        }
+    }
+}
+
+/// Converts the bytes of a tpq file into the bytes of the output.
+///
+/// PNG output carries no georeferencing, since that lives in side files.
+/// Compressed and COG output are composed in a GDAL MEM dataset and copied
+/// out through `/vsimem/`.
+pub fn convert_to_vec(input: &[u8], options: &ConvertOptions) -> Result<Vec<u8>> {
+    let tpq = TpqFile::from_bytes(input.to_vec())?;
+    let collage_img = DynamicImage::ImageRgba8(tpq.collage()?);
+    let collage_img = if options.alpha {
+        collage_img
+    } else {
+        DynamicImage::ImageRgb8(pixels::rgb(&collage_img).into_owned())
+    };
+    let georef = GeoRef::from_header(
+        tpq.header(),
+        collage_img.width() as f64,
+        collage_img.height() as f64,
+    );
+
+    let mut data = Vec::new();
+    match options.format {
+        Format::Png => PngEncoder::new(&mut data).encode(
+            collage_img.as_bytes(),
+            collage_img.width(),
+            collage_img.height(),
+            collage_img.color(),
+        )?,
+        #[cfg(feature = "mbtiles")]
+        Format::MbTiles => {
+            return Err(TopodumpError::Unsupported(
+                "mbtiles output can only be written to a file",
+            ))
+        }
+        Format::GTiff | Format::Cog => {
+            if options.format == Format::GTiff && options.compression == Compression::None {
+                encode_geotiff(Cursor::new(&mut data), &collage_img, Some(&georef), None)?;
+            } else {
+                data = translate(&collage_img, &georef, options)?;
+            }
+        }
+    }
+    Ok(data)
+}
+
+// Composes a MEM dataset and copies it out compressed, in a single pass
+#[cfg(feature = "gdal")]
+fn translate(image: &DynamicImage, georef: &GeoRef, options: &ConvertOptions) -> Result<Vec<u8>> {
+    // Unique per call so concurrent conversions don't share files
+    static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
+    let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+    let output_path = format!("/vsimem/topodump_{}_{}_out.tif", std::process::id(), id);
+
+    let bands = if options.alpha { 4 } else { 3 };
+    let dataset = gdal_util::create_mem(image.width(), image.height(), bands)?;
+    gdal_util::write_image(&dataset.0, 0, 0, image, bands)?;
+    gdal_util::georeference(&dataset.0, georef)?;
+    let quality = quality_option(options.format, options.compression, options.quality);
+    let mut creation = vec![("COMPRESS", options.compression.gdal_name())];
+    if let Some((name, value)) = &quality {
+        creation.push((*name, value.as_str()));
+    }
+    create_copy(
+        &dataset.0,
+        options.format.driver(),
+        Path::new(&output_path),
+        &creation,
+    )?;
+    gdal_util::take_mem_file(&output_path)
+}
+
+#[cfg(not(feature = "gdal"))]
+fn translate(
+    _image: &DynamicImage,
+    _georef: &GeoRef,
+    _options: &ConvertOptions,
+) -> Result<Vec<u8>> {
+    Err(TopodumpError::Unsupported(
+        "compressed and COG output require the gdal feature",
+    ))
+}
@@ -0,0 +1,1128 @@
+//! Several quads of the same series merged into one output.
+
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{self, File};
+use std::io::BufReader;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use image::{DynamicImage, GenericImageView, Rgba, RgbaImage};
+#[cfg(feature = "parallel")]
+use rayon::prelude::*;
+
+use crate::batch::find_tpq_files;
+use crate::checksum::sha256_hex;
+use crate::conversion::{resolve_format, Compression, Format, Quality, Resampling};
+use crate::error::{Result, TopodumpError};
+use crate::georef::{GeoRef, NAD27_WKT};
+use crate::header::{check_image_size, read_tpq_header, TpqHeader, DEFAULT_MAX_PIXELS};
+use crate::output::{tile_index_path, GeoTiffWriter, OutputWriter, PngWriter, RetiledWriter};
+use crate::progress::{Progress, ProgressCallback};
+use crate::scale::Series;
+use crate::tpq::TpqFile;
+
+// Rows of output pixels written per stripe by default
+const DEFAULT_STRIPE_HEIGHT: u32 = 2048;
+
+// Quads may differ in resolution by this fraction before they're refused
+const RESOLUTION_TOLERANCE: f64 = 0.01;
+
+// Quads whose bounds differ by less than this fraction of their span are
+// the same quad
+const DUPLICATE_TOLERANCE: f64 = 0.01;
+
+// A pixel with every channel at least this light is bare paper...
+const PAPER_LEVEL: u8 = 235;
+// ...and a line of pixels this much paper is collar rather than map
+const COLLAR_FRACTION: f64 = 0.97;
+
+/// Which quad's pixels a mosaic keeps where quads overlap.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Overlap {
+    /// The quad listed first.
+    First,
+    /// The quad listed last.
+    Last,
+    /// The quad with the latest year in its header, then the one listed last.
+    Newest,
+    /// The quad with the finest resolution, then the one listed last.
+    HighestRes,
+}
+
+impl FromStr for Overlap {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "first" => Ok(Overlap::First),
+            "last" => Ok(Overlap::Last),
+            "newest" => Ok(Overlap::Newest),
+            "highest-res" => Ok(Overlap::HighestRes),
+            _ => Err(format!("unknown overlap rule: {}", s)),
+        }
+    }
+}
+
+// The latest year a header gives, or 0 if it gives none
+fn newest_year(header: &TpqHeader) -> u32 {
+    [&header.year1, &header.year2]
+        .iter()
+        .filter_map(|year| year.trim_matches(char::from(0)).trim().parse().ok())
+        .max()
+        .unwrap_or(0)
+}
+
+// A rectangle of mosaic pixels
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct Region {
+    x: u32,
+    y: u32,
+    width: u32,
+    height: u32,
+}
+
+impl Region {
+    fn right(self) -> u32 {
+        self.x + self.width
+    }
+
+    fn bottom(self) -> u32 {
+        self.y + self.height
+    }
+
+    fn contains(self, x: u32, y: u32) -> bool {
+        x >= self.x && x < self.right() && y >= self.y && y < self.bottom()
+    }
+
+    fn intersects(self, other: Region) -> bool {
+        self.x < other.right()
+            && other.x < self.right()
+            && self.y < other.bottom()
+            && other.y < self.bottom()
+    }
+
+    fn intersection(self, other: Region) -> Option<Region> {
+        if !self.intersects(other) {
+            return None;
+        }
+        let (x, y) = (self.x.max(other.x), self.y.max(other.y));
+        Some(Region {
+            x,
+            y,
+            width: self.right().min(other.right()) - x,
+            height: self.bottom().min(other.bottom()) - y,
+        })
+    }
+
+    // The parts of `self` outside `cover`, as up to four rectangles
+    fn subtract(self, cover: Region) -> Vec<Region> {
+        if !self.intersects(cover) {
+            return vec![self];
+        }
+        let (left, top) = (cover.x.max(self.x), cover.y.max(self.y));
+        let (right, bottom) = (
+            cover.right().min(self.right()),
+            cover.bottom().min(self.bottom()),
+        );
+        let mut parts = Vec::new();
+        if top > self.y {
+            parts.push(Region {
+                height: top - self.y,
+                ..self
+            });
+        }
+        if bottom < self.bottom() {
+            parts.push(Region {
+                y: bottom,
+                height: self.bottom() - bottom,
+                ..self
+            });
+        }
+        if left > self.x {
+            parts.push(Region {
+                y: top,
+                width: left - self.x,
+                height: bottom - top,
+                ..self
+            });
+        }
+        if right < self.right() {
+            parts.push(Region {
+                x: right,
+                y: top,
+                width: self.right() - right,
+                height: bottom - top,
+            });
+        }
+        parts
+    }
+}
+
+// One of the edges in `edges` within `tolerance` of `edge`, if any, so the
+// shared boundary of neighbouring sheets lands on the same pixel for both
+fn snap(edges: &[f64], edge: f64, tolerance: f64) -> f64 {
+    edges
+        .iter()
+        .copied()
+        .find(|other| (other - edge).abs() <= tolerance)
+        .unwrap_or(edge)
+}
+
+// Where the `index`th of `count` maplets starts across `span` pixels
+fn cell_start(index: u32, count: u32, span: u32) -> u32 {
+    (index as u64 * span as u64 / count as u64) as u32
+}
+
+// The maplet of `count` across `span` pixels that pixel `p` falls in
+fn cell_index(p: u32, count: u32, span: u32) -> u32 {
+    let mut index = ((p as u64 * count as u64) / span as u64) as u32;
+    while index + 1 < count && cell_start(index + 1, count, span) <= p {
+        index += 1;
+    }
+    while index > 0 && cell_start(index, count, span) > p {
+        index -= 1;
+    }
+    index.min(count - 1)
+}
+
+fn paper<'a>(pixels: impl Iterator<Item = &'a Rgba<u8>>) -> bool {
+    let (mut total, mut light) = (0, 0);
+    for pixel in pixels {
+        total += 1;
+        if pixel.0[..3].iter().all(|&level| level >= PAPER_LEVEL) {
+            light += 1;
+        }
+    }
+    total > 0 && light as f64 >= total as f64 * COLLAR_FRACTION
+}
+
+// How many lines of `image` running in from one side are paper: rows if
+// `rows`, else columns, counted from the bottom or right if `reverse`
+fn paper_lines(image: &RgbaImage, rows: bool, reverse: bool) -> u32 {
+    let (width, height) = image.dimensions();
+    let (lines, len) = if rows {
+        (height, width)
+    } else {
+        (width, height)
+    };
+    let mut count = 0;
+    while count < lines {
+        let line = if reverse { lines - 1 - count } else { count };
+        let pixels = (0..len).map(|i| {
+            if rows {
+                image.get_pixel(i, line)
+            } else {
+                image.get_pixel(line, i)
+            }
+        });
+        if !paper(pixels) {
+            break;
+        }
+        count += 1;
+    }
+    count
+}
+
+// The maplets at `positions`, each (placement, row, col), in order and
+// resampled to their cells, decoded together on the rayon pool
+#[cfg(feature = "parallel")]
+fn decode_cells(quads: &OpenQuads, positions: &[(usize, u32, u32)]) -> Result<Vec<DynamicImage>> {
+    positions
+        .par_iter()
+        .map(|&(index, row, col)| quads.decode(index, row, col))
+        .collect()
+}
+
+#[cfg(not(feature = "parallel"))]
+fn decode_cells(quads: &OpenQuads, positions: &[(usize, u32, u32)]) -> Result<Vec<DynamicImage>> {
+    positions
+        .iter()
+        .map(|&(index, row, col)| quads.decode(index, row, col))
+        .collect()
+}
+
+// The quads a mosaic is reading, each opened once and closed once the
+// stripes have passed it, with the pixels of those below the one being
+// written that a blend has needed
+struct OpenQuads<'a> {
+    placements: &'a [Placement],
+    quads: HashMap<usize, TpqFile>,
+    maplets: HashMap<(usize, u32, u32), RgbaImage>,
+}
+
+impl<'a> OpenQuads<'a> {
+    fn new(placements: &'a [Placement]) -> Self {
+        OpenQuads {
+            placements,
+            quads: HashMap::new(),
+            maplets: HashMap::new(),
+        }
+    }
+
+    fn open(&mut self, index: usize) -> Result<()> {
+        if !self.quads.contains_key(&index) {
+            let tpq = TpqFile::open(&self.placements[index].path)?;
+            self.quads.insert(index, tpq);
+        }
+        Ok(())
+    }
+
+    // Maplet (`row`, `col`) of placement `index`, which must be open,
+    // resampled to its cell
+    fn decode(&self, index: usize, row: u32, col: u32) -> Result<DynamicImage> {
+        let maplet = self.quads[&index].maplet(row, col)?;
+        Ok(self.placements[index].fit(maplet, row, col))
+    }
+
+    // The pixel of placement `index` at mosaic pixel (`x`, `y`)
+    fn pixel(&mut self, index: usize, x: u32, y: u32) -> Result<Rgba<u8>> {
+        let placements = self.placements;
+        let placement = &placements[index];
+        let row = cell_index(
+            y - placement.y,
+            placement.header.lat_count,
+            placement.height,
+        );
+        let col = cell_index(
+            x - placement.x,
+            placement.header.long_count,
+            placement.width,
+        );
+        let cell = placement.cell(row, col);
+        if !self.maplets.contains_key(&(index, row, col)) {
+            self.open(index)?;
+            let maplet = self.decode(index, row, col)?;
+            self.maplets.insert((index, row, col), maplet.to_rgba8());
+        }
+        Ok(*self.maplets[&(index, row, col)].get_pixel(x - cell.x, y - cell.y))
+    }
+
+    // Forgets the maplets, and closes the quads, that end above `top`, which
+    // no stripe from there down reaches
+    fn release_above(&mut self, top: u32) {
+        let placements = self.placements;
+        self.maplets
+            .retain(|&(index, row, col), _| placements[index].cell(row, col).bottom() > top);
+        self.quads
+            .retain(|&index, _| placements[index].region().bottom() > top);
+    }
+}
+
+// Where one quad lands in the mosaic
+struct Placement {
+    path: PathBuf,
+    header: TpqHeader,
+    x: u32,
+    y: u32,
+    // The pixels it fills, which differ slightly from its own size if it's
+    // stretched to meet its neighbours
+    width: u32,
+    height: u32,
+    // Degrees per pixel, for Overlap::HighestRes
+    resolution: f64,
+    // Where quads overlap, the one of highest rank is kept
+    rank: usize,
+    // The part of `region()` inside the map's neatline, which is all of it
+    // unless its collar is trimmed
+    neatline: Region,
+    resampling: Resampling,
+}
+
+impl Placement {
+    fn region(&self) -> Region {
+        Region {
+            x: self.x,
+            y: self.y,
+            width: self.width,
+            height: self.height,
+        }
+    }
+
+    // Maplet (`row`, `col`) resampled to the cell it fills, if the quad is
+    // stretched
+    fn fit(&self, maplet: DynamicImage, row: u32, col: u32) -> DynamicImage {
+        let cell = self.cell(row, col);
+        if maplet.dimensions() == (cell.width, cell.height) {
+            maplet
+        } else {
+            maplet.resize_exact(cell.width, cell.height, self.resampling.filter())
+        }
+    }
+
+    // The pixels maplet (`row`, `col`) fills
+    fn cell(&self, row: u32, col: u32) -> Region {
+        let (columns, rows) = (self.header.long_count, self.header.lat_count);
+        let (left, right) = (
+            cell_start(col, columns, self.width),
+            cell_start(col + 1, columns, self.width),
+        );
+        let (top, bottom) = (
+            cell_start(row, rows, self.height),
+            cell_start(row + 1, rows, self.height),
+        );
+        Region {
+            x: self.x + left,
+            y: self.y + top,
+            width: right - left,
+            height: bottom - top,
+        }
+    }
+
+    // The maplets in `rows` and `cols` assembled, with the pixels they fill
+    fn assemble(
+        &self,
+        tpq: &TpqFile,
+        rows: Range<u32>,
+        cols: Range<u32>,
+    ) -> Result<(Region, RgbaImage)> {
+        let (first, last) = (
+            self.cell(rows.start, cols.start),
+            self.cell(rows.end - 1, cols.end - 1),
+        );
+        let region = Region {
+            width: last.right() - first.x,
+            height: last.bottom() - first.y,
+            ..first
+        };
+        let mut image = RgbaImage::new(region.width, region.height);
+        for row in rows {
+            for col in cols.clone() {
+                let cell = self.cell(row, col);
+                let maplet = self.fit(tpq.maplet(row, col)?, row, col).to_rgba8();
+                image::imageops::replace(&mut image, &maplet, cell.x - region.x, cell.y - region.y);
+            }
+        }
+        Ok((region, image))
+    }
+
+    // The quad within the blank margin around its neatline, looked for in
+    // its outermost maplets
+    fn find_neatline(&self) -> Result<Region> {
+        let tpq = TpqFile::open(&self.path)?;
+        let (rows, cols) = (self.header.lat_count, self.header.long_count);
+        let (_, top) = self.assemble(&tpq, 0..1, 0..cols)?;
+        let (_, bottom) = self.assemble(&tpq, rows - 1..rows, 0..cols)?;
+        let (_, left) = self.assemble(&tpq, 0..rows, 0..1)?;
+        let (_, right) = self.assemble(&tpq, 0..rows, cols - 1..cols)?;
+        let (top, bottom, left, right) = (
+            paper_lines(&top, true, false),
+            paper_lines(&bottom, true, true),
+            paper_lines(&left, false, false),
+            paper_lines(&right, false, true),
+        );
+        let region = self.region();
+        if left + right >= region.width || top + bottom >= region.height {
+            // Blank all over: nothing to tell a collar by
+            return Ok(region);
+        }
+        Ok(Region {
+            x: region.x + left,
+            y: region.y + top,
+            width: region.width - left - right,
+            height: region.height - top - bottom,
+        })
+    }
+}
+
+/// A mosaic of quads, configured builder-style and executed with
+/// [`Mosaic::run`].
+///
+/// The output is written in horizontal stripes, opening only the quads that
+/// intersect the current one, so with the `gdal` feature a GeoTIFF mosaic's
+/// memory use doesn't grow with its size. Other outputs are assembled in
+/// memory. Each quad is opened once, when the stripes reach it. With the
+/// `parallel` feature, the maplets in a stripe are decoded together on the
+/// rayon pool, whichever quads they're from, while the writing stays in
+/// order, so memory is still bounded by the stripe height.
+///
+/// Edges of neighbouring quads within half a pixel of each other are snapped
+/// together, resampling a quad whose resolution differs slightly from the
+/// first's so it meets its neighbours without a gap or an overlap.
+pub struct Mosaic {
+    inputs: Vec<PathBuf>,
+    output: PathBuf,
+    format: Format,
+    format_given: bool,
+    compression: Compression,
+    quality: Quality,
+    resampling: Resampling,
+    alpha: bool,
+    overlap: Overlap,
+    blend: u32,
+    trim_collar: bool,
+    retile: Option<(u32, u32)>,
+    stripe_height: u32,
+    resume: bool,
+    // Rows a resumed run's earlier run already wrote
+    resumed_rows: u32,
+    progress: Option<ProgressCallback>,
+}
+
+impl Mosaic {
+    pub fn new<P: Into<PathBuf>>(inputs: Vec<PathBuf>, output: P) -> Self {
+        Mosaic {
+            inputs,
+            output: output.into(),
+            format: Format::GTiff,
+            format_given: false,
+            compression: Compression::None,
+            quality: Quality::default(),
+            resampling: Resampling::Bilinear,
+            alpha: false,
+            overlap: Overlap::Last,
+            blend: 0,
+            trim_collar: false,
+            retile: None,
+            stripe_height: DEFAULT_STRIPE_HEIGHT,
+            resume: false,
+            resumed_rows: 0,
+            progress: None,
+        }
+    }
+
+    /// Defaults to the format the output's extension calls for, or GeoTIFF.
+    pub fn format(mut self, format: Format) -> Self {
+        self.format = format;
+        self.format_given = true;
+        self
+    }
+
+    pub fn compression(mut self, compression: Compression) -> Self {
+        self.compression = compression;
+        self
+    }
+
+    /// The JPEG quality of JPEG-compressed output.
+    pub fn quality(mut self, quality: impl Into<Quality>) -> Self {
+        self.quality = quality.into();
+        self
+    }
+
+    /// How stretched quads and COG overviews are resampled, bilinearly by
+    /// default.
+    pub fn resampling(mut self, resampling: Resampling) -> Self {
+        self.resampling = resampling;
+        self
+    }
+
+    /// Writes an alpha band, leaving the area no quad covers transparent
+    /// rather than white.
+    pub fn alpha(mut self, alpha: bool) -> Self {
+        self.alpha = alpha;
+        self
+    }
+
+    /// Which quad is kept where quads overlap; defaults to [`Overlap::Last`].
+    pub fn overlap(mut self, overlap: Overlap) -> Self {
+        self.overlap = overlap;
+        self
+    }
+
+    /// Feathers each kept quad into the quads it overlaps over this many
+    /// pixels inside its edge, hiding tone differences between sheets.
+    pub fn blend(mut self, pixels: u32) -> Self {
+        self.blend = pixels;
+        self
+    }
+
+    /// Crops each quad to its neatline before placing it, leaving out the
+    /// white margin and marginalia some products scan with the map so the
+    /// quads beside or beneath show instead. The neatline is found where the
+    /// near-white lines running in from each edge of the quad end.
+    pub fn trim_collar(mut self, trim: bool) -> Self {
+        self.trim_collar = trim;
+        self
+    }
+
+    /// Writes a grid of GeoTIFF tiles of at most `width`×`height` pixels,
+    /// named after the output, and a GeoJSON index of them at
+    /// [`tile_index_path`] instead of one file.
+    pub fn retile(mut self, width: u32, height: u32) -> Self {
+        self.retile = Some((width, height));
+        self
+    }
+
+    /// Rows of pixels written per stripe; quads are held open, and their
+    /// maplets decoded, one stripe at a time.
+    pub fn stripe_height(mut self, rows: u32) -> Self {
+        self.stripe_height = rows.max(1);
+        self
+    }
+
+    /// Continues a retiled mosaic from the tiles a run that stopped early
+    /// finished, as recorded in the journal at
+    /// [`journal_path`](crate::output::journal_path), if it had the same
+    /// inputs and settings. Mosaics written to one file can't be resumed.
+    pub fn resume(mut self, resume: bool) -> Self {
+        self.resume = resume;
+        self
+    }
+
+    /// Reports each maplet as it's written, with its row and column within
+    /// its own quad.
+    pub fn on_progress(mut self, f: impl FnMut(Progress) + Send + 'static) -> Self {
+        self.progress = Some(Box::new(f));
+        self
+    }
+
+    fn report(&mut self, progress: Progress) {
+        if let Some(f) = self.progress.as_mut() {
+            f(progress);
+        }
+    }
+
+    // Tells this mosaic's tiles from another's in a journal
+    fn job(&self) -> Result<String> {
+        let mut job = format!(
+            "{:?} {:?} {:?} {:?} {:?} {} {:?} {} {}",
+            self.retile,
+            self.format,
+            self.compression,
+            self.quality.for_format(self.format),
+            self.resampling,
+            self.alpha,
+            self.overlap,
+            self.blend,
+            self.trim_collar
+        );
+        for input in &self.inputs {
+            job.push_str(&format!(
+                "\n{} {}",
+                input.display(),
+                fs::metadata(input)?.len()
+            ));
+        }
+        Ok(sha256_hex(job.as_bytes()))
+    }
+
+    // The writer, with any rows a resumed run needn't write again
+    fn writer(&self, georef: &GeoRef) -> Result<(Box<dyn OutputWriter + Send>, u32)> {
+        if let Some(tile_size) = self.retile {
+            let mut writer = RetiledWriter::new(
+                &self.output,
+                georef,
+                tile_size,
+                self.format,
+                self.compression,
+            )
+            .quality(self.quality.for_format(self.format))
+            .resampling(self.resampling)
+            .alpha(self.alpha)
+            .job(self.job()?);
+            let rows = if self.resume { writer.resume()? } else { 0 };
+            return Ok((Box::new(writer), rows));
+        }
+        if self.resume {
+            return Err(TopodumpError::Unsupported(
+                "resuming mosaics that aren't retiled",
+            ));
+        }
+        let writer: Box<dyn OutputWriter + Send> = match self.format {
+            Format::GTiff | Format::Cog => Box::new(
+                GeoTiffWriter::new(&self.output, self.format, self.compression)
+                    .quality(self.quality.for_format(self.format))
+                    .resampling(self.resampling)
+                    .alpha(self.alpha),
+            ),
+            Format::Png => Box::new(PngWriter::new(&self.output).alpha(self.alpha)),
+            #[cfg(feature = "mbtiles")]
+            Format::MbTiles => return Err(TopodumpError::Unsupported("mbtiles mosaics")),
+        };
+        Ok((writer, 0))
+    }
+
+    /// Positions every input on a shared pixel grid, returning the placements
+    /// and the mosaic's georeferencing and size.
+    fn plan(&self) -> Result<(Vec<Placement>, GeoRef, u32, u32)> {
+        let mut quads = Vec::new();
+        for path in &self.inputs {
+            let tpq = TpqFile::open(path)?;
+            let (maplet_width, maplet_height) = tpq.maplet_dimensions()?;
+            quads.push((
+                path.clone(),
+                tpq.header().clone(),
+                maplet_width,
+                maplet_height,
+            ));
+        }
+        let (_, first, first_width, first_height) = quads
+            .first()
+            .ok_or(TopodumpError::Unsupported("mosaics without inputs"))?;
+        let resolution = |header: &TpqHeader, maplet_width: u32, maplet_height: u32| -> Result<_> {
+            let (width, height) =
+                header.image_size((maplet_width, maplet_height), DEFAULT_MAX_PIXELS)?;
+            Ok((
+                (header.e_long - header.w_long) / width as f64,
+                (header.n_lat - header.s_lat) / height as f64,
+            ))
+        };
+        let (dx, dy) = resolution(first, *first_width, *first_height)?;
+
+        let west = quads
+            .iter()
+            .map(|(_, header, ..)| header.w_long)
+            .fold(f64::INFINITY, f64::min);
+        let north = quads
+            .iter()
+            .map(|(_, header, ..)| header.n_lat)
+            .fold(f64::NEG_INFINITY, f64::max);
+
+        let longitudes = quads
+            .iter()
+            .flat_map(|(_, header, ..)| [header.w_long, header.e_long])
+            .collect::<Vec<_>>();
+        let latitudes = quads
+            .iter()
+            .flat_map(|(_, header, ..)| [header.n_lat, header.s_lat])
+            .collect::<Vec<_>>();
+        let column = |longitude| ((snap(&longitudes, longitude, dx / 2.0) - west) / dx).round();
+        let row = |latitude| ((north - snap(&latitudes, latitude, dy / 2.0)) / dy).round();
+
+        let mut placements = Vec::new();
+        let (mut width, mut height) = (0u64, 0u64);
+        for (path, header, maplet_width, maplet_height) in quads {
+            let (quad_dx, quad_dy) = resolution(&header, maplet_width, maplet_height)?;
+            if (quad_dx - dx).abs() > dx * RESOLUTION_TOLERANCE
+                || (quad_dy - dy).abs() > dy * RESOLUTION_TOLERANCE
+            {
+                return Err(TopodumpError::Unsupported(
+                    "mosaics of quads with different resolutions",
+                ));
+            }
+            let (left, right) = (column(header.w_long), column(header.e_long));
+            let (top, bottom) = (row(header.n_lat), row(header.s_lat));
+            let region = Region {
+                x: left as u32,
+                y: top as u32,
+                // At least a pixel per maplet
+                width: ((right - left) as u32).max(header.long_count),
+                height: ((bottom - top) as u32).max(header.lat_count),
+            };
+            let mut placement = Placement {
+                x: region.x,
+                y: region.y,
+                width: region.width,
+                height: region.height,
+                path,
+                header,
+                resolution: quad_dx * quad_dy,
+                rank: 0,
+                neatline: region,
+                resampling: self.resampling,
+            };
+            if self.trim_collar {
+                placement.neatline = placement.find_neatline()?;
+            }
+            width = width.max(region.right() as u64);
+            height = height.max(region.bottom() as u64);
+            placements.push(placement);
+        }
+        // Streamed GeoTIFFs are only bounded by the format, and tiles each
+        // by the default; the rest are assembled in memory
+        let max_pixels =
+            if self.retile.is_some() || cfg!(feature = "gdal") && self.format == Format::GTiff {
+                u64::MAX
+            } else {
+                DEFAULT_MAX_PIXELS
+            };
+        let (width, height) = check_image_size(width, height, max_pixels)?;
+
+        // Ranked from the quad kept least to the one kept most
+        let mut order = (0..placements.len()).collect::<Vec<_>>();
+        match self.overlap {
+            Overlap::First => order.reverse(),
+            Overlap::Last => {}
+            Overlap::Newest => order.sort_by_key(|&i| newest_year(&placements[i].header)),
+            Overlap::HighestRes => order.sort_by(|&a, &b| {
+                placements[b]
+                    .resolution
+                    .total_cmp(&placements[a].resolution)
+            }),
+        }
+        for (rank, i) in order.into_iter().enumerate() {
+            placements[i].rank = rank;
+        }
+        placements.sort_by_key(|placement| placement.y);
+
+        let georef = GeoRef {
+            transform: [west, dx, 0.0, north, 0.0, -dy],
+            wkt: NAD27_WKT.to_string(),
+        };
+        Ok((placements, georef, width, height))
+    }
+
+    /// Runs the mosaic, returning the path of the written output, or of the
+    /// tile index if retiled.
+    pub fn run(mut self) -> Result<PathBuf> {
+        self.format = resolve_format(self.format_given.then(|| self.format), &self.output)?;
+        let (placements, georef, width, height) = self.plan()?;
+        let total = placements
+            .iter()
+            .map(|placement| placement.header.long_count * placement.header.lat_count)
+            .sum();
+
+        let (mut writer, resumed_rows) = self.writer(&georef)?;
+        self.resumed_rows = resumed_rows;
+        writer.create(width, height)?;
+        if let Err(err) = self
+            .write_stripes(&placements, height, total, writer.as_mut())
+            .and_then(|()| {
+                writer.set_georef(&georef)?;
+                writer.finalize()
+            })
+        {
+            return Err(err.after_cleanup(&self.output, writer.discard()));
+        }
+
+        let output = match self.retile {
+            Some(_) => tile_index_path(&self.output),
+            None => self.output.clone(),
+        };
+        self.report(Progress::OutputWritten {
+            path: output.clone(),
+        });
+        Ok(output)
+    }
+
+    fn write_stripes(
+        &mut self,
+        placements: &[Placement],
+        height: u32,
+        total: u32,
+        writer: &mut dyn OutputWriter,
+    ) -> Result<()> {
+        let mut quads = OpenQuads::new(placements);
+        let mut top = 0;
+        while top < height {
+            let bottom = top + self.stripe_height;
+            // Each maplet belongs to the stripe its top edge falls in
+            let stripe = (0..placements.len())
+                .filter(|&index| {
+                    let placement = &placements[index];
+                    placement.y < bottom && placement.region().bottom() > top
+                })
+                .map(|index| (index, self.stripe_cells(placements, index, top..bottom)))
+                .collect::<Vec<_>>();
+            // Every maplet shown in the stripe is decoded at once, whichever
+            // quad it's from
+            let mut shown = Vec::new();
+            for (index, cells) in &stripe {
+                quads.open(*index)?;
+                shown.extend(
+                    cells
+                        .iter()
+                        .filter(|(.., parts)| !parts.is_empty())
+                        .map(|&(row, col, _)| (*index, row, col)),
+                );
+            }
+            let mut maplets = decode_cells(&quads, &shown)?.into_iter();
+            for (index, cells) in stripe {
+                self.write_cells(index, cells, &mut maplets, total, &mut quads, writer)?;
+            }
+            quads.release_above(bottom);
+            writer.rows_complete(bottom)?;
+            top = bottom;
+        }
+        Ok(())
+    }
+
+    // The maplets of placement `index` starting in `rows`, with the parts of
+    // them left showing
+    fn stripe_cells(
+        &self,
+        placements: &[Placement],
+        index: usize,
+        rows: Range<u32>,
+    ) -> Vec<(u32, u32, Vec<Region>)> {
+        let placement = &placements[index];
+        // Quads kept over this one, whatever order they're written in
+        let covers = placements
+            .iter()
+            .filter(|other| other.rank > placement.rank)
+            .map(|other| other.neatline)
+            .filter(|cover| cover.intersects(placement.neatline))
+            .collect::<Vec<_>>();
+        (0..placement.header.lat_count)
+            .filter(|&row| rows.contains(&placement.cell(row, 0).y))
+            .flat_map(|row| (0..placement.header.long_count).map(move |col| (row, col)))
+            .map(|(row, col)| {
+                let whole = placement.cell(row, col);
+                if whole.bottom() <= self.resumed_rows {
+                    return (row, col, Vec::new());
+                }
+                let mut parts: Vec<_> =
+                    placement.neatline.intersection(whole).into_iter().collect();
+                for cover in &covers {
+                    parts = parts
+                        .into_iter()
+                        .flat_map(|part| part.subtract(*cover))
+                        .collect();
+                }
+                (row, col, parts)
+            })
+            .collect()
+    }
+
+    // Writes `cells` of placement `index`, taking the maplets of those
+    // showing from `maplets` in order
+    fn write_cells(
+        &mut self,
+        index: usize,
+        cells: Vec<(u32, u32, Vec<Region>)>,
+        maplets: &mut impl Iterator<Item = DynamicImage>,
+        total: u32,
+        quads: &mut OpenQuads,
+        writer: &mut dyn OutputWriter,
+    ) -> Result<()> {
+        let placements = quads.placements;
+        let placement = &placements[index];
+        // Quads this one is kept over, which it's blended into
+        let unders = placements
+            .iter()
+            .enumerate()
+            .filter(|(_, other)| {
+                other.rank < placement.rank && other.neatline.intersects(placement.neatline)
+            })
+            .map(|(index, _)| index)
+            .collect::<Vec<_>>();
+        for (row, col, parts) in cells {
+            if !parts.is_empty() {
+                let whole = placement.cell(row, col);
+                let (x, y) = (whole.x, whole.y);
+                let mut maplet = maplets.next().expect("decoded every maplet shown");
+                if self.blend > 0 && !unders.is_empty() {
+                    maplet = self.feather(placement, whole, maplet, &unders, quads)?;
+                }
+                for part in parts {
+                    if part == whole {
+                        writer.write_region(x, y, &maplet)?;
+                    } else {
+                        let visible =
+                            maplet.crop_imm(part.x - x, part.y - y, part.width, part.height);
+                        writer.write_region(part.x, part.y, &visible)?;
+                    }
+                }
+            }
+            self.report(Progress::MapletDecoded { row, col, total });
+        }
+        Ok(())
+    }
+
+    // `maplet`, filling `cell` of `placement`, with the pixels within
+    // `self.blend` of the quad's edge faded into the topmost quad below them
+    fn feather(
+        &self,
+        placement: &Placement,
+        cell: Region,
+        maplet: DynamicImage,
+        unders: &[usize],
+        quads: &mut OpenQuads,
+    ) -> Result<DynamicImage> {
+        let region = placement.neatline;
+        // Most maplets are nowhere near the edge
+        if cell.x >= region.x + self.blend
+            && cell.right() + self.blend <= region.right()
+            && cell.y >= region.y + self.blend
+            && cell.bottom() + self.blend <= region.bottom()
+        {
+            return Ok(maplet);
+        }
+        let mut pixels = maplet.to_rgba8();
+        for (px, py, pixel) in pixels.enumerate_pixels_mut() {
+            let (x, y) = (cell.x + px, cell.y + py);
+            if !region.contains(x, y) {
+                continue;
+            }
+            let distance = (x - region.x)
+                .min(region.right() - 1 - x)
+                .min(y - region.y)
+                .min(region.bottom() - 1 - y);
+            if distance >= self.blend {
+                continue;
+            }
+            let under = unders
+                .iter()
+                .copied()
+                .filter(|&index| quads.placements[index].neatline.contains(x, y))
+                .max_by_key(|&index| quads.placements[index].rank);
+            if let Some(under) = under {
+                let below = quads.pixel(under, x, y)?;
+                let weight = (distance + 1) as f32 / (self.blend + 1) as f32;
+                for channel in 0..4 {
+                    pixel[channel] = (pixel[channel] as f32 * weight
+                        + below[channel] as f32 * (1.0 - weight))
+                        .round() as u8;
+                }
+            }
+        }
+        Ok(DynamicImage::ImageRgba8(pixels))
+    }
+}
+
+/// The inputs of a mosaic: `paths`, with directories replaced by every tpq
+/// file under them.
+pub fn mosaic_inputs(paths: &[PathBuf]) -> Result<Vec<PathBuf>> {
+    let mut inputs = Vec::new();
+    for path in paths {
+        if path.is_dir() {
+            inputs.extend(find_tpq_files(path)?);
+        } else {
+            inputs.push(path.clone());
+        }
+    }
+    Ok(inputs)
+}
+
+fn read_header(path: &Path) -> Result<TpqHeader> {
+    let mut header = read_tpq_header(&mut BufReader::new(File::open(path)?))?;
+    header.normalize_bounds()?;
+    Ok(header)
+}
+
+/// The quads among `inputs` whose bounds intersect the extent
+/// (`west`, `south`, `east`, `north`), told from their headers alone.
+pub fn quads_in_extent(
+    inputs: Vec<PathBuf>,
+    (west, south, east, north): (f64, f64, f64, f64),
+) -> Result<Vec<PathBuf>> {
+    let mut selected = Vec::new();
+    for input in inputs {
+        let header = read_header(&input)?;
+        if header.w_long < east
+            && west < header.e_long
+            && header.s_lat < north
+            && south < header.n_lat
+        {
+            selected.push(input);
+        }
+    }
+    if selected.is_empty() {
+        return Err(TopodumpError::NoQuadsInExtent {
+            west,
+            south,
+            east,
+            north,
+        });
+    }
+    Ok(selected)
+}
+
+/// A quad found more than once among a mosaic's inputs, as when the discs
+/// of neighbouring states both carry a border quad.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Duplicate {
+    pub kept: PathBuf,
+    pub dropped: Vec<PathBuf>,
+}
+
+fn same_quad(a: &TpqHeader, b: &TpqHeader) -> bool {
+    let (x, y) = (
+        (a.e_long - a.w_long) * DUPLICATE_TOLERANCE,
+        (a.n_lat - a.s_lat) * DUPLICATE_TOLERANCE,
+    );
+    (a.w_long - b.w_long).abs() <= x
+        && (a.e_long - b.e_long).abs() <= x
+        && (a.n_lat - b.n_lat).abs() <= y
+        && (a.s_lat - b.s_lat).abs() <= y
+}
+
+// Degrees of longitude per pixel
+fn quad_resolution(path: &Path) -> Result<f64> {
+    let tpq = TpqFile::open(path)?;
+    let (maplet_width, _) = tpq.maplet_dimensions()?;
+    let header = tpq.header();
+    Ok((header.e_long - header.w_long) / (header.long_count * maplet_width).max(1) as f64)
+}
+
+/// `inputs` with every quad whose header gives the same bounds as another's
+/// left out but one, chosen by `keep` as [`Mosaic::overlap`] chooses between
+/// overlapping quads, along with the duplicates left out. Quads are kept in
+/// the order given.
+pub fn deduplicate(inputs: Vec<PathBuf>, keep: Overlap) -> Result<(Vec<PathBuf>, Vec<Duplicate>)> {
+    let headers = inputs
+        .iter()
+        .map(|input| read_header(input))
+        .collect::<Result<Vec<_>>>()?;
+    let mut groups: Vec<Vec<usize>> = Vec::new();
+    for (i, header) in headers.iter().enumerate() {
+        match groups
+            .iter_mut()
+            .find(|group| same_quad(&headers[group[0]], header))
+        {
+            Some(group) => group.push(i),
+            None => groups.push(vec![i]),
+        }
+    }
+
+    let mut kept = Vec::new();
+    let mut duplicates = Vec::new();
+    for group in groups {
+        let best = match keep {
+            _ if group.len() == 1 => group[0],
+            Overlap::First => group[0],
+            Overlap::Last => group[group.len() - 1],
+            // The last of the newest
+            Overlap::Newest => *group
+                .iter()
+                .max_by_key(|&&i| newest_year(&headers[i]))
+                .expect("groups aren't empty"),
+            Overlap::HighestRes => {
+                let mut best = (group[0], quad_resolution(&inputs[group[0]])?);
+                for &i in &group[1..] {
+                    let resolution = quad_resolution(&inputs[i])?;
+                    if resolution <= best.1 {
+                        best = (i, resolution);
+                    }
+                }
+                best.0
+            }
+        };
+        if group.len() > 1 {
+            duplicates.push(Duplicate {
+                kept: inputs[best].clone(),
+                dropped: group
+                    .iter()
+                    .filter(|&&i| i != best)
+                    .map(|&i| inputs[i].clone())
+                    .collect(),
+            });
+        }
+        kept.push(best);
+    }
+    kept.sort_unstable();
+    Ok((
+        kept.into_iter().map(|i| inputs[i].clone()).collect(),
+        duplicates,
+    ))
+}
+
+/// `inputs` split by the series of each quad, as its header and maplet size
+/// tell it, since quads of different scales don't share a pixel grid. Quads
+/// keep their order within a series.
+pub fn group_by_series(inputs: Vec<PathBuf>) -> Result<BTreeMap<Option<Series>, Vec<PathBuf>>> {
+    let mut groups = BTreeMap::<_, Vec<_>>::new();
+    for input in inputs {
+        let tpq = TpqFile::open(&input)?;
+        let (_, maplet_height) = tpq.maplet_dimensions()?;
+        let series = tpq.header().series(maplet_height);
+        groups.entry(series).or_default().push(input);
+    }
+    Ok(groups)
+}
+
+/// Where the mosaic of one series of a set of quads spanning several goes:
+/// `output` with the series' slug, or `unknown`, added to its name, so
+/// `arizona.tif` becomes `arizona_24k.tif`.
+pub fn series_output(output: &Path, series: Option<Series>) -> PathBuf {
+    let stem = output
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let slug = series.map_or("unknown", Series::slug);
+    let name = match output.extension() {
+        Some(extension) => format!("{}_{}.{}", stem, slug, extension.to_string_lossy()),
+        None => format!("{}_{}", stem, slug),
+    };
+    output.with_file_name(name)
+}
@@ -0,0 +1,367 @@
+#[cfg(not(feature = "gdal"))]
+use std::fs::File;
+#[cfg(not(feature = "gdal"))]
+use std::io::{BufWriter, Write};
+#[cfg(not(feature = "gdal"))]
+use std::path::Path;
+use std::path::PathBuf;
+
+use image::DynamicImage;
+
+use super::part::PartFile;
+#[cfg(not(feature = "gdal"))]
+use super::tiff::encode_geotiff;
+use super::OutputWriter;
+#[cfg(not(feature = "gdal"))]
+use super::{canvas, paste};
+use crate::cancel::CancelToken;
+#[cfg(not(feature = "gdal"))]
+use crate::cancel::CancellableWriter;
+#[cfg(feature = "gdal")]
+use crate::conversion::quality_option;
+use crate::conversion::{Compression, Format, Resampling};
+use crate::error::{Result, TopodumpError};
+#[cfg(feature = "gdal")]
+use crate::gdal_util::{self, create_copy, OwnedDataset};
+use crate::georef::GeoRef;
+
+// Tiles of the GDAL dataset written as maplets arrive
+#[cfg(feature = "gdal")]
+const BLOCK_SIZE: &str = "256";
+
+/// GeoTIFF and COG output.
+///
+/// With the `gdal` feature, GeoTIFF maplets are streamed into a tiled GDAL
+/// dataset so memory use doesn't grow with the quad, while COGs are composed
+/// in a MEM dataset and copied out compressed in a single pass; compressed
+/// variants are available. Without it, the image is buffered, GeoTIFF tags are written
+/// directly and only uncompressed GeoTIFFs are available.
+pub struct GeoTiffWriter {
+    path: PathBuf,
+    format: Format,
+    compression: Compression,
+    quality: Option<u8>,
+    resampling: Resampling,
+    quantize: Option<u16>,
+    cancel: Option<CancelToken>,
+    alpha: bool,
+    grayscale: bool,
+    icc_profile: Option<Vec<u8>>,
+    keep_partial: bool,
+    part: Option<PartFile>,
+    #[cfg(feature = "gdal")]
+    dataset: Option<OwnedDataset>,
+    #[cfg(not(feature = "gdal"))]
+    collage_img: DynamicImage,
+    georef: Option<GeoRef>,
+}
+
+impl GeoTiffWriter {
+    pub fn new<P: Into<PathBuf>>(path: P, format: Format, compression: Compression) -> Self {
+        GeoTiffWriter {
+            path: path.into(),
+            format,
+            compression,
+            quality: None,
+            resampling: Resampling::Bilinear,
+            quantize: None,
+            cancel: None,
+            alpha: false,
+            grayscale: false,
+            icc_profile: None,
+            keep_partial: false,
+            part: None,
+            #[cfg(feature = "gdal")]
+            dataset: None,
+            #[cfg(not(feature = "gdal"))]
+            collage_img: canvas(0, 0, false),
+            georef: None,
+        }
+    }
+
+    /// Aborts encoding once `token` is cancelled.
+    pub fn cancel_token(mut self, token: Option<CancelToken>) -> Self {
+        self.cancel = token;
+        self
+    }
+
+    /// The JPEG quality with JPEG compression, or GDAL's default if `None`.
+    /// Only GDAL writes compressed output.
+    pub fn quality(mut self, quality: Option<u8>) -> Self {
+        self.quality = quality;
+        self
+    }
+
+    /// How COG overviews are downsampled, bilinearly by default.
+    pub fn resampling(mut self, resampling: Resampling) -> Self {
+        self.resampling = resampling;
+        self
+    }
+
+    /// Writes a paletted GeoTIFF of at most this many colours, up to 256,
+    /// composed in memory. Needs the `gdal` feature, and leaves out alpha.
+    pub fn quantize(mut self, colors: Option<u16>) -> Self {
+        self.quantize = colors;
+        self
+    }
+
+    /// Adds an alpha band, e.g. to keep areas outside a cutline transparent.
+    pub fn alpha(mut self, alpha: bool) -> Self {
+        self.alpha = alpha;
+        self
+    }
+
+    /// Writes a single gray band, plus any alpha, in place of RGB. Without
+    /// the `gdal` feature, grayscale with alpha is written as RGBA.
+    pub fn grayscale(mut self, grayscale: bool) -> Self {
+        self.grayscale = grayscale;
+        self
+    }
+
+    /// Embeds an ICC profile, as [`IccProfile::bytes`](crate::IccProfile::bytes)
+    /// gives it.
+    pub fn icc_profile(mut self, profile: Option<Vec<u8>>) -> Self {
+        self.icc_profile = profile;
+        self
+    }
+
+    /// Finishes writing an output that couldn't be georeferenced instead of
+    /// removing it, then fails with [`TopodumpError::GeoreferenceFailed`].
+    pub fn keep_partial(mut self, keep_partial: bool) -> Self {
+        self.keep_partial = keep_partial;
+        self
+    }
+
+    #[cfg(feature = "gdal")]
+    fn bands(&self) -> u32 {
+        let colour = if self.grayscale { 1 } else { 3 };
+        colour + self.alpha as u32
+    }
+
+    fn is_cancelled(&self) -> bool {
+        self.cancel
+            .as_ref()
+            .map_or(false, CancelToken::is_cancelled)
+    }
+
+    fn check_cancelled(&self) -> Result<()> {
+        if self.is_cancelled() {
+            Err(TopodumpError::Cancelled)
+        } else {
+            Ok(())
+        }
+    }
+
+    // Leaves any earlier output at the real path alone
+    fn remove_part(&mut self) -> Result<()> {
+        if let Some(part) = self.part.take() {
+            part.remove()?;
+        }
+        Ok(())
+    }
+
+    // COGs and paletted output can only be produced by copying a finished
+    // dataset
+    fn is_direct(&self) -> bool {
+        self.format == Format::GTiff && self.quantize.is_none()
+    }
+
+    #[cfg(not(feature = "gdal"))]
+    fn encode_tiff(&self, path: &Path, georef: Option<&GeoRef>) -> Result<()> {
+        let file = File::create(path)?;
+        let mut writer = BufWriter::new(CancellableWriter::new(file, self.cancel.clone()));
+        let gray;
+        let image = if self.grayscale && !self.alpha {
+            gray = DynamicImage::ImageLuma8(self.collage_img.to_luma8());
+            &gray
+        } else {
+            &self.collage_img
+        };
+        encode_geotiff(&mut writer, image, georef, self.icc_profile.as_deref())?;
+        writer.flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(feature = "gdal")]
+impl OutputWriter for GeoTiffWriter {
+    fn create(&mut self, width: u32, height: u32) -> Result<()> {
+        let part = PartFile::new(&self.path);
+        self.dataset = Some(if self.is_direct() {
+            let mut options = vec![
+                ("TILED", "YES"),
+                ("BLOCKXSIZE", BLOCK_SIZE),
+                ("BLOCKYSIZE", BLOCK_SIZE),
+                (
+                    "PHOTOMETRIC",
+                    if self.grayscale { "MINISBLACK" } else { "RGB" },
+                ),
+                ("ALPHA", if self.alpha { "YES" } else { "NO" }),
+                ("COMPRESS", self.compression.gdal_name()),
+                ("BIGTIFF", "IF_SAFER"),
+                // Compress blocks on GDAL's worker threads while maplets keep arriving
+                ("NUM_THREADS", "ALL_CPUS"),
+            ];
+            let quality = quality_option(self.format, self.compression, self.quality);
+            if let Some((name, value)) = &quality {
+                options.push((*name, value.as_str()));
+            }
+            let dataset = gdal_util::create(
+                "GTiff",
+                part.path(),
+                (width, height),
+                self.bands(),
+                &options,
+            )?;
+            if let Some(profile) = &self.icc_profile {
+                gdal_util::set_icc_profile(&dataset.0, profile)?;
+            }
+            dataset
+        } else {
+            gdal_util::create_mem(width, height, self.bands())?
+        });
+        self.part = Some(part);
+        Ok(())
+    }
+
+    fn write_region(&mut self, x: u32, y: u32, image: &DynamicImage) -> Result<()> {
+        self.check_cancelled()?;
+        let dataset = &self.dataset.as_ref().expect("create wasn't called").0;
+        gdal_util::write_image(dataset, x, y, image, self.bands())
+    }
+
+    fn set_georef(&mut self, georef: &GeoRef) -> Result<()> {
+        self.georef = Some(georef.clone());
+        Ok(())
+    }
+
+    fn finalize(&mut self) -> Result<()> {
+        let dataset = self.dataset.take().expect("create wasn't called");
+        let part = self.part.take().expect("create wasn't called");
+
+        let georeferenced = match &self.georef {
+            Some(georef) => gdal_util::georeference(&dataset.0, georef),
+            None => Ok(()),
+        };
+        let mut georef_failure = None;
+        match georeferenced {
+            Ok(()) => {}
+            Err(geo_err) if self.keep_partial => georef_failure = Some(geo_err),
+            Err(geo_err) => {
+                drop(dataset);
+                return Err(part.remove_after(geo_err));
+            }
+        }
+
+        if self.is_direct() {
+            // Closing flushes the remaining blocks to disk
+            drop(dataset);
+            self.check_cancelled()?;
+        } else {
+            self.check_cancelled()?;
+            let mut options = vec![
+                ("COMPRESS", self.compression.gdal_name()),
+                ("NUM_THREADS", "ALL_CPUS"),
+            ];
+            if self.format == Format::Cog {
+                options.push(("RESAMPLING", self.resampling.gdal_name()));
+            }
+            let quality = quality_option(self.format, self.compression, self.quality);
+            if let Some((name, value)) = &quality {
+                options.push((*name, value.as_str()));
+            }
+            match self.quantize {
+                Some(colors) => {
+                    let paletted = gdal_util::paletted(&dataset.0, self.bands(), colors)?;
+                    // Georeferenced again, as the palette is a copy
+                    let georeferenced = match &self.georef {
+                        Some(georef) => gdal_util::georeference(&paletted.0, georef),
+                        None => Ok(()),
+                    };
+                    match georeferenced {
+                        Ok(()) => {}
+                        Err(geo_err) if self.keep_partial => {
+                            georef_failure.get_or_insert(geo_err);
+                        }
+                        Err(geo_err) => {
+                            drop((paletted, dataset));
+                            return Err(part.remove_after(geo_err));
+                        }
+                    }
+                    if let Some(profile) = &self.icc_profile {
+                        gdal_util::set_icc_profile(&paletted.0, profile)?;
+                    }
+                    create_copy(&paletted.0, self.format.driver(), part.path(), &options)?;
+                }
+                None => {
+                    if let Some(profile) = &self.icc_profile {
+                        gdal_util::set_icc_profile(&dataset.0, profile)?;
+                    }
+                    create_copy(&dataset.0, self.format.driver(), part.path(), &options)?
+                }
+            }
+            self.check_cancelled()?;
+        }
+        part.commit()?;
+
+        match georef_failure {
+            Some(source) => Err(TopodumpError::GeoreferenceFailed {
+                path: self.path.clone(),
+                source: Box::new(source),
+            }),
+            None => Ok(()),
+        }
+    }
+
+    fn discard(&mut self) -> Result<()> {
+        self.dataset = None;
+        self.remove_part()
+    }
+}
+
+#[cfg(not(feature = "gdal"))]
+impl OutputWriter for GeoTiffWriter {
+    fn create(&mut self, width: u32, height: u32) -> Result<()> {
+        self.collage_img = canvas(width, height, self.alpha);
+        self.part = Some(PartFile::new(&self.path));
+        Ok(())
+    }
+
+    fn write_region(&mut self, x: u32, y: u32, image: &DynamicImage) -> Result<()> {
+        paste(&mut self.collage_img, image, x, y);
+        Ok(())
+    }
+
+    fn set_georef(&mut self, georef: &GeoRef) -> Result<()> {
+        self.georef = Some(georef.clone());
+        Ok(())
+    }
+
+    fn finalize(&mut self) -> Result<()> {
+        if self.quantize.is_some() {
+            return Err(TopodumpError::Unsupported(
+                "paletted GeoTIFFs require the gdal feature",
+            ));
+        }
+        if !self.is_direct() || self.compression != Compression::None {
+            return Err(TopodumpError::Unsupported(
+                "compressed and COG output require the gdal feature",
+            ));
+        }
+
+        self.check_cancelled()?;
+        let part = self.part.take().expect("create wasn't called");
+        if let Err(err) = self.encode_tiff(part.path(), self.georef.as_ref()) {
+            drop(part);
+            self.check_cancelled()?;
+            return Err(err);
+        }
+        part.commit()?;
+
+        Ok(())
+    }
+
+    fn discard(&mut self) -> Result<()> {
+        self.remove_part()
+    }
+}
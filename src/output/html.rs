@@ -0,0 +1,229 @@
+use std::fs;
+use std::path::PathBuf;
+
+use image::{DynamicImage, Rgba, RgbaImage};
+
+use super::OutputWriter;
+use crate::conversion::Resampling;
+use crate::error::Result;
+use crate::georef::GeoRef;
+use crate::pixels;
+use crate::tiles::{
+    encode_png, image_for_zoom, is_empty, native_zoom, render_tile, tiles_covering,
+    OVERVIEW_LEVELS, TILE_SIZE,
+};
+use crate::vrt::escape_xml;
+
+// Zooms Leaflet keeps enlarging the deepest tiles for
+const OVERZOOM_LEVELS: u8 = 2;
+
+const LEAFLET: &str = "https://unpkg.com/leaflet@1.9.4/dist/leaflet";
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Passes everything on to another writer while keeping a copy of the
+/// image, then writes it as a pyramid of Web Mercator PNG tiles,
+/// `tiles/{z}/{x}/{y}.png` in a directory, with an `index.html` showing
+/// them in Leaflet, so a converted quad can be looked over in a browser
+/// without any other tools.
+///
+/// As with [`MbTilesWriter`](super::MbTilesWriter), NAD27 coordinates are
+/// used as-is.
+pub struct HtmlPreviewWriter {
+    inner: Box<dyn OutputWriter + Send>,
+    dir: PathBuf,
+    name: String,
+    resampling: Resampling,
+    wms_xml: bool,
+    url: Option<String>,
+    collage_img: RgbaImage,
+    georef: Option<GeoRef>,
+}
+
+impl HtmlPreviewWriter {
+    /// Writes the preview of an image called `name` into `dir`.
+    pub fn new<P: Into<PathBuf>>(inner: Box<dyn OutputWriter + Send>, dir: P, name: &str) -> Self {
+        HtmlPreviewWriter {
+            inner,
+            dir: dir.into(),
+            name: name.to_string(),
+            resampling: Resampling::Bilinear,
+            wms_xml: false,
+            url: None,
+            collage_img: RgbaImage::new(0, 0),
+            georef: None,
+        }
+    }
+
+    /// How tiles are resampled from the image, bilinearly by default.
+    pub fn resampling(mut self, resampling: Resampling) -> Self {
+        self.resampling = resampling;
+        self
+    }
+
+    /// Also writes `wms.xml`, a GDAL WMS description of the tiles, so GDAL
+    /// based clients can open them as a layer. They're fetched from under
+    /// `url` if the directory will be served from there, and otherwise
+    /// from the directory itself.
+    pub fn wms_xml(mut self, url: Option<String>) -> Self {
+        self.wms_xml = true;
+        self.url = url;
+        self
+    }
+
+    fn save_wms_xml(&self, max_zoom: u8) -> Result<()> {
+        let url = match &self.url {
+            Some(url) => url.trim_end_matches('/').to_string(),
+            None => {
+                let dir = self.dir.canonicalize()?;
+                let dir = dir.to_string_lossy().replace('\\', "/");
+                format!(
+                    "file://{}{}",
+                    if dir.starts_with('/') { "" } else { "/" },
+                    dir
+                )
+            }
+        };
+        // The whole Web Mercator world, as GDAL's TMS driver expects
+        let half_world = 20_037_508.342_789_244;
+        let xml = format!(
+            r#"<GDAL_WMS>
+  <Service name="TMS">
+    <ServerUrl>{url}/tiles/${{z}}/${{x}}/${{y}}.png</ServerUrl>
+  </Service>
+  <DataWindow>
+    <UpperLeftX>-{half_world}</UpperLeftX>
+    <UpperLeftY>{half_world}</UpperLeftY>
+    <LowerRightX>{half_world}</LowerRightX>
+    <LowerRightY>-{half_world}</LowerRightY>
+    <TileLevel>{max_zoom}</TileLevel>
+    <TileCountX>1</TileCountX>
+    <TileCountY>1</TileCountY>
+    <YOrigin>top</YOrigin>
+  </DataWindow>
+  <Projection>EPSG:3857</Projection>
+  <BlockSizeX>{tile_size}</BlockSizeX>
+  <BlockSizeY>{tile_size}</BlockSizeY>
+  <BandsCount>4</BandsCount>
+  <ZeroBlockHttpCodes>204,404</ZeroBlockHttpCodes>
+  <ZeroBlockOnServerException>true</ZeroBlockOnServerException>
+</GDAL_WMS>
+"#,
+            url = escape_xml(&url),
+            half_world = half_world,
+            max_zoom = max_zoom,
+            tile_size = TILE_SIZE,
+        );
+        fs::write(self.dir.join("wms.xml"), xml)?;
+        Ok(())
+    }
+
+    fn save(&self, georef: &GeoRef) -> Result<()> {
+        let bounds = georef.bounds(self.collage_img.width(), self.collage_img.height());
+        let max_zoom = native_zoom(georef.transform[1]);
+        let min_zoom = max_zoom.saturating_sub(OVERVIEW_LEVELS);
+        for z in min_zoom..=max_zoom {
+            let (image, transform) =
+                image_for_zoom(&self.collage_img, &georef.transform, z, self.resampling);
+            for tile in tiles_covering(bounds, z) {
+                let tile_img = render_tile(&image, &transform, tile, self.resampling);
+                if is_empty(&tile_img) {
+                    continue;
+                }
+                let dir = self
+                    .dir
+                    .join("tiles")
+                    .join(tile.z.to_string())
+                    .join(tile.x.to_string());
+                fs::create_dir_all(&dir)?;
+                fs::write(dir.join(format!("{}.png", tile.y)), encode_png(&tile_img)?)?;
+            }
+        }
+
+        let (west, south, east, north) = bounds;
+        let index = format!(
+            r#"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<meta name="viewport" content="width=device-width, initial-scale=1">
+<title>{name}</title>
+<link rel="stylesheet" href="{leaflet}.css">
+<script src="{leaflet}.js"></script>
+<style>html, body, #map {{ height: 100%; margin: 0; }}</style>
+</head>
+<body>
+<div id="map"></div>
+<script>
+var bounds = [[{south}, {west}], [{north}, {east}]];
+var map = L.map("map");
+L.tileLayer("tiles/{{z}}/{{x}}/{{y}}.png", {{
+  minZoom: {min_zoom},
+  maxNativeZoom: {max_zoom},
+  maxZoom: {overzoom},
+  bounds: bounds,
+  attribution: "{name}"
+}}).addTo(map);
+map.fitBounds(bounds);
+</script>
+</body>
+</html>
+"#,
+            name = escape_html(&self.name),
+            leaflet = LEAFLET,
+            south = south,
+            west = west,
+            north = north,
+            east = east,
+            min_zoom = min_zoom,
+            max_zoom = max_zoom,
+            overzoom = max_zoom + OVERZOOM_LEVELS,
+        );
+        fs::write(self.dir.join("index.html"), index)?;
+        if self.wms_xml {
+            self.save_wms_xml(max_zoom)?;
+        }
+        Ok(())
+    }
+}
+
+impl OutputWriter for HtmlPreviewWriter {
+    fn create(&mut self, width: u32, height: u32) -> Result<()> {
+        self.inner.create(width, height)?;
+        self.collage_img = RgbaImage::from_pixel(width, height, Rgba([255, 255, 255, 255]));
+        Ok(())
+    }
+
+    fn write_region(&mut self, x: u32, y: u32, image: &DynamicImage) -> Result<()> {
+        self.inner.write_region(x, y, image)?;
+        pixels::copy_into(&mut self.collage_img, &*pixels::rgba(image), x, y);
+        Ok(())
+    }
+
+    fn set_georef(&mut self, georef: &GeoRef) -> Result<()> {
+        self.georef = Some(georef.clone());
+        self.inner.set_georef(georef)
+    }
+
+    fn rows_complete(&mut self, rows: u32) -> Result<()> {
+        self.inner.rows_complete(rows)
+    }
+
+    fn finalize(&mut self) -> Result<()> {
+        self.inner.finalize()?;
+        match self.georef.clone() {
+            Some(georef) => self.save(&georef),
+            // Tiles can't be placed without georeferencing
+            None => Ok(()),
+        }
+    }
+
+    fn discard(&mut self) -> Result<()> {
+        self.inner.discard()
+    }
+}
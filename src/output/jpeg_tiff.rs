@@ -0,0 +1,292 @@
+//! JPEG-compressed tiled TIFFs built from the maplets' own JPEG streams,
+//! skipping the decode and re-encode entirely.
+
+use std::fs::File;
+use std::io::{BufWriter, Seek, SeekFrom, Write};
+use std::path::Path;
+
+use super::part::PartFile;
+use super::tiff::{
+    GEO_KEY_DIRECTORY, ICC_PROFILE, MODEL_PIXEL_SCALE, MODEL_TIEPOINT, NAD27_GEO_KEYS,
+};
+use crate::error::{Result, TopodumpError};
+use crate::georef::{GeoRef, NAD27_WKT};
+use crate::tpq::TpqFile;
+
+// TIFF requires tile sizes to be multiples of 16
+const TILE_ALIGNMENT: u32 = 16;
+
+const PHOTOMETRIC_MIN_IS_BLACK: u16 = 1;
+const PHOTOMETRIC_RGB: u16 = 2;
+const PHOTOMETRIC_YCBCR: u16 = 6;
+const COMPRESSION_JPEG: u16 = 7;
+
+/// What a baseline JPEG's frame header and markers say about it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct JpegInfo {
+    width: u32,
+    height: u32,
+    components: u8,
+    // Luma sampling factors, i.e. chroma subsampling for YCbCr
+    sampling: (u8, u8),
+    // An Adobe marker with transform 0 means the samples are RGB
+    rgb: bool,
+}
+
+fn read_u16(data: &[u8], at: usize) -> Option<u16> {
+    Some(u16::from_be_bytes([*data.get(at)?, *data.get(at + 1)?]))
+}
+
+fn jpeg_info(data: &[u8]) -> Option<JpegInfo> {
+    if data.get(..2)? != [0xff, 0xd8] {
+        return None;
+    }
+    let mut rgb = false;
+    let mut at = 2;
+    loop {
+        if *data.get(at)? != 0xff {
+            return None;
+        }
+        let marker = *data.get(at + 1)?;
+        match marker {
+            // Fill bytes and markers without a payload
+            0xff => {
+                at += 1;
+                continue;
+            }
+            0x01 | 0xd0..=0xd7 => {
+                at += 2;
+                continue;
+            }
+            _ => {}
+        }
+        let len = read_u16(data, at + 2)? as usize;
+        let segment = data.get(at + 4..at + 2 + len)?;
+        match marker {
+            // Baseline and extended sequential frames; TIFF can't hold progressive ones
+            0xc0 | 0xc1 => {
+                let components = *segment.get(5)?;
+                let sampling = *segment.get(7)?;
+                return Some(JpegInfo {
+                    height: read_u16(segment, 1)? as u32,
+                    width: read_u16(segment, 3)? as u32,
+                    components,
+                    sampling: (sampling >> 4, sampling & 0x0f),
+                    rgb,
+                });
+            }
+            0xee if segment.starts_with(b"Adobe") => rgb = segment.get(11) == Some(&0),
+            // Any other frame type, or scan data before a frame
+            0xc2..=0xcf | 0xda => return None,
+            _ => {}
+        }
+        at += 2 + len;
+    }
+}
+
+/// Whether every maplet can become a TIFF tile as-is, returning their shared
+/// layout if so.
+fn tile_layout(tpq: &TpqFile) -> Result<Option<JpegInfo>> {
+    let mut layout = None;
+    for maplet in tpq.raw_maplets() {
+        let (_, _, data) = maplet?;
+        let info = match jpeg_info(&data) {
+            Some(info) => info,
+            None => return Ok(None),
+        };
+        if layout.map_or(false, |layout| layout != info) {
+            return Ok(None);
+        }
+        layout = Some(info);
+    }
+    Ok(layout.filter(|info| {
+        info.width % TILE_ALIGNMENT == 0
+            && info.height % TILE_ALIGNMENT == 0
+            && match info.components {
+                1 => true,
+                3 => {
+                    info.rgb
+                        || ([1, 2, 4].contains(&info.sampling.0)
+                            && [1, 2, 4].contains(&info.sampling.1))
+                }
+                _ => false,
+            }
+    }))
+}
+
+enum Value {
+    Undefined(Vec<u8>),
+    Short(Vec<u16>),
+    Long(Vec<u32>),
+    Double(Vec<f64>),
+}
+
+impl Value {
+    fn field_type(&self) -> u16 {
+        match self {
+            Value::Undefined(_) => 7,
+            Value::Short(_) => 3,
+            Value::Long(_) => 4,
+            Value::Double(_) => 12,
+        }
+    }
+
+    fn count(&self) -> usize {
+        match self {
+            Value::Undefined(values) => values.len(),
+            Value::Short(values) => values.len(),
+            Value::Long(values) => values.len(),
+            Value::Double(values) => values.len(),
+        }
+    }
+
+    fn bytes(&self) -> Vec<u8> {
+        match self {
+            Value::Undefined(values) => values.clone(),
+            Value::Short(values) => values.iter().flat_map(|v| v.to_le_bytes()).collect(),
+            Value::Long(values) => values.iter().flat_map(|v| v.to_le_bytes()).collect(),
+            Value::Double(values) => values.iter().flat_map(|v| v.to_le_bytes()).collect(),
+        }
+    }
+}
+
+fn to_u32(offset: u64) -> Result<u32> {
+    if offset > u32::MAX as u64 {
+        return Err(TopodumpError::Unsupported(
+            "JPEG passthrough output larger than 4 GB",
+        ));
+    }
+    Ok(offset as u32)
+}
+
+fn write_ifd<W: Write + Seek>(writer: &mut W, mut entries: Vec<(u16, Value)>) -> Result<u32> {
+    entries.sort_by_key(|(tag, _)| *tag);
+    let ifd_offset = writer.seek(SeekFrom::End(0))?;
+    let mut extra_offset = ifd_offset + 2 + 12 * entries.len() as u64 + 4;
+
+    let mut ifd = Vec::new();
+    let mut extra = Vec::new();
+    ifd.extend_from_slice(&(entries.len() as u16).to_le_bytes());
+    for (tag, value) in &entries {
+        let bytes = value.bytes();
+        ifd.extend_from_slice(&tag.to_le_bytes());
+        ifd.extend_from_slice(&value.field_type().to_le_bytes());
+        ifd.extend_from_slice(&(value.count() as u32).to_le_bytes());
+        if bytes.len() <= 4 {
+            let mut inline = [0u8; 4];
+            inline[..bytes.len()].copy_from_slice(&bytes);
+            ifd.extend_from_slice(&inline);
+        } else {
+            ifd.extend_from_slice(&to_u32(extra_offset)?.to_le_bytes());
+            extra_offset += bytes.len() as u64;
+            extra.extend_from_slice(&bytes);
+        }
+    }
+    // No further IFDs
+    ifd.extend_from_slice(&0u32.to_le_bytes());
+
+    writer.write_all(&ifd)?;
+    writer.write_all(&extra)?;
+    to_u32(ifd_offset)
+}
+
+fn write_tiles(
+    writer: &mut BufWriter<File>,
+    tpq: &TpqFile,
+    info: JpegInfo,
+    georef: &GeoRef,
+    icc_profile: Option<&[u8]>,
+    on_maplet: &mut dyn FnMut(u32, u32) -> Result<()>,
+) -> Result<()> {
+    // Little-endian header, its IFD offset patched in once the tiles are written
+    writer.write_all(&[b'I', b'I', 42, 0, 0, 0, 0, 0])?;
+
+    let mut offsets = Vec::new();
+    let mut counts = Vec::new();
+    for maplet in tpq.raw_maplets() {
+        let (row, col, data) = maplet?;
+        offsets.push(to_u32(writer.stream_position()?)?);
+        counts.push(data.len() as u32);
+        writer.write_all(&data)?;
+        // Keep every tile at an even offset, as TIFF readers expect
+        if data.len() % 2 == 1 {
+            writer.write_all(&[0])?;
+        }
+        on_maplet(row, col)?;
+    }
+
+    let header = tpq.header();
+    let samples = info.components as usize;
+    let photometric = match (info.components, info.rgb) {
+        (1, _) => PHOTOMETRIC_MIN_IS_BLACK,
+        (_, true) => PHOTOMETRIC_RGB,
+        _ => PHOTOMETRIC_YCBCR,
+    };
+    let [x, dx, _, y, _, dy] = georef.transform;
+    let mut entries = vec![
+        (256, Value::Long(vec![header.long_count * info.width])),
+        (257, Value::Long(vec![header.lat_count * info.height])),
+        (258, Value::Short(vec![8; samples])),
+        (259, Value::Short(vec![COMPRESSION_JPEG])),
+        (262, Value::Short(vec![photometric])),
+        (277, Value::Short(vec![samples as u16])),
+        // Chunky
+        (284, Value::Short(vec![1])),
+        (322, Value::Long(vec![info.width])),
+        (323, Value::Long(vec![info.height])),
+        (324, Value::Long(offsets)),
+        (325, Value::Long(counts)),
+        (MODEL_PIXEL_SCALE, Value::Double(vec![dx, -dy, 0.0])),
+        (
+            MODEL_TIEPOINT,
+            Value::Double(vec![0.0, 0.0, 0.0, x, y, 0.0]),
+        ),
+    ];
+    if photometric == PHOTOMETRIC_YCBCR {
+        entries.push((
+            530,
+            Value::Short(vec![info.sampling.0 as u16, info.sampling.1 as u16]),
+        ));
+    }
+    if georef.wkt == NAD27_WKT {
+        entries.push((GEO_KEY_DIRECTORY, Value::Short(NAD27_GEO_KEYS.to_vec())));
+    }
+    if let Some(profile) = icc_profile {
+        entries.push((ICC_PROFILE, Value::Undefined(profile.to_vec())));
+    }
+
+    let ifd_offset = write_ifd(writer, entries)?;
+    writer.seek(SeekFrom::Start(4))?;
+    writer.write_all(&ifd_offset.to_le_bytes())?;
+    writer.flush()?;
+    Ok(())
+}
+
+/// Writes `tpq` to `path` as a JPEG-compressed GeoTIFF whose tiles are the
+/// original maplets, tagged with `icc_profile` if given, calling
+/// `on_maplet` after each one.
+///
+/// Returns `false`, writing nothing, unless every maplet is a baseline JPEG
+/// of the same size and that size is a valid TIFF tile.
+pub(crate) fn write_jpeg_tiff(
+    tpq: &TpqFile,
+    path: &Path,
+    georef: &GeoRef,
+    icc_profile: Option<&[u8]>,
+    on_maplet: &mut dyn FnMut(u32, u32) -> Result<()>,
+) -> Result<bool> {
+    let info = match tile_layout(tpq)? {
+        Some(info) => info,
+        None => return Ok(false),
+    };
+
+    let part = PartFile::new(path);
+    let mut writer = BufWriter::new(File::create(part.path())?);
+    if let Err(err) = write_tiles(&mut writer, tpq, info, georef, icc_profile, on_maplet) {
+        drop(writer);
+        return Err(part.remove_after(err));
+    }
+    drop(writer);
+    part.commit()?;
+    Ok(true)
+}
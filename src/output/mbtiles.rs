@@ -0,0 +1,123 @@
+use std::fs::remove_file;
+use std::path::PathBuf;
+
+use image::{DynamicImage, Rgba, RgbaImage};
+use rusqlite::{params, Connection};
+
+use super::{OutputWriter, PartFile};
+use crate::conversion::Resampling;
+use crate::error::Result;
+use crate::georef::GeoRef;
+use crate::pixels;
+use crate::tiles::{
+    encode_png, image_for_zoom, is_empty, native_zoom, render_tile, tiles_covering, OVERVIEW_LEVELS,
+};
+
+/// MBTiles output: a pyramid of Web Mercator PNG tiles in SQLite.
+///
+/// NAD27 coordinates are used as-is, which is off by at most tens of metres.
+pub struct MbTilesWriter {
+    path: PathBuf,
+    name: String,
+    collage_img: RgbaImage,
+    georef: Option<GeoRef>,
+    resampling: Resampling,
+}
+
+impl MbTilesWriter {
+    pub fn new<P: Into<PathBuf>>(path: P, name: &str) -> Self {
+        MbTilesWriter {
+            path: path.into(),
+            name: name.to_string(),
+            collage_img: RgbaImage::new(0, 0),
+            georef: None,
+            resampling: Resampling::Bilinear,
+        }
+    }
+
+    /// How tiles are resampled from the image, bilinearly by default.
+    pub fn resampling(mut self, resampling: Resampling) -> Self {
+        self.resampling = resampling;
+        self
+    }
+}
+
+impl OutputWriter for MbTilesWriter {
+    fn create(&mut self, width: u32, height: u32) -> Result<()> {
+        self.collage_img = RgbaImage::from_pixel(width, height, Rgba([255, 255, 255, 255]));
+        Ok(())
+    }
+
+    fn write_region(&mut self, x: u32, y: u32, image: &DynamicImage) -> Result<()> {
+        pixels::copy_into(&mut self.collage_img, &*pixels::rgba(image), x, y);
+        Ok(())
+    }
+
+    fn set_georef(&mut self, georef: &GeoRef) -> Result<()> {
+        self.georef = Some(georef.clone());
+        Ok(())
+    }
+
+    fn finalize(&mut self) -> Result<()> {
+        let georef = match self.georef.clone() {
+            Some(georef) => georef,
+            // Tiles can't be placed without georeferencing
+            None => return Ok(()),
+        };
+        let bounds = georef.bounds(self.collage_img.width(), self.collage_img.height());
+        let max_zoom = native_zoom(georef.transform[1]);
+        let min_zoom = max_zoom.saturating_sub(OVERVIEW_LEVELS);
+
+        let part = PartFile::new(&self.path);
+        // Left behind if an earlier run was killed
+        if part.path().exists() {
+            remove_file(part.path())?;
+        }
+        let mut connection = Connection::open(part.path())?;
+        connection.execute_batch(
+            "CREATE TABLE metadata (name TEXT, value TEXT);
+             CREATE TABLE tiles (zoom_level INTEGER, tile_column INTEGER, tile_row INTEGER, tile_data BLOB);
+             CREATE UNIQUE INDEX tile_index ON tiles (zoom_level, tile_column, tile_row);",
+        )?;
+
+        let transaction = connection.transaction()?;
+        let metadata = [
+            ("name", self.name.clone()),
+            ("format", "png".to_string()),
+            ("type", "baselayer".to_string()),
+            ("version", "1.0".to_string()),
+            (
+                "bounds",
+                format!("{},{},{},{}", bounds.0, bounds.1, bounds.2, bounds.3),
+            ),
+            ("minzoom", min_zoom.to_string()),
+            ("maxzoom", max_zoom.to_string()),
+        ];
+        for (name, value) in &metadata {
+            transaction.execute(
+                "INSERT INTO metadata (name, value) VALUES (?1, ?2)",
+                params![name, value],
+            )?;
+        }
+
+        for z in min_zoom..=max_zoom {
+            let (image, transform) =
+                image_for_zoom(&self.collage_img, &georef.transform, z, self.resampling);
+            for tile in tiles_covering(bounds, z) {
+                let tile_img = render_tile(&image, &transform, tile, self.resampling);
+                if is_empty(&tile_img) {
+                    continue;
+                }
+                transaction.execute(
+                    "INSERT INTO tiles (zoom_level, tile_column, tile_row, tile_data) VALUES (?1, ?2, ?3, ?4)",
+                    params![tile.z, tile.x, tile.tms_y(), encode_png(&tile_img)?],
+                )?;
+            }
+        }
+        transaction.commit()?;
+        drop(connection);
+        part.commit()?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,82 @@
+use image::{imageops, DynamicImage, Rgb, RgbImage, Rgba, RgbaImage};
+
+use crate::error::Result;
+use crate::georef::GeoRef;
+use crate::pixels;
+
+mod geotiff;
+mod html;
+mod jpeg_tiff;
+#[cfg(feature = "mbtiles")]
+mod mbtiles;
+mod part;
+mod png;
+mod retile;
+mod stats;
+mod thumbnail;
+mod tiff;
+mod tile_cache;
+
+pub use self::geotiff::GeoTiffWriter;
+pub use self::html::HtmlPreviewWriter;
+pub(crate) use self::jpeg_tiff::write_jpeg_tiff;
+#[cfg(feature = "mbtiles")]
+pub use self::mbtiles::MbTilesWriter;
+pub(crate) use self::part::PartFile;
+pub use self::png::PngWriter;
+pub use self::retile::{journal_path, tile_index_path, RetiledWriter};
+pub use self::stats::{stats_path, StatsWriter};
+pub use self::thumbnail::{thumbnail_path, Thumbnail, ThumbnailWriter, THUMBNAIL_WIDTH};
+pub use self::tiff::encode_geotiff;
+pub use self::tile_cache::{ExistingTiles, TileCacheWriter};
+
+/// A destination for converted imagery.
+///
+/// A conversion calls [`create`](OutputWriter::create) once, then
+/// [`write_region`](OutputWriter::write_region) for each maplet,
+/// [`set_georef`](OutputWriter::set_georef), and finally
+/// [`finalize`](OutputWriter::finalize). If anything fails after `create`,
+/// [`discard`](OutputWriter::discard) is called instead of `finalize`.
+pub trait OutputWriter {
+    fn create(&mut self, width: u32, height: u32) -> Result<()>;
+
+    fn write_region(&mut self, x: u32, y: u32, image: &DynamicImage) -> Result<()>;
+
+    fn set_georef(&mut self, georef: &GeoRef) -> Result<()>;
+
+    fn finalize(&mut self) -> Result<()>;
+
+    /// Called once nothing more will be written above row `rows`, so a
+    /// writer can finish that part of its output early.
+    fn rows_complete(&mut self, _rows: u32) -> Result<()> {
+        Ok(())
+    }
+
+    /// Removes any partially written output, leaving an earlier output at
+    /// the same path in place.
+    fn discard(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
+// A white canvas for writers that assemble the whole image, with an alpha
+// channel only when asked for
+pub(crate) fn canvas(width: u32, height: u32, alpha: bool) -> DynamicImage {
+    if alpha {
+        DynamicImage::ImageRgba8(RgbaImage::from_pixel(
+            width,
+            height,
+            Rgba([255, 255, 255, 255]),
+        ))
+    } else {
+        DynamicImage::ImageRgb8(RgbImage::from_pixel(width, height, Rgb([255, 255, 255])))
+    }
+}
+
+pub(crate) fn paste(canvas: &mut DynamicImage, image: &DynamicImage, x: u32, y: u32) {
+    match canvas {
+        DynamicImage::ImageRgb8(canvas) => pixels::copy_into(canvas, &*pixels::rgb(image), x, y),
+        DynamicImage::ImageRgba8(canvas) => pixels::copy_into(canvas, &*pixels::rgba(image), x, y),
+        canvas => imageops::replace(canvas, image, x, y),
+    }
+}
@@ -0,0 +1,59 @@
+use std::ffi::OsString;
+use std::fs::{remove_file, rename};
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::error::TopodumpError;
+
+/// An output written as `<path>.part` beside its destination and renamed into
+/// place once complete, so an interrupted or failed conversion never leaves
+/// a half-written file under the real name. Dropped without
+/// [`commit`](Self::commit), the partial file is removed.
+pub(crate) struct PartFile {
+    path: PathBuf,
+    part: Option<PathBuf>,
+}
+
+impl PartFile {
+    pub(crate) fn new(path: &Path) -> Self {
+        let mut part = OsString::from(path);
+        part.push(".part");
+        PartFile {
+            path: path.to_path_buf(),
+            part: Some(PathBuf::from(part)),
+        }
+    }
+
+    /// Where to write the output until it's committed.
+    pub(crate) fn path(&self) -> &Path {
+        self.part.as_deref().expect("already committed or removed")
+    }
+
+    pub(crate) fn commit(mut self) -> io::Result<()> {
+        let part = self.part.take().expect("already committed or removed");
+        rename(part, &self.path)
+    }
+
+    /// Removes the partial file, reporting failure unlike dropping it.
+    pub(crate) fn remove(mut self) -> io::Result<()> {
+        match self.part.take().map(remove_file) {
+            Some(Err(err)) if err.kind() != io::ErrorKind::NotFound => Err(err),
+            _ => Ok(()),
+        }
+    }
+
+    /// Removes the partial file after `err`, returning `err` along with any
+    /// failure to remove it.
+    pub(crate) fn remove_after(self, err: TopodumpError) -> TopodumpError {
+        let path = self.path().to_path_buf();
+        err.after_cleanup(&path, self.remove().map_err(TopodumpError::from))
+    }
+}
+
+impl Drop for PartFile {
+    fn drop(&mut self) {
+        if let Some(part) = self.part.take() {
+            let _ = remove_file(part);
+        }
+    }
+}
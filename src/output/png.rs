@@ -0,0 +1,191 @@
+use std::borrow::Cow;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use image::{DynamicImage, GenericImageView, ImageFormat};
+
+use super::{canvas, paste, OutputWriter, PartFile};
+use crate::error::Result;
+use crate::georef::{write_world_file, GeoRef};
+use crate::palette::quantize;
+use crate::pixels;
+
+fn to_io(err: png::EncodingError) -> io::Error {
+    io::Error::new(io::ErrorKind::Other, err)
+}
+
+// iCCP has to come before any palette, the profile deflated
+fn write_icc_profile<W: Write>(writer: &mut png::Writer<W>, profile: &[u8]) -> io::Result<()> {
+    let mut data = b"ICC profile\0\0".to_vec();
+    data.extend_from_slice(&deflate::deflate_bytes_zlib(profile));
+    writer.write_chunk(*b"iCCP", &data).map_err(to_io)
+}
+
+fn save_paletted(
+    path: &Path,
+    image: &DynamicImage,
+    colors: u16,
+    alpha: bool,
+    icc_profile: Option<&[u8]>,
+) -> Result<()> {
+    let image = pixels::rgba(image);
+    let paletted = quantize(&image, colors);
+    let mut encoder = png::Encoder::new(
+        BufWriter::new(File::create(path)?),
+        image.width(),
+        image.height(),
+    );
+    encoder.set_color(png::ColorType::Indexed);
+    encoder.set_depth(png::BitDepth::Eight);
+    // With a profile the palette is written after it, not by write_header
+    if icc_profile.is_none() {
+        encoder.set_palette(paletted.rgb());
+        if alpha {
+            encoder.set_trns(paletted.alpha());
+        }
+    }
+    let mut writer = encoder.write_header().map_err(to_io)?;
+    if let Some(profile) = icc_profile {
+        write_icc_profile(&mut writer, profile)?;
+        writer
+            .write_chunk(*b"PLTE", &paletted.rgb())
+            .map_err(to_io)?;
+        if alpha {
+            writer
+                .write_chunk(*b"tRNS", &paletted.alpha())
+                .map_err(to_io)?;
+        }
+    }
+    writer.write_image_data(&paletted.indices).map_err(to_io)?;
+    Ok(())
+}
+
+fn save_with_profile(path: &Path, image: &DynamicImage, icc_profile: &[u8]) -> Result<()> {
+    let (color, data) = match image {
+        DynamicImage::ImageLuma8(luma) => (png::ColorType::Grayscale, Cow::Borrowed(luma.as_raw())),
+        DynamicImage::ImageLumaA8(luma_alpha) => (
+            png::ColorType::GrayscaleAlpha,
+            Cow::Borrowed(luma_alpha.as_raw()),
+        ),
+        DynamicImage::ImageRgb8(rgb) => (png::ColorType::RGB, Cow::Borrowed(rgb.as_raw())),
+        other => (
+            png::ColorType::RGBA,
+            Cow::Owned(other.to_rgba8().into_raw()),
+        ),
+    };
+    let mut encoder = png::Encoder::new(
+        BufWriter::new(File::create(path)?),
+        image.width(),
+        image.height(),
+    );
+    encoder.set_color(color);
+    encoder.set_depth(png::BitDepth::Eight);
+    let mut writer = encoder.write_header().map_err(to_io)?;
+    write_icc_profile(&mut writer, icc_profile)?;
+    writer.write_image_data(&data).map_err(to_io)?;
+    Ok(())
+}
+
+/// PNG output, georeferenced by a world file and .prj alongside it.
+pub struct PngWriter {
+    path: PathBuf,
+    alpha: bool,
+    grayscale: bool,
+    quantize: Option<u16>,
+    icc_profile: Option<Vec<u8>>,
+    collage_img: DynamicImage,
+    georef: Option<GeoRef>,
+}
+
+impl PngWriter {
+    pub fn new<P: Into<PathBuf>>(path: P) -> Self {
+        PngWriter {
+            path: path.into(),
+            alpha: false,
+            grayscale: false,
+            quantize: None,
+            icc_profile: None,
+            collage_img: canvas(0, 0, false),
+            georef: None,
+        }
+    }
+
+    /// Adds an alpha channel, e.g. to keep areas outside a cutline transparent.
+    pub fn alpha(mut self, alpha: bool) -> Self {
+        self.alpha = alpha;
+        self
+    }
+
+    /// Writes a grayscale PNG, with alpha if asked for, rather than colour.
+    pub fn grayscale(mut self, grayscale: bool) -> Self {
+        self.grayscale = grayscale;
+        self
+    }
+
+    /// Writes a paletted PNG of at most this many colours, up to 256, rather
+    /// than true colour.
+    pub fn quantize(mut self, colors: Option<u16>) -> Self {
+        self.quantize = colors;
+        self
+    }
+
+    /// Embeds an ICC profile, as [`IccProfile::bytes`](crate::IccProfile::bytes)
+    /// gives it.
+    pub fn icc_profile(mut self, profile: Option<Vec<u8>>) -> Self {
+        self.icc_profile = profile;
+        self
+    }
+}
+
+impl OutputWriter for PngWriter {
+    fn create(&mut self, width: u32, height: u32) -> Result<()> {
+        self.collage_img = canvas(width, height, self.alpha);
+        Ok(())
+    }
+
+    fn write_region(&mut self, x: u32, y: u32, image: &DynamicImage) -> Result<()> {
+        paste(&mut self.collage_img, image, x, y);
+        Ok(())
+    }
+
+    fn set_georef(&mut self, georef: &GeoRef) -> Result<()> {
+        self.georef = Some(georef.clone());
+        Ok(())
+    }
+
+    fn finalize(&mut self) -> Result<()> {
+        let part = PartFile::new(&self.path);
+        let icc_profile = self.icc_profile.as_deref();
+        if let Some(colors) = self.quantize {
+            save_paletted(
+                part.path(),
+                &self.collage_img,
+                colors,
+                self.alpha,
+                icc_profile,
+            )?;
+        } else {
+            let gray;
+            let image = if !self.grayscale {
+                &self.collage_img
+            } else {
+                gray = if self.alpha {
+                    DynamicImage::ImageLumaA8(self.collage_img.to_luma_alpha8())
+                } else {
+                    DynamicImage::ImageLuma8(self.collage_img.to_luma8())
+                };
+                &gray
+            };
+            match icc_profile {
+                Some(profile) => save_with_profile(part.path(), image, profile)?,
+                None => image.save_with_format(part.path(), ImageFormat::Png)?,
+            }
+        }
+        if let Some(georef) = &self.georef {
+            write_world_file(&self.path, georef)?;
+        }
+        part.commit()?;
+        Ok(())
+    }
+}
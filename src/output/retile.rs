@@ -0,0 +1,329 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::{self, remove_file};
+use std::io;
+use std::path::{Path, PathBuf};
+
+use image::{DynamicImage, GenericImageView};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use super::{GeoTiffWriter, OutputWriter, PartFile};
+use crate::conversion::{Compression, Format, Resampling};
+use crate::error::{Result, TopodumpError};
+use crate::georef::GeoRef;
+
+/// Where [`RetiledWriter`] lists the tiles it wrote for `output`, e.g.
+/// `arizona_index.geojson` for `arizona.tif`.
+pub fn tile_index_path(output: &Path) -> PathBuf {
+    output.with_file_name(format!("{}_index.geojson", file_stem(output)))
+}
+
+/// Where [`RetiledWriter`] records the tiles it has finished until it's
+/// done, e.g. `arizona_journal.json` for `arizona.tif`.
+pub fn journal_path(output: &Path) -> PathBuf {
+    output.with_file_name(format!("{}_journal.json", file_stem(output)))
+}
+
+// What a run that stopped early had finished
+#[derive(Serialize, Deserialize)]
+struct Journal {
+    job: String,
+    // Every row above this was written to finished tiles
+    rows: u32,
+    tiles: Vec<(u32, u32, PathBuf)>,
+}
+
+fn file_stem(output: &Path) -> String {
+    output.file_stem().map_or_else(
+        || "output".to_string(),
+        |stem| stem.to_string_lossy().to_string(),
+    )
+}
+
+/// Output split into a grid of georeferenced GeoTIFF tiles of a fixed size,
+/// `<stem>_<row>_<col>.tif` beside `output`, with a GeoJSON index of their
+/// footprints at [`tile_index_path`]. Tiles nothing is written to are left
+/// out.
+///
+/// Each row of tiles is finished once
+/// [`rows_complete`](OutputWriter::rows_complete) passes it, so only one
+/// row of tiles is open at a time when writing top to bottom. Finished
+/// tiles are recorded in a journal at [`journal_path`], removed once the
+/// index is written, so a run that stops early can be picked up again with
+/// [`resume`](Self::resume).
+pub struct RetiledWriter {
+    output: PathBuf,
+    georef: GeoRef,
+    tile_width: u32,
+    tile_height: u32,
+    format: Format,
+    compression: Compression,
+    quality: Option<u8>,
+    resampling: Resampling,
+    alpha: bool,
+    width: u32,
+    height: u32,
+    // Open tiles by (row, col)
+    open: BTreeMap<(u32, u32), GeoTiffWriter>,
+    finished: Vec<(u32, u32, PathBuf)>,
+    // Tiles finished by an earlier run, which aren't written again
+    resumed: BTreeSet<(u32, u32)>,
+    job: String,
+}
+
+impl RetiledWriter {
+    /// Tiles `output`, a mosaic georeferenced by `georef`, into tiles of at
+    /// most `tile_width`×`tile_height` pixels.
+    pub fn new<P: Into<PathBuf>>(
+        output: P,
+        georef: &GeoRef,
+        (tile_width, tile_height): (u32, u32),
+        format: Format,
+        compression: Compression,
+    ) -> Self {
+        RetiledWriter {
+            output: output.into(),
+            georef: georef.clone(),
+            tile_width: tile_width.max(1),
+            tile_height: tile_height.max(1),
+            format,
+            compression,
+            quality: None,
+            resampling: Resampling::Bilinear,
+            alpha: false,
+            width: 0,
+            height: 0,
+            open: BTreeMap::new(),
+            finished: Vec::new(),
+            resumed: BTreeSet::new(),
+            job: String::new(),
+        }
+    }
+
+    /// Identifies the job whose tiles these are, so a journal left by a
+    /// different job isn't resumed.
+    pub fn job(mut self, job: impl Into<String>) -> Self {
+        self.job = job.into();
+        self
+    }
+
+    /// Picks up the tiles an earlier run of the same job finished, leaving
+    /// them as they are, and returns the number of rows of the mosaic they
+    /// hold, which needn't be written again. Returns 0, starting afresh,
+    /// without a journal of the same job whose tiles are all still there.
+    pub fn resume(&mut self) -> Result<u32> {
+        let journal: Journal = match fs::read(journal_path(&self.output)) {
+            Ok(journal) => serde_json::from_slice(&journal)?,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(0),
+            Err(err) => return Err(err.into()),
+        };
+        if journal.job != self.job || !journal.tiles.iter().all(|(.., path)| path.is_file()) {
+            return Ok(0);
+        }
+        self.resumed = journal
+            .tiles
+            .iter()
+            .map(|&(row, col, _)| (row, col))
+            .collect();
+        self.finished = journal.tiles;
+        Ok(journal.rows)
+    }
+
+    /// As for [`GeoTiffWriter::quality`].
+    pub fn quality(mut self, quality: Option<u8>) -> Self {
+        self.quality = quality;
+        self
+    }
+
+    /// As for [`GeoTiffWriter::resampling`].
+    pub fn resampling(mut self, resampling: Resampling) -> Self {
+        self.resampling = resampling;
+        self
+    }
+
+    pub fn alpha(mut self, alpha: bool) -> Self {
+        self.alpha = alpha;
+        self
+    }
+
+    fn tile_path(&self, row: u32, col: u32) -> PathBuf {
+        self.output.with_file_name(format!(
+            "{}_{:03}_{:03}.{}",
+            file_stem(&self.output),
+            row,
+            col,
+            self.format.extension()
+        ))
+    }
+
+    // The pixels tile (`row`, `col`) covers: (x, y, width, height)
+    fn tile_region(&self, row: u32, col: u32) -> (u32, u32, u32, u32) {
+        let (x, y) = (col * self.tile_width, row * self.tile_height);
+        (
+            x,
+            y,
+            self.tile_width.min(self.width - x),
+            self.tile_height.min(self.height - y),
+        )
+    }
+
+    fn finish(&mut self, row: u32, col: u32) -> Result<()> {
+        let mut tile = match self.open.remove(&(row, col)) {
+            Some(tile) => tile,
+            None => return Ok(()),
+        };
+        let (x, y, ..) = self.tile_region(row, col);
+        let written = tile
+            .set_georef(&self.georef.window(x, y))
+            .and_then(|()| tile.finalize());
+        if let Err(err) = written {
+            tile.discard()?;
+            return Err(err);
+        }
+        self.finished.push((row, col, self.tile_path(row, col)));
+        Ok(())
+    }
+
+    fn write_journal(&self, rows: u32) -> Result<()> {
+        let journal = Journal {
+            job: self.job.clone(),
+            rows,
+            tiles: self.finished.clone(),
+        };
+        let part = PartFile::new(&journal_path(&self.output));
+        fs::write(part.path(), serde_json::to_vec(&journal)?)?;
+        part.commit()?;
+        Ok(())
+    }
+
+    fn write_index(&self) -> Result<()> {
+        let features = self
+            .finished
+            .iter()
+            .map(|(row, col, path)| {
+                let (x, y, width, height) = self.tile_region(*row, *col);
+                let (west, south, east, north) = self.georef.window(x, y).bounds(width, height);
+                json!({
+                    "type": "Feature",
+                    "properties": {
+                        "location": path.file_name().map(|name| name.to_string_lossy()),
+                        "row": row,
+                        "col": col,
+                    },
+                    "geometry": {
+                        "type": "Polygon",
+                        "coordinates": [[
+                            [west, north],
+                            [east, north],
+                            [east, south],
+                            [west, south],
+                            [west, north],
+                        ]],
+                    },
+                })
+            })
+            .collect::<Vec<_>>();
+        let index = json!({
+            "type": "FeatureCollection",
+            "features": features,
+        });
+        fs::write(
+            tile_index_path(&self.output),
+            serde_json::to_vec_pretty(&index)?,
+        )?;
+        Ok(())
+    }
+}
+
+impl OutputWriter for RetiledWriter {
+    fn create(&mut self, width: u32, height: u32) -> Result<()> {
+        if !matches!(self.format, Format::GTiff | Format::Cog) {
+            return Err(TopodumpError::Unsupported(
+                "tiles other than GeoTIFF or COG",
+            ));
+        }
+        self.width = width;
+        self.height = height;
+        Ok(())
+    }
+
+    fn write_region(&mut self, x: u32, y: u32, image: &DynamicImage) -> Result<()> {
+        let right = (x + image.width()).min(self.width);
+        let bottom = (y + image.height()).min(self.height);
+        for row in y / self.tile_height..=(bottom.max(1) - 1) / self.tile_height {
+            for col in x / self.tile_width..=(right.max(1) - 1) / self.tile_width {
+                let (tile_x, tile_y, tile_width, tile_height) = self.tile_region(row, col);
+                let (left, top) = (x.max(tile_x), y.max(tile_y));
+                let (width, height) = (
+                    right.min(tile_x + tile_width).saturating_sub(left),
+                    bottom.min(tile_y + tile_height).saturating_sub(top),
+                );
+                if width == 0 || height == 0 || self.resumed.contains(&(row, col)) {
+                    continue;
+                }
+                if !self.open.contains_key(&(row, col)) {
+                    let mut tile =
+                        GeoTiffWriter::new(self.tile_path(row, col), self.format, self.compression)
+                            .quality(self.quality)
+                            .resampling(self.resampling)
+                            .alpha(self.alpha);
+                    tile.create(tile_width, tile_height)?;
+                    self.open.insert((row, col), tile);
+                }
+                let part = image.crop_imm(left - x, top - y, width, height);
+                self.open
+                    .get_mut(&(row, col))
+                    .expect("just opened")
+                    .write_region(left - tile_x, top - tile_y, &part)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Tiles are georeferenced from the mosaic's georeferencing given to
+    /// [`RetiledWriter::new`].
+    fn set_georef(&mut self, _georef: &GeoRef) -> Result<()> {
+        Ok(())
+    }
+
+    fn rows_complete(&mut self, rows: u32) -> Result<()> {
+        let done = self
+            .open
+            .keys()
+            .copied()
+            .filter(|&(row, _)| (row + 1) * self.tile_height <= rows)
+            .collect::<Vec<_>>();
+        for (row, col) in done {
+            self.finish(row, col)?;
+        }
+        let rows = if rows >= self.height {
+            self.height
+        } else {
+            rows / self.tile_height * self.tile_height
+        };
+        self.write_journal(rows)
+    }
+
+    fn finalize(&mut self) -> Result<()> {
+        let remaining = self.open.keys().copied().collect::<Vec<_>>();
+        for (row, col) in remaining {
+            self.finish(row, col)?;
+        }
+        self.finished.sort();
+        self.write_index()?;
+        match remove_file(journal_path(&self.output)) {
+            Err(err) if err.kind() != io::ErrorKind::NotFound => Err(err.into()),
+            _ => Ok(()),
+        }
+    }
+
+    /// Removes the tiles still open, keeping those finished and the journal
+    /// of them to resume from.
+    fn discard(&mut self) -> Result<()> {
+        for tile in self.open.values_mut() {
+            tile.discard()?;
+        }
+        self.open.clear();
+        Ok(())
+    }
+}
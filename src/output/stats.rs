@@ -0,0 +1,175 @@
+use std::ffi::OsString;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use image::DynamicImage;
+
+use super::{OutputWriter, PartFile};
+use crate::error::Result;
+use crate::georef::GeoRef;
+use crate::pixels;
+
+/// Where [`StatsWriter`] puts the statistics of `output`: GDAL's sidecar,
+/// e.g. `arizona.tif.aux.xml` for `arizona.tif`.
+pub fn stats_path(output: &Path) -> PathBuf {
+    let mut name = OsString::from(output.as_os_str());
+    name.push(".aux.xml");
+    PathBuf::from(name)
+}
+
+// A band's minimum, maximum, mean and standard deviation, or None if it has
+// no samples
+fn summarize(histogram: &[u64; 256]) -> Option<(u8, u8, f64, f64)> {
+    let count: u64 = histogram.iter().sum();
+    if count == 0 {
+        return None;
+    }
+    let min = histogram.iter().position(|&n| n > 0)? as u8;
+    let max = histogram.iter().rposition(|&n| n > 0)? as u8;
+    let values = || {
+        histogram
+            .iter()
+            .enumerate()
+            .map(|(v, &n)| (v as f64, n as f64))
+    };
+    let mean = values().map(|(v, n)| v * n).sum::<f64>() / count as f64;
+    let variance = values().map(|(v, n)| (v - mean).powi(2) * n).sum::<f64>() / count as f64;
+    Some((min, max, mean, variance.sqrt()))
+}
+
+/// Passes everything on to another writer while counting a histogram of
+/// each band as regions arrive, then writes them with each band's minimum,
+/// maximum, mean and standard deviation to GDAL's sidecar at
+/// [`stats_path`] once the output is written, so GIS software needn't scan
+/// the whole image when first opening it. Transparent pixels count towards
+/// the alpha band only.
+pub struct StatsWriter {
+    inner: Box<dyn OutputWriter + Send>,
+    path: PathBuf,
+    grayscale: bool,
+    alpha: bool,
+    // One per band, alpha last
+    histograms: Vec<[u64; 256]>,
+    pixels: u64,
+    transparent: u64,
+}
+
+impl StatsWriter {
+    /// Counts the bands of a grayscale or RGB output, each with alpha if
+    /// `alpha`.
+    pub fn new<P: Into<PathBuf>>(
+        inner: Box<dyn OutputWriter + Send>,
+        path: P,
+        grayscale: bool,
+        alpha: bool,
+    ) -> Self {
+        let colour = if grayscale { 1 } else { 3 };
+        let bands = colour + alpha as usize;
+        StatsWriter {
+            inner,
+            path: path.into(),
+            grayscale,
+            alpha,
+            histograms: vec![[0; 256]; bands],
+            pixels: 0,
+            transparent: 0,
+        }
+    }
+
+    fn count(&mut self, colour: &[u8], alpha: u8) {
+        self.pixels += 1;
+        if self.alpha {
+            self.histograms[colour.len()][alpha as usize] += 1;
+            if alpha == 0 {
+                self.transparent += 1;
+                return;
+            }
+        }
+        for (histogram, &sample) in self.histograms.iter_mut().zip(colour) {
+            histogram[sample as usize] += 1;
+        }
+    }
+
+    fn save(&self) -> Result<()> {
+        let colour_bands = self.histograms.len() - self.alpha as usize;
+        let mut xml = String::from("<PAMDataset>\n");
+        for (i, histogram) in self.histograms.iter().enumerate() {
+            let samples = if i < colour_bands {
+                self.pixels - self.transparent
+            } else {
+                self.pixels
+            };
+            let counts = histogram
+                .iter()
+                .map(u64::to_string)
+                .collect::<Vec<_>>()
+                .join("|");
+            xml.push_str(&format!(
+                "  <PAMRasterBand band=\"{}\">\n    <Histograms>\n      <HistItem>\n        \
+                 <HistMin>-0.5</HistMin>\n        <HistMax>255.5</HistMax>\n        \
+                 <BucketCount>256</BucketCount>\n        \
+                 <IncludeOutOfRange>0</IncludeOutOfRange>\n        \
+                 <Approximate>0</Approximate>\n        <HistCounts>{}</HistCounts>\n      \
+                 </HistItem>\n    </Histograms>\n",
+                i + 1,
+                counts
+            ));
+            if let Some((min, max, mean, stddev)) = summarize(histogram) {
+                let valid = 100.0 * samples as f64 / self.pixels.max(1) as f64;
+                xml.push_str(&format!(
+                    "    <Metadata>\n      \
+                     <MDI key=\"STATISTICS_MAXIMUM\">{}</MDI>\n      \
+                     <MDI key=\"STATISTICS_MEAN\">{}</MDI>\n      \
+                     <MDI key=\"STATISTICS_MINIMUM\">{}</MDI>\n      \
+                     <MDI key=\"STATISTICS_STDDEV\">{}</MDI>\n      \
+                     <MDI key=\"STATISTICS_VALID_PERCENT\">{}</MDI>\n    </Metadata>\n",
+                    max, mean, min, stddev, valid
+                ));
+            }
+            xml.push_str("  </PAMRasterBand>\n");
+        }
+        xml.push_str("</PAMDataset>\n");
+
+        let part = PartFile::new(&self.path);
+        fs::write(part.path(), xml)?;
+        part.commit()?;
+        Ok(())
+    }
+}
+
+impl OutputWriter for StatsWriter {
+    fn create(&mut self, width: u32, height: u32) -> Result<()> {
+        self.inner.create(width, height)
+    }
+
+    fn write_region(&mut self, x: u32, y: u32, image: &DynamicImage) -> Result<()> {
+        self.inner.write_region(x, y, image)?;
+        if self.grayscale {
+            for pixel in pixels::luma_alpha(image).pixels() {
+                self.count(&pixel.0[..1], pixel.0[1]);
+            }
+        } else {
+            for pixel in pixels::rgba(image).pixels() {
+                self.count(&pixel.0[..3], pixel.0[3]);
+            }
+        }
+        Ok(())
+    }
+
+    fn set_georef(&mut self, georef: &GeoRef) -> Result<()> {
+        self.inner.set_georef(georef)
+    }
+
+    fn rows_complete(&mut self, rows: u32) -> Result<()> {
+        self.inner.rows_complete(rows)
+    }
+
+    fn finalize(&mut self) -> Result<()> {
+        self.inner.finalize()?;
+        self.save()
+    }
+
+    fn discard(&mut self) -> Result<()> {
+        self.inner.discard()
+    }
+}
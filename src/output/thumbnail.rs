@@ -0,0 +1,150 @@
+use std::convert::Infallible;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use image::imageops::{self, FilterType};
+use image::{DynamicImage, ImageFormat, Rgb, RgbImage};
+
+use super::{OutputWriter, PartFile};
+use crate::error::Result;
+use crate::georef::GeoRef;
+use crate::pixels;
+
+/// How wide thumbnails are at most.
+pub const THUMBNAIL_WIDTH: u32 = 1024;
+
+/// Where a thumbnail goes: beside the output, at [`thumbnail_path`], or at
+/// a path of its own.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Thumbnail {
+    Auto,
+    Path(PathBuf),
+}
+
+impl Thumbnail {
+    /// The thumbnail's path for `output`.
+    pub fn path_for(&self, output: &Path) -> PathBuf {
+        match self {
+            Thumbnail::Auto => thumbnail_path(output),
+            Thumbnail::Path(path) => path.clone(),
+        }
+    }
+}
+
+impl FromStr for Thumbnail {
+    type Err = Infallible;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        Ok(match s {
+            "auto" => Thumbnail::Auto,
+            path => Thumbnail::Path(path.into()),
+        })
+    }
+}
+
+/// Where an automatic thumbnail of `output` goes, e.g. `arizona_thumb.jpg`
+/// for `arizona.tif`.
+pub fn thumbnail_path(output: &Path) -> PathBuf {
+    let stem = output.file_stem().map_or_else(
+        || "output".to_string(),
+        |stem| stem.to_string_lossy().to_string(),
+    );
+    output.with_file_name(format!("{}_thumb.jpg", stem))
+}
+
+/// Passes everything on to another writer while drawing a quick-look of at
+/// most [`THUMBNAIL_WIDTH`] pixels across, written once the output is, as
+/// JPEG if its path ends `.jpg` or `.jpeg` and otherwise as PNG. Each region
+/// is downscaled as it arrives, so the full image is never held here.
+pub struct ThumbnailWriter {
+    inner: Box<dyn OutputWriter + Send>,
+    path: PathBuf,
+    width: u32,
+    height: u32,
+    thumbnail: RgbImage,
+}
+
+impl ThumbnailWriter {
+    pub fn new<P: Into<PathBuf>>(inner: Box<dyn OutputWriter + Send>, path: P) -> Self {
+        ThumbnailWriter {
+            inner,
+            path: path.into(),
+            width: 0,
+            height: 0,
+            thumbnail: RgbImage::new(0, 0),
+        }
+    }
+
+    fn save(&self) -> Result<()> {
+        let jpeg = self.path.extension().map_or(false, |extension| {
+            let extension = extension.to_string_lossy().to_ascii_lowercase();
+            extension == "jpg" || extension == "jpeg"
+        });
+        let format = if jpeg {
+            ImageFormat::Jpeg
+        } else {
+            ImageFormat::Png
+        };
+        let part = PartFile::new(&self.path);
+        self.thumbnail.save_with_format(part.path(), format)?;
+        part.commit()?;
+        Ok(())
+    }
+}
+
+impl OutputWriter for ThumbnailWriter {
+    fn create(&mut self, width: u32, height: u32) -> Result<()> {
+        self.inner.create(width, height)?;
+        let thumbnail_width = width.min(THUMBNAIL_WIDTH).max(1);
+        let thumbnail_height =
+            ((height as u64 * thumbnail_width as u64 / width.max(1) as u64) as u32).max(1);
+        self.width = width.max(1);
+        self.height = height.max(1);
+        self.thumbnail =
+            RgbImage::from_pixel(thumbnail_width, thumbnail_height, Rgb([255, 255, 255]));
+        Ok(())
+    }
+
+    fn write_region(&mut self, x: u32, y: u32, image: &DynamicImage) -> Result<()> {
+        self.inner.write_region(x, y, image)?;
+        let (thumbnail_width, thumbnail_height) = self.thumbnail.dimensions();
+        let scale = |p: u32, span: u32, scaled: u32| {
+            (p.min(span) as u64 * scaled as u64 / span as u64) as u32
+        };
+        let (left, right) = (
+            scale(x, self.width, thumbnail_width),
+            scale(x + image.width(), self.width, thumbnail_width),
+        );
+        let (top, bottom) = (
+            scale(y, self.height, thumbnail_height),
+            scale(y + image.height(), self.height, thumbnail_height),
+        );
+        if right > left && bottom > top {
+            let region = imageops::resize(
+                &*pixels::rgb(image),
+                right - left,
+                bottom - top,
+                FilterType::Triangle,
+            );
+            pixels::copy_into(&mut self.thumbnail, &region, left, top);
+        }
+        Ok(())
+    }
+
+    fn set_georef(&mut self, georef: &GeoRef) -> Result<()> {
+        self.inner.set_georef(georef)
+    }
+
+    fn rows_complete(&mut self, rows: u32) -> Result<()> {
+        self.inner.rows_complete(rows)
+    }
+
+    fn finalize(&mut self) -> Result<()> {
+        self.inner.finalize()?;
+        self.save()
+    }
+
+    fn discard(&mut self) -> Result<()> {
+        self.inner.discard()
+    }
+}
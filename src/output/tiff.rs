@@ -0,0 +1,101 @@
+use std::io::{Seek, Write};
+
+use image::DynamicImage;
+use tiff::encoder::{colortype, TiffEncoder};
+use tiff::tags::Tag;
+
+use crate::error::Result;
+use crate::georef::{GeoRef, NAD27_WKT};
+
+pub(super) const MODEL_PIXEL_SCALE: u16 = 33550;
+pub(super) const MODEL_TIEPOINT: u16 = 33922;
+pub(super) const GEO_KEY_DIRECTORY: u16 = 34735;
+pub(super) const ICC_PROFILE: u16 = 34675;
+
+// GTModelType geographic, RasterType pixel-is-area, GCS_NAD27, angular unit degrees
+pub(super) const NAD27_GEO_KEYS: [u16; 20] = [
+    1, 1, 0, 4, //
+    1024, 0, 1, 2, //
+    1025, 0, 1, 1, //
+    2048, 0, 1, 4267, //
+    2054, 0, 1, 9102,
+];
+
+/// Encodes a grayscale, RGB or RGBA TIFF, embedding GeoTIFF tags when `georef` is given
+/// and an ICC profile when `icc_profile` is.
+///
+/// Only NAD27 geographic coordinates can be described without GDAL; other
+/// CRSs get their geotransform but no GeoKeys.
+pub fn encode_geotiff<W: Write + Seek>(
+    writer: W,
+    image: &DynamicImage,
+    georef: Option<&GeoRef>,
+    icc_profile: Option<&[u8]>,
+) -> Result<()> {
+    match image {
+        DynamicImage::ImageLuma8(luma) => encode::<_, colortype::Gray8>(
+            writer,
+            luma.dimensions(),
+            luma.as_raw(),
+            georef,
+            icc_profile,
+        ),
+        DynamicImage::ImageRgb8(rgb) => encode::<_, colortype::RGB8>(
+            writer,
+            rgb.dimensions(),
+            rgb.as_raw(),
+            georef,
+            icc_profile,
+        ),
+        DynamicImage::ImageRgba8(rgba) => encode::<_, colortype::RGBA8>(
+            writer,
+            rgba.dimensions(),
+            rgba.as_raw(),
+            georef,
+            icc_profile,
+        ),
+        other => {
+            let rgba = other.to_rgba8();
+            encode::<_, colortype::RGBA8>(
+                writer,
+                rgba.dimensions(),
+                rgba.as_raw(),
+                georef,
+                icc_profile,
+            )
+        }
+    }
+}
+
+fn encode<W: Write + Seek, C: colortype::ColorType<Inner = u8>>(
+    writer: W,
+    (width, height): (u32, u32),
+    data: &[u8],
+    georef: Option<&GeoRef>,
+    icc_profile: Option<&[u8]>,
+) -> Result<()> {
+    let mut encoder = TiffEncoder::new(writer)?;
+    let mut tiff_image = encoder.new_image::<C>(width, height)?;
+
+    if let Some(georef) = georef {
+        let [x, dx, _, y, _, dy] = georef.transform;
+        let directory = tiff_image.encoder();
+        directory.write_tag(Tag::Unknown(MODEL_PIXEL_SCALE), &[dx, -dy, 0.0][..])?;
+        directory.write_tag(
+            Tag::Unknown(MODEL_TIEPOINT),
+            &[0.0, 0.0, 0.0, x, y, 0.0][..],
+        )?;
+        if georef.wkt == NAD27_WKT {
+            directory.write_tag(Tag::Unknown(GEO_KEY_DIRECTORY), &NAD27_GEO_KEYS[..])?;
+        }
+    }
+
+    if let Some(profile) = icc_profile {
+        tiff_image
+            .encoder()
+            .write_tag(Tag::Unknown(ICC_PROFILE), profile)?;
+    }
+
+    tiff_image.write_data(data)?;
+    Ok(())
+}
@@ -0,0 +1,174 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Mutex;
+
+use image::{imageops, DynamicImage, Rgba, RgbaImage};
+
+use super::{OutputWriter, PartFile};
+use crate::conversion::Resampling;
+use crate::error::Result;
+use crate::georef::GeoRef;
+use crate::pixels;
+use crate::tiles::{
+    encode_png, image_for_zoom, is_empty, native_zoom, render_tile, tiles_covering, OVERVIEW_LEVELS,
+};
+
+// Held while a tile is checked for and written, so quads converted at once
+// that share a tile both end up in it
+static TILE_LOCK: Mutex<()> = Mutex::new(());
+
+/// What a [`TileCacheWriter`] does with tiles already in its cache.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ExistingTiles {
+    /// Leaves them as they are, only adding tiles the cache lacks.
+    Keep,
+    /// Draws the new tile over the old, so tiles along the edges of
+    /// neighbouring quads hold both.
+    Merge,
+    /// Replaces them.
+    Overwrite,
+}
+
+impl FromStr for ExistingTiles {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "keep" => Ok(ExistingTiles::Keep),
+            "merge" => Ok(ExistingTiles::Merge),
+            "overwrite" => Ok(ExistingTiles::Overwrite),
+            _ => Err(format!("unknown handling of existing tiles: {}", s)),
+        }
+    }
+}
+
+/// Passes everything on to another writer while keeping a copy of the
+/// image, then adds it as Web Mercator PNG tiles to a `{z}/{x}/{y}.png`
+/// cache directory, or `{z}/{x}/{y}` counted from the south with
+/// [`tms`](Self::tms), as web maps and tile servers read them, so coverage
+/// can be extended a quad at a time.
+///
+/// As with [`HtmlPreviewWriter`](super::HtmlPreviewWriter), NAD27
+/// coordinates are used as-is.
+pub struct TileCacheWriter {
+    inner: Box<dyn OutputWriter + Send>,
+    dir: PathBuf,
+    resampling: Resampling,
+    existing: ExistingTiles,
+    tms: bool,
+    collage_img: RgbaImage,
+    georef: Option<GeoRef>,
+}
+
+impl TileCacheWriter {
+    pub fn new<P: Into<PathBuf>>(inner: Box<dyn OutputWriter + Send>, dir: P) -> Self {
+        TileCacheWriter {
+            inner,
+            dir: dir.into(),
+            resampling: Resampling::Bilinear,
+            existing: ExistingTiles::Merge,
+            tms: false,
+            collage_img: RgbaImage::new(0, 0),
+            georef: None,
+        }
+    }
+
+    /// How tiles are resampled from the image, bilinearly by default.
+    pub fn resampling(mut self, resampling: Resampling) -> Self {
+        self.resampling = resampling;
+        self
+    }
+
+    /// What's done with tiles already in the cache, merged by default.
+    pub fn existing(mut self, existing: ExistingTiles) -> Self {
+        self.existing = existing;
+        self
+    }
+
+    /// Numbers rows from the south, as TMS does, rather than the north.
+    pub fn tms(mut self, tms: bool) -> Self {
+        self.tms = tms;
+        self
+    }
+
+    fn write_tile(&self, path: &Path, tile_img: RgbaImage) -> Result<()> {
+        let mut png = encode_png(&tile_img)?;
+        let _lock = TILE_LOCK.lock().unwrap();
+        if path.exists() {
+            match self.existing {
+                ExistingTiles::Keep => return Ok(()),
+                ExistingTiles::Merge => {
+                    let mut merged = image::load_from_memory(&fs::read(path)?)?.into_rgba8();
+                    if merged.dimensions() == tile_img.dimensions() {
+                        imageops::overlay(&mut merged, &tile_img, 0, 0);
+                        png = encode_png(&merged)?;
+                    }
+                }
+                ExistingTiles::Overwrite => {}
+            }
+        }
+        // Servers reading the cache meanwhile never see half a tile
+        let part = PartFile::new(path);
+        fs::write(part.path(), png)?;
+        part.commit()?;
+        Ok(())
+    }
+
+    fn save(&self, georef: &GeoRef) -> Result<()> {
+        let bounds = georef.bounds(self.collage_img.width(), self.collage_img.height());
+        let max_zoom = native_zoom(georef.transform[1]);
+        let min_zoom = max_zoom.saturating_sub(OVERVIEW_LEVELS);
+        for z in min_zoom..=max_zoom {
+            let (image, transform) =
+                image_for_zoom(&self.collage_img, &georef.transform, z, self.resampling);
+            for tile in tiles_covering(bounds, z) {
+                let tile_img = render_tile(&image, &transform, tile, self.resampling);
+                if is_empty(&tile_img) {
+                    continue;
+                }
+                let y = if self.tms { tile.tms_y() } else { tile.y };
+                let dir = self.dir.join(tile.z.to_string()).join(tile.x.to_string());
+                fs::create_dir_all(&dir)?;
+                self.write_tile(&dir.join(format!("{}.png", y)), tile_img)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl OutputWriter for TileCacheWriter {
+    fn create(&mut self, width: u32, height: u32) -> Result<()> {
+        self.inner.create(width, height)?;
+        self.collage_img = RgbaImage::from_pixel(width, height, Rgba([255, 255, 255, 255]));
+        Ok(())
+    }
+
+    fn write_region(&mut self, x: u32, y: u32, image: &DynamicImage) -> Result<()> {
+        self.inner.write_region(x, y, image)?;
+        pixels::copy_into(&mut self.collage_img, &*pixels::rgba(image), x, y);
+        Ok(())
+    }
+
+    fn set_georef(&mut self, georef: &GeoRef) -> Result<()> {
+        self.georef = Some(georef.clone());
+        self.inner.set_georef(georef)
+    }
+
+    fn rows_complete(&mut self, rows: u32) -> Result<()> {
+        self.inner.rows_complete(rows)
+    }
+
+    fn finalize(&mut self) -> Result<()> {
+        self.inner.finalize()?;
+        match self.georef.clone() {
+            Some(georef) => self.save(&georef),
+            // Tiles can't be placed without georeferencing
+            None => Ok(()),
+        }
+    }
+
+    fn discard(&mut self) -> Result<()> {
+        self.inner.discard()
+    }
+}
@@ -0,0 +1,269 @@
+//! What's drawn over the output: lines along the quad's neatline and a
+//! graticule, for printed field maps, and a stamp of attribution text.
+
+use image::{DynamicImage, GenericImageView, Rgb, RgbImage, Rgba};
+
+use crate::conversion::Corner;
+use crate::georef::GeoRef;
+
+// How thick lines are drawn, in pixels
+const LINE_WIDTH: u32 = 2;
+
+// A stamp's font pixels are this many times smaller than the output's
+// shorter side, but never smaller than a pixel...
+const STAMP_SCALE_DIVISOR: u32 = 800;
+// ...and it's this many font pixels from the corner
+const STAMP_MARGIN: u32 = 2;
+
+const GLYPH_WIDTH: u32 = 5;
+const GLYPH_HEIGHT: u32 = 7;
+
+// Printable ASCII from the space on, a column of each glyph per byte, left
+// to right, with its top row in the lowest bit
+const GLYPHS: [[u8; 5]; 95] = [
+    [0x00, 0x00, 0x00, 0x00, 0x00],
+    [0x00, 0x00, 0x5f, 0x00, 0x00],
+    [0x00, 0x07, 0x00, 0x07, 0x00],
+    [0x14, 0x7f, 0x14, 0x7f, 0x14],
+    [0x24, 0x2a, 0x7f, 0x2a, 0x12],
+    [0x23, 0x13, 0x08, 0x64, 0x62],
+    [0x36, 0x49, 0x55, 0x22, 0x50],
+    [0x00, 0x05, 0x03, 0x00, 0x00],
+    [0x00, 0x1c, 0x22, 0x41, 0x00],
+    [0x00, 0x41, 0x22, 0x1c, 0x00],
+    [0x14, 0x08, 0x3e, 0x08, 0x14],
+    [0x08, 0x08, 0x3e, 0x08, 0x08],
+    [0x00, 0x50, 0x30, 0x00, 0x00],
+    [0x08, 0x08, 0x08, 0x08, 0x08],
+    [0x00, 0x60, 0x60, 0x00, 0x00],
+    [0x20, 0x10, 0x08, 0x04, 0x02],
+    [0x3e, 0x51, 0x49, 0x45, 0x3e],
+    [0x00, 0x42, 0x7f, 0x40, 0x00],
+    [0x42, 0x61, 0x51, 0x49, 0x46],
+    [0x21, 0x41, 0x45, 0x4b, 0x31],
+    [0x18, 0x14, 0x12, 0x7f, 0x10],
+    [0x27, 0x45, 0x45, 0x45, 0x39],
+    [0x3c, 0x4a, 0x49, 0x49, 0x30],
+    [0x01, 0x71, 0x09, 0x05, 0x03],
+    [0x36, 0x49, 0x49, 0x49, 0x36],
+    [0x06, 0x49, 0x49, 0x29, 0x1e],
+    [0x00, 0x36, 0x36, 0x00, 0x00],
+    [0x00, 0x56, 0x36, 0x00, 0x00],
+    [0x08, 0x14, 0x22, 0x41, 0x00],
+    [0x14, 0x14, 0x14, 0x14, 0x14],
+    [0x00, 0x41, 0x22, 0x14, 0x08],
+    [0x02, 0x01, 0x51, 0x09, 0x06],
+    [0x32, 0x49, 0x79, 0x41, 0x3e],
+    [0x7e, 0x11, 0x11, 0x11, 0x7e],
+    [0x7f, 0x49, 0x49, 0x49, 0x36],
+    [0x3e, 0x41, 0x41, 0x41, 0x22],
+    [0x7f, 0x41, 0x41, 0x22, 0x1c],
+    [0x7f, 0x49, 0x49, 0x49, 0x41],
+    [0x7f, 0x09, 0x09, 0x09, 0x01],
+    [0x3e, 0x41, 0x49, 0x49, 0x7a],
+    [0x7f, 0x08, 0x08, 0x08, 0x7f],
+    [0x00, 0x41, 0x7f, 0x41, 0x00],
+    [0x20, 0x40, 0x41, 0x3f, 0x01],
+    [0x7f, 0x08, 0x14, 0x22, 0x41],
+    [0x7f, 0x40, 0x40, 0x40, 0x40],
+    [0x7f, 0x02, 0x0c, 0x02, 0x7f],
+    [0x7f, 0x04, 0x08, 0x10, 0x7f],
+    [0x3e, 0x41, 0x41, 0x41, 0x3e],
+    [0x7f, 0x09, 0x09, 0x09, 0x06],
+    [0x3e, 0x41, 0x51, 0x21, 0x5e],
+    [0x7f, 0x09, 0x19, 0x29, 0x46],
+    [0x46, 0x49, 0x49, 0x49, 0x31],
+    [0x01, 0x01, 0x7f, 0x01, 0x01],
+    [0x3f, 0x40, 0x40, 0x40, 0x3f],
+    [0x1f, 0x20, 0x40, 0x20, 0x1f],
+    [0x3f, 0x40, 0x38, 0x40, 0x3f],
+    [0x63, 0x14, 0x08, 0x14, 0x63],
+    [0x07, 0x08, 0x70, 0x08, 0x07],
+    [0x61, 0x51, 0x49, 0x45, 0x43],
+    [0x00, 0x7f, 0x41, 0x41, 0x00],
+    [0x02, 0x04, 0x08, 0x10, 0x20],
+    [0x00, 0x41, 0x41, 0x7f, 0x00],
+    [0x04, 0x02, 0x01, 0x02, 0x04],
+    [0x40, 0x40, 0x40, 0x40, 0x40],
+    [0x00, 0x01, 0x02, 0x04, 0x00],
+    [0x20, 0x54, 0x54, 0x54, 0x78],
+    [0x7f, 0x48, 0x44, 0x44, 0x38],
+    [0x38, 0x44, 0x44, 0x44, 0x20],
+    [0x38, 0x44, 0x44, 0x48, 0x7f],
+    [0x38, 0x54, 0x54, 0x54, 0x18],
+    [0x08, 0x7e, 0x09, 0x01, 0x02],
+    [0x0c, 0x52, 0x52, 0x52, 0x3e],
+    [0x7f, 0x08, 0x04, 0x04, 0x78],
+    [0x00, 0x44, 0x7d, 0x40, 0x00],
+    [0x20, 0x40, 0x44, 0x3d, 0x00],
+    [0x7f, 0x10, 0x28, 0x44, 0x00],
+    [0x00, 0x41, 0x7f, 0x40, 0x00],
+    [0x7c, 0x04, 0x18, 0x04, 0x78],
+    [0x7c, 0x08, 0x04, 0x04, 0x78],
+    [0x38, 0x44, 0x44, 0x44, 0x38],
+    [0x7c, 0x14, 0x14, 0x14, 0x08],
+    [0x08, 0x14, 0x14, 0x18, 0x7c],
+    [0x7c, 0x08, 0x04, 0x04, 0x08],
+    [0x48, 0x54, 0x54, 0x54, 0x20],
+    [0x04, 0x3f, 0x44, 0x40, 0x20],
+    [0x3c, 0x40, 0x40, 0x20, 0x7c],
+    [0x1c, 0x20, 0x40, 0x20, 0x1c],
+    [0x3c, 0x40, 0x30, 0x40, 0x3c],
+    [0x44, 0x28, 0x10, 0x28, 0x44],
+    [0x0c, 0x50, 0x50, 0x50, 0x3c],
+    [0x44, 0x64, 0x54, 0x4c, 0x44],
+    [0x00, 0x08, 0x36, 0x41, 0x00],
+    [0x00, 0x00, 0x7f, 0x00, 0x00],
+    [0x00, 0x41, 0x36, 0x08, 0x00],
+    [0x08, 0x04, 0x08, 0x10, 0x08],
+];
+
+/// The columns and rows of an output that lines run along.
+#[derive(Clone, Debug)]
+pub(crate) struct Overlay {
+    colour: [u8; 3],
+    columns: Vec<u32>,
+    rows: Vec<u32>,
+}
+
+impl Overlay {
+    /// Lines over an output of `width`×`height` pixels georeferenced by
+    /// `georef`: along its edges, which are the quad's neatline, if
+    /// `bounds`, and along every multiple of `graticule` degrees of
+    /// longitude and latitude within it.
+    pub(crate) fn new(
+        colour: [u8; 3],
+        georef: &GeoRef,
+        (width, height): (u32, u32),
+        bounds: bool,
+        graticule: Option<f64>,
+    ) -> Self {
+        let [x, dx, _, y, _, dy] = georef.transform;
+        let mut columns = Vec::new();
+        let mut rows = Vec::new();
+        if bounds {
+            columns.extend([0, width.saturating_sub(LINE_WIDTH)]);
+            rows.extend([0, height.saturating_sub(LINE_WIDTH)]);
+        }
+        if let Some(interval) = graticule.filter(|&interval| interval > 0.0) {
+            let (west, south, east, north) = georef.bounds(width, height);
+            let lines = |low: f64, high: f64| {
+                let first = (low / interval).ceil() as i64;
+                let last = (high / interval).floor() as i64;
+                (first..=last).map(move |i| i as f64 * interval)
+            };
+            // Centred on the line, so it straddles the pixel it falls in
+            let pixel = |degrees: f64, origin: f64, size: f64, span: u32| {
+                let p = ((degrees - origin) / size).floor() as i64 - (LINE_WIDTH / 2) as i64;
+                p.clamp(0, span.saturating_sub(LINE_WIDTH) as i64) as u32
+            };
+            columns.extend(lines(west, east).map(|long| pixel(long, x, dx, width)));
+            rows.extend(lines(south, north).map(|lat| pixel(lat, y, dy, height)));
+        }
+        Overlay {
+            colour,
+            columns,
+            rows,
+        }
+    }
+
+    /// `image`, placed at (`x`, `y`) in the output, with the lines crossing
+    /// it drawn in. Alpha is left as it is.
+    pub(crate) fn draw(&self, image: DynamicImage, (x, y): (u32, u32)) -> DynamicImage {
+        let (width, height) = (image.width(), image.height());
+        let crosses = |lines: &[u32], start: u32, span: u32| {
+            lines
+                .iter()
+                .any(|&line| line < start + span && line + LINE_WIDTH > start)
+        };
+        if !crosses(&self.columns, x, width) && !crosses(&self.rows, y, height) {
+            return image;
+        }
+        let on_line = |lines: &[u32], p: u32| {
+            lines
+                .iter()
+                .any(|&line| (line..line + LINE_WIDTH).contains(&p))
+        };
+        let mut rgba = image.into_rgba8();
+        for (px, py, pixel) in rgba.enumerate_pixels_mut() {
+            if on_line(&self.columns, x + px) || on_line(&self.rows, y + py) {
+                pixel.0[..3].copy_from_slice(&self.colour);
+            }
+        }
+        DynamicImage::ImageRgba8(rgba)
+    }
+}
+
+/// Text in black on white in a corner of the output.
+#[derive(Clone, Debug)]
+pub(crate) struct Stamp {
+    image: RgbImage,
+    x: u32,
+    y: u32,
+}
+
+impl Stamp {
+    /// `text` in `corner` of an output of `width`×`height` pixels, scaled
+    /// to it. Characters other than printable ASCII are drawn as `?`.
+    pub(crate) fn new(text: &str, corner: Corner, (width, height): (u32, u32)) -> Self {
+        let scale = (width.min(height) / STAMP_SCALE_DIVISOR).max(1);
+        let glyphs = text
+            .chars()
+            .map(|c| match c {
+                ' '..='~' => GLYPHS[(c as u32 - ' ' as u32) as usize],
+                _ => GLYPHS[('?' as u32 - ' ' as u32) as usize],
+            })
+            .collect::<Vec<_>>();
+        // A font pixel of white around the text and between characters
+        let columns = glyphs.len() as u32 * (GLYPH_WIDTH + 1) + 1;
+        let rows = GLYPH_HEIGHT + 2;
+        let mut image = RgbImage::from_pixel(columns * scale, rows * scale, Rgb([255, 255, 255]));
+        for (i, glyph) in glyphs.iter().enumerate() {
+            for (column, bits) in glyph.iter().enumerate() {
+                for row in 0..GLYPH_HEIGHT {
+                    if bits & (1 << row) == 0 {
+                        continue;
+                    }
+                    let left = (i as u32 * (GLYPH_WIDTH + 1) + column as u32 + 1) * scale;
+                    let top = (row + 1) * scale;
+                    for y in top..top + scale {
+                        for x in left..left + scale {
+                            image.put_pixel(x, y, Rgb([0, 0, 0]));
+                        }
+                    }
+                }
+            }
+        }
+
+        let margin = STAMP_MARGIN * scale;
+        let (left, top) = (margin, margin);
+        let right = width.saturating_sub(image.width() + margin);
+        let bottom = height.saturating_sub(image.height() + margin);
+        let (x, y) = match corner {
+            Corner::TopLeft => (left, top),
+            Corner::TopRight => (right, top),
+            Corner::BottomLeft => (left, bottom),
+            Corner::BottomRight => (right, bottom),
+        };
+        Stamp { image, x, y }
+    }
+
+    /// `image`, placed at (`x`, `y`) in the output, with the part of the
+    /// stamp over it drawn in, opaque.
+    pub(crate) fn draw(&self, image: DynamicImage, (x, y): (u32, u32)) -> DynamicImage {
+        let (left, top) = (self.x.max(x), self.y.max(y));
+        let right = (self.x + self.image.width()).min(x + image.width());
+        let bottom = (self.y + self.image.height()).min(y + image.height());
+        if left >= right || top >= bottom {
+            return image;
+        }
+        let mut rgba = image.into_rgba8();
+        for py in top..bottom {
+            for px in left..right {
+                let Rgb([r, g, b]) = *self.image.get_pixel(px - self.x, py - self.y);
+                rgba.put_pixel(px - x, py - y, Rgba([r, g, b, 255]));
+            }
+        }
+        DynamicImage::ImageRgba8(rgba)
+    }
+}
@@ -0,0 +1,54 @@
+//! Imagery reduced to a small palette, for paletted outputs. Scanned topo
+//! maps use few colours, so this shrinks them with little visible loss.
+
+use std::collections::HashMap;
+
+use color_quant::NeuQuant;
+use image::RgbaImage;
+
+// One in this many pixels trains the palette, NeuQuant's suggested balance
+// of speed and quality
+const SAMPLE_FACTOR: i32 = 10;
+
+/// An image as indices into a palette of RGBA colours.
+pub(crate) struct Paletted {
+    pub(crate) palette: Vec<[u8; 4]>,
+    pub(crate) indices: Vec<u8>,
+}
+
+impl Paletted {
+    /// The palette's colours as RGB triples, as PNG's PLTE chunk holds them.
+    pub(crate) fn rgb(&self) -> Vec<u8> {
+        self.palette
+            .iter()
+            .flat_map(|colour| colour[..3].to_vec())
+            .collect()
+    }
+
+    /// The palette's alpha values, as PNG's tRNS chunk holds them.
+    pub(crate) fn alpha(&self) -> Vec<u8> {
+        self.palette.iter().map(|colour| colour[3]).collect()
+    }
+}
+
+/// `image` reduced to a palette of at most `colors` colours, 2 to 256.
+pub(crate) fn quantize(image: &RgbaImage, colors: u16) -> Paletted {
+    let quant = NeuQuant::new(SAMPLE_FACTOR, colors.clamp(2, 256) as usize, image.as_raw());
+    let palette = quant
+        .color_map_rgba()
+        .chunks_exact(4)
+        .map(|colour| [colour[0], colour[1], colour[2], colour[3]])
+        .collect();
+    // Neighbouring pixels often repeat, and searching the network doesn't
+    // come cheap
+    let mut seen = HashMap::new();
+    let indices = image
+        .pixels()
+        .map(|pixel| {
+            *seen
+                .entry(pixel.0)
+                .or_insert_with(|| quant.index_of(&pixel.0) as u8)
+        })
+        .collect();
+    Paletted { palette, indices }
+}
@@ -0,0 +1,92 @@
+//! Bulk pixel conversions and copies.
+//!
+//! The image crate converts and composites a pixel at a time through generic
+//! accessors, which doesn't vectorize. These work on whole rows of raw
+//! samples in fixed-size chunks the compiler turns into SIMD shuffles and
+//! `memcpy`s, which matters on mosaics of hundreds of megapixels.
+
+use std::borrow::Cow;
+
+use image::{DynamicImage, GrayAlphaImage, GrayImage, ImageBuffer, Pixel, RgbImage, RgbaImage};
+
+pub(crate) fn rgba_to_rgb(rgba: &[u8]) -> Vec<u8> {
+    let mut rgb = vec![0; rgba.len() / 4 * 3];
+    for (to, from) in rgb.chunks_exact_mut(3).zip(rgba.chunks_exact(4)) {
+        to.copy_from_slice(&from[..3]);
+    }
+    rgb
+}
+
+pub(crate) fn rgb_to_rgba(rgb: &[u8]) -> Vec<u8> {
+    let mut rgba = vec![255; rgb.len() / 3 * 4];
+    for (to, from) in rgba.chunks_exact_mut(4).zip(rgb.chunks_exact(3)) {
+        to[..3].copy_from_slice(from);
+    }
+    rgba
+}
+
+/// `image` as RGB, borrowed when it already is.
+pub(crate) fn rgb(image: &DynamicImage) -> Cow<'_, RgbImage> {
+    match image {
+        DynamicImage::ImageRgb8(rgb) => Cow::Borrowed(rgb),
+        DynamicImage::ImageRgba8(rgba) => Cow::Owned(
+            RgbImage::from_raw(rgba.width(), rgba.height(), rgba_to_rgb(rgba))
+                .expect("buffer matches dimensions"),
+        ),
+        other => Cow::Owned(other.to_rgb8()),
+    }
+}
+
+/// `image` as RGBA, opaque unless it has alpha, borrowed when it already is.
+pub(crate) fn rgba(image: &DynamicImage) -> Cow<'_, RgbaImage> {
+    match image {
+        DynamicImage::ImageRgba8(rgba) => Cow::Borrowed(rgba),
+        DynamicImage::ImageRgb8(rgb) => Cow::Owned(
+            RgbaImage::from_raw(rgb.width(), rgb.height(), rgb_to_rgba(rgb))
+                .expect("buffer matches dimensions"),
+        ),
+        other => Cow::Owned(other.to_rgba8()),
+    }
+}
+
+/// `image` as grayscale, borrowed when it already is.
+pub(crate) fn luma(image: &DynamicImage) -> Cow<'_, GrayImage> {
+    match image {
+        DynamicImage::ImageLuma8(luma) => Cow::Borrowed(luma),
+        other => Cow::Owned(other.to_luma8()),
+    }
+}
+
+/// `image` as grayscale with alpha, borrowed when it already is.
+pub(crate) fn luma_alpha(image: &DynamicImage) -> Cow<'_, GrayAlphaImage> {
+    match image {
+        DynamicImage::ImageLumaA8(luma_alpha) => Cow::Borrowed(luma_alpha),
+        other => Cow::Owned(other.to_luma_alpha8()),
+    }
+}
+
+/// Copies `image` into `canvas` with its top-left corner at (`x`, `y`),
+/// clipped to the canvas, a row at a time.
+pub(crate) fn copy_into<P: Pixel<Subpixel = u8> + 'static>(
+    canvas: &mut ImageBuffer<P, Vec<u8>>,
+    image: &ImageBuffer<P, Vec<u8>>,
+    x: u32,
+    y: u32,
+) {
+    let channels = P::CHANNEL_COUNT as usize;
+    let (canvas_width, canvas_height) = canvas.dimensions();
+    if x >= canvas_width || y >= canvas_height {
+        return;
+    }
+    let width = image.width().min(canvas_width - x) as usize;
+    let height = image.height().min(canvas_height - y) as usize;
+
+    let canvas_stride = canvas_width as usize * channels;
+    let image_stride = image.width() as usize * channels;
+    let row_len = width * channels;
+    let canvas_start = y as usize * canvas_stride + x as usize * channels;
+    let canvas_rows = canvas[canvas_start..].chunks_mut(canvas_stride);
+    for (to, from) in canvas_rows.zip(image.chunks(image_stride)).take(height) {
+        to[..row_len].copy_from_slice(&from[..row_len]);
+    }
+}
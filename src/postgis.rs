@@ -0,0 +1,110 @@
+//! SQL registering converted GeoTIFFs in PostGIS as out-db rasters, like
+//! `raster2pgsql -R -I`, so an archive is loaded with a single `psql -f`.
+
+use std::fmt::Write;
+
+use crate::conversion::{format_for_extension, ConversionSummary, Format};
+use crate::error::{Result, TopodumpError};
+use crate::georef::NAD27_WKT;
+
+// The SRID of an output's CRS, from its EPSG code where the WKT gives one,
+// or 0, PostGIS' unknown SRID
+fn srid(wkt: &str) -> i32 {
+    if wkt == NAD27_WKT {
+        return 4267;
+    }
+    // The CRS' own code comes last, after those of its datum and ellipsoid
+    let code = |marker: &str| {
+        let start = wkt.rfind(marker)? + marker.len();
+        let digits = wkt[start..]
+            .trim_start_matches('"')
+            .chars()
+            .take_while(char::is_ascii_digit)
+            .collect::<String>();
+        digits.parse().ok()
+    };
+    code(r#"AUTHORITY["EPSG",""#)
+        .or_else(|| code(r#"ID["EPSG","#))
+        .unwrap_or(0)
+}
+
+fn quote_literal(text: &str) -> String {
+    format!("'{}'", text.replace('\'', "''"))
+}
+
+// Each part of an optionally schema-qualified name, quoted
+fn quote_identifier(name: &str) -> String {
+    name.split('.')
+        .map(|part| format!("\"{}\"", part.replace('"', "\"\"")))
+        .collect::<Vec<_>>()
+        .join(".")
+}
+
+/// SQL creating `table` if needed and inserting a raster for each GeoTIFF of
+/// `conversions`, each of its `bands` read by PostGIS from the file in place.
+/// The outputs' absolute paths are recorded, so the database server must see
+/// them at the same paths.
+pub fn postgis_sql(conversions: &[ConversionSummary], table: &str, bands: u32) -> Result<String> {
+    let outputs = conversions
+        .iter()
+        .filter(|conversion| format_for_extension(&conversion.output) == Some(Format::GTiff))
+        .collect::<Vec<_>>();
+    if outputs.is_empty() {
+        return Err(TopodumpError::Unsupported(
+            "PostGIS rasters without GeoTIFF outputs",
+        ));
+    }
+    let table_name = quote_identifier(table);
+    let index_name = quote_identifier(&format!(
+        "{}_rast_gist",
+        table.rsplit('.').next().unwrap_or(table)
+    ));
+    let band_list = (1..=bands)
+        .map(|band| band.to_string())
+        .collect::<Vec<_>>()
+        .join(",");
+
+    let mut sql = String::new();
+    // Writing to a String can't fail
+    let _ = writeln!(sql, "BEGIN;");
+    let _ = writeln!(
+        sql,
+        "CREATE TABLE IF NOT EXISTS {} (rid serial PRIMARY KEY, rast raster, filename text, quad text, state text);",
+        table_name
+    );
+    for output in outputs {
+        let path = output.output.canonicalize()?;
+        let [x, dx, rx, y, ry, dy] = output.georef.transform;
+        let _ = writeln!(
+            sql,
+            "INSERT INTO {} (rast, filename, quad, state) VALUES (ST_AddBand(ST_MakeEmptyRaster({}, {}, {:.16e}, {:.16e}, {:.16e}, {:.16e}, {:.16e}, {:.16e}, {}), 1, {}, ARRAY[{}]::integer[], NULL), {}, {}, {});",
+            table_name,
+            output.width,
+            output.height,
+            x,
+            y,
+            dx,
+            dy,
+            rx,
+            ry,
+            srid(&output.georef.wkt),
+            quote_literal(&path.to_string_lossy()),
+            band_list,
+            quote_literal(
+                &path
+                    .file_name()
+                    .map_or_else(String::new, |name| name.to_string_lossy().to_string())
+            ),
+            quote_literal(output.header.quad_name.trim()),
+            quote_literal(output.header.state_name.trim())
+        );
+    }
+    let _ = writeln!(
+        sql,
+        "CREATE INDEX IF NOT EXISTS {} ON {} USING gist (ST_ConvexHull(rast));",
+        index_name, table_name
+    );
+    let _ = writeln!(sql, "ANALYZE {};", table_name);
+    let _ = writeln!(sql, "COMMIT;");
+    Ok(sql)
+}
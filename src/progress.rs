@@ -0,0 +1,11 @@
+use std::path::PathBuf;
+
+/// Conversion stages reported to [`Conversion::on_progress`](crate::Conversion::on_progress).
+#[derive(Clone, Debug)]
+pub enum Progress {
+    HeaderParsed { long_count: u32, lat_count: u32 },
+    MapletDecoded { row: u32, col: u32, total: u32 },
+    OutputWritten { path: PathBuf },
+}
+
+pub(crate) type ProgressCallback = Box<dyn FnMut(Progress) + Send>;
@@ -0,0 +1,133 @@
+//! Configuration publishing converted GeoTIFFs as web map services: a
+//! MapServer `LAYER` for each output, or a GeoServer ImageMosaic over them all.
+
+use std::fmt::Write;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::conversion::{format_for_extension, ConversionSummary, Format};
+use crate::error::{Result, TopodumpError};
+
+fn geotiffs(conversions: &[ConversionSummary]) -> Result<Vec<&ConversionSummary>> {
+    let outputs = conversions
+        .iter()
+        .filter(|conversion| format_for_extension(&conversion.output) == Some(Format::GTiff))
+        .collect::<Vec<_>>();
+    if outputs.is_empty() {
+        return Err(TopodumpError::Unsupported(
+            "publishing without GeoTIFF outputs",
+        ));
+    }
+    Ok(outputs)
+}
+
+fn quote(text: &str) -> String {
+    format!("\"{}\"", text.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+// A layer name of letters, digits and underscores, as WMS clients expect
+fn layer_name(output: &Path) -> String {
+    let stem = output
+        .file_stem()
+        .map_or_else(String::new, |stem| stem.to_string_lossy().to_string());
+    let name = stem
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect::<String>();
+    if name.starts_with(|c: char| c.is_ascii_alphabetic()) {
+        name
+    } else {
+        format!("quad_{}", name)
+    }
+}
+
+/// MapServer `LAYER` blocks for the GeoTIFFs of `conversions`, in a `GROUP`
+/// named `group` so they can be requested as one WMS layer, to be
+/// `INCLUDE`d in a mapfile. Each takes its projection from its file.
+pub fn mapserver_layers(conversions: &[ConversionSummary], group: &str) -> Result<String> {
+    let mut map = String::new();
+    let mut names = Vec::new();
+    for output in geotiffs(conversions)? {
+        let path = output.output.canonicalize()?;
+        let mut name = layer_name(&path);
+        // Quads converted twice, e.g. from overlapping discs
+        if names.contains(&name) {
+            name = format!("{}_{}", name, names.len());
+        }
+        let (west, south, east, north) = output.georef.bounds(output.width, output.height);
+        let title = format!(
+            "{}, {}",
+            output.header.quad_name.trim(),
+            output.header.state_name.trim()
+        );
+        // Writing to a String can't fail
+        let _ = writeln!(map, "LAYER");
+        let _ = writeln!(map, "  NAME {}", quote(&name));
+        let _ = writeln!(map, "  GROUP {}", quote(group));
+        let _ = writeln!(map, "  TYPE RASTER");
+        let _ = writeln!(map, "  STATUS ON");
+        let _ = writeln!(map, "  DATA {}", quote(&path.to_string_lossy()));
+        let _ = writeln!(map, "  EXTENT {} {} {} {}", west, south, east, north);
+        let _ = writeln!(map, "  PROJECTION");
+        let _ = writeln!(map, "    AUTO");
+        let _ = writeln!(map, "  END");
+        let _ = writeln!(map, "  METADATA");
+        let _ = writeln!(map, "    \"wms_title\" {}", quote(&title));
+        let _ = writeln!(map, "    \"wms_group_title\" {}", quote(group));
+        let _ = writeln!(map, "  END");
+        let _ = writeln!(map, "END");
+        names.push(name);
+    }
+    Ok(map)
+}
+
+// The deepest directory holding every output
+fn common_dir(outputs: &[&ConversionSummary]) -> Result<PathBuf> {
+    let mut common: Option<PathBuf> = None;
+    for output in outputs {
+        let dir = output
+            .output
+            .canonicalize()?
+            .parent()
+            .map_or_else(PathBuf::new, Path::to_path_buf);
+        common = Some(match common {
+            None => dir,
+            Some(common) => common
+                .components()
+                .zip(dir.components())
+                .take_while(|(a, b)| a == b)
+                .map(|(a, _)| a)
+                .collect(),
+        });
+    }
+    Ok(common.unwrap_or_default())
+}
+
+/// Writes a GeoServer ImageMosaic `indexer.properties` named `name` into the
+/// deepest directory holding every GeoTIFF of `conversions`, returning its
+/// path, so that directory can be added as an ImageMosaic store. The
+/// granules are indexed by their absolute paths and may differ in
+/// resolution, but must share a CRS.
+pub fn write_geoserver_mosaic(conversions: &[ConversionSummary], name: &str) -> Result<PathBuf> {
+    let outputs = geotiffs(conversions)?;
+    if outputs
+        .iter()
+        .any(|output| output.georef.wkt != outputs[0].georef.wkt)
+    {
+        return Err(TopodumpError::Unsupported(
+            "ImageMosaics of outputs in different CRSs",
+        ));
+    }
+    let path = common_dir(&outputs)?.join("indexer.properties");
+    let mut properties = String::new();
+    let _ = writeln!(properties, "Name={}", name);
+    let _ = writeln!(properties, "TypeName={}", name);
+    let _ = writeln!(properties, "Recursive=true");
+    let _ = writeln!(properties, "Wildcard=*.tif");
+    let _ = writeln!(properties, "AbsolutePath=true");
+    let _ = writeln!(properties, "Heterogeneous=true");
+    let _ = writeln!(properties, "Schema=*the_geom:Polygon,location:String");
+    let _ = writeln!(properties, "Caching=false");
+    fs::write(&path, properties)?;
+    Ok(path)
+}
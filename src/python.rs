@@ -0,0 +1,96 @@
+//! The `topodump` Python module, built with maturin and the `python` feature.
+
+use std::path::PathBuf;
+
+use numpy::{PyArray1, PyArray3};
+use pyo3::exceptions::{PyIOError, PyRuntimeError, PyValueError};
+use pyo3::prelude::*;
+use pyo3::types::PyDict;
+use pyo3::wrap_pyfunction;
+
+use crate::conversion::{Compression, Conversion, Format};
+use crate::error::TopodumpError;
+use crate::tpq::TpqFile;
+
+impl From<TopodumpError> for PyErr {
+    fn from(err: TopodumpError) -> PyErr {
+        match err {
+            TopodumpError::Io(_) => PyIOError::new_err(err.to_string()),
+            _ => PyRuntimeError::new_err(err.to_string()),
+        }
+    }
+}
+
+/// read_header(path)
+/// --
+///
+/// Returns the header of a tpq file as a dict.
+#[pyfunction]
+fn read_header(py: Python, path: PathBuf) -> PyResult<PyObject> {
+    let tpq = TpqFile::open(path)?;
+    let header = tpq.header();
+    let dict = PyDict::new(py);
+    dict.set_item("version", header.version)?;
+    dict.set_item("west", header.w_long)?;
+    dict.set_item("north", header.n_lat)?;
+    dict.set_item("east", header.e_long)?;
+    dict.set_item("south", header.s_lat)?;
+    dict.set_item("topo", &header.topo)?;
+    dict.set_item("quad_name", &header.quad_name)?;
+    dict.set_item("state_name", &header.state_name)?;
+    dict.set_item("source", &header.source)?;
+    dict.set_item("year1", &header.year1)?;
+    dict.set_item("year2", &header.year2)?;
+    dict.set_item("contour", &header.contour)?;
+    dict.set_item("color_depth", header.color_depth)?;
+    dict.set_item("columns", header.long_count)?;
+    dict.set_item("rows", header.lat_count)?;
+    Ok(dict.into())
+}
+
+/// convert(path, out=None, format=None, compression=None)
+/// --
+///
+/// Converts a tpq file and returns the output path.
+#[pyfunction]
+fn convert(
+    path: PathBuf,
+    out: Option<PathBuf>,
+    format: Option<&str>,
+    compression: Option<&str>,
+) -> PyResult<String> {
+    let mut conversion = Conversion::new(path);
+    if let Some(out) = out {
+        conversion = conversion.output(out);
+    }
+    if let Some(format) = format {
+        conversion = conversion.format(format.parse::<Format>().map_err(PyValueError::new_err)?);
+    }
+    if let Some(compression) = compression {
+        conversion = conversion.compression(
+            compression
+                .parse::<Compression>()
+                .map_err(PyValueError::new_err)?,
+        );
+    }
+    Ok(conversion.run()?.to_string_lossy().to_string())
+}
+
+/// read_array(path)
+/// --
+///
+/// Decodes a tpq file into a (height, width, 4) RGBA uint8 array.
+#[pyfunction]
+fn read_array<'py>(py: Python<'py>, path: PathBuf) -> PyResult<&'py PyArray3<u8>> {
+    let collage_img = py.allow_threads(|| TpqFile::open(path)?.collage())?;
+    let (width, height) = (collage_img.width() as usize, collage_img.height() as usize);
+    PyArray1::from_vec(py, collage_img.into_raw()).reshape([height, width, 4])
+}
+
+#[pymodule]
+fn topodump(_py: Python, m: &PyModule) -> PyResult<()> {
+    m.add_function(wrap_pyfunction!(read_header, m)?)?;
+    m.add_function(wrap_pyfunction!(convert, m)?)?;
+    m.add_function(wrap_pyfunction!(read_array, m)?)?;
+    Ok(())
+}
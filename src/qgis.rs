@@ -0,0 +1,148 @@
+//! QGIS project (.qgs) and layer definition (.qlr) files loading converted rasters.
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::conversion::ConversionSummary;
+use crate::error::Result;
+use crate::vrt::escape_xml;
+
+struct Layer {
+    id: String,
+    name: String,
+    source: String,
+}
+
+// QGIS resolves "./" sources against the project file
+fn layer_source(output: &Path, project_dir: &Path) -> String {
+    let output = output
+        .canonicalize()
+        .unwrap_or_else(|_| output.to_path_buf());
+    let project_dir = project_dir
+        .canonicalize()
+        .unwrap_or_else(|_| project_dir.to_path_buf());
+    match output.strip_prefix(&project_dir) {
+        Ok(relative) => format!("./{}", relative.display()),
+        Err(_) => output.display().to_string(),
+    }
+}
+
+fn write_layer_tree(xml: &mut String, groups: &BTreeMap<String, BTreeMap<String, Vec<Layer>>>) {
+    for (state, scales) in groups {
+        let _ = writeln!(
+            xml,
+            r#"  <layer-tree-group name="{}" checked="Qt::Checked" expanded="1">"#,
+            escape_xml(state)
+        );
+        for (scale, layers) in scales {
+            let _ = writeln!(
+                xml,
+                r#"    <layer-tree-group name="{}" checked="Qt::Checked" expanded="0">"#,
+                escape_xml(scale)
+            );
+            for layer in layers {
+                let _ = writeln!(
+                    xml,
+                    r#"      <layer-tree-layer id="{}" name="{}" source="{}" providerKey="gdal" checked="Qt::Checked" expanded="0"/>"#,
+                    escape_xml(&layer.id),
+                    escape_xml(&layer.name),
+                    escape_xml(&layer.source)
+                );
+            }
+            let _ = writeln!(xml, "    </layer-tree-group>");
+        }
+        let _ = writeln!(xml, "  </layer-tree-group>");
+    }
+}
+
+fn write_map_layers(xml: &mut String, groups: &BTreeMap<String, BTreeMap<String, Vec<Layer>>>) {
+    for layer in groups.values().flat_map(|scales| scales.values()).flatten() {
+        let _ = writeln!(xml, r#"    <maplayer type="raster">"#);
+        let _ = writeln!(xml, "      <id>{}</id>", escape_xml(&layer.id));
+        let _ = writeln!(
+            xml,
+            "      <datasource>{}</datasource>",
+            escape_xml(&layer.source)
+        );
+        let _ = writeln!(
+            xml,
+            "      <layername>{}</layername>",
+            escape_xml(&layer.name)
+        );
+        let _ = writeln!(xml, "      <provider>gdal</provider>");
+        let _ = writeln!(xml, "    </maplayer>");
+    }
+}
+
+/// Writes a project loading `conversions`, grouped by state and then map
+/// series. A `.qlr` extension produces a layer definition instead of a project.
+pub fn write_qgis_project(path: &Path, conversions: &[ConversionSummary]) -> Result<()> {
+    let project_dir = path
+        .parent()
+        .map_or_else(|| PathBuf::from("."), Path::to_path_buf);
+
+    let mut groups = BTreeMap::<String, BTreeMap<String, Vec<Layer>>>::new();
+    for (index, conversion) in conversions.iter().enumerate() {
+        let name = conversion
+            .output
+            .file_stem()
+            .map_or_else(String::new, |stem| stem.to_string_lossy().to_string());
+        let scale = conversion
+            .series
+            .map_or_else(|| "Unknown scale".to_string(), |series| series.to_string());
+        groups
+            .entry(conversion.header.state_name.clone())
+            .or_default()
+            .entry(scale)
+            .or_default()
+            .push(Layer {
+                id: format!("{}_{}", name, index),
+                name,
+                source: layer_source(&conversion.output, &project_dir),
+            });
+    }
+
+    let layer_definition = path
+        .extension()
+        .map_or(false, |extension| extension.eq_ignore_ascii_case("qlr"));
+    let mut xml = String::new();
+    if layer_definition {
+        let _ = writeln!(xml, "<!DOCTYPE qgis-layer-definition>");
+        let _ = writeln!(xml, "<qlr>");
+        let _ = writeln!(
+            xml,
+            r#" <layer-tree-group name="" checked="Qt::Checked" expanded="1">"#
+        );
+        write_layer_tree(&mut xml, &groups);
+        let _ = writeln!(xml, " </layer-tree-group>");
+        let _ = writeln!(xml, "  <maplayers>");
+        write_map_layers(&mut xml, &groups);
+        let _ = writeln!(xml, "  </maplayers>");
+        let _ = writeln!(xml, "</qlr>");
+    } else {
+        let _ = writeln!(
+            xml,
+            "<!DOCTYPE qgis PUBLIC 'http://mrcc.com/qgis.dtd' 'SYSTEM'>"
+        );
+        let _ = writeln!(xml, r#"<qgis version="3.16.0" projectname="topodump">"#);
+        let _ = writeln!(
+            xml,
+            r#" <layer-tree-group name="" checked="Qt::Checked" expanded="1">"#
+        );
+        write_layer_tree(&mut xml, &groups);
+        let _ = writeln!(xml, " </layer-tree-group>");
+        let _ = writeln!(xml, "  <projectlayers>");
+        write_map_layers(&mut xml, &groups);
+        let _ = writeln!(xml, "  </projectlayers>");
+        let _ = writeln!(
+            xml,
+            r#"  <properties><Paths><Absolute type="bool">false</Absolute></Paths></properties>"#
+        );
+        let _ = writeln!(xml, "</qgis>");
+    }
+
+    fs::write(path, xml)?;
+    Ok(())
+}
@@ -0,0 +1,130 @@
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+// Relative slack allowed when matching a quad's extent against a series footprint
+const EXTENT_TOLERANCE: f64 = 0.05;
+
+// Metres per degree of latitude, close enough for classification
+const METRES_PER_DEGREE: f64 = 111_320.0;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub enum Series {
+    #[serde(rename = "24k")]
+    Scale24k,
+    #[serde(rename = "62k")]
+    Scale62k,
+    #[serde(rename = "63k")]
+    Scale63k,
+    #[serde(rename = "100k")]
+    Scale100k,
+    #[serde(rename = "250k")]
+    Scale250k,
+    #[serde(rename = "500k")]
+    Scale500k,
+}
+
+impl Series {
+    pub const ALL: [Series; 6] = [
+        Series::Scale24k,
+        Series::Scale62k,
+        Series::Scale63k,
+        Series::Scale100k,
+        Series::Scale250k,
+        Series::Scale500k,
+    ];
+
+    pub fn denominator(self) -> u32 {
+        match self {
+            Series::Scale24k => 24_000,
+            Series::Scale62k => 62_500,
+            Series::Scale63k => 63_360,
+            Series::Scale100k => 100_000,
+            Series::Scale250k => 250_000,
+            Series::Scale500k => 500_000,
+        }
+    }
+
+    /// Short form used in output file names, e.g. "24k".
+    pub fn slug(self) -> &'static str {
+        match self {
+            Series::Scale24k => "24k",
+            Series::Scale62k => "62k",
+            Series::Scale63k => "63k",
+            Series::Scale100k => "100k",
+            Series::Scale250k => "250k",
+            Series::Scale500k => "500k",
+        }
+    }
+
+    // (lat span, min long span, max long span) in degrees of the standard USGS sheets
+    fn footprint(self) -> Option<(f64, f64, f64)> {
+        match self {
+            Series::Scale24k => Some((0.125, 0.125, 0.125)),
+            Series::Scale62k => Some((0.25, 0.25, 0.25)),
+            // Alaska 15' quads widen from 20' to 36' with latitude
+            Series::Scale63k => Some((0.25, 0.333, 0.6)),
+            Series::Scale100k => Some((0.5, 1.0, 1.0)),
+            Series::Scale250k => Some((1.0, 2.0, 3.0)),
+            Series::Scale500k => None,
+        }
+    }
+
+    /// The span in degrees of longitude and latitude of the cells the
+    /// series' standard sheets are laid out on, if they're all the same.
+    pub fn cell_size(self) -> Option<(f64, f64)> {
+        match self.footprint()? {
+            (lat, long_min, long_max) if long_min == long_max => Some((long_min, lat)),
+            _ => None,
+        }
+    }
+
+    // Ground size of a pixel for a sheet scanned at 254 dpi
+    fn nominal_resolution(self) -> f64 {
+        self.denominator() as f64 * 0.0001
+    }
+}
+
+impl fmt::Display for Series {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let denominator = self.denominator();
+        write!(f, "1:{},{:03}", denominator / 1000, denominator % 1000)
+    }
+}
+
+fn within(value: f64, min: f64, max: f64) -> bool {
+    value >= min * (1.0 - EXTENT_TOLERANCE) && value <= max * (1.0 + EXTENT_TOLERANCE)
+}
+
+/// Infers the map series from a quad's extent, falling back to its pixel
+/// resolution for non-standard sheets.
+pub fn infer_series(lat_span: f64, long_span: f64, pixel_height: u32) -> Option<Series> {
+    let lat_span = lat_span.abs();
+    let long_span = long_span.abs();
+
+    let by_extent = Series::ALL.iter().copied().find(|series| {
+        series
+            .footprint()
+            .map_or(false, |(lat, long_min, long_max)| {
+                within(lat_span, lat, lat) && within(long_span, long_min, long_max)
+            })
+    });
+    if by_extent.is_some() || pixel_height == 0 {
+        return by_extent;
+    }
+
+    let resolution = lat_span * METRES_PER_DEGREE / pixel_height as f64;
+    Series::ALL
+        .iter()
+        .copied()
+        .map(|series| {
+            (
+                series,
+                (resolution / series.nominal_resolution()).ln().abs(),
+            )
+        })
+        // Further than a factor of two from every series isn't a guess worth making
+        .filter(|(_, distance)| *distance < 2f64.ln())
+        .min_by(|(_, a), (_, b)| a.partial_cmp(b).unwrap())
+        .map(|(series, _)| series)
+}
@@ -0,0 +1,125 @@
+//! Where a [`TpqFile`](crate::TpqFile) reads its bytes from.
+
+use std::borrow::Cow;
+use std::fs::File;
+use std::io;
+use std::ops::Range;
+use std::path::Path;
+
+#[cfg(feature = "mmap")]
+use memmap2::Mmap;
+
+/// Random access to the bytes of a tpq file.
+pub trait TpqSource: Send + Sync {
+    /// Length of the file in bytes.
+    fn size(&self) -> u64;
+
+    /// Reads `range`, borrowing when the bytes are already in memory.
+    fn read_range(&self, range: Range<u64>) -> io::Result<Cow<'_, [u8]>>;
+}
+
+fn out_of_bounds() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::UnexpectedEof,
+        "read past the end of the file",
+    )
+}
+
+impl TpqSource for Vec<u8> {
+    fn size(&self) -> u64 {
+        self.len() as u64
+    }
+
+    fn read_range(&self, range: Range<u64>) -> io::Result<Cow<'_, [u8]>> {
+        self.get(range.start as usize..range.end as usize)
+            .map(Cow::Borrowed)
+            .ok_or_else(out_of_bounds)
+    }
+}
+
+/// Reads a file on demand, so only the maplets being decoded are in memory.
+pub struct FileSource {
+    // Read at an offset rather than seeked, so several decoding threads can
+    // read maplets at once without waiting on each other
+    file: File,
+    size: u64,
+}
+
+impl FileSource {
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let file = File::open(path)?;
+        let size = file.metadata()?.len();
+        Ok(FileSource { file, size })
+    }
+}
+
+impl TpqSource for FileSource {
+    fn size(&self) -> u64 {
+        self.size
+    }
+
+    fn read_range(&self, range: Range<u64>) -> io::Result<Cow<'_, [u8]>> {
+        if range.end > self.size || range.start > range.end {
+            return Err(out_of_bounds());
+        }
+        let mut data = vec![0u8; (range.end - range.start) as usize];
+        read_exact_at(&self.file, &mut data, range.start)?;
+        Ok(Cow::Owned(data))
+    }
+}
+
+#[cfg(unix)]
+fn read_exact_at(file: &File, buf: &mut [u8], offset: u64) -> io::Result<()> {
+    use std::os::unix::fs::FileExt;
+    file.read_exact_at(buf, offset)
+}
+
+// Windows only has the equivalent of `read_at`, which may read less
+#[cfg(windows)]
+fn read_exact_at(file: &File, mut buf: &mut [u8], mut offset: u64) -> io::Result<()> {
+    use std::os::windows::fs::FileExt;
+    while !buf.is_empty() {
+        match file.seek_read(buf, offset) {
+            Ok(0) => return Err(out_of_bounds()),
+            Ok(read) => {
+                let rest = buf;
+                buf = &mut rest[read..];
+                offset += read as u64;
+            }
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(())
+}
+
+/// Serves maplets straight from the page cache, without copying.
+#[cfg(feature = "mmap")]
+pub struct MmapSource(Mmap);
+
+#[cfg(feature = "mmap")]
+impl MmapSource {
+    /// Maps a file into memory.
+    ///
+    /// The file must not be truncated or modified while mapped; doing so is
+    /// undefined behaviour, which is why this is opt-in.
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let file = File::open(path)?;
+        // Safety: callers opting into mmap accept that the file must not change underneath it
+        Ok(MmapSource(unsafe { Mmap::map(&file)? }))
+    }
+}
+
+#[cfg(feature = "mmap")]
+impl TpqSource for MmapSource {
+    fn size(&self) -> u64 {
+        self.0.len() as u64
+    }
+
+    fn read_range(&self, range: Range<u64>) -> io::Result<Cow<'_, [u8]>> {
+        self.0
+            .get(range.start as usize..range.end as usize)
+            .map(Cow::Borrowed)
+            .ok_or_else(out_of_bounds)
+    }
+}
@@ -0,0 +1,267 @@
+//! STAC catalogs of converted quads, with a collection for each state and
+//! map series, so an archive can be searched with standard STAC tooling.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+use serde_json::{json, Value};
+
+use crate::conversion::ConversionSummary;
+use crate::error::Result;
+use crate::output::thumbnail_path;
+
+const STAC_VERSION: &str = "1.0.0";
+
+// Lower-case letters and digits, with anything else between them as a dash
+fn slug(text: &str) -> String {
+    let mut slug = String::new();
+    for c in text.trim_matches(char::from(0)).trim().chars() {
+        if c.is_ascii_alphanumeric() {
+            slug.push(c.to_ascii_lowercase());
+        } else if !slug.is_empty() && !slug.ends_with('-') {
+            slug.push('-');
+        }
+    }
+    let slug = slug.trim_end_matches('-');
+    if slug.is_empty() {
+        "unknown".to_string()
+    } else {
+        slug.to_string()
+    }
+}
+
+// A link from files in `from_dir` to `to`, relative wherever both are on the
+// same filesystem root
+fn href(from_dir: &Path, to: &Path) -> String {
+    let from_dir = from_dir
+        .canonicalize()
+        .unwrap_or_else(|_| from_dir.to_path_buf());
+    let to = to.canonicalize().unwrap_or_else(|_| to.to_path_buf());
+    let parts = |path: &Path| {
+        path.components()
+            .map(|component| component.as_os_str().to_string_lossy().to_string())
+            .collect::<Vec<_>>()
+    };
+    let (from, to_parts) = (parts(&from_dir), parts(&to));
+    // On another drive
+    if from.first() != to_parts.first() {
+        return to.display().to_string();
+    }
+    let common = from
+        .iter()
+        .zip(&to_parts)
+        .take_while(|(a, b)| a == b)
+        .count();
+    let mut relative = vec!["..".to_string(); from.len() - common];
+    relative.extend_from_slice(&to_parts[common..]);
+    if common == from.len() {
+        format!("./{}", relative.join("/"))
+    } else {
+        relative.join("/")
+    }
+}
+
+// The first and last years a quad's maps date from
+fn years(conversion: &ConversionSummary) -> Option<(u32, u32)> {
+    let years = [&conversion.header.year1, &conversion.header.year2]
+        .iter()
+        .filter_map(|year| year.trim_matches(char::from(0)).trim().parse().ok())
+        .collect::<Vec<u32>>();
+    Some((*years.iter().min()?, *years.iter().max()?))
+}
+
+fn media_type(output: &Path) -> &'static str {
+    let extension = output
+        .extension()
+        .map(|extension| extension.to_string_lossy().to_ascii_lowercase());
+    match extension.as_deref() {
+        Some("png") => "image/png",
+        Some("mbtiles") => "application/vnd.sqlite3",
+        _ => "image/tiff; application=geotiff",
+    }
+}
+
+fn item(conversion: &ConversionSummary, id: &str, item_dir: &Path) -> Value {
+    let header = &conversion.header;
+    let (west, south, east, north) = (header.w_long, header.s_lat, header.e_long, header.n_lat);
+    let (start, end) = match years(conversion) {
+        Some((first, last)) => (
+            Value::from(format!("{}-01-01T00:00:00Z", first)),
+            Value::from(format!("{}-12-31T23:59:59Z", last)),
+        ),
+        None => (Value::Null, Value::Null),
+    };
+    let mut assets = json!({
+        "data": {
+            "href": href(item_dir, &conversion.output),
+            "type": media_type(&conversion.output),
+            "roles": ["data"],
+        },
+    });
+    let thumbnail = thumbnail_path(&conversion.output);
+    if thumbnail.is_file() {
+        assets["thumbnail"] = json!({
+            "href": href(item_dir, &thumbnail),
+            "type": "image/jpeg",
+            "roles": ["thumbnail"],
+        });
+    }
+    json!({
+        "type": "Feature",
+        "stac_version": STAC_VERSION,
+        "id": id,
+        "bbox": [west, south, east, north],
+        "geometry": {
+            "type": "Polygon",
+            "coordinates": [[
+                [west, north],
+                [east, north],
+                [east, south],
+                [west, south],
+                [west, north],
+            ]],
+        },
+        "properties": {
+            "title": header.quad_name.trim(),
+            "datetime": null,
+            "start_datetime": start,
+            "end_datetime": end,
+            "state": header.state_name.trim(),
+            "series": conversion.series.map(|series| series.to_string()),
+        },
+        "links": [
+            {"rel": "root", "href": "../../catalog.json", "type": "application/json"},
+            {"rel": "parent", "href": "../collection.json", "type": "application/json"},
+            {"rel": "collection", "href": "../collection.json", "type": "application/json"},
+        ],
+        "assets": assets,
+    })
+}
+
+fn write_json(path: &Path, value: &Value) -> Result<()> {
+    fs::write(path, serde_json::to_vec_pretty(value)?)?;
+    Ok(())
+}
+
+/// Writes a STAC catalog of `conversions` into `dir`: `catalog.json`, with a
+/// collection for each state and series in `<collection>/collection.json`
+/// and an item for each quad in `<collection>/<item>/<item>.json`, linking
+/// to its output and thumbnail. Quad bounds are given in NAD27 as-is.
+pub fn write_stac_catalog(dir: &Path, conversions: &[ConversionSummary]) -> Result<()> {
+    let mut collections = BTreeMap::<String, Vec<&ConversionSummary>>::new();
+    for conversion in conversions {
+        let series = conversion.series.map_or("unknown", |series| series.slug());
+        collections
+            .entry(format!(
+                "{}-{}",
+                slug(&conversion.header.state_name),
+                series
+            ))
+            .or_default()
+            .push(conversion);
+    }
+
+    fs::create_dir_all(dir)?;
+    let mut children = Vec::new();
+    for (collection_id, conversions) in &collections {
+        let collection_dir = dir.join(collection_id);
+        let mut items = Vec::new();
+        let mut bbox = [
+            f64::INFINITY,
+            f64::INFINITY,
+            f64::NEG_INFINITY,
+            f64::NEG_INFINITY,
+        ];
+        let mut interval = None::<(u32, u32)>;
+        for conversion in conversions {
+            let stem = conversion
+                .output
+                .file_stem()
+                .map_or_else(String::new, |stem| stem.to_string_lossy().to_string());
+            let mut id = slug(&stem);
+            // Quads converted twice, e.g. from overlapping discs
+            if items.contains(&format!("./{0}/{0}.json", id)) {
+                id = format!("{}-{}", id, items.len());
+            }
+            let item_dir = collection_dir.join(&id);
+            fs::create_dir_all(&item_dir)?;
+            write_json(
+                &item_dir.join(format!("{}.json", id)),
+                &item(conversion, &id, &item_dir),
+            )?;
+            items.push(format!("./{0}/{0}.json", id));
+
+            let header = &conversion.header;
+            bbox = [
+                bbox[0].min(header.w_long),
+                bbox[1].min(header.s_lat),
+                bbox[2].max(header.e_long),
+                bbox[3].max(header.n_lat),
+            ];
+            if let Some((first, last)) = years(conversion) {
+                interval = Some(interval.map_or((first, last), |(start, end)| {
+                    (start.min(first), end.max(last))
+                }));
+            }
+        }
+
+        let first = conversions[0];
+        let title = match first.series {
+            Some(series) => format!("{} {}", first.header.state_name.trim(), series),
+            None => first.header.state_name.trim().to_string(),
+        };
+        let (start, end) = match interval {
+            Some((start, end)) => (
+                Value::from(format!("{}-01-01T00:00:00Z", start)),
+                Value::from(format!("{}-12-31T23:59:59Z", end)),
+            ),
+            None => (Value::Null, Value::Null),
+        };
+        let mut links = vec![
+            json!({"rel": "root", "href": "../catalog.json", "type": "application/json"}),
+            json!({"rel": "parent", "href": "../catalog.json", "type": "application/json"}),
+        ];
+        links.extend(
+            items
+                .iter()
+                .map(|item| json!({"rel": "item", "href": item, "type": "application/geo+json"})),
+        );
+        write_json(
+            &collection_dir.join("collection.json"),
+            &json!({
+                "type": "Collection",
+                "stac_version": STAC_VERSION,
+                "id": collection_id,
+                "title": title,
+                "description": format!("TOPO! quads of {}", title),
+                "license": "various",
+                "extent": {
+                    "spatial": {"bbox": [bbox]},
+                    "temporal": {"interval": [[start, end]]},
+                },
+                "links": links,
+            }),
+        )?;
+        children.push(json!({
+            "rel": "child",
+            "href": format!("./{}/collection.json", collection_id),
+            "type": "application/json",
+            "title": title,
+        }));
+    }
+
+    let mut links =
+        vec![json!({"rel": "root", "href": "./catalog.json", "type": "application/json"})];
+    links.extend(children);
+    write_json(
+        &dir.join("catalog.json"),
+        &json!({
+            "type": "Catalog",
+            "stac_version": STAC_VERSION,
+            "id": "topodump",
+            "description": "Converted TOPO! quads",
+            "links": links,
+        }),
+    )
+}
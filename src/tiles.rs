@@ -0,0 +1,212 @@
+use std::borrow::Cow;
+use std::f64::consts::PI;
+
+use image::{codecs::png::PngEncoder, imageops, ColorType, Rgba, RgbaImage};
+
+use crate::conversion::Resampling;
+use crate::error::Result;
+
+pub const TILE_SIZE: u32 = 256;
+
+/// The deepest zoom level tiles are made for.
+pub const MAX_ZOOM: u8 = 22;
+
+/// Zoom levels generated below the native one.
+pub const OVERVIEW_LEVELS: u8 = 4;
+
+// Web Mercator can't represent the poles
+const MAX_LATITUDE: f64 = 85.051_128_779_806_59;
+
+// Web Mercator's sphere, in metres
+const EARTH_RADIUS: f64 = 6_378_137.0;
+
+/// An XYZ (slippy map) tile address, with y counted down from the north.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct TileCoord {
+    pub z: u8,
+    pub x: u32,
+    pub y: u32,
+}
+
+impl TileCoord {
+    /// Row as counted by TMS and MBTiles, from the south.
+    pub fn tms_y(self) -> u32 {
+        (1 << self.z) - 1 - self.y
+    }
+}
+
+fn world_pixels(z: u8) -> f64 {
+    TILE_SIZE as f64 * (1u64 << z) as f64
+}
+
+fn long_to_world(long: f64, z: u8) -> f64 {
+    (long + 180.0) / 360.0 * world_pixels(z)
+}
+
+fn lat_to_world(lat: f64, z: u8) -> f64 {
+    let lat = lat.max(-MAX_LATITUDE).min(MAX_LATITUDE).to_radians();
+    (1.0 - (lat.tan() + 1.0 / lat.cos()).ln() / PI) / 2.0 * world_pixels(z)
+}
+
+fn world_to_long(x: f64, z: u8) -> f64 {
+    x / world_pixels(z) * 360.0 - 180.0
+}
+
+fn world_to_lat(y: f64, z: u8) -> f64 {
+    (PI * (1.0 - 2.0 * y / world_pixels(z)))
+        .sinh()
+        .atan()
+        .to_degrees()
+}
+
+/// The deepest zoom whose pixels are no finer than `degrees_per_pixel` at the equator.
+pub fn native_zoom(degrees_per_pixel: f64) -> u8 {
+    let zoom = (360.0 / (TILE_SIZE as f64 * degrees_per_pixel.abs()))
+        .log2()
+        .ceil();
+    zoom.max(0.0).min(MAX_ZOOM as f64) as u8
+}
+
+/// The Web Mercator extent, in metres, of the tiles `tile_size` pixels
+/// across at zoom `z` that cover (west, south, east, north), and the size of
+/// their pixels: ((min x, min y, max x, max y), resolution).
+pub fn tile_grid_extent(
+    bounds: (f64, f64, f64, f64),
+    tile_size: u32,
+    z: u8,
+) -> ((f64, f64, f64, f64), f64) {
+    let (west, south, east, north) = bounds;
+    let half_world = PI * EARTH_RADIUS;
+    let resolution = 2.0 * half_world / (tile_size as f64 * (1u64 << z) as f64);
+    let tile = tile_size as f64 * resolution;
+    let x = |long: f64| long.to_radians() * EARTH_RADIUS;
+    let y = |lat: f64| {
+        let lat = lat.max(-MAX_LATITUDE).min(MAX_LATITUDE).to_radians();
+        (PI / 4.0 + lat / 2.0).tan().ln() * EARTH_RADIUS
+    };
+    let down = |metres: f64| ((metres + half_world) / tile).floor() * tile - half_world;
+    let up = |metres: f64| ((metres + half_world) / tile).ceil() * tile - half_world;
+    (
+        (down(x(west)), down(y(south)), up(x(east)), up(y(north))),
+        resolution,
+    )
+}
+
+/// Tiles at zoom `z` intersecting (west, south, east, north).
+pub fn tiles_covering(bounds: (f64, f64, f64, f64), z: u8) -> Vec<TileCoord> {
+    let (west, south, east, north) = bounds;
+    let last = (1u32 << z) - 1;
+    let to_tile = |world: f64| ((world / TILE_SIZE as f64).floor().max(0.0) as u32).min(last);
+    let (x0, x1) = (
+        to_tile(long_to_world(west, z)),
+        to_tile(long_to_world(east, z)),
+    );
+    let (y0, y1) = (
+        to_tile(lat_to_world(north, z)),
+        to_tile(lat_to_world(south, z)),
+    );
+    (y0..=y1)
+        .flat_map(|y| (x0..=x1).map(move |x| TileCoord { z, x, y }))
+        .collect()
+}
+
+/// `image`, georeferenced by `transform`, downscaled with `resampling` to
+/// about the resolution of zoom `z` if it's finer, with its transform. Tiles
+/// below the image's own zoom are rendered from this, so they aren't just
+/// a sample of its pixels.
+pub fn image_for_zoom<'a>(
+    image: &'a RgbaImage,
+    transform: &[f64; 6],
+    z: u8,
+    resampling: Resampling,
+) -> (Cow<'a, RgbaImage>, [f64; 6]) {
+    let factor = 360.0 / world_pixels(z) / transform[1].abs();
+    if factor <= 1.0 || resampling == Resampling::Nearest {
+        return (Cow::Borrowed(image), *transform);
+    }
+    let (width, height) = image.dimensions();
+    let (scaled_width, scaled_height) = (
+        ((width as f64 / factor).round() as u32).max(1),
+        ((height as f64 / factor).round() as u32).max(1),
+    );
+    let scaled = imageops::resize(image, scaled_width, scaled_height, resampling.filter());
+    let mut transform = *transform;
+    transform[1] *= width as f64 / scaled_width as f64;
+    transform[5] *= height as f64 / scaled_height as f64;
+    (Cow::Owned(scaled), transform)
+}
+
+/// Resamples a geographically referenced image into one Web Mercator tile,
+/// leaving pixels outside the image transparent. Pixels are interpolated
+/// bilinearly unless `resampling` is nearest-neighbour; render tiles of
+/// lower zooms from [`image_for_zoom`] for the other filters to apply.
+pub fn render_tile(
+    image: &RgbaImage,
+    transform: &[f64; 6],
+    tile: TileCoord,
+    resampling: Resampling,
+) -> RgbaImage {
+    let mut tile_img = RgbaImage::from_pixel(TILE_SIZE, TILE_SIZE, Rgba([0, 0, 0, 0]));
+    let (width, height) = (image.width() as f64, image.height() as f64);
+    for py in 0..TILE_SIZE {
+        let lat = world_to_lat((tile.y * TILE_SIZE + py) as f64 + 0.5, tile.z);
+        let row = (lat - transform[3]) / transform[5];
+        if row < 0.0 || row >= height {
+            continue;
+        }
+        for px in 0..TILE_SIZE {
+            let long = world_to_long((tile.x * TILE_SIZE + px) as f64 + 0.5, tile.z);
+            let col = (long - transform[0]) / transform[1];
+            if col < 0.0 || col >= width {
+                continue;
+            }
+            let pixel = match resampling {
+                Resampling::Nearest => *image.get_pixel(col as u32, row as u32),
+                _ => interpolate(image, col, row),
+            };
+            tile_img.put_pixel(px, py, pixel);
+        }
+    }
+    tile_img
+}
+
+// The pixel at fractional (`col`, `row`), mixed from the four nearest
+fn interpolate(image: &RgbaImage, col: f64, row: f64) -> Rgba<u8> {
+    let (x, y) = ((col - 0.5).max(0.0), (row - 0.5).max(0.0));
+    let (x0, y0) = (x.floor() as u32, y.floor() as u32);
+    let (x1, y1) = (
+        (x0 + 1).min(image.width() - 1),
+        (y0 + 1).min(image.height() - 1),
+    );
+    let (fx, fy) = (x - x0 as f64, y - y0 as f64);
+    let (a, b, c, d) = (
+        image.get_pixel(x0, y0),
+        image.get_pixel(x1, y0),
+        image.get_pixel(x0, y1),
+        image.get_pixel(x1, y1),
+    );
+    let mut pixel = [0; 4];
+    for (channel, value) in pixel.iter_mut().enumerate() {
+        let top = a[channel] as f64 * (1.0 - fx) + b[channel] as f64 * fx;
+        let bottom = c[channel] as f64 * (1.0 - fx) + d[channel] as f64 * fx;
+        *value = (top * (1.0 - fy) + bottom * fy).round() as u8;
+    }
+    Rgba(pixel)
+}
+
+/// Whether a rendered tile has no visible pixels and can be skipped.
+pub fn is_empty(tile_img: &RgbaImage) -> bool {
+    tile_img.pixels().all(|pixel| pixel[3] == 0)
+}
+
+/// A tile as the PNG stored in tile pyramids.
+pub fn encode_png(tile_img: &RgbaImage) -> Result<Vec<u8>> {
+    let mut data = Vec::new();
+    PngEncoder::new(&mut data).encode(
+        tile_img.as_raw(),
+        tile_img.width(),
+        tile_img.height(),
+        ColorType::Rgba8,
+    )?;
+    Ok(data)
+}
@@ -0,0 +1,140 @@
+//! Where a conversion's time went, stage by stage.
+
+use std::ops::AddAssign;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+
+use crate::maplet::{DecodeResult, MapletDecoder};
+
+/// Wall-clock and CPU time spent in one stage, summed over every thread that
+/// worked on it. CPU time is that of those threads alone, so it misses work
+/// GDAL hands to its own threads, and is zero where threads' CPU clocks are
+/// unavailable.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StageTime {
+    pub wall: Duration,
+    pub cpu: Duration,
+}
+
+impl AddAssign for StageTime {
+    fn add_assign(&mut self, other: Self) {
+        self.wall += other.wall;
+        self.cpu += other.cpu;
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Timings {
+    /// Reading the header and maplet offsets.
+    pub header: StageTime,
+    /// Decoding maplets, across all decoding threads.
+    pub decode: StageTime,
+    /// Placing maplets in the output, including any cutline.
+    pub composite: StageTime,
+    /// Writing and compressing the output once every maplet is in place.
+    pub encode: StageTime,
+    /// Attaching the georeferencing.
+    pub georef: StageTime,
+}
+
+impl Timings {
+    pub fn stages(&self) -> [(&'static str, StageTime); 5] {
+        [
+            ("header", self.header),
+            ("decode", self.decode),
+            ("composite", self.composite),
+            ("encode", self.encode),
+            ("georef", self.georef),
+        ]
+    }
+}
+
+impl AddAssign for Timings {
+    fn add_assign(&mut self, other: Self) {
+        self.header += other.header;
+        self.decode += other.decode;
+        self.composite += other.composite;
+        self.encode += other.encode;
+        self.georef += other.georef;
+    }
+}
+
+#[cfg(unix)]
+fn thread_cpu_time() -> Duration {
+    let mut time = libc::timespec {
+        tv_sec: 0,
+        tv_nsec: 0,
+    };
+    if unsafe { libc::clock_gettime(libc::CLOCK_THREAD_CPUTIME_ID, &mut time) } != 0 {
+        return Duration::ZERO;
+    }
+    Duration::new(time.tv_sec as u64, time.tv_nsec as u32)
+}
+
+#[cfg(not(unix))]
+fn thread_cpu_time() -> Duration {
+    Duration::ZERO
+}
+
+/// Times work on the current thread.
+pub(crate) struct Stopwatch {
+    wall: Instant,
+    cpu: Duration,
+}
+
+impl Stopwatch {
+    pub(crate) fn start() -> Self {
+        Stopwatch {
+            wall: Instant::now(),
+            cpu: thread_cpu_time(),
+        }
+    }
+
+    pub(crate) fn elapsed(&self) -> StageTime {
+        StageTime {
+            wall: self.wall.elapsed(),
+            cpu: thread_cpu_time().saturating_sub(self.cpu),
+        }
+    }
+}
+
+/// A stage timed from several threads at once.
+#[derive(Default)]
+pub(crate) struct SharedStage {
+    wall_nanos: AtomicU64,
+    cpu_nanos: AtomicU64,
+}
+
+impl SharedStage {
+    pub(crate) fn add(&self, time: StageTime) {
+        self.wall_nanos
+            .fetch_add(time.wall.as_nanos() as u64, Ordering::Relaxed);
+        self.cpu_nanos
+            .fetch_add(time.cpu.as_nanos() as u64, Ordering::Relaxed);
+    }
+
+    pub(crate) fn get(&self) -> StageTime {
+        StageTime {
+            wall: Duration::from_nanos(self.wall_nanos.load(Ordering::Relaxed)),
+            cpu: Duration::from_nanos(self.cpu_nanos.load(Ordering::Relaxed)),
+        }
+    }
+}
+
+/// Adds the time spent in `inner` to `spent`.
+pub(crate) struct TimedDecoder {
+    pub(crate) inner: Arc<dyn MapletDecoder>,
+    pub(crate) spent: Arc<SharedStage>,
+}
+
+impl MapletDecoder for TimedDecoder {
+    fn decode(&self, data: &[u8]) -> DecodeResult {
+        let stopwatch = Stopwatch::start();
+        let decoded = self.inner.decode(data);
+        self.spent.add(stopwatch.elapsed());
+        decoded
+    }
+}
@@ -0,0 +1,390 @@
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::convert::TryInto;
+use std::io;
+use std::io::Cursor;
+use std::ops::Range;
+use std::path::Path;
+#[cfg(feature = "parallel")]
+use std::sync::mpsc;
+use std::sync::Arc;
+#[cfg(feature = "parallel")]
+use std::thread;
+
+use image::{
+    io::Reader as ImageReader, DynamicImage, GenericImageView, ImageFormat, Rgba, RgbaImage,
+};
+#[cfg(feature = "parallel")]
+use rayon::prelude::*;
+
+use crate::error::{Result, TopodumpError};
+use crate::georef::GeoRef;
+use crate::header::{read_tpq_header, read_tpq_u32, TpqHeader, DEFAULT_MAX_PIXELS, HEADER_LEN};
+use crate::maplet::{default_decoder, MapletDecoder, OFFSET_TABLE_START};
+use crate::pixels;
+#[cfg(feature = "mmap")]
+use crate::source::MmapSource;
+use crate::source::{FileSource, TpqSource};
+
+// Bytes of the offset table examined per read when measuring it
+const TABLE_SCAN_CHUNK: u64 = 4096;
+
+/// A parsed tpq file: its header plus the maplet offset table.
+pub struct TpqFile {
+    header: TpqHeader,
+    raw_header: Vec<u8>,
+    source: Box<dyn TpqSource>,
+    // Row-major, one per maplet
+    offsets: Vec<u32>,
+    // Deduplicated and sorted, for finding where each maplet ends
+    sorted_offsets: Vec<u32>,
+    decoder: Arc<dyn MapletDecoder>,
+    #[cfg_attr(not(feature = "parallel"), allow(dead_code))]
+    max_decoding: Option<usize>,
+    warnings: Vec<String>,
+}
+
+impl TpqFile {
+    /// Opens a file, reading maplets from it as they're needed.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Self::from_source(FileSource::open(path)?)
+    }
+
+    /// Opens a memory-mapped file; see [`MmapSource::open`] for the caveats.
+    #[cfg(feature = "mmap")]
+    pub fn open_mmap<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Self::from_source(MmapSource::open(path)?)
+    }
+
+    pub fn from_bytes(data: Vec<u8>) -> Result<Self> {
+        Self::from_source(data)
+    }
+
+    pub fn from_source(source: impl TpqSource + 'static) -> Result<Self> {
+        let raw_header = source
+            .read_range(0..HEADER_LEN as u64)
+            .map_err(TopodumpError::HeaderParse)?
+            .into_owned();
+        let mut header = read_tpq_header(&mut Cursor::new(&raw_header))?;
+        let warnings = header.normalize_bounds()?;
+        header.check_degenerate()?;
+
+        let maplet_count = header.long_count as u64 * header.lat_count as u64;
+        check_maplet_count(&source, &header, maplet_count)?;
+        let table = source.read_range(OFFSET_TABLE_START..OFFSET_TABLE_START + 4 * maplet_count)?;
+        let mut cursor = Cursor::new(table.as_ref());
+        let offsets = (0..maplet_count)
+            .map(|_| read_tpq_u32(&mut cursor))
+            .collect::<io::Result<Vec<_>>>()?;
+        drop(table);
+
+        let mut sorted_offsets = offsets.clone();
+        sorted_offsets.sort_unstable();
+        sorted_offsets.dedup();
+
+        Ok(TpqFile {
+            header,
+            raw_header,
+            source: Box::new(source),
+            offsets,
+            sorted_offsets,
+            decoder: default_decoder(),
+            max_decoding: None,
+            warnings,
+        })
+    }
+
+    /// Replaces the decoder used by [`maplet`](TpqFile::maplet) and
+    /// [`maplets`](TpqFile::maplets).
+    pub fn set_decoder(&mut self, decoder: Arc<dyn MapletDecoder>) {
+        self.decoder = decoder;
+    }
+
+    /// Caps how many maplets [`for_each_maplet`](TpqFile::for_each_maplet)
+    /// decodes at once, bounding the memory they occupy.
+    pub fn set_max_decoding(&mut self, max_decoding: usize) {
+        self.max_decoding = Some(max_decoding.max(1));
+    }
+
+    pub fn header(&self) -> &TpqHeader {
+        &self.header
+    }
+
+    /// Problems with the header that were worked around, such as bounds in
+    /// the wrong order.
+    pub fn warnings(&self) -> &[String] {
+        &self.warnings
+    }
+
+    /// The undecoded bytes behind [`header`](TpqFile::header).
+    pub fn raw_header(&self) -> &[u8] {
+        &self.raw_header
+    }
+
+    pub fn maplet_count(&self) -> u32 {
+        self.header.long_count * self.header.lat_count
+    }
+
+    /// Byte range of one maplet within the file.
+    pub fn maplet_range(&self, row: u32, col: u32) -> Result<Range<usize>> {
+        let offset = self.offsets[(row * self.header.long_count + col) as usize];
+        let end = match self.sorted_offsets.binary_search(&offset) {
+            Ok(index) => self.sorted_offsets.get(index + 1),
+            Err(_) => None,
+        }
+        .map_or(self.size(), |&end| (end as usize).min(self.size()));
+        if offset as usize >= end {
+            return Err(TopodumpError::MapletOffset {
+                row,
+                col,
+                offset: offset as usize,
+            });
+        }
+        Ok(offset as usize..end)
+    }
+
+    fn size(&self) -> usize {
+        self.source.size() as usize
+    }
+
+    /// The raw JPEG bytes of one maplet.
+    pub fn maplet_data(&self, row: u32, col: u32) -> Result<Cow<'_, [u8]>> {
+        let range = self.maplet_range(row, col)?;
+        Ok(self
+            .source
+            .read_range(range.start as u64..range.end as u64)?)
+    }
+
+    pub fn maplet(&self, row: u32, col: u32) -> Result<DynamicImage> {
+        self.decoder
+            .decode(&self.maplet_data(row, col)?)
+            .map_err(|source| TopodumpError::MapletDecode { row, col, source })
+    }
+
+    /// The size of the first maplet, read from its JPEG header without
+    /// decoding it; every maplet of a quad is the same size.
+    pub fn maplet_dimensions(&self) -> Result<(u32, u32)> {
+        let data = self.maplet_data(0, 0)?;
+        ImageReader::with_format(Cursor::new(&data[..]), ImageFormat::Jpeg)
+            .into_dimensions()
+            .map_err(|err| TopodumpError::MapletDecode {
+                row: 0,
+                col: 0,
+                source: Box::new(err),
+            })
+    }
+
+    /// One decoded maplet with the georeferencing of just that maplet, for
+    /// reading part of a quad without converting all of it.
+    pub fn maplet_georef(&self, row: u32, col: u32) -> Result<(DynamicImage, GeoRef)> {
+        let maplet = self.maplet(row, col)?;
+        let (maplet_width, maplet_height) = maplet.dimensions();
+        let (width, height) = self
+            .header
+            .image_size((maplet_width, maplet_height), u64::MAX)?;
+        let georef = GeoRef::from_header(&self.header, width as f64, height as f64)
+            .window(col * maplet_width, row * maplet_height);
+        Ok((maplet, georef))
+    }
+
+    /// The (row, col) of the maplet covering a NAD27 longitude/latitude.
+    pub fn maplet_containing(&self, long: f64, lat: f64) -> Option<(u32, u32)> {
+        let header = &self.header;
+        let col = (long - header.w_long) / (header.e_long - header.w_long);
+        let row = (header.n_lat - lat) / (header.n_lat - header.s_lat);
+        if !(0.0..=1.0).contains(&col) || !(0.0..=1.0).contains(&row) {
+            return None;
+        }
+        Some((
+            ((row * header.lat_count as f64) as u32).min(header.lat_count - 1),
+            ((col * header.long_count as f64) as u32).min(header.long_count - 1),
+        ))
+    }
+
+    /// Cells whose offsets point at the same data as an earlier cell in
+    /// row-major order, which corrupt offset tables produce, errors naming
+    /// both.
+    pub fn duplicate_maplets(&self) -> Vec<TopodumpError> {
+        let mut first_at = BTreeMap::new();
+        self.positions()
+            .zip(&self.offsets)
+            .filter_map(|((row, col), offset)| {
+                let &(first_row, first_col) = first_at.entry(offset).or_insert((row, col));
+                ((first_row, first_col) != (row, col)).then(|| TopodumpError::DuplicateMaplet {
+                    row,
+                    col,
+                    first_row,
+                    first_col,
+                })
+            })
+            .collect()
+    }
+
+    /// Raw maplets as `(row, col, jpeg)` in row-major order.
+    pub fn raw_maplets(&self) -> impl Iterator<Item = Result<(u32, u32, Cow<'_, [u8]>)>> + '_ {
+        self.positions()
+            .map(move |(row, col)| Ok((row, col, self.maplet_data(row, col)?)))
+    }
+
+    /// Decoded maplets as `(row, col, image)` in row-major order, decoding lazily.
+    pub fn maplets(&self) -> impl Iterator<Item = Result<(u32, u32, DynamicImage)>> + '_ {
+        self.positions()
+            .map(move |(row, col)| Ok((row, col, self.maplet(row, col)?)))
+    }
+
+    /// Decodes the maplets after the first `skip`, handing each to `f` on the
+    /// calling thread and stopping at the first error from either.
+    ///
+    /// With the `parallel` feature, maplets are decoded on the rayon pool, but
+    /// still arrive in row-major order so outputs written as they arrive come
+    /// out byte-for-byte the same every time.
+    pub fn for_each_maplet<F>(&self, skip: usize, f: F) -> Result<()>
+    where
+        F: FnMut(u32, u32, DynamicImage) -> Result<()>,
+    {
+        self.for_each_maplet_with(skip, |_, _, maplet| Ok(maplet), f)
+    }
+
+    /// Like [`for_each_maplet`](TpqFile::for_each_maplet), but also runs
+    /// `prepare` on each maplet alongside its decoding, so per-maplet work
+    /// overlaps with `f` handling earlier ones.
+    #[cfg(feature = "parallel")]
+    pub fn for_each_maplet_with<P, F>(&self, skip: usize, prepare: P, mut f: F) -> Result<()>
+    where
+        P: Fn(u32, u32, DynamicImage) -> Result<DynamicImage> + Sync,
+        F: FnMut(u32, u32, DynamicImage) -> Result<()>,
+    {
+        let positions = self.positions().skip(skip).collect::<Vec<_>>();
+        let threads = rayon::current_num_threads();
+        // A smaller pool of its own keeps fewer decoded maplets in memory at once
+        let pool = self
+            .max_decoding
+            .filter(|&max_decoding| max_decoding < threads)
+            .and_then(|max_decoding| {
+                rayon::ThreadPoolBuilder::new()
+                    .num_threads(max_decoding)
+                    .build()
+                    .ok()
+            });
+        let threads = pool
+            .as_ref()
+            .map_or(threads, rayon::ThreadPool::current_num_threads);
+        // Bounded so decoding can't run far ahead of a slow consumer
+        let (sender, receiver) = mpsc::sync_channel(threads);
+        thread::scope(|scope| {
+            scope.spawn(|| {
+                let sender = sender;
+                // A pool's worth at a time, collected in order
+                let decode = || {
+                    for chunk in positions.chunks(threads.max(1)) {
+                        let maplets = chunk
+                            .par_iter()
+                            .map(|&(row, col)| {
+                                self.maplet(row, col)
+                                    .and_then(|maplet| prepare(row, col, maplet))
+                                    .map(|maplet| (row, col, maplet))
+                            })
+                            .collect::<Vec<_>>();
+                        for maplet in maplets {
+                            // Fails once the receiver is dropped, which stops the remaining work
+                            if sender.send(maplet).is_err() {
+                                return;
+                            }
+                        }
+                    }
+                };
+                match &pool {
+                    Some(pool) => pool.install(decode),
+                    None => decode(),
+                }
+            });
+            receiver.into_iter().try_for_each(|maplet| {
+                let (row, col, maplet) = maplet?;
+                f(row, col, maplet)
+            })
+        })
+    }
+
+    #[cfg(not(feature = "parallel"))]
+    pub fn for_each_maplet_with<P, F>(&self, skip: usize, prepare: P, mut f: F) -> Result<()>
+    where
+        P: Fn(u32, u32, DynamicImage) -> Result<DynamicImage> + Sync,
+        F: FnMut(u32, u32, DynamicImage) -> Result<()>,
+    {
+        self.maplets().skip(skip).try_for_each(|maplet| {
+            let (row, col, maplet) = maplet?;
+            f(row, col, prepare(row, col, maplet)?)
+        })
+    }
+
+    /// Decodes every maplet into one in-memory image.
+    pub fn collage(&self) -> Result<RgbaImage> {
+        let first_maplet = self.maplet(0, 0)?;
+        let (maplet_width, maplet_height) = first_maplet.dimensions();
+        let (width, height) = self
+            .header
+            .image_size((maplet_width, maplet_height), DEFAULT_MAX_PIXELS)?;
+        let mut collage_img = RgbaImage::from_pixel(width, height, Rgba([255, 255, 255, 255]));
+        pixels::copy_into(&mut collage_img, &*pixels::rgba(&first_maplet), 0, 0);
+        self.for_each_maplet(1, |row, col, maplet| {
+            pixels::copy_into(
+                &mut collage_img,
+                &*pixels::rgba(&maplet),
+                col * maplet_width,
+                row * maplet_height,
+            );
+            Ok(())
+        })?;
+        Ok(collage_img)
+    }
+
+    fn positions(&self) -> impl Iterator<Item = (u32, u32)> {
+        let long_count = self.header.long_count;
+        (0..self.header.lat_count).flat_map(move |row| (0..long_count).map(move |col| (row, col)))
+    }
+}
+
+// Entries before the first maplet that could be maplet offsets: each must
+// point past itself and inside the file
+fn offset_table_len(source: &dyn TpqSource) -> io::Result<u64> {
+    let size = source.size();
+    let mut first_maplet = size;
+    let mut position = OFFSET_TABLE_START;
+    let mut entries = 0;
+    while position + 4 <= first_maplet {
+        let end = (position + TABLE_SCAN_CHUNK).min(first_maplet);
+        let chunk = source.read_range(position..end - (end - position) % 4)?;
+        for entry in chunk.chunks_exact(4) {
+            let offset = u32::from_le_bytes(entry.try_into().unwrap()) as u64;
+            if position + 4 > first_maplet || offset < position + 4 || offset >= size {
+                return Ok(entries);
+            }
+            first_maplet = first_maplet.min(offset);
+            entries += 1;
+            position += 4;
+        }
+    }
+    Ok(entries)
+}
+
+// Catches corrupt counts before they turn into a huge table read or a
+// decode failure partway through
+fn check_maplet_count(source: &dyn TpqSource, header: &TpqHeader, maplet_count: u64) -> Result<()> {
+    let size = source.size();
+    if OFFSET_TABLE_START + 4 * maplet_count > size {
+        return Err(TopodumpError::MapletCountExceedsFile {
+            columns: header.long_count,
+            rows: header.lat_count,
+            size,
+        });
+    }
+    let entries = offset_table_len(source)?;
+    if entries < maplet_count {
+        return Err(TopodumpError::MapletCountExceedsTable {
+            columns: header.long_count,
+            rows: header.lat_count,
+            entries,
+        });
+    }
+    Ok(())
+}
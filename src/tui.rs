@@ -0,0 +1,418 @@
+//! An interactive terminal browser of a collection of tpq files, for
+//! triaging a newly acquired disc: each quad's metadata, where it sits on
+//! its series' grid, and keys to convert, extract or verify it.
+
+use std::collections::BTreeSet;
+use std::fs;
+use std::io::{self, Stdout, Write};
+use std::path::{Path, PathBuf};
+
+use crossterm::event::{self, Event, KeyCode, KeyEventKind};
+use crossterm::style::{Attribute, Print, SetAttribute};
+use crossterm::terminal::{self, Clear, ClearType, EnterAlternateScreen, LeaveAlternateScreen};
+use crossterm::{cursor, execute, queue};
+
+use crate::conversion::Conversion;
+use crate::coverage::{coverage, Cell, Coverage};
+use crate::error::Result;
+use crate::header::TpqHeader;
+use crate::tpq::TpqFile;
+use crate::verify::verify;
+
+// Rows below the list for the selected quad's details, a message and help
+const FOOTER_ROWS: u16 = 9;
+
+const HELP: &str =
+    "↑/↓ move  space mark  c convert  x extract maplets  v verify  q quit  (on the marked quads, or the selected one)";
+
+struct Quad {
+    path: PathBuf,
+    // Unless it couldn't be opened
+    header: Option<(TpqHeader, (u32, u32))>,
+    status: String,
+}
+
+impl Quad {
+    fn open(path: PathBuf) -> Self {
+        let header = TpqFile::open(&path).and_then(|tpq| {
+            let dimensions = tpq.maplet_dimensions()?;
+            Ok((tpq.header().clone(), dimensions))
+        });
+        match header {
+            Ok(header) => Quad {
+                path,
+                header: Some(header),
+                status: String::new(),
+            },
+            Err(err) => Quad {
+                path,
+                header: None,
+                status: format!("unreadable: {}", err),
+            },
+        }
+    }
+
+    fn name(&self) -> String {
+        match &self.header {
+            Some((header, _)) => header.quad_name.trim().to_string(),
+            None => self
+                .path
+                .file_name()
+                .map_or_else(String::new, |name| name.to_string_lossy().to_string()),
+        }
+    }
+}
+
+fn years(header: &TpqHeader) -> String {
+    let (year1, year2) = (header.year1.trim(), header.year2.trim());
+    if year2.is_empty() || year2 == year1 {
+        year1.to_string()
+    } else {
+        format!("{}, {}", year1, year2)
+    }
+}
+
+// `text` cut or padded to `width` columns
+fn fit(text: &str, width: usize) -> String {
+    let mut fitted = text.chars().take(width).collect::<String>();
+    let len = fitted.chars().count();
+    fitted.extend(std::iter::repeat(' ').take(width - len));
+    fitted
+}
+
+// Restores the terminal however browsing ends
+struct Screen {
+    stdout: Stdout,
+}
+
+impl Screen {
+    fn enter() -> Result<Self> {
+        terminal::enable_raw_mode()?;
+        let mut stdout = io::stdout();
+        execute!(stdout, EnterAlternateScreen, cursor::Hide)?;
+        Ok(Screen { stdout })
+    }
+}
+
+impl Drop for Screen {
+    fn drop(&mut self) {
+        let _ = execute!(self.stdout, cursor::Show, LeaveAlternateScreen);
+        let _ = terminal::disable_raw_mode();
+    }
+}
+
+struct Browser {
+    quads: Vec<Quad>,
+    coverage: Coverage,
+    selected: usize,
+    scroll: usize,
+    marked: BTreeSet<usize>,
+    message: String,
+}
+
+impl Browser {
+    fn draw(&mut self, screen: &mut Screen) -> Result<()> {
+        let (width, height) = terminal::size()?;
+        let (width, height) = (width as usize, height.max(FOOTER_ROWS + 3) as usize);
+        let list_rows = height - FOOTER_ROWS as usize - 1;
+        let list_width = (width * 3 / 5).max(1);
+        if self.selected < self.scroll {
+            self.scroll = self.selected;
+        } else if self.selected >= self.scroll + list_rows {
+            self.scroll = self.selected + 1 - list_rows;
+        }
+
+        let out = &mut screen.stdout;
+        queue!(out, Clear(ClearType::All), cursor::MoveTo(0, 0))?;
+        queue!(
+            out,
+            SetAttribute(Attribute::Bold),
+            Print(fit(
+                &format!(
+                    "topodump: {} quads, {} marked",
+                    self.quads.len(),
+                    self.marked.len()
+                ),
+                width
+            )),
+            SetAttribute(Attribute::Reset)
+        )?;
+        for (row, index) in (self.scroll..self.quads.len()).take(list_rows).enumerate() {
+            let quad = &self.quads[index];
+            let mark = if self.marked.contains(&index) {
+                '*'
+            } else {
+                ' '
+            };
+            let line = match &quad.header {
+                Some((header, (_, maplet_height))) => format!(
+                    "{}{:<24} {:<14} {:<9} {:<11} {}",
+                    mark,
+                    header.quad_name.trim(),
+                    header.state_name.trim(),
+                    header
+                        .series(*maplet_height)
+                        .map_or_else(|| "unknown".to_string(), |series| series.to_string()),
+                    years(header),
+                    quad.status
+                ),
+                None => format!("{}{:<24} {}", mark, quad.name(), quad.status),
+            };
+            queue!(out, cursor::MoveTo(0, row as u16 + 1))?;
+            if index == self.selected {
+                queue!(out, SetAttribute(Attribute::Reverse))?;
+            }
+            queue!(
+                out,
+                Print(fit(&line, list_width)),
+                SetAttribute(Attribute::Reset)
+            )?;
+        }
+
+        let map = self.coverage_map(width.saturating_sub(list_width + 1), list_rows);
+        for (row, line) in map.iter().enumerate() {
+            queue!(
+                out,
+                cursor::MoveTo(list_width as u16 + 1, row as u16 + 1),
+                Print(line)
+            )?;
+        }
+
+        let mut footer = self.details();
+        footer.resize(FOOTER_ROWS as usize - 2, String::new());
+        footer.push(self.message.clone());
+        footer.push(HELP.to_string());
+        for (row, line) in footer.iter().enumerate() {
+            queue!(
+                out,
+                cursor::MoveTo(0, (list_rows + 1 + row) as u16),
+                Print(fit(line, width))
+            )?;
+        }
+        out.flush()?;
+        Ok(())
+    }
+
+    fn details(&self) -> Vec<String> {
+        let quad = match self.quads.get(self.selected) {
+            Some(quad) => quad,
+            None => return vec!["No tpq files found".to_string()],
+        };
+        let mut lines = vec![format!("File:    {}", quad.path.display())];
+        if let Some((header, (maplet_width, maplet_height))) = &quad.header {
+            lines.push(format!(
+                "Quad:    {}, {} ({})",
+                header.quad_name.trim(),
+                header.state_name.trim(),
+                years(header)
+            ));
+            lines.push(format!("Source:  {}", header.source.trim()));
+            lines.push(format!(
+                "Bounds:  W {:.4} S {:.4} E {:.4} N {:.4}",
+                header.w_long, header.s_lat, header.e_long, header.n_lat
+            ));
+            lines.push(format!(
+                "Maplets: {} x {} of {} x {} px, {} x {} px in all",
+                header.long_count,
+                header.lat_count,
+                maplet_width,
+                maplet_height,
+                header.long_count as u64 * *maplet_width as u64,
+                header.lat_count as u64 * *maplet_height as u64
+            ));
+        }
+        if !quad.status.is_empty() {
+            lines.push(format!("Status:  {}", quad.status));
+        }
+        lines
+    }
+
+    // The selected quad's series' grid around it, `@` where it lies, `#`
+    // where other quads cover and `.` where none does
+    fn coverage_map(&self, width: usize, height: usize) -> Vec<String> {
+        let path = match self.quads.get(self.selected) {
+            Some(quad) => &quad.path,
+            None => return Vec::new(),
+        };
+        let group = self
+            .coverage
+            .groups
+            .iter()
+            .find(|group| group.quads.iter().any(|(quad, _)| quad == path));
+        let group = match group {
+            Some(group) if width > 0 && height > 1 => group,
+            _ => return Vec::new(),
+        };
+        let present = group.present();
+        let selected = group
+            .quads
+            .iter()
+            .filter(|(quad, _)| quad == path)
+            .flat_map(|(_, cells)| cells.iter().copied())
+            .collect::<BTreeSet<_>>();
+        let (cols, rows) = (
+            present.iter().map(|cell| cell.col),
+            present.iter().map(|cell| cell.row),
+        );
+        let (west, east) = (cols.clone().min().unwrap_or(0), cols.max().unwrap_or(0));
+        let (south, north) = (rows.clone().min().unwrap_or(0), rows.max().unwrap_or(0));
+
+        // Centred on the quad where the grid doesn't fit
+        let centre = selected.iter().next().copied().unwrap_or(Cell {
+            col: west,
+            row: north,
+        });
+        let (width, rows) = (width as i32, height as i32 - 1);
+        let first_col = (centre.col - width / 2).min(east - width + 1).max(west);
+        let top_row = (centre.row + rows / 2).max(south + rows - 1).min(north);
+
+        let mut lines = vec![fit(
+            &format!(
+                "{} {}: {} cells, {} missing",
+                group.state,
+                group.series,
+                present.len(),
+                group.missing.len()
+            ),
+            width as usize,
+        )];
+        for row in (top_row - rows + 1..=top_row)
+            .rev()
+            .filter(|row| *row >= south)
+        {
+            let line = (first_col..first_col + width)
+                .filter(|col| *col <= east)
+                .map(|col| {
+                    let cell = Cell { col, row };
+                    if selected.contains(&cell) {
+                        '@'
+                    } else if present.contains(&cell) {
+                        '#'
+                    } else {
+                        '.'
+                    }
+                })
+                .collect::<String>();
+            lines.push(line);
+        }
+        lines
+    }
+
+    // The marked quads, or the selected one if none are
+    fn targets(&self) -> Vec<usize> {
+        if self.marked.is_empty() {
+            (self.selected < self.quads.len())
+                .then(|| self.selected)
+                .into_iter()
+                .collect()
+        } else {
+            self.marked.iter().copied().collect()
+        }
+    }
+
+    // Runs `action` on each target, showing how each went as it finishes
+    fn apply(
+        &mut self,
+        screen: &mut Screen,
+        verb: &str,
+        action: fn(&Path) -> Result<String>,
+    ) -> Result<()> {
+        let targets = self.targets();
+        for (done, index) in targets.iter().enumerate() {
+            self.message = format!(
+                "Working on {} ({}/{})…",
+                self.quads[*index].name(),
+                done + 1,
+                targets.len()
+            );
+            self.draw(screen)?;
+            self.quads[*index].status = match action(&self.quads[*index].path) {
+                Ok(status) => status,
+                Err(err) => format!("failed: {}", err),
+            };
+        }
+        self.message = format!("{} {} of {} quads", verb, targets.len(), self.quads.len());
+        self.marked.clear();
+        Ok(())
+    }
+}
+
+fn convert(path: &Path) -> Result<String> {
+    let output = Conversion::new(path).run()?;
+    Ok(format!("converted to {}", output.display()))
+}
+
+// Writes the maplets' JPEG data as is into `<stem>_maplets/`
+fn extract(path: &Path) -> Result<String> {
+    let tpq = TpqFile::open(path)?;
+    let stem = path.file_stem().map_or_else(
+        || "tpq".to_string(),
+        |stem| stem.to_string_lossy().to_string(),
+    );
+    let dir = path.with_file_name(format!("{}_maplets", stem));
+    fs::create_dir_all(&dir)?;
+    let mut count = 0;
+    for maplet in tpq.raw_maplets() {
+        let (row, col, data) = maplet?;
+        fs::write(dir.join(format!("r{:02}_c{:02}.jpg", row, col)), data)?;
+        count += 1;
+    }
+    Ok(format!("extracted {} maplets to {}", count, dir.display()))
+}
+
+fn check(path: &Path) -> Result<String> {
+    let tpq = TpqFile::open(path)?;
+    let problems = verify(&tpq);
+    Ok(match problems.first() {
+        None => "verified OK".to_string(),
+        Some(first) => format!("{} problems, e.g. {}", problems.len(), first),
+    })
+}
+
+/// Browses `inputs` in the terminal until `q` or Esc is pressed, listing
+/// each quad with its metadata beside a map of the cells its series covers
+/// in its state. Quads can be marked and converted with default settings,
+/// have their maplets extracted as JPEGs, or be verified.
+pub fn browse(inputs: Vec<PathBuf>) -> Result<()> {
+    let coverage = coverage(&inputs);
+    let mut browser = Browser {
+        quads: inputs.into_iter().map(Quad::open).collect(),
+        coverage,
+        selected: 0,
+        scroll: 0,
+        marked: BTreeSet::new(),
+        message: String::new(),
+    };
+    let mut screen = Screen::enter()?;
+    loop {
+        browser.draw(&mut screen)?;
+        let key = match event::read()? {
+            Event::Key(key) if key.kind == KeyEventKind::Press => key,
+            _ => continue,
+        };
+        let last = browser.quads.len().saturating_sub(1);
+        match key.code {
+            KeyCode::Char('q') | KeyCode::Esc => return Ok(()),
+            KeyCode::Up | KeyCode::Char('k') => {
+                browser.selected = browser.selected.saturating_sub(1)
+            }
+            KeyCode::Down | KeyCode::Char('j') => {
+                browser.selected = (browser.selected + 1).min(last)
+            }
+            KeyCode::PageUp => browser.selected = browser.selected.saturating_sub(10),
+            KeyCode::PageDown => browser.selected = (browser.selected + 10).min(last),
+            KeyCode::Home => browser.selected = 0,
+            KeyCode::End => browser.selected = last,
+            KeyCode::Char(' ') if !browser.quads.is_empty() => {
+                if !browser.marked.remove(&browser.selected) {
+                    browser.marked.insert(browser.selected);
+                }
+                browser.selected = (browser.selected + 1).min(last);
+            }
+            KeyCode::Char('c') => browser.apply(&mut screen, "Converted", convert)?,
+            KeyCode::Char('x') => browser.apply(&mut screen, "Extracted", extract)?,
+            KeyCode::Char('v') => browser.apply(&mut screen, "Verified", check)?,
+            _ => {}
+        }
+    }
+}
@@ -0,0 +1,147 @@
+//! Uploading outputs to object storage as they're written, so cloud ingestion
+//! needn't sync a local copy afterwards.
+
+use std::fs::{self, File};
+use std::io::Read;
+use std::path::Path;
+use std::sync::Arc;
+
+use object_store::aws::AmazonS3Builder;
+use object_store::azure::MicrosoftAzureBuilder;
+use object_store::gcp::GoogleCloudStorageBuilder;
+use object_store::path::Path as ObjectPath;
+use object_store::ObjectStore;
+use tokio::io::AsyncWriteExt;
+use tokio::runtime::{self, Runtime};
+
+use crate::error::{Result, TopodumpError};
+
+/// Files at least this large are uploaded in parts rather than read whole.
+pub const MULTIPART_THRESHOLD: u64 = 64 << 20;
+
+// How much of a file is read per write to a multipart upload
+const PART_SIZE: usize = 8 << 20;
+
+const SCHEMES: [&str; 5] = ["s3", "gcs", "gs", "az", "azure"];
+
+/// Uploads files under a bucket and prefix, e.g. `s3://bucket/prefix/`, in
+/// `s3://`, `gcs://` (or `gs://`) or `az://` (or `azure://`) storage.
+/// Credentials and regions come from the environment as for each service's
+/// own tools, e.g. `AWS_ACCESS_KEY_ID`, `GOOGLE_SERVICE_ACCOUNT` or
+/// `AZURE_STORAGE_ACCOUNT_NAME`.
+///
+/// Uploads block, so they can be made from conversion threads; several
+/// threads may upload at once.
+pub struct Uploader {
+    runtime: Runtime,
+    store: Arc<dyn ObjectStore>,
+    url: String,
+    prefix: String,
+}
+
+impl Uploader {
+    pub fn new(url: &str) -> Result<Self> {
+        let (scheme, rest) = url
+            .split_once("://")
+            .filter(|(scheme, _)| SCHEMES.contains(scheme))
+            .ok_or_else(|| TopodumpError::InvalidStoreUrl(url.to_string()))?;
+        let (bucket, prefix) = rest.split_once('/').unwrap_or((rest, ""));
+        if bucket.is_empty() {
+            return Err(TopodumpError::InvalidStoreUrl(url.to_string()));
+        }
+        let store: Arc<dyn ObjectStore> = match scheme {
+            "s3" => Arc::new(
+                AmazonS3Builder::from_env()
+                    .with_bucket_name(bucket)
+                    .build()?,
+            ),
+            "gcs" | "gs" => Arc::new(
+                GoogleCloudStorageBuilder::from_env()
+                    .with_bucket_name(bucket)
+                    .build()?,
+            ),
+            _ => Arc::new(
+                MicrosoftAzureBuilder::from_env()
+                    .with_container_name(bucket)
+                    .build()?,
+            ),
+        };
+        let runtime = runtime::Builder::new_multi_thread().enable_all().build()?;
+        Ok(Uploader {
+            runtime,
+            store,
+            url: format!("{}://{}", scheme, bucket),
+            prefix: prefix.trim_matches('/').to_string(),
+        })
+    }
+
+    fn location(&self, key: &str) -> Result<ObjectPath> {
+        let key = key.trim_matches('/');
+        let location = match (self.prefix.is_empty(), key.is_empty()) {
+            (true, _) => key.to_string(),
+            (false, true) => self.prefix.clone(),
+            (false, false) => format!("{}/{}", self.prefix, key),
+        };
+        Ok(ObjectPath::parse(location).map_err(object_store::Error::from)?)
+    }
+
+    /// The URL `key` is uploaded to under the prefix.
+    pub fn url(&self, key: &str) -> Result<String> {
+        Ok(format!("{}/{}", self.url, self.location(key)?))
+    }
+
+    /// Uploads the file at `path` as `key` under the prefix, in parts if
+    /// it's at least [`MULTIPART_THRESHOLD`] bytes.
+    pub fn upload(&self, path: &Path, key: &str) -> Result<()> {
+        let location = self.location(key)?;
+        let mut file = File::open(path)?;
+        if file.metadata()?.len() < MULTIPART_THRESHOLD {
+            let mut data = Vec::new();
+            file.read_to_end(&mut data)?;
+            return self.runtime.block_on(async {
+                self.store.put(&location, data.into()).await?;
+                Ok::<_, TopodumpError>(())
+            });
+        }
+
+        self.runtime.block_on(async {
+            let (id, mut writer) = self.store.put_multipart(&location).await?;
+            let mut part = vec![0; PART_SIZE];
+            let written = async {
+                loop {
+                    let len = file.read(&mut part)?;
+                    if len == 0 {
+                        break;
+                    }
+                    writer.write_all(&part[..len]).await?;
+                }
+                writer.shutdown().await
+            };
+            if let Err(err) = written.await {
+                // Don't leave parts behind to be billed for
+                let _ = self.store.abort_multipart(&location, &id).await;
+                return Err(err.into());
+            }
+            Ok::<_, TopodumpError>(())
+        })
+    }
+
+    /// Uploads each file in `dir` under `key` and removes it once uploaded.
+    pub fn upload_dir(&self, dir: &Path, key: &str) -> Result<()> {
+        for entry in fs::read_dir(dir)? {
+            let entry = entry?;
+            if !entry.file_type()?.is_file() {
+                continue;
+            }
+            let name = entry.file_name().to_string_lossy().to_string();
+            let file_key = if key.is_empty() {
+                name
+            } else {
+                format!("{}/{}", key.trim_end_matches('/'), name)
+            };
+            self.upload(&entry.path(), &file_key)?;
+            fs::remove_file(entry.path())?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,131 @@
+//! Checks of a tpq file's integrity, beyond what opening it checks.
+
+use image::{GenericImageView, RgbaImage};
+
+use crate::error::TopodumpError;
+use crate::tpq::TpqFile;
+
+/// Every problem found in `tpq`: maplets sharing an offset, maplets that
+/// fail to decode, and maplets of a different size than the first.
+pub fn verify(tpq: &TpqFile) -> Vec<TopodumpError> {
+    let mut problems = tpq.duplicate_maplets();
+    let mut expected = None;
+    for maplet in tpq.maplets() {
+        let (row, col, maplet) = match maplet {
+            Ok(maplet) => maplet,
+            Err(err) => {
+                problems.push(err);
+                continue;
+            }
+        };
+        let (width, height) = maplet.dimensions();
+        let &mut (expected_width, expected_height) = expected.get_or_insert((width, height));
+        if (width, height) != (expected_width, expected_height) {
+            problems.push(TopodumpError::MapletSize {
+                row,
+                col,
+                width,
+                height,
+                expected_width,
+                expected_height,
+            });
+        }
+    }
+    problems
+}
+
+// A boundary is suspicious when the change across it is this many times the
+// change between neighbouring pixels on either side...
+const SEAM_RATIO: f64 = 4.0;
+// ...and at least this large, in levels per channel, so faint noise isn't
+const SEAM_MIN_DIFFERENCE: f64 = 12.0;
+
+/// The maplet boundary a [`Seam`] lies on.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Edge {
+    Right,
+    Bottom,
+}
+
+/// A maplet boundary with a sharper discontinuity than the imagery around
+/// it, typically a misplaced or mismatched maplet in the source.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Seam {
+    pub row: u32,
+    pub col: u32,
+    pub edge: Edge,
+    /// The change across the boundary relative to that beside it.
+    pub score: f64,
+}
+
+// Mean absolute difference per channel between the lines of `len` pixels
+// starting at `a` and `b`, running down the image if `down`, else across
+fn difference(collage: &RgbaImage, a: (u32, u32), b: (u32, u32), len: u32, down: bool) -> f64 {
+    let at = |(x, y): (u32, u32), i: u32| {
+        if down {
+            collage.get_pixel(x, y + i)
+        } else {
+            collage.get_pixel(x + i, y)
+        }
+    };
+    let total: u32 = (0..len)
+        .map(|i| {
+            let (pa, pb) = (at(a, i), at(b, i));
+            (0..3)
+                .map(|channel| (pa[channel] as i32 - pb[channel] as i32).unsigned_abs())
+                .sum::<u32>()
+        })
+        .sum();
+    total as f64 / (3 * len.max(1)) as f64
+}
+
+// The score of a boundary between `lines[1]` and `lines[2]`, with the lines
+// just beyond them on either side, if it's a seam
+fn seam_score(collage: &RgbaImage, lines: [(u32, u32); 4], len: u32, down: bool) -> Option<f64> {
+    let across = difference(collage, lines[1], lines[2], len, down);
+    let beside = (difference(collage, lines[0], lines[1], len, down)
+        + difference(collage, lines[2], lines[3], len, down))
+        / 2.0;
+    let score = across / beside.max(1.0);
+    (across >= SEAM_MIN_DIFFERENCE && score >= SEAM_RATIO).then(|| score)
+}
+
+/// Boundaries between the maplets of `collage`, a quad's maplets of
+/// `maplet_width`×`maplet_height` assembled by [`TpqFile::collage`], that
+/// stand out from their surroundings, in row-major order.
+pub fn seams(collage: &RgbaImage, (maplet_width, maplet_height): (u32, u32)) -> Vec<Seam> {
+    let (width, height) = collage.dimensions();
+    let mut seams = Vec::new();
+    if maplet_width < 2 || maplet_height < 2 {
+        return seams;
+    }
+    for row in 0..height / maplet_height {
+        for col in 0..width / maplet_width {
+            let (left, top) = (col * maplet_width, row * maplet_height);
+            let (right, bottom) = (left + maplet_width, top + maplet_height);
+            if right + 1 < width {
+                let lines = [right - 2, right - 1, right, right + 1].map(|x| (x, top));
+                if let Some(score) = seam_score(collage, lines, maplet_height, true) {
+                    seams.push(Seam {
+                        row,
+                        col,
+                        edge: Edge::Right,
+                        score,
+                    });
+                }
+            }
+            if bottom + 1 < height {
+                let lines = [bottom - 2, bottom - 1, bottom, bottom + 1].map(|y| (left, y));
+                if let Some(score) = seam_score(collage, lines, maplet_width, false) {
+                    seams.push(Seam {
+                        row,
+                        col,
+                        edge: Edge::Bottom,
+                        score,
+                    });
+                }
+            }
+        }
+    }
+    seams
+}
@@ -0,0 +1,217 @@
+//! GDAL VRT descriptions of tpq files.
+//!
+//! GDAL can't read tpq files itself, but each maplet is a plain JPEG that
+//! GDAL can open in place through `/vsisubfile/`. A VRT stitching those
+//! together lets `gdalinfo`, `gdal_translate` and QGIS open a tpq directly.
+
+use std::fmt::Write;
+use std::path::{Path, PathBuf};
+
+use image::GenericImageView;
+
+use crate::conversion::{format_for_extension, ConversionSummary, Format};
+use crate::error::{Result, TopodumpError};
+use crate::georef::GeoRef;
+use crate::tpq::TpqFile;
+
+pub(crate) fn escape_xml(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Builds a VRT over the maplets of `tpq`, which was read from `tpq_path`.
+pub fn tpq_vrt(tpq: &TpqFile, tpq_path: &Path) -> Result<String> {
+    let tpq_path = tpq_path.canonicalize()?;
+    let header = tpq.header();
+    let (maplet_width, maplet_height) = tpq.maplet(0, 0)?.dimensions();
+    let (width, height) = header.image_size((maplet_width, maplet_height), u64::MAX)?;
+    let georef = GeoRef::from_header(header, width as f64, height as f64);
+
+    let mut sources = Vec::new();
+    for row in 0..header.lat_count {
+        for col in 0..header.long_count {
+            let range = tpq.maplet_range(row, col)?;
+            sources.push((
+                format!(
+                    "/vsisubfile/{}_{},{}",
+                    range.start,
+                    range.len(),
+                    tpq_path.display()
+                ),
+                col * maplet_width,
+                row * maplet_height,
+            ));
+        }
+    }
+
+    let mut vrt = String::new();
+    // Writing to a String can't fail
+    let _ = writeln!(
+        vrt,
+        r#"<VRTDataset rasterXSize="{}" rasterYSize="{}">"#,
+        width, height
+    );
+    let _ = writeln!(vrt, "  <SRS>{}</SRS>", escape_xml(&georef.wkt));
+    let transform = georef
+        .transform
+        .iter()
+        .map(|value| format!("{:.16e}", value))
+        .collect::<Vec<_>>()
+        .join(", ");
+    let _ = writeln!(vrt, "  <GeoTransform>{}</GeoTransform>", transform);
+    for (band, interp) in ["Red", "Green", "Blue"].iter().enumerate() {
+        let _ = writeln!(
+            vrt,
+            r#"  <VRTRasterBand dataType="Byte" band="{}">"#,
+            band + 1
+        );
+        let _ = writeln!(vrt, "    <ColorInterp>{}</ColorInterp>", interp);
+        for (filename, x, y) in &sources {
+            let _ = writeln!(vrt, "    <SimpleSource>");
+            let _ = writeln!(
+                vrt,
+                r#"      <SourceFilename relativeToVRT="0">{}</SourceFilename>"#,
+                escape_xml(filename)
+            );
+            let _ = writeln!(vrt, "      <SourceBand>{}</SourceBand>", band + 1);
+            let _ = writeln!(
+                vrt,
+                r#"      <SourceProperties RasterXSize="{0}" RasterYSize="{1}" DataType="Byte" BlockXSize="{0}" BlockYSize="1"/>"#,
+                maplet_width, maplet_height
+            );
+            let _ = writeln!(
+                vrt,
+                r#"      <SrcRect xOff="0" yOff="0" xSize="{}" ySize="{}"/>"#,
+                maplet_width, maplet_height
+            );
+            let _ = writeln!(
+                vrt,
+                r#"      <DstRect xOff="{}" yOff="{}" xSize="{}" ySize="{}"/>"#,
+                x, y, maplet_width, maplet_height
+            );
+            let _ = writeln!(vrt, "    </SimpleSource>");
+        }
+        let _ = writeln!(vrt, "  </VRTRasterBand>");
+    }
+    let _ = writeln!(vrt, "</VRTDataset>");
+
+    Ok(vrt)
+}
+
+// `path` relative to `dir` if it's under it, for VRTs that move with their outputs
+fn source_path(path: &Path, dir: &Path) -> (PathBuf, bool) {
+    let path = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+    match dir
+        .canonicalize()
+        .map(|dir| path.strip_prefix(dir).map(Path::to_path_buf))
+    {
+        Ok(Ok(relative)) => (relative, true),
+        _ => (path, false),
+    }
+}
+
+/// Builds a VRT written at `vrt_path` mosaicking the GeoTIFF outputs of
+/// `conversions`, at the finest of their resolutions, with coarser outputs
+/// drawn first so finer ones cover them. `alpha` says the outputs have an
+/// alpha band.
+pub fn batch_vrt(
+    conversions: &[ConversionSummary],
+    vrt_path: &Path,
+    alpha: bool,
+) -> Result<String> {
+    let mut outputs = conversions
+        .iter()
+        .filter(|conversion| format_for_extension(&conversion.output) == Some(Format::GTiff))
+        .collect::<Vec<_>>();
+    let wkt = outputs
+        .first()
+        .ok_or(TopodumpError::Unsupported("VRTs without GeoTIFF outputs"))?
+        .georef
+        .wkt
+        .clone();
+    outputs.sort_by(|a, b| b.georef.transform[1].total_cmp(&a.georef.transform[1]));
+    let vrt_dir = vrt_path
+        .parent()
+        .filter(|dir| !dir.as_os_str().is_empty())
+        .unwrap_or_else(|| Path::new("."));
+
+    let (mut west, mut south, mut east, mut north) = (
+        f64::INFINITY,
+        f64::INFINITY,
+        f64::NEG_INFINITY,
+        f64::NEG_INFINITY,
+    );
+    let (mut dx, mut dy) = (f64::INFINITY, f64::INFINITY);
+    for output in &outputs {
+        let bounds = output.georef.bounds(output.width, output.height);
+        west = west.min(bounds.0);
+        south = south.min(bounds.1);
+        east = east.max(bounds.2);
+        north = north.max(bounds.3);
+        dx = dx.min(output.georef.transform[1].abs());
+        dy = dy.min(output.georef.transform[5].abs());
+    }
+    let width = ((east - west) / dx).round() as u64;
+    let height = ((north - south) / dy).round() as u64;
+
+    let mut vrt = String::new();
+    // Writing to a String can't fail
+    let _ = writeln!(
+        vrt,
+        r#"<VRTDataset rasterXSize="{}" rasterYSize="{}">"#,
+        width, height
+    );
+    let _ = writeln!(vrt, "  <SRS>{}</SRS>", escape_xml(&wkt));
+    let transform = [west, dx, 0.0, north, 0.0, -dy]
+        .iter()
+        .map(|value| format!("{:.16e}", value))
+        .collect::<Vec<_>>()
+        .join(", ");
+    let _ = writeln!(vrt, "  <GeoTransform>{}</GeoTransform>", transform);
+    let bands: &[&str] = if alpha {
+        &["Red", "Green", "Blue", "Alpha"]
+    } else {
+        &["Red", "Green", "Blue"]
+    };
+    for (band, interp) in bands.iter().enumerate() {
+        let _ = writeln!(
+            vrt,
+            r#"  <VRTRasterBand dataType="Byte" band="{}">"#,
+            band + 1
+        );
+        let _ = writeln!(vrt, "    <ColorInterp>{}</ColorInterp>", interp);
+        for output in &outputs {
+            let (filename, relative) = source_path(&output.output, vrt_dir);
+            let (output_west, output_south, output_east, output_north) =
+                output.georef.bounds(output.width, output.height);
+            let _ = writeln!(vrt, "    <SimpleSource>");
+            let _ = writeln!(
+                vrt,
+                r#"      <SourceFilename relativeToVRT="{}">{}</SourceFilename>"#,
+                relative as u8,
+                escape_xml(&filename.to_string_lossy())
+            );
+            let _ = writeln!(vrt, "      <SourceBand>{}</SourceBand>", band + 1);
+            let _ = writeln!(
+                vrt,
+                r#"      <SrcRect xOff="0" yOff="0" xSize="{}" ySize="{}"/>"#,
+                output.width, output.height
+            );
+            let _ = writeln!(
+                vrt,
+                r#"      <DstRect xOff="{}" yOff="{}" xSize="{}" ySize="{}"/>"#,
+                (output_west - west) / dx,
+                (north - output_north) / dy,
+                (output_east - output_west) / dx,
+                (output_north - output_south) / dy
+            );
+            let _ = writeln!(vrt, "    </SimpleSource>");
+        }
+        let _ = writeln!(vrt, "  </VRTRasterBand>");
+    }
+    let _ = writeln!(vrt, "</VRTDataset>");
+
+    Ok(vrt)
+}
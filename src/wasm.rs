@@ -0,0 +1,71 @@
+//! Browser bindings, built with `wasm-pack build --no-default-features --features wasm`.
+
+use image::{codecs::png::PngEncoder, ColorType};
+use wasm_bindgen::prelude::*;
+
+use crate::georef::{world_file_contents, GeoRef};
+use crate::tpq::TpqFile;
+
+fn to_js(err: impl std::fmt::Display) -> JsValue {
+    JsValue::from_str(&err.to_string())
+}
+
+/// A PNG rendering of a tpq file with its world file and .prj contents.
+#[wasm_bindgen]
+pub struct PngConversion {
+    png: Vec<u8>,
+    world_file: String,
+    prj: String,
+}
+
+#[wasm_bindgen]
+impl PngConversion {
+    #[wasm_bindgen(getter)]
+    pub fn png(&self) -> Vec<u8> {
+        self.png.clone()
+    }
+
+    #[wasm_bindgen(getter, js_name = worldFile)]
+    pub fn world_file(&self) -> String {
+        self.world_file.clone()
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn prj(&self) -> String {
+        self.prj.clone()
+    }
+}
+
+/// Returns the header of a tpq file as a JSON string.
+#[wasm_bindgen(js_name = readHeader)]
+pub fn read_header(data: &[u8]) -> Result<String, JsValue> {
+    let tpq = TpqFile::from_bytes(data.to_vec()).map_err(to_js)?;
+    serde_json::to_string(tpq.header()).map_err(to_js)
+}
+
+#[wasm_bindgen(js_name = convertToPng)]
+pub fn convert_to_png(data: &[u8]) -> Result<PngConversion, JsValue> {
+    let tpq = TpqFile::from_bytes(data.to_vec()).map_err(to_js)?;
+    let collage_img = tpq.collage().map_err(to_js)?;
+    let georef = GeoRef::from_header(
+        tpq.header(),
+        collage_img.width() as f64,
+        collage_img.height() as f64,
+    );
+
+    let mut png = Vec::new();
+    PngEncoder::new(&mut png)
+        .encode(
+            collage_img.as_raw(),
+            collage_img.width(),
+            collage_img.height(),
+            ColorType::Rgba8,
+        )
+        .map_err(to_js)?;
+
+    Ok(PngConversion {
+        png,
+        world_file: world_file_contents(&georef),
+        prj: georef.wkt,
+    })
+}
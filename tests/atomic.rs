@@ -0,0 +1,28 @@
+mod common;
+
+use std::fs;
+
+use topodump::{CancelToken, Conversion, TopodumpError};
+
+use common::{scratch_dir, synthetic_tpq, write_tpq};
+
+#[test]
+fn cancelled_conversion_keeps_earlier_output() {
+    let dir = scratch_dir("atomic");
+    let input = dir.join("quad.tpq");
+    write_tpq(&input, &synthetic_tpq(3, 2));
+    let output = dir.join("quad.tif");
+    fs::write(&output, b"earlier output").unwrap();
+
+    let cancel = CancelToken::new();
+    cancel.cancel();
+    let result = Conversion::new(&input)
+        .output(&output)
+        .cancel_token(cancel)
+        .run();
+
+    assert!(matches!(result, Err(TopodumpError::Cancelled)));
+    assert_eq!(fs::read(&output).unwrap(), b"earlier output");
+    assert!(!dir.join("quad.tif.part").exists());
+    fs::remove_dir_all(dir).unwrap();
+}
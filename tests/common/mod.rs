@@ -0,0 +1,100 @@
+#![allow(dead_code)]
+
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use image::codecs::jpeg::JpegEncoder;
+use image::{ColorType, Rgb, RgbImage};
+
+pub const MAPLET_SIZE: u32 = 32;
+
+fn put_string(out: &mut Vec<u8>, value: &str, size: usize) {
+    let mut field = vec![0u8; size];
+    field[..value.len()].copy_from_slice(value.as_bytes());
+    out.extend_from_slice(&field);
+}
+
+pub const WEST: f64 = -113.125;
+pub const NORTH: f64 = 34.125;
+pub const EAST: f64 = -113.0;
+pub const SOUTH: f64 = 34.0;
+
+pub fn maplet_colour(row: u32, col: u32) -> Rgb<u8> {
+    Rgb([(row * 60) as u8, (col * 60) as u8, 128])
+}
+
+/// A quad whose maplets are solid colours derived from their position.
+pub fn synthetic_tpq(long_count: u32, lat_count: u32) -> Vec<u8> {
+    synthetic_tpq_sized(long_count, lat_count, MAPLET_SIZE)
+}
+
+pub fn synthetic_tpq_sized(long_count: u32, lat_count: u32, maplet_size: u32) -> Vec<u8> {
+    let mut data = Vec::new();
+    data.extend_from_slice(&1u32.to_le_bytes());
+    // A 7.5' quad, so the series is inferred as 1:24,000
+    for value in &[WEST, NORTH, EAST, SOUTH] {
+        data.extend_from_slice(&value.to_le_bytes());
+    }
+    put_string(&mut data, "TOPO", 220);
+    put_string(&mut data, "Synthetic Quad", 128);
+    put_string(&mut data, "Arizona", 32);
+    put_string(&mut data, "USGS", 32);
+    put_string(&mut data, "1970", 4);
+    put_string(&mut data, "1990", 4);
+    put_string(&mut data, "40 ft", 24);
+    put_string(&mut data, "jpg", 4);
+    for value in &[24, 0, long_count, lat_count, maplet_size, maplet_size] {
+        data.extend_from_slice(&value.to_le_bytes());
+    }
+    data.resize(1024, 0);
+
+    let maplets = (0..lat_count)
+        .flat_map(|row| (0..long_count).map(move |col| (row, col)))
+        .map(|(row, col)| {
+            let maplet = RgbImage::from_pixel(maplet_size, maplet_size, maplet_colour(row, col));
+            let mut jpeg = Vec::new();
+            JpegEncoder::new_with_quality(&mut jpeg, 95)
+                .encode(maplet.as_raw(), maplet_size, maplet_size, ColorType::Rgb8)
+                .unwrap();
+            jpeg
+        })
+        .collect::<Vec<_>>();
+
+    let mut offset = data.len() + 4 * maplets.len();
+    for maplet in &maplets {
+        data.extend_from_slice(&(offset as u32).to_le_bytes());
+        offset += maplet.len();
+    }
+    for maplet in &maplets {
+        data.extend_from_slice(maplet);
+    }
+    data
+}
+
+/// A fresh directory for one test's files.
+pub fn scratch_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("topodump-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+pub fn write_tpq(path: &Path, data: &[u8]) {
+    fs::File::create(path).unwrap().write_all(data).unwrap();
+}
+
+/// `tpq` moved by whole quads, east by `east` and north by `north`.
+pub fn shifted_tpq(mut tpq: Vec<u8>, east: i32, north: i32) -> Vec<u8> {
+    let (width, height) = (EAST - WEST, NORTH - SOUTH);
+    let bounds = [
+        WEST + east as f64 * width,
+        NORTH + north as f64 * height,
+        EAST + east as f64 * width,
+        SOUTH + north as f64 * height,
+    ];
+    for (i, value) in bounds.iter().enumerate() {
+        tpq[4 + 8 * i..12 + 8 * i].copy_from_slice(&value.to_le_bytes());
+    }
+    tpq
+}
@@ -0,0 +1,82 @@
+mod common;
+
+use std::sync::Arc;
+use std::thread;
+
+use topodump::{convert_to_vec, CancelToken, Conversion, ConvertOptions, Format, TpqFile};
+
+use common::{scratch_dir, synthetic_tpq, write_tpq};
+
+const THREADS: usize = 8;
+
+fn assert_send<T: Send>() {}
+fn assert_send_sync<T: Send + Sync>() {}
+
+#[test]
+fn library_types_cross_threads() {
+    assert_send_sync::<TpqFile>();
+    assert_send_sync::<CancelToken>();
+    assert_send::<Conversion>();
+}
+
+#[test]
+fn shared_tpq_file_decodes_from_many_threads() {
+    let tpq = Arc::new(TpqFile::from_bytes(synthetic_tpq(4, 4)).unwrap());
+    let expected = tpq.collage().unwrap();
+
+    let handles = (0..THREADS)
+        .map(|_| {
+            let tpq = Arc::clone(&tpq);
+            thread::spawn(move || tpq.collage().unwrap())
+        })
+        .collect::<Vec<_>>();
+    for handle in handles {
+        assert_eq!(handle.join().unwrap(), expected);
+    }
+}
+
+#[test]
+fn concurrent_file_conversions_match_sequential() {
+    let dir = scratch_dir("concurrent");
+    let data = synthetic_tpq(3, 2);
+    let inputs = (0..THREADS)
+        .map(|i| {
+            let input = dir.join(format!("quad{}.tpq", i));
+            write_tpq(&input, &data);
+            input
+        })
+        .collect::<Vec<_>>();
+
+    let handles = inputs
+        .iter()
+        .cloned()
+        .map(|input| {
+            thread::spawn(move || Conversion::new(input).format(Format::GTiff).run().unwrap())
+        })
+        .collect::<Vec<_>>();
+    let outputs = handles
+        .into_iter()
+        .map(|handle| std::fs::read(handle.join().unwrap()).unwrap())
+        .collect::<Vec<_>>();
+
+    for output in &outputs {
+        assert_eq!(output, &outputs[0]);
+    }
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn concurrent_in_memory_conversions_match_sequential() {
+    let data = Arc::new(synthetic_tpq(3, 2));
+    let expected = convert_to_vec(&data, &ConvertOptions::default()).unwrap();
+
+    let handles = (0..THREADS)
+        .map(|_| {
+            let data = Arc::clone(&data);
+            thread::spawn(move || convert_to_vec(&data, &ConvertOptions::default()).unwrap())
+        })
+        .collect::<Vec<_>>();
+    for handle in handles {
+        assert_eq!(handle.join().unwrap(), expected);
+    }
+}
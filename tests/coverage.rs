@@ -0,0 +1,73 @@
+mod common;
+
+use std::fs;
+
+use topodump::coverage::{coverage, Cell};
+use topodump::index::{index, IndexFormat};
+use topodump::Series;
+
+use common::{scratch_dir, shifted_tpq, synthetic_tpq, write_tpq};
+
+#[test]
+fn reports_missing_cells_and_neighbours() {
+    let dir = scratch_dir("coverage");
+    let (west, east) = (dir.join("west.tpq"), dir.join("east.tpq"));
+    write_tpq(&west, &synthetic_tpq(2, 2));
+    write_tpq(&east, &shifted_tpq(synthetic_tpq(2, 2), 2, 0));
+
+    let report = coverage(&[west.clone(), east]);
+    assert!(report.skipped.is_empty());
+    assert_eq!(report.groups.len(), 1);
+    let group = &report.groups[0];
+    assert_eq!(
+        (group.state.as_str(), group.series),
+        ("Arizona", Series::Scale24k)
+    );
+    assert_eq!(
+        group.missing,
+        vec![Cell {
+            col: -904,
+            row: 272
+        }]
+    );
+    assert_eq!(group.name(group.missing[0]), "34112-A8");
+    assert_eq!(
+        group.name(Cell {
+            col: -905,
+            row: 272
+        }),
+        "34113-A1"
+    );
+
+    let (path, neighbours) = &group.absent_neighbours[0];
+    assert_eq!(path, &west);
+    assert_eq!(neighbours.len(), 8);
+}
+
+#[test]
+fn index_lists_footprints_as_csv_and_shapefile() {
+    let dir = scratch_dir("index");
+    let (west, east) = (dir.join("west.tpq"), dir.join("east.tpq"));
+    write_tpq(&west, &synthetic_tpq(2, 2));
+    write_tpq(&east, &shifted_tpq(synthetic_tpq(2, 2), 2, 0));
+
+    let index = index(&[west.clone(), east]);
+    assert!(index.skipped.is_empty());
+    assert_eq!(index.footprints[0].location, dir.join("west_24k.tif"));
+
+    let mut csv = Vec::new();
+    index.write(IndexFormat::Csv, &mut csv).unwrap();
+    let csv = String::from_utf8(csv).unwrap();
+    let lines = csv.lines().collect::<Vec<_>>();
+    assert_eq!(lines.len(), 3);
+    assert!(lines[0].starts_with("path,location,quad,state"));
+    assert!(lines[1].ends_with("))\""));
+
+    let shp = dir.join("index.shp");
+    index.write_shapefile(&shp).unwrap();
+    // A 100-byte header, then an 8-byte header and 128 bytes per polygon
+    assert_eq!(fs::read(&shp).unwrap().len(), 100 + 2 * 136);
+    assert_eq!(fs::read(dir.join("index.shx")).unwrap().len(), 100 + 2 * 8);
+    let dbf = fs::read(dir.join("index.dbf")).unwrap();
+    assert_eq!(u32::from_le_bytes([dbf[4], dbf[5], dbf[6], dbf[7]]), 2);
+}
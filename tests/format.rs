@@ -0,0 +1,246 @@
+mod common;
+
+use std::fs;
+
+use image::GenericImageView;
+use topodump::output::{stats_path, thumbnail_path, Thumbnail};
+use topodump::{Conversion, Corner, Format, IccProfile, TopodumpError};
+
+use common::{scratch_dir, synthetic_tpq, write_tpq, MAPLET_SIZE};
+
+const PNG_MAGIC: &[u8] = b"\x89PNG";
+
+#[test]
+fn format_follows_output_extension() {
+    let dir = scratch_dir("format-extension");
+    let input = dir.join("quad.tpq");
+    write_tpq(&input, &synthetic_tpq(2, 2));
+
+    let output = Conversion::new(&input)
+        .output(dir.join("quad.png"))
+        .run()
+        .unwrap();
+    assert!(fs::read(output).unwrap().starts_with(PNG_MAGIC));
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn format_conflicting_with_extension_is_rejected() {
+    let dir = scratch_dir("format-conflict");
+    let input = dir.join("quad.tpq");
+    write_tpq(&input, &synthetic_tpq(2, 2));
+
+    let result = Conversion::new(&input)
+        .output(dir.join("quad.png"))
+        .format(Format::GTiff)
+        .run();
+    assert!(matches!(
+        result,
+        Err(TopodumpError::FormatMismatch {
+            format: Format::GTiff,
+            ..
+        })
+    ));
+    assert!(!dir.join("quad.png").exists());
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn unknown_extension_is_rejected() {
+    let dir = scratch_dir("format-unknown-extension");
+    let input = dir.join("quad.tpq");
+    write_tpq(&input, &synthetic_tpq(2, 2));
+
+    let result = Conversion::new(&input).output(dir.join("quad.jpg")).run();
+    assert!(matches!(result, Err(TopodumpError::UnknownExtension(_))));
+    let result = Conversion::new(&input)
+        .output(dir.join("quad.jpg"))
+        .format(Format::Png)
+        .run();
+    assert!(matches!(
+        result,
+        Err(TopodumpError::FormatMismatch {
+            format: Format::Png,
+            ..
+        })
+    ));
+    assert!(!dir.join("quad.jpg").exists());
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn quantized_png_is_paletted() {
+    let dir = scratch_dir("format-quantize");
+    let input = dir.join("quad.tpq");
+    write_tpq(&input, &synthetic_tpq(2, 2));
+
+    let output = Conversion::new(&input)
+        .output(dir.join("quad.png"))
+        .quantize(16)
+        .run()
+        .unwrap();
+    let image = image::open(&output).unwrap();
+    assert_eq!(image.dimensions(), (2 * MAPLET_SIZE, 2 * MAPLET_SIZE));
+    // Colour type 3 in the IHDR chunk marks an indexed PNG
+    assert_eq!(fs::read(&output).unwrap()[25], 3);
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn grayscale_png_has_one_band() {
+    let dir = scratch_dir("format-grayscale");
+    let input = dir.join("quad.tpq");
+    write_tpq(&input, &synthetic_tpq(2, 2));
+
+    let output = Conversion::new(&input)
+        .output(dir.join("quad.png"))
+        .grayscale(true)
+        .run()
+        .unwrap();
+    let image = image::open(&output).unwrap();
+    assert_eq!(image.color(), image::ColorType::L8);
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn brightness_lightens_output() {
+    let dir = scratch_dir("format-brightness");
+    let input = dir.join("quad.tpq");
+    write_tpq(&input, &synthetic_tpq(2, 2));
+
+    let plain = Conversion::new(&input)
+        .output(dir.join("plain.png"))
+        .run()
+        .unwrap();
+    let lightened = Conversion::new(&input)
+        .output(dir.join("lightened.png"))
+        .brightness(0.2)
+        .run()
+        .unwrap();
+    let (plain, lightened) = (
+        image::open(plain).unwrap().to_rgb8(),
+        image::open(lightened).unwrap().to_rgb8(),
+    );
+    let (before, after) = (plain.get_pixel(0, 0), lightened.get_pixel(0, 0));
+    assert!(after[2] > before[2]);
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn max_size_downscales_proportionally() {
+    let dir = scratch_dir("format-max-size");
+    let input = dir.join("quad.tpq");
+    write_tpq(&input, &synthetic_tpq(4, 2));
+
+    let summary = Conversion::new(&input)
+        .output(dir.join("quad.png"))
+        .max_size(MAPLET_SIZE)
+        .run_with_summary()
+        .unwrap();
+    assert_eq!(
+        (summary.width, summary.height),
+        (MAPLET_SIZE, MAPLET_SIZE / 2)
+    );
+    let image = image::open(&summary.output).unwrap();
+    assert_eq!(image.dimensions(), (MAPLET_SIZE, MAPLET_SIZE / 2));
+    // Each pixel covers four times the longitude
+    let full = Conversion::new(&input)
+        .output(dir.join("full.png"))
+        .run_with_summary()
+        .unwrap();
+    let ratio = summary.georef.transform[1] / full.georef.transform[1];
+    assert!((ratio - 4.0).abs() < 1e-9);
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn stamp_is_drawn_in_its_corner() {
+    let dir = scratch_dir("format-stamp");
+    let input = dir.join("quad.tpq");
+    write_tpq(&input, &synthetic_tpq(2, 2));
+
+    let output = Conversion::new(&input)
+        .output(dir.join("quad.png"))
+        .stamp("A")
+        .stamp_corner(Corner::TopLeft)
+        .run()
+        .unwrap();
+    let image = image::open(output).unwrap().to_rgb8();
+    // White around the glyph, whose left column starts a row down
+    assert_eq!(image.get_pixel(2, 2).0, [255, 255, 255]);
+    assert_eq!(image.get_pixel(3, 4).0, [0, 0, 0]);
+    assert_ne!(
+        image.get_pixel(2 * MAPLET_SIZE - 3, 2 * MAPLET_SIZE - 3).0,
+        [255, 255, 255]
+    );
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn icc_profile_is_embedded_in_png() {
+    let dir = scratch_dir("format-icc");
+    let input = dir.join("quad.tpq");
+    write_tpq(&input, &synthetic_tpq(2, 2));
+
+    let output = Conversion::new(&input)
+        .output(dir.join("quad.png"))
+        .icc_profile(IccProfile::Srgb)
+        .run()
+        .unwrap();
+    let data = fs::read(&output).unwrap();
+    assert!(data.windows(4).any(|chunk| chunk == b"iCCP"));
+    let image = image::open(&output).unwrap();
+    assert_eq!(image.dimensions(), (2 * MAPLET_SIZE, 2 * MAPLET_SIZE));
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn stats_are_written_beside_output() {
+    let dir = scratch_dir("format-stats");
+    let input = dir.join("quad.tpq");
+    write_tpq(&input, &synthetic_tpq(2, 2));
+
+    let output = Conversion::new(&input)
+        .output(dir.join("quad.png"))
+        .stats(true)
+        .run()
+        .unwrap();
+    let stats = fs::read_to_string(stats_path(&output)).unwrap();
+    assert_eq!(stats.matches("<PAMRasterBand").count(), 3);
+    // Every maplet's blue is 128
+    assert!(stats.contains("<MDI key=\"STATISTICS_MINIMUM\">128</MDI>"));
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn thumbnail_is_written_beside_output() {
+    let dir = scratch_dir("format-thumbnail");
+    let input = dir.join("quad.tpq");
+    write_tpq(&input, &synthetic_tpq(2, 2));
+
+    let output = Conversion::new(&input)
+        .output(dir.join("quad.png"))
+        .thumbnail(Thumbnail::Auto)
+        .run()
+        .unwrap();
+    let thumbnail = image::open(thumbnail_path(&output)).unwrap();
+    assert_eq!(thumbnail.dimensions(), (2 * MAPLET_SIZE, 2 * MAPLET_SIZE));
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[cfg(feature = "gdal")]
+#[test]
+fn verified_output_keeps_keyed_out_colour() {
+    let dir = scratch_dir("format-verify-transparent");
+    let input = dir.join("quad.tpq");
+    write_tpq(&input, &synthetic_tpq(2, 2));
+
+    let output = Conversion::new(&input)
+        .output(dir.join("quad.tif"))
+        .transparent_colour(common::maplet_colour(0, 0).0, 0)
+        .verify_output(true)
+        .run()
+        .unwrap();
+    assert!(output.exists());
+    fs::remove_dir_all(dir).unwrap();
+}
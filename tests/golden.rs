@@ -0,0 +1,185 @@
+//! Converts generated fixtures and checks the outputs against known values.
+//!
+//! Output checksums live in `tests/golden/checksums.json`. Run with
+//! `TOPODUMP_BLESS=1` to record them after an intentional output change.
+//! Until they're first recorded, on a machine with GDAL, comparing them is
+//! held back behind `--ignored`:
+//! `TOPODUMP_BLESS=1 cargo test --test golden -- --ignored`.
+
+mod common;
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+use image::{GenericImageView, Rgba};
+use topodump::georef::NAD27_WKT;
+use topodump::{Conversion, Format};
+
+use common::{
+    maplet_colour, scratch_dir, synthetic_tpq, write_tpq, EAST, MAPLET_SIZE, NORTH, SOUTH, WEST,
+};
+
+const LONG_COUNT: u32 = 3;
+const LAT_COUNT: u32 = 2;
+// JPEG round trips of solid colours are close but not always exact
+const COLOUR_TOLERANCE: i32 = 3;
+
+fn fnv1a(data: &[u8]) -> String {
+    let hash = data.iter().fold(0xcbf2_9ce4_8422_2325u64, |hash, &byte| {
+        (hash ^ byte as u64).wrapping_mul(0x0100_0000_01b3)
+    });
+    format!("{:016x}", hash)
+}
+
+fn check_checksum(case: &str, output: &Path) {
+    let golden_path = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/golden/checksums.json");
+    let mut golden: BTreeMap<String, String> = fs::read(&golden_path)
+        .map(|data| serde_json::from_slice(&data).unwrap())
+        .unwrap_or_default();
+    let actual = fnv1a(&fs::read(output).unwrap());
+
+    if std::env::var_os("TOPODUMP_BLESS").is_some() {
+        eprintln!("recording {} checksum {}", case, actual);
+        golden.insert(case.to_string(), actual);
+        fs::write(
+            &golden_path,
+            serde_json::to_string_pretty(&golden).unwrap() + "\n",
+        )
+        .unwrap();
+        return;
+    }
+    let expected = golden.get(case).unwrap_or_else(|| {
+        panic!(
+            "no {} checksum in {}; run with TOPODUMP_BLESS=1 to record it",
+            case,
+            golden_path.display()
+        )
+    });
+    assert_eq!(
+        &actual, expected,
+        "{} output changed; rerun with TOPODUMP_BLESS=1 if intended",
+        case
+    );
+}
+
+fn expected_transform() -> [f64; 6] {
+    let (width, height) = (LONG_COUNT * MAPLET_SIZE, LAT_COUNT * MAPLET_SIZE);
+    [
+        WEST,
+        (EAST - WEST) / width as f64,
+        0.0,
+        NORTH,
+        0.0,
+        -(NORTH - SOUTH) / height as f64,
+    ]
+}
+
+fn assert_close(actual: &[f64], expected: &[f64]) {
+    for (a, e) in actual.iter().zip(expected) {
+        assert!((a - e).abs() < 1e-9, "{:?} != {:?}", actual, expected);
+    }
+}
+
+fn assert_pixel_samples(image: &impl GenericImageView<Pixel = Rgba<u8>>) {
+    assert_eq!(
+        image.dimensions(),
+        (LONG_COUNT * MAPLET_SIZE, LAT_COUNT * MAPLET_SIZE)
+    );
+    for row in 0..LAT_COUNT {
+        for col in 0..LONG_COUNT {
+            let centre = MAPLET_SIZE / 2;
+            let pixel = image.get_pixel(col * MAPLET_SIZE + centre, row * MAPLET_SIZE + centre);
+            let expected = maplet_colour(row, col);
+            for channel in 0..3 {
+                let diff = pixel[channel] as i32 - expected[channel] as i32;
+                assert!(
+                    diff.abs() <= COLOUR_TOLERANCE,
+                    "maplet ({}, {}) is {:?}, expected {:?}",
+                    row,
+                    col,
+                    pixel,
+                    expected
+                );
+            }
+            assert_eq!(pixel[3], 255);
+        }
+    }
+}
+
+fn convert(case: &str, format: Format) -> std::path::PathBuf {
+    let dir = scratch_dir(case);
+    let input = dir.join("fixture.tpq");
+    write_tpq(&input, &synthetic_tpq(LONG_COUNT, LAT_COUNT));
+    Conversion::new(input).format(format).run().unwrap()
+}
+
+#[test]
+fn png_output() {
+    let output = convert("golden-png", Format::Png);
+    assert_eq!(output.file_name().unwrap(), "fixture_24k.png");
+
+    let world_file = fs::read_to_string(output.with_extension("pgw")).unwrap();
+    let values = world_file
+        .lines()
+        .map(|line| line.parse::<f64>().unwrap())
+        .collect::<Vec<_>>();
+    let [x, dx, _, y, _, dy] = expected_transform();
+    assert_close(&values, &[dx, 0.0, 0.0, dy, x + dx / 2.0, y + dy / 2.0]);
+    assert_eq!(
+        fs::read_to_string(output.with_extension("prj")).unwrap(),
+        NAD27_WKT
+    );
+
+    let image = image::open(&output).unwrap();
+    assert_eq!(image.color(), image::ColorType::Rgb8);
+    assert_pixel_samples(&image);
+}
+
+#[test]
+fn geotiff_output() {
+    let output = convert("golden-gtiff", Format::GTiff);
+    assert_eq!(output.file_name().unwrap(), "fixture_24k.tif");
+
+    #[cfg(feature = "gdal")]
+    {
+        let dataset = gdal::Dataset::open(&output).unwrap();
+        assert_close(&dataset.geo_transform().unwrap(), &expected_transform());
+        assert!(dataset.projection().contains("1927"));
+    }
+
+    let image = image::open(&output).unwrap();
+    assert_eq!(image.color(), image::ColorType::Rgb8);
+    assert_pixel_samples(&image);
+}
+
+#[test]
+#[ignore]
+fn output_checksums() {
+    check_checksum("png", &convert("golden-png-checksum", Format::Png));
+    check_checksum("gtiff", &convert("golden-gtiff-checksum", Format::GTiff));
+}
+
+#[cfg(feature = "gdal")]
+#[test]
+fn cog_alpha_band_is_tagged_as_alpha() {
+    let dir = scratch_dir("golden-cog-alpha");
+    let input = dir.join("fixture.tpq");
+    write_tpq(&input, &synthetic_tpq(LONG_COUNT, LAT_COUNT));
+
+    let output = Conversion::new(&input)
+        .format(Format::Cog)
+        .alpha(true)
+        .run()
+        .unwrap();
+    let dataset = gdal::Dataset::open(&output).unwrap();
+    assert_eq!(dataset.raster_count(), 4);
+    let interp = unsafe {
+        gdal_sys::GDALGetRasterColorInterpretation(gdal_sys::GDALGetRasterBand(
+            dataset.c_dataset(),
+            4,
+        ))
+    };
+    assert_eq!(interp, gdal_sys::GDALColorInterp::GCI_AlphaBand);
+    fs::remove_dir_all(dir).unwrap();
+}
@@ -0,0 +1,48 @@
+#![cfg(feature = "mbtiles")]
+
+mod common;
+
+use std::fs;
+
+use rusqlite::Connection;
+use topodump::gpkg::{GeoPackageBuild, TILE_TABLE};
+use topodump::Series;
+
+use common::{scratch_dir, shifted_tpq, synthetic_tpq, write_tpq};
+
+#[test]
+fn quads_are_tiled_into_one_pyramid() {
+    let dir = scratch_dir("gpkg");
+    let west = dir.join("west.tpq");
+    let east = dir.join("east.tpq");
+    write_tpq(&west, &synthetic_tpq(2, 2));
+    write_tpq(&east, &shifted_tpq(synthetic_tpq(2, 2), 1, 0));
+    let output = dir.join("state.gpkg");
+
+    let summary = GeoPackageBuild::new(vec![west, east], &output)
+        .run()
+        .unwrap();
+    assert!(summary.skipped.is_empty());
+    assert_eq!(summary.levels.len(), 1);
+    assert_eq!(summary.levels[0].series, Series::Scale24k);
+    assert_eq!(summary.levels[0].quads, 2);
+
+    let connection = Connection::open(&output).unwrap();
+    let application_id: i32 = connection
+        .query_row("PRAGMA application_id", [], |row| row.get(0))
+        .unwrap();
+    assert_eq!(application_id, 0x4750_4B47);
+    let tiles: u32 = connection
+        .query_row(&format!("SELECT COUNT(*) FROM {}", TILE_TABLE), [], |row| {
+            row.get(0)
+        })
+        .unwrap();
+    assert!(tiles > 0);
+    let matrices: u32 = connection
+        .query_row("SELECT COUNT(*) FROM gpkg_tile_matrix", [], |row| {
+            row.get(0)
+        })
+        .unwrap();
+    assert_eq!(matrices as usize, summary.levels[0].zooms.clone().count());
+    fs::remove_dir_all(dir).unwrap();
+}
@@ -0,0 +1,222 @@
+mod common;
+
+use std::convert::TryInto;
+use std::fs;
+use std::path::Path;
+
+use image::GenericImageView;
+use topodump::mosaic::{
+    deduplicate, group_by_series, quads_in_extent, series_output, Duplicate, Mosaic, Overlap,
+};
+use topodump::{Format, Series};
+
+use common::{maplet_colour, scratch_dir, shifted_tpq, synthetic_tpq, write_tpq, MAPLET_SIZE};
+
+#[test]
+fn neighbouring_quads() {
+    let dir = scratch_dir("mosaic");
+    let west = dir.join("west.tpq");
+    let east = dir.join("east.tpq");
+    write_tpq(&west, &synthetic_tpq(2, 2));
+    write_tpq(&east, &shifted_tpq(synthetic_tpq(2, 2), 1, 0));
+
+    let output = Mosaic::new(vec![east, west], dir.join("mosaic.png"))
+        .format(Format::Png)
+        // Smaller than a maplet, so some stripes start no maplets
+        .stripe_height(MAPLET_SIZE / 2)
+        .run()
+        .unwrap();
+
+    let image = image::open(&output).unwrap();
+    assert_eq!(image.dimensions(), (4 * MAPLET_SIZE, 2 * MAPLET_SIZE));
+    for row in 0..2 {
+        for col in 0..4 {
+            let centre = MAPLET_SIZE / 2;
+            let pixel = image
+                .get_pixel(col * MAPLET_SIZE + centre, row * MAPLET_SIZE + centre)
+                .0;
+            let expected = maplet_colour(row, col % 2).0;
+            for channel in 0..3 {
+                assert!((pixel[channel] as i32 - expected[channel] as i32).abs() <= 3);
+            }
+        }
+    }
+
+    let world_file = fs::read_to_string(output.with_extension("pgw")).unwrap();
+    let values = world_file
+        .lines()
+        .map(|line| line.parse::<f64>().unwrap())
+        .collect::<Vec<_>>();
+    let dx = (common::EAST - common::WEST) / (2 * MAPLET_SIZE) as f64;
+    assert!((values[4] - (common::WEST + dx / 2.0)).abs() < 1e-9);
+}
+
+// Where the header stores its second year, and the offset table starts
+const YEAR2_OFFSET: usize = 452;
+const OFFSET_TABLE_START: usize = 1024;
+
+// The centre pixel of the top-left maplet of a mosaic of `inputs`
+fn top_left_colour(dir: &Path, inputs: &[&Path], overlap: Overlap) -> [u8; 4] {
+    let output = Mosaic::new(
+        inputs.iter().map(|input| input.to_path_buf()).collect(),
+        dir.join("overlap.png"),
+    )
+    .overlap(overlap)
+    .run()
+    .unwrap();
+    image::open(output)
+        .unwrap()
+        .get_pixel(MAPLET_SIZE / 2, MAPLET_SIZE / 2)
+        .0
+}
+
+fn close_to(pixel: [u8; 4], row: u32, col: u32) -> bool {
+    let expected = maplet_colour(row, col).0;
+    (0..3).all(|channel| (pixel[channel] as i32 - expected[channel] as i32).abs() <= 3)
+}
+
+#[test]
+fn overlap_rules_choose_the_kept_quad() {
+    let dir = scratch_dir("mosaic-overlap");
+    let older = dir.join("older.tpq");
+    let newer = dir.join("newer.tpq");
+    write_tpq(&older, &synthetic_tpq(2, 2));
+    // The same cells, but with the first and last maplets swapped
+    let mut data = synthetic_tpq(2, 2);
+    let (first, last) = (OFFSET_TABLE_START, OFFSET_TABLE_START + 12);
+    let first_offset = data[first..first + 4].to_vec();
+    data.copy_within(last..last + 4, first);
+    data[last..last + 4].copy_from_slice(&first_offset);
+    data[YEAR2_OFFSET..YEAR2_OFFSET + 4].copy_from_slice(b"2000");
+    write_tpq(&newer, &data);
+
+    let first = top_left_colour(&dir, &[&older, &newer], Overlap::First);
+    assert!(close_to(first, 0, 0));
+    let last = top_left_colour(&dir, &[&older, &newer], Overlap::Last);
+    assert!(close_to(last, 1, 1));
+    let newest = top_left_colour(&dir, &[&newer, &older], Overlap::Newest);
+    assert!(close_to(newest, 1, 1));
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn retiled_mosaic_writes_indexed_tiles() {
+    let dir = scratch_dir("mosaic-retile");
+    let input = dir.join("quad.tpq");
+    write_tpq(&input, &synthetic_tpq(2, 2));
+
+    let index = Mosaic::new(vec![input], dir.join("state.tif"))
+        .retile(48, 48)
+        .run()
+        .unwrap();
+    assert_eq!(index, dir.join("state_index.geojson"));
+    let index: serde_json::Value = serde_json::from_slice(&fs::read(index).unwrap()).unwrap();
+    assert_eq!(index["features"].as_array().unwrap().len(), 4);
+    let corner = image::open(dir.join("state_001_001.tif")).unwrap();
+    assert_eq!(
+        corner.dimensions(),
+        (2 * MAPLET_SIZE - 48, 2 * MAPLET_SIZE - 48)
+    );
+    assert!(!dir.join("state.tif").exists());
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn extent_selects_intersecting_quads() {
+    let dir = scratch_dir("mosaic-extent");
+    let west = dir.join("west.tpq");
+    let east = dir.join("east.tpq");
+    write_tpq(&west, &synthetic_tpq(2, 2));
+    write_tpq(&east, &shifted_tpq(synthetic_tpq(2, 2), 1, 0));
+    let inputs = vec![west, east.clone()];
+
+    let extent = (-112.95, 34.05, -112.9, 34.1);
+    assert_eq!(quads_in_extent(inputs.clone(), extent).unwrap(), vec![east]);
+    assert!(quads_in_extent(inputs, (-100.0, 34.0, -99.0, 35.0)).is_err());
+}
+
+#[test]
+fn duplicate_quads_are_kept_once() {
+    let dir = scratch_dir("mosaic-duplicates");
+    let older = dir.join("older.tpq");
+    let newer = dir.join("newer.tpq");
+    let beside = dir.join("beside.tpq");
+    write_tpq(&older, &synthetic_tpq(2, 2));
+    let mut data = synthetic_tpq(2, 2);
+    data[YEAR2_OFFSET..YEAR2_OFFSET + 4].copy_from_slice(b"2000");
+    write_tpq(&newer, &data);
+    write_tpq(&beside, &shifted_tpq(synthetic_tpq(2, 2), 1, 0));
+
+    let inputs = vec![newer.clone(), beside.clone(), older.clone()];
+    let (kept, duplicates) = deduplicate(inputs.clone(), Overlap::Newest).unwrap();
+    assert_eq!(kept, vec![newer.clone(), beside.clone()]);
+    assert_eq!(
+        duplicates,
+        vec![Duplicate {
+            kept: newer.clone(),
+            dropped: vec![older.clone()],
+        }]
+    );
+    let (kept, _) = deduplicate(inputs, Overlap::Last).unwrap();
+    assert_eq!(kept, vec![beside, older]);
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn retiled_mosaic_resumes_after_failing() {
+    let dir = scratch_dir("mosaic-resume");
+    let (north, south) = (dir.join("north.tpq"), dir.join("south.tpq"));
+    write_tpq(&north, &synthetic_tpq(2, 2));
+    let data = shifted_tpq(synthetic_tpq(2, 2), 0, -1);
+    // The south quad's last maplet no longer starts like a JPEG
+    let table = OFFSET_TABLE_START + 12;
+    let last = u32::from_le_bytes(data[table..table + 4].try_into().unwrap()) as usize;
+    let mut corrupt = data.clone();
+    corrupt[last..last + 16].fill(0);
+    write_tpq(&south, &corrupt);
+
+    let mosaic = || {
+        Mosaic::new(vec![north.clone(), south.clone()], dir.join("state.tif"))
+            .retile(MAPLET_SIZE, MAPLET_SIZE)
+            .stripe_height(MAPLET_SIZE)
+    };
+    assert!(mosaic().run().is_err());
+    assert!(dir.join("state_journal.json").exists());
+    assert!(dir.join("state_000_000.tif").exists());
+
+    write_tpq(&south, &data);
+    let index = mosaic().resume(true).run().unwrap();
+    let index: serde_json::Value = serde_json::from_slice(&fs::read(index).unwrap()).unwrap();
+    assert_eq!(index["features"].as_array().unwrap().len(), 8);
+    assert!(!dir.join("state_journal.json").exists());
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn quads_are_grouped_by_series() {
+    let dir = scratch_dir("mosaic-series");
+    let quad = dir.join("quad.tpq");
+    let sheet = dir.join("sheet.tpq");
+    write_tpq(&quad, &synthetic_tpq(2, 2));
+    // A 30' by 60' sheet, as the 100k series has
+    let mut tpq = synthetic_tpq(2, 2);
+    let bounds = [-114.0, 35.0, -113.0, 34.5];
+    for (i, value) in bounds.iter().enumerate() {
+        tpq[4 + 8 * i..12 + 8 * i].copy_from_slice(&value.to_le_bytes());
+    }
+    write_tpq(&sheet, &tpq);
+
+    let groups = group_by_series(vec![sheet.clone(), quad.clone()]).unwrap();
+    assert_eq!(groups.len(), 2);
+    assert_eq!(groups[&Some(Series::Scale24k)], vec![quad]);
+    assert_eq!(groups[&Some(Series::Scale100k)], vec![sheet]);
+
+    assert_eq!(
+        series_output(Path::new("out/arizona.tif"), Some(Series::Scale24k)),
+        Path::new("out/arizona_24k.tif")
+    );
+    assert_eq!(
+        series_output(Path::new("arizona"), None),
+        Path::new("arizona_unknown")
+    );
+}
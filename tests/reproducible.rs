@@ -0,0 +1,41 @@
+mod common;
+
+use std::fs;
+
+use topodump::{convert_to_vec, Conversion, ConvertOptions, Format};
+
+use common::{scratch_dir, synthetic_tpq, write_tpq};
+
+// Enough maplets that parallel decoding finishes them out of order
+const MAPLETS: u32 = 8;
+
+#[test]
+fn repeated_conversions_are_byte_identical() {
+    let dir = scratch_dir("reproducible");
+    let input = dir.join("quad.tpq");
+    write_tpq(&input, &synthetic_tpq(MAPLETS, MAPLETS));
+
+    for format in &[Format::GTiff, Format::Png] {
+        let convert = |name: &str| {
+            let output = dir.join(format!("{}.{}", name, format.extension()));
+            Conversion::new(&input)
+                .output(&output)
+                .format(*format)
+                .run()
+                .unwrap();
+            fs::read(output).unwrap()
+        };
+        assert_eq!(convert("first"), convert("second"));
+    }
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn repeated_in_memory_conversions_are_byte_identical() {
+    let data = synthetic_tpq(MAPLETS, MAPLETS);
+    let options = ConvertOptions::default();
+    assert_eq!(
+        convert_to_vec(&data, &options).unwrap(),
+        convert_to_vec(&data, &options).unwrap()
+    );
+}
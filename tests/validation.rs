@@ -0,0 +1,111 @@
+mod common;
+
+use topodump::verify::{seams, verify};
+use topodump::{TopodumpError, TpqFile};
+
+use common::synthetic_tpq;
+
+// Where the header stores its west and east bounds and row count
+const WEST_OFFSET: usize = 4;
+const EAST_OFFSET: usize = 20;
+const LAT_COUNT_OFFSET: usize = 496;
+const OFFSET_TABLE_START: usize = 1024;
+
+fn with_lat_count(mut tpq: Vec<u8>, lat_count: u32) -> Vec<u8> {
+    tpq[LAT_COUNT_OFFSET..LAT_COUNT_OFFSET + 4].copy_from_slice(&lat_count.to_le_bytes());
+    tpq
+}
+
+#[test]
+fn quads_without_maplets_or_area_are_rejected() {
+    let empty = TpqFile::from_bytes(with_lat_count(synthetic_tpq(3, 2), 0));
+    assert!(matches!(empty, Err(TopodumpError::DegenerateQuad(_))));
+
+    let mut flat = synthetic_tpq(3, 2);
+    let west = flat[WEST_OFFSET..WEST_OFFSET + 8].to_vec();
+    flat[EAST_OFFSET..EAST_OFFSET + 8].copy_from_slice(&west);
+    let flat = TpqFile::from_bytes(flat);
+    assert!(matches!(flat, Err(TopodumpError::DegenerateQuad(_))));
+}
+
+#[test]
+fn maplet_count_beyond_offset_table_is_rejected() {
+    let result = TpqFile::from_bytes(with_lat_count(synthetic_tpq(3, 2), 5));
+    assert!(matches!(
+        result,
+        Err(TopodumpError::MapletCountExceedsTable {
+            columns: 3,
+            rows: 5,
+            entries: 6,
+        })
+    ));
+}
+
+#[test]
+fn maplet_count_beyond_file_is_rejected() {
+    let result = TpqFile::from_bytes(with_lat_count(synthetic_tpq(3, 2), 1_000_000));
+    assert!(matches!(
+        result,
+        Err(TopodumpError::MapletCountExceedsFile {
+            columns: 3,
+            rows: 1_000_000,
+            ..
+        })
+    ));
+}
+
+#[test]
+fn shared_maplet_offsets_are_flagged() {
+    let mut data = synthetic_tpq(3, 2);
+    // Point (0, 1) at (0, 0)'s maplet
+    data.copy_within(
+        OFFSET_TABLE_START..OFFSET_TABLE_START + 4,
+        OFFSET_TABLE_START + 4,
+    );
+    let tpq = TpqFile::from_bytes(data).unwrap();
+
+    let problems = verify(&tpq);
+    assert!(matches!(
+        problems.as_slice(),
+        [
+            TopodumpError::DuplicateMaplet {
+                row: 0,
+                col: 1,
+                first_row: 0,
+                first_col: 0,
+            },
+            ..
+        ]
+    ));
+}
+
+#[test]
+fn boundaries_between_solid_maplets_are_seams() {
+    let tpq = TpqFile::from_bytes(synthetic_tpq(3, 2)).unwrap();
+    let collage = tpq.collage().unwrap();
+    let found = seams(&collage, tpq.maplet_dimensions().unwrap());
+    // Every internal boundary: two per row across, three between the rows
+    assert_eq!(found.len(), 7);
+}
+
+#[test]
+fn swapped_bounds_are_normalized() {
+    let mut data = synthetic_tpq(3, 2);
+    let west = data[WEST_OFFSET..WEST_OFFSET + 8].to_vec();
+    data.copy_within(EAST_OFFSET..EAST_OFFSET + 8, WEST_OFFSET);
+    data[EAST_OFFSET..EAST_OFFSET + 8].copy_from_slice(&west);
+    let tpq = TpqFile::from_bytes(data).unwrap();
+
+    assert!(tpq.header().w_long < tpq.header().e_long);
+    assert_eq!(tpq.warnings().len(), 1);
+}
+
+#[test]
+fn bounds_off_the_globe_are_rejected() {
+    let mut data = synthetic_tpq(3, 2);
+    data[WEST_OFFSET..WEST_OFFSET + 8].copy_from_slice(&(-200.0f64).to_le_bytes());
+    assert!(matches!(
+        TpqFile::from_bytes(data),
+        Err(TopodumpError::BoundsOutOfRange { .. })
+    ));
+}